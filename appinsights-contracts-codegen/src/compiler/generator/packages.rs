@@ -19,15 +19,16 @@ impl PackageGenerator {
     }
 }
 
-impl ToString for PackageGenerator {
-    fn to_string(&self) -> String {
-        codegen::Scope::new()
+impl std::fmt::Display for PackageGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = codegen::Scope::new()
             .raw("// NOTE: This file was automatically generated.")
             .raw("#![allow(unused_variables, dead_code, unused_imports)]")
             .raw(&self.modules.join("\n"))
             .raw(&self.usages.join("\n"))
             .push_trait(telemetry_data_trait())
-            .to_string()
+            .to_string();
+        write!(f, "{}", scope)
     }
 }
 
@@ -50,8 +50,8 @@ impl Visitor for SchemaGenerator {
     }
 }
 
-impl ToString for SchemaGenerator {
-    fn to_string(&self) -> String {
-        self.body.to_string()
+impl std::fmt::Display for SchemaGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.body.to_string())
     }
 }
@@ -244,7 +244,7 @@ impl TelemetryDataTraitGenerator {
             ))
             .arg_ref_self()
             .ret("String")
-            .line(&format!(r#"String::from("{}")"#, name));
+            .line(format!(r#"String::from("{}")"#, name));
 
         Self {
             implementation,
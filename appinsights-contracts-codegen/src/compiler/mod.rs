@@ -39,13 +39,13 @@ fn compile_files<'a>(modules: impl Iterator<Item = &'a Module>) -> Result<()> {
 }
 
 fn compile(module: &Module) -> Result<()> {
-    let parser = Parser::default();
+    let parser = Parser;
     let schema = parser.parse(module.source_path())?;
 
     let mut generator = SchemaGenerator::new();
     generator.visit_schema(&schema);
 
-    fs::write(&module.path(), generator.to_string())?;
+    fs::write(module.path(), generator.to_string())?;
     Ok(())
 }
 
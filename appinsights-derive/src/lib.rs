@@ -0,0 +1,127 @@
+//! Derive macros for the [`appinsights`](https://docs.rs/appinsights) crate. They are re-exported by
+//! `appinsights` with the `derive` feature enabled and should not be used directly.
+#![deny(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Implements `From<T> for EventTelemetry` so a domain struct can be submitted as a custom event.
+///
+/// An event is named after the struct unless `#[telemetry(name = "...")]` is given. Fields annotated
+/// with `#[telemetry(property)]` are converted with `ToString` and become custom properties, fields
+/// annotated with `#[telemetry(measurement)]` are cast to `f64` and become measurements. Other fields
+/// are ignored. Measurements that are `NaN` or infinite are discarded.
+///
+/// # Examples
+/// ```rust, ignore
+/// use appinsights::{telemetry::EventTelemetry, Telemetry};
+///
+/// #[derive(Telemetry)]
+/// #[telemetry(name = "OrderPlaced")]
+/// struct Order {
+///     #[telemetry(property)]
+///     customer: String,
+///     #[telemetry(measurement)]
+///     total: f64,
+///     items: Vec<String>,
+/// }
+///
+/// let order = Order { customer: "contoso".into(), total: 42.0, items: Vec::new() };
+/// client.track(EventTelemetry::from(order));
+/// ```
+#[proc_macro_derive(Telemetry, attributes(telemetry))]
+pub fn derive_telemetry(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// A kind of telemetry data a field is converted to.
+enum FieldKind {
+    Property,
+    Measurement,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+
+    let mut name = ident.to_string();
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("telemetry")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "Telemetry can be derived for structs with named fields only",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "Telemetry can be derived for structs only",
+            ))
+        }
+    };
+
+    let mut properties = Vec::new();
+    let mut measurements = Vec::new();
+    for field in fields {
+        let field_ident = field.ident.as_ref().expect("named field");
+        let key = field_ident.to_string();
+
+        match field_kind(field)? {
+            Some(FieldKind::Property) => properties.push(quote! {
+                ::appinsights::telemetry::Telemetry::properties_mut(&mut telemetry)
+                    .insert(#key.to_string(), ::std::string::ToString::to_string(&value.#field_ident));
+            }),
+            Some(FieldKind::Measurement) => measurements.push(quote! {
+                let _ = telemetry.measurements_mut().insert(#key, value.#field_ident as f64);
+            }),
+            None => {}
+        }
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::std::convert::From<#ident #ty_generics> for ::appinsights::telemetry::EventTelemetry #where_clause {
+            fn from(value: #ident #ty_generics) -> Self {
+                let mut telemetry = ::appinsights::telemetry::EventTelemetry::new(#name);
+                #(#properties)*
+                #(#measurements)*
+                telemetry
+            }
+        }
+    })
+}
+
+/// Returns a kind of telemetry data specified by a `#[telemetry(...)]` attribute of the field if any.
+fn field_kind(field: &syn::Field) -> syn::Result<Option<FieldKind>> {
+    let mut kind = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("telemetry")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("property") {
+                kind = Some(FieldKind::Property);
+                Ok(())
+            } else if meta.path.is_ident("measurement") {
+                kind = Some(FieldKind::Measurement);
+                Ok(())
+            } else {
+                Err(meta.error("expected `property` or `measurement`"))
+            }
+        })?;
+    }
+    Ok(kind)
+}
@@ -0,0 +1,113 @@
+use std::{
+    sync::{
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+use appinsights::{
+    schema::Envelope,
+    telemetry::{ContextTags, Properties, SeverityLevel, Telemetry, TraceTelemetry},
+    FlushError, TelemetryChannel, TelemetryClient, TelemetryConfig, TelemetryContext, TelemetryItem,
+};
+use async_trait::async_trait;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures_channel::oneshot;
+
+const I_KEY: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Maximum number of telemetry items submitted in a single batch by default.
+const BATCH_SIZE: usize = 256;
+
+/// A channel that hands telemetry items over to a consumer thread, so producers pay for queueing only.
+struct ConsumerChannel {
+    sender: Mutex<Sender<TelemetryItem>>,
+}
+
+impl ConsumerChannel {
+    fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || for _ in receiver {});
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+}
+
+#[async_trait]
+impl TelemetryChannel for ConsumerChannel {
+    fn send(&self, item: TelemetryItem) {
+        let _ = self.sender.lock().unwrap().send(item);
+    }
+
+    fn flush(&self) {}
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(Ok(()));
+        receiver
+    }
+
+    async fn close(&self) {}
+
+    async fn terminate(&self) {}
+}
+
+fn envelope() -> Envelope {
+    let context = TelemetryContext::new(I_KEY.into(), ContextTags::default(), Properties::default());
+    let mut telemetry = TraceTelemetry::new("Unable to connect to a database", SeverityLevel::Warning);
+    telemetry
+        .properties_mut()
+        .insert("component".into(), "data_processor".into());
+    Envelope::from((context, telemetry))
+}
+
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialization");
+
+    let item = envelope();
+    group.bench_function("trace", |b| b.iter(|| serde_json::to_vec(black_box(&item)).unwrap()));
+
+    let batch: Vec<_> = (0..BATCH_SIZE).map(|_| envelope()).collect();
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    group.bench_function("batch", |b| b.iter(|| serde_json::to_vec(black_box(&batch)).unwrap()));
+
+    group.finish();
+}
+
+fn tracking(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tracking");
+    group.throughput(Throughput::Elements(1));
+
+    let config = TelemetryConfig::new(I_KEY.into());
+    let client = TelemetryClient::with_channel(&config, ConsumerChannel::spawn());
+    group.bench_function("track_trace", |b| {
+        b.iter(|| client.track_trace("Unable to connect to a database", SeverityLevel::Warning))
+    });
+
+    group.finish();
+}
+
+fn sampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sampling");
+    group.throughput(Throughput::Elements(1));
+
+    for rate in [100.0, 50.0] {
+        let config = TelemetryConfig::builder()
+            .i_key(I_KEY)
+            .sampling_rate(rate)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, ConsumerChannel::spawn());
+
+        group.bench_function(format!("{}%", rate), |b| {
+            b.iter_batched(envelope, |item| client.track_envelope(item), BatchSize::SmallInput)
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, serialization, tracking, sampling);
+criterion_main!(benches);
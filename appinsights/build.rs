@@ -0,0 +1,16 @@
+use std::{env, process::Command};
+
+fn main() {
+    // expose a version of the compiler the crate is built with to report it in SDK heartbeat
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|output| output.split_whitespace().nth(1).map(String::from))
+        .unwrap_or_else(|| "unknown".into());
+
+    println!("cargo:rustc-env=APPINSIGHTS_RUSTC_VERSION={}", version);
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
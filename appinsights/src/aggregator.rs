@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use crate::{
+    telemetry::{AggregateMetricTelemetry, Stats},
+    TelemetryClient,
+};
+
+/// Accumulates metric values in memory and periodically submits them as pre-aggregated metric
+/// telemetry items, one per metric name, instead of one telemetry item per value.
+///
+/// Values are recorded without blocking other writers: a shared lock is only taken exclusively
+/// when a metric is recorded for the first time in a window or when aggregated values are collected.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # async fn run() {
+/// let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+///
+/// // every value is accumulated and submitted once per aggregation window
+/// for latency in &[12.0, 15.5, 9.7] {
+///     client.aggregator().record("request_latency_ms", *latency);
+/// }
+/// # }
+/// ```
+pub struct MetricsAggregator {
+    series: RwLock<HashMap<String, Series>>,
+    window: Duration,
+    started: AtomicBool,
+}
+
+impl MetricsAggregator {
+    /// Creates a new aggregator that accumulates values over specified time window.
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            series: RwLock::default(),
+            window,
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Records a measured value of a metric with specified name. Values that are not finite numbers
+    /// are ignored.
+    pub fn record(&self, name: &str, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        if let Some(series) = self.series.read().unwrap().get(name) {
+            series.add(value);
+            return;
+        }
+
+        self.series.write().unwrap().entry(name.into()).or_default().add(value);
+    }
+
+    /// Returns a time window values are accumulated over.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Marks aggregation as started and returns `true` if it was not started before.
+    pub(crate) fn start(&self) -> bool {
+        !self.started.swap(true, Ordering::SeqCst)
+    }
+
+    /// Submits metrics accumulated so far with specified client right away instead of waiting for the
+    /// end of the aggregation window, and starts a new window. Returns submitted telemetry items, e.g.
+    /// to inspect them in tests. Nothing is submitted when no values were recorded since last time.
+    ///
+    /// # Examples
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+    /// client.aggregator().record("request_latency_ms", 12.0);
+    ///
+    /// let metrics = client.aggregator().flush_to_client(&client);
+    /// assert_eq!(metrics.len(), 1);
+    /// ```
+    pub fn flush_to_client(&self, client: &TelemetryClient) -> Vec<AggregateMetricTelemetry> {
+        let metrics = self.drain();
+        for telemetry in &metrics {
+            client.track(telemetry.clone());
+        }
+        metrics
+    }
+
+    /// Collects metrics accumulated so far and starts a new aggregation window.
+    pub(crate) fn drain(&self) -> Vec<AggregateMetricTelemetry> {
+        let series = mem::take(&mut *self.series.write().unwrap());
+        series
+            .into_iter()
+            .map(|(name, series)| {
+                let mut telemetry = AggregateMetricTelemetry::new(name);
+                *telemetry.stats_mut() = series.stats();
+                telemetry
+            })
+            .collect()
+    }
+}
+
+/// Aggregated values of a single metric.
+struct Series {
+    count: AtomicU64,
+    sum: AtomicF64,
+    sum_of_squares: AtomicF64,
+    min: AtomicF64,
+    max: AtomicF64,
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            sum: AtomicF64::new(0.0),
+            sum_of_squares: AtomicF64::new(0.0),
+            min: AtomicF64::new(f64::INFINITY),
+            max: AtomicF64::new(f64::NEG_INFINITY),
+        }
+    }
+}
+
+impl Series {
+    fn add(&self, value: f64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.update(|sum| sum + value);
+        self.sum_of_squares.update(|sum| sum + value * value);
+        self.min.update(|min| min.min(value));
+        self.max.update(|max| max.max(value));
+    }
+
+    fn stats(&self) -> Stats {
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum.load();
+        let mean = sum / count as f64;
+        let variance = self.sum_of_squares.load() / count as f64 - mean * mean;
+
+        Stats {
+            value: sum,
+            min: self.min.load(),
+            max: self.max.load(),
+            count: count.min(i32::MAX as u64) as i32,
+            std_dev: variance.max(0.0).sqrt(),
+        }
+    }
+}
+
+/// A floating point number which can be safely shared between threads.
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn update<F: Fn(f64) -> f64>(&self, f: F) {
+        let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some(f(f64::from_bits(bits)).to_bits())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn it_aggregates_values_by_name() {
+        let aggregator = MetricsAggregator::new(Duration::from_secs(60));
+        for value in &[9.0, 10.0, 11.0, 7.0, 13.0] {
+            aggregator.record("latency", *value);
+        }
+        aggregator.record("queue", 1.0);
+
+        let mut metrics = aggregator.drain();
+        metrics.sort_by_key(|telemetry| telemetry.stats().count);
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(
+            metrics[1].stats(),
+            &Stats {
+                value: 50.0,
+                min: 7.0,
+                max: 13.0,
+                count: 5,
+                std_dev: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn it_starts_new_window_when_drained() {
+        let aggregator = MetricsAggregator::new(Duration::from_secs(60));
+        aggregator.record("latency", 1.0);
+
+        assert_eq!(aggregator.drain().len(), 1);
+        assert!(aggregator.drain().is_empty());
+    }
+
+    #[test]
+    fn it_ignores_values_that_are_not_finite() {
+        let aggregator = MetricsAggregator::new(Duration::from_secs(60));
+        aggregator.record("latency", f64::NAN);
+        aggregator.record("latency", f64::INFINITY);
+
+        assert!(aggregator.drain().is_empty());
+    }
+
+    #[test]
+    fn it_records_values_from_several_threads() {
+        let aggregator = Arc::new(MetricsAggregator::new(Duration::from_secs(60)));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let aggregator = aggregator.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        aggregator.record("latency", f64::from(i));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let metrics = aggregator.drain();
+        assert_eq!(metrics[0].stats().count, 4000);
+        assert_eq!(metrics[0].stats().value, 4.0 * 499_500.0);
+    }
+}
@@ -0,0 +1,120 @@
+//! Module for instrumenting [Axum](https://docs.rs/axum) web applications.
+//!
+//! Requests are tracked by [`AppInsightsLayer`](../tower/struct.AppInsightsLayer.html) re-exported
+//! from [`tower`](../tower/index.html) module. Handlers access an operation the request belongs to
+//! with [`AppInsightsContext`](struct.AppInsightsContext.html) extractor, so telemetry they track
+//! is correlated with the request.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{
+//!     axum::{AppInsightsContext, AppInsightsLayer},
+//!     telemetry::{SeverityLevel, Telemetry, TraceTelemetry},
+//!     TelemetryClient,
+//! };
+//! use axum::{routing::get, Router};
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let tracker = client.clone();
+//! let app: Router = Router::new()
+//!     .route(
+//!         "/",
+//!         get(move |AppInsightsContext(operation): AppInsightsContext| async move {
+//!             let mut trace = TraceTelemetry::new("Serving index", SeverityLevel::Information);
+//!             trace.set_operation(&operation);
+//!             tracker.track(trace);
+//!         }),
+//!     )
+//!     .layer(AppInsightsLayer::new(client));
+//! ```
+use std::convert::Infallible;
+
+use ::axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+pub use crate::tower::{AppInsightsLayer, AppInsightsService};
+use crate::{telemetry::OperationContext, tower::incoming_operation};
+
+/// An Axum extractor that provides an operation an incoming request belongs to. With
+/// [`AppInsightsLayer`](struct.AppInsightsLayer.html) applied the operation parent id is the id of
+/// submitted request telemetry, otherwise the operation is read from request headers as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppInsightsContext(pub OperationContext);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AppInsightsContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let operation = match parts.extensions.get::<OperationContext>() {
+            Some(operation) => operation.clone(),
+            None => incoming_operation(&parts.headers, &[]),
+        };
+        Ok(Self(operation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use crossbeam_queue::SegQueue;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data},
+        TelemetryClient, TelemetryConfig,
+    };
+
+    #[tokio::test]
+    async fn it_tracks_incoming_request() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        let mut app: Router = Router::new()
+            .route(
+                "/users",
+                get(|AppInsightsContext(operation): AppInsightsContext| async move {
+                    (
+                        StatusCode::NOT_FOUND,
+                        operation.parent_id().unwrap_or_default().to_string(),
+                    )
+                }),
+            )
+            .layer(AppInsightsLayer::new(client));
+
+        let request = Request::get("http://example.com/users?id=1")
+            .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(
+            tags.get("ai.operation.id"),
+            Some(&"0af7651916cd43dd8448eb211c80319c".to_string())
+        );
+        assert_eq!(tags.get("ai.operation.parentId"), Some(&"b7ad6b7169203331".to_string()));
+
+        match envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.name, Some("GET http://example.com/users".into()));
+                assert_eq!(data.response_code, "404");
+                assert!(!data.success);
+                assert_eq!(body, data.id.as_bytes());
+            }
+            _ => panic!("unexpected data"),
+        }
+    }
+}
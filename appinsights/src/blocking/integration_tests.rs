@@ -13,18 +13,11 @@ use hyper::{
     service::{make_service_fn, service_fn},
     Body, Request, Response, Server, StatusCode,
 };
-use lazy_static::lazy_static;
 use matches::assert_matches;
-use parking_lot::Mutex;
 use serde_json::json;
 use tokio::sync::oneshot;
 
-use crate::{blocking::TelemetryClient, timeout, TelemetryConfig};
-
-lazy_static! {
-    /// A global lock since most tests need to run in serial.
-    static ref SERIAL_TEST_MUTEX: Mutex<()> = Mutex::new(());
-}
+use crate::{blocking::TelemetryClient, client::integration_tests::SERIAL_TEST_MUTEX, timeout, TelemetryConfig};
 
 macro_rules! manual_timeout_test {
     (fn $name: ident() $body: block) => {
@@ -341,10 +334,11 @@ manual_timeout_test! {
 
 fn create_client(endpoint: &str) -> TelemetryClient {
     let config = TelemetryConfig::builder()
-        .i_key("instrumentation key")
+        .i_key("00000000-0000-0000-0000-000000000000")
         .endpoint(endpoint)
         .interval(Duration::from_millis(300))
-        .build();
+        .build()
+        .unwrap();
 
     TelemetryClient::from_config(config)
 }
@@ -474,7 +468,7 @@ impl Builder {
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().expect("runtime");
             rt.block_on(async move {
-                let server = Server::bind(&([0, 0, 0, 0], 0).into()).serve(make_service);
+                let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
 
                 let url = format!("http://{}", server.local_addr());
                 url_sender.send(url).unwrap();
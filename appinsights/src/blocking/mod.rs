@@ -12,7 +12,7 @@
 //! use appinsights::blocking::TelemetryClient;
 //!
 //! // configure telemetry client with default settings
-//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
 //!
 //! // send event telemetry to the Application Insights server
 //! client.track_event("Application started");
@@ -22,20 +22,25 @@
 //! client.close_channel();
 //! ```
 
-use std::{fmt::Display, time::Duration};
+use std::{
+    fmt::Display,
+    sync::{mpsc as std_mpsc, Arc},
+    time::Duration,
+};
 
 use http::{Method, Uri};
 use log::debug;
 use tokio::sync::mpsc;
 
 use crate::{
-    channel::{InMemoryChannel, TelemetryChannel},
+    channel::{self, FlushError, InMemoryChannel, SendError, TelemetryChannel},
     contracts::Envelope,
+    processor::{Pipeline, TelemetryProcessor},
     telemetry::{
-        AvailabilityTelemetry, EventTelemetry, MetricTelemetry, RemoteDependencyTelemetry, RequestTelemetry,
-        SeverityLevel, Telemetry, TraceTelemetry,
+        AvailabilityTelemetry, EventTelemetry, ExceptionTelemetry, MetricTelemetry, RemoteDependencyTelemetry,
+        RequestTelemetry, SeverityLevel, Telemetry, TraceTelemetry,
     },
-    TelemetryConfig, TelemetryContext,
+    time, TelemetryConfig, TelemetryContext, TelemetryItem, TelemetryTracker,
 };
 
 /// A blocking version of Application Insights telemetry client. It provides an interface to track telemetry items.
@@ -51,12 +56,12 @@ impl TelemetryClient {
 
     /// Creates a new telemetry client configured with specified configuration.
     pub fn from_config(config: TelemetryConfig) -> Self {
-        Self::create(config, |config| InMemoryChannel::new(config))
+        Self::create(config, InMemoryChannel::new)
     }
 
     pub(crate) fn create<C, F>(config: TelemetryConfig, channel: F) -> Self
     where
-        C: TelemetryChannel,
+        C: TelemetryChannel + 'static,
         F: FnOnce(&TelemetryConfig) -> C + Send + 'static,
     {
         let inner = ChannelHandle::new(config, channel);
@@ -73,6 +78,12 @@ impl TelemetryClient {
         self.inner.enabled(enabled);
     }
 
+    /// Changes a minimum severity of trace telemetry items to submit. Trace telemetry items of lower
+    /// severity are silently swallowed by the client.
+    pub fn set_min_trace_severity(&self, severity: SeverityLevel) {
+        self.inner.pipeline.set_min_trace_severity(severity);
+    }
+
     /// Returns an immutable reference to a collection of tag data to attach to the telemetry item.
     pub fn context(&self) -> &TelemetryContext {
         &self.inner.context
@@ -83,6 +94,13 @@ impl TelemetryClient {
         &mut self.inner.context
     }
 
+    /// Appends a telemetry processor to the end of processors chain. Every telemetry item tracked
+    /// by this client passes through all processors in the order they were added before it is queued
+    /// for submission.
+    pub fn add_processor(&mut self, processor: impl TelemetryProcessor + 'static) {
+        self.inner.pipeline.add_processor(processor);
+    }
+
     /// Logs a user action with the specified name.
     pub fn track_event(&self, name: impl Into<String>) {
         let event = EventTelemetry::new(name);
@@ -95,6 +113,12 @@ impl TelemetryClient {
         self.track(event)
     }
 
+    /// Logs an error with its chain of sources.
+    pub fn track_exception(&self, error: &dyn std::error::Error) {
+        let event = ExceptionTelemetry::from_std_error(error);
+        self.track(event)
+    }
+
     /// Logs a numeric value that is not specified with a specific event.
     /// Typically used to send regular reports of performance indicators.
     pub fn track_metric(&self, name: impl Into<String>, value: f64) {
@@ -135,11 +159,66 @@ impl TelemetryClient {
         self.inner.track(event);
     }
 
+    /// Submits a raw envelope. See [`track_envelope`](../struct.TelemetryClient.html#method.track_envelope)
+    /// of the async client for details.
+    pub fn track_envelope(&self, envelope: Envelope) {
+        self.inner.track_envelope(envelope);
+    }
+
     /// Forces all pending telemetry items to be submitted. The current thread will not be blocked.
     pub fn flush_channel(&self) {
         self.inner.flush();
     }
 
+    /// Forces all pending telemetry items to be submitted and blocks the current thread until the server
+    /// acknowledges them. When a timeout is specified it returns [`FlushError::Timeout`](../enum.FlushError.html)
+    /// if items were not acknowledged in time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::blocking::TelemetryClient;
+    /// # use std::time::Duration;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// client.track_event("job completed");
+    ///
+    /// // make sure the event is delivered before a short-living process exits
+    /// if let Err(err) = client.flush(Some(Duration::from_secs(5))) {
+    ///     eprintln!("telemetry was not submitted: {}", err);
+    /// }
+    /// ```
+    pub fn flush(&self, timeout: Option<Duration>) -> Result<(), FlushError> {
+        self.inner.flush_and_wait(timeout)
+    }
+
+    /// Submits a telemetry item without waiting for the next submission interval and blocks the current
+    /// thread until the server acknowledges it, e.g. to report a fatal error right before a process
+    /// aborts. The item goes through processors, but it is never sampled out. Items pending in the
+    /// channel are not affected. Returns
+    /// [`SendError::Discarded`](../enum.SendError.html#variant.Discarded) when the item was not
+    /// submitted, e.g. because the client is disabled or a processor dropped it, or an error the
+    /// submission failed with otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::{blocking::TelemetryClient, telemetry::{SeverityLevel, TraceTelemetry}};
+    /// # use std::time::Duration;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let trace = TraceTelemetry::new("configuration is corrupted", SeverityLevel::Critical);
+    /// if let Err(err) = client.send_immediately(trace, Some(Duration::from_secs(5))) {
+    ///     eprintln!("telemetry was not submitted: {}", err);
+    /// }
+    /// std::process::abort();
+    /// ```
+    pub fn send_immediately<E>(&self, event: E, timeout: Option<Duration>) -> Result<(), SendError>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        self.inner.send_immediately(event, timeout)
+    }
+
     /// Flushes and tears down the submission flow and closes internal channels.
     /// It blocks the current thread until all pending telemetry items have been submitted and it is safe to
     /// shutdown without losing telemetry.
@@ -193,8 +272,43 @@ impl TelemetryClient {
     pub fn terminate(self) {}
 }
 
+impl TelemetryTracker for TelemetryClient {
+    fn track_event(&self, name: String) {
+        TelemetryClient::track_event(self, name)
+    }
+
+    fn track_trace(&self, message: String, severity: SeverityLevel) {
+        TelemetryClient::track_trace(self, message, severity)
+    }
+
+    fn track_exception(&self, error: &dyn std::error::Error) {
+        TelemetryClient::track_exception(self, error)
+    }
+
+    fn track_metric(&self, name: String, value: f64) {
+        TelemetryClient::track_metric(self, name, value)
+    }
+
+    fn track_request(&self, method: Method, uri: Uri, duration: Duration, response_code: String) {
+        TelemetryClient::track_request(self, method, uri, duration, response_code)
+    }
+
+    fn track_remote_dependency(&self, name: String, dependency_type: String, target: String, success: bool) {
+        TelemetryClient::track_remote_dependency(self, name, dependency_type, target, success)
+    }
+
+    fn track_availability(&self, name: String, duration: Duration, success: bool) {
+        TelemetryClient::track_availability(self, name, duration, success)
+    }
+
+    fn flush_channel(&self) {
+        TelemetryClient::flush_channel(self)
+    }
+}
+
 struct ChannelHandle {
     enabled: bool,
+    pipeline: Pipeline,
     context: TelemetryContext,
     inner: InnerChannelHandle,
 }
@@ -202,10 +316,11 @@ struct ChannelHandle {
 impl ChannelHandle {
     fn new<C, F>(config: TelemetryConfig, channel: F) -> Self
     where
-        C: TelemetryChannel,
+        C: TelemetryChannel + 'static,
         F: FnOnce(&TelemetryConfig) -> C + Send + 'static,
     {
         let context = TelemetryContext::from_config(&config);
+        let pipeline = Pipeline::new(config.sampling_rate(), config.min_trace_severity());
 
         let (tx, mut rx) = mpsc::unbounded_channel::<(ClientCommand, OneshotResponse)>();
 
@@ -218,16 +333,29 @@ impl ChannelHandle {
                     .expect("tokio runtime");
 
                 let f = async move {
-                    let mut channel = channel(&config);
+                    let channel = Arc::new(channel(&config));
 
                     while let Some((command, req_tx)) = rx.recv().await {
                         match command {
-                            ClientCommand::Envelope(envelop) => channel.send(envelop),
+                            ClientCommand::Item(item) => channel.send(*item),
                             ClientCommand::Flush => channel.flush(),
+                            ClientCommand::FlushAndWait(timeout, sender) => {
+                                // wait in a separate task so other commands are still processed meanwhile
+                                let receiver = channel.flush_and_wait();
+                                tokio::spawn(async move {
+                                    let _ = sender.send(channel::wait_flushed(receiver, timeout).await);
+                                });
+                            }
+                            ClientCommand::SendImmediately(item, timeout, sender) => {
+                                let channel = channel.clone();
+                                tokio::spawn(async move {
+                                    let _ = sender.send(channel::send_immediately(&*channel, *item, timeout).await);
+                                });
+                            }
                             ClientCommand::Stop => channel.close().await,
                             ClientCommand::Terminate => channel.terminate().await,
                         }
-                        let _ = req_tx.send(());
+                        let _ = req_tx.try_send(());
                     }
                 };
                 rt.block_on(f);
@@ -242,6 +370,7 @@ impl ChannelHandle {
         ChannelHandle {
             inner,
             enabled: true,
+            pipeline,
             context,
         }
     }
@@ -259,27 +388,79 @@ impl ChannelHandle {
         E: Telemetry,
         (TelemetryContext, E): Into<Envelope>,
     {
-        if self.is_enabled() {
-            let envelop = (self.context.clone(), event).into();
-            let command = ClientCommand::Envelope(envelop);
+        self.track_with(event, false);
+    }
+
+    /// Submits a telemetry event that is never sampled out right away and waits until it is sent.
+    fn send_immediately<E>(&self, event: E, timeout: Option<Duration>) -> Result<(), SendError>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        let item = self.prepare_with(event, true).ok_or(SendError::Discarded)?;
+        self.inner.send_immediately(item, timeout)
+    }
+
+    fn track_with<E>(&self, event: E, never_sample: bool)
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        if let Some(item) = self.prepare_with(event, never_sample) {
+            self.submit(item);
+        }
+    }
 
-            let (tx, mut rx) = mpsc::channel(1);
+    /// Runs a telemetry event through processors. Returns `None` when it must not be submitted.
+    fn prepare_with<E>(&self, event: E, never_sample: bool) -> Option<TelemetryItem>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        if self.is_enabled() && self.pipeline.accepts(&event) {
+            time::check_timestamp(event.timestamp());
 
-            self.inner
-                .tx
-                .as_ref()
-                .expect("sync thread exited early")
-                .send((command, tx))
-                .expect("sync thread panicked");
+            let never_sample = never_sample || event.never_sample();
+            self.pipeline.process((self.context.next(), event).into(), never_sample)
+        } else {
+            None
+        }
+    }
 
-            let _ = rx.blocking_recv();
+    fn track_envelope(&self, mut envelope: Envelope) {
+        if self.is_enabled() {
+            if envelope.i_key.is_none() {
+                envelope.i_key = Some(self.context.i_key.clone());
+            }
+            if let Some(item) = self.pipeline.process(envelope, false) {
+                self.submit(item);
+            }
         }
     }
 
+    fn submit(&self, item: TelemetryItem) {
+        let command = ClientCommand::Item(Box::new(item));
+
+        let (tx, mut rx) = mpsc::channel(1);
+
+        self.inner
+            .tx
+            .as_ref()
+            .expect("sync thread exited early")
+            .send((command, tx))
+            .expect("sync thread panicked");
+
+        let _ = rx.blocking_recv();
+    }
+
     fn flush(&self) {
         self.inner.flush();
     }
 
+    fn flush_and_wait(&self, timeout: Option<Duration>) -> Result<(), FlushError> {
+        self.inner.flush_and_wait(timeout)
+    }
+
     fn close(mut self) {
         self.inner.shutdown(ClientCommand::Stop)
     }
@@ -301,6 +482,24 @@ impl InnerChannelHandle {
         }
     }
 
+    fn flush_and_wait(&self, timeout: Option<Duration>) -> Result<(), FlushError> {
+        let sender = self.tx.as_ref().ok_or(FlushError::Closed)?;
+
+        let (tx, rx) = std_mpsc::channel();
+        send_command(sender, ClientCommand::FlushAndWait(timeout, tx));
+
+        rx.recv().unwrap_or(Err(FlushError::Closed))
+    }
+
+    fn send_immediately(&self, item: TelemetryItem, timeout: Option<Duration>) -> Result<(), SendError> {
+        let sender = self.tx.as_ref().ok_or(SendError::Closed)?;
+
+        let (tx, rx) = std_mpsc::channel();
+        send_command(sender, ClientCommand::SendImmediately(Box::new(item), timeout, tx));
+
+        rx.recv().unwrap_or(Err(SendError::Closed))
+    }
+
     fn shutdown(&mut self, command: ClientCommand) {
         if let Some(sender) = self.tx.take() {
             send_command(&sender, command);
@@ -326,8 +525,14 @@ fn send_command(sender: &ThreadSender, command: ClientCommand) {
 
 #[derive(Debug, Clone)]
 enum ClientCommand {
-    Envelope(Envelope),
+    Item(Box<TelemetryItem>),
     Flush,
+    FlushAndWait(Option<Duration>, std_mpsc::Sender<Result<(), FlushError>>),
+    SendImmediately(
+        Box<TelemetryItem>,
+        Option<Duration>,
+        std_mpsc::Sender<Result<(), SendError>>,
+    ),
     Stop,
     Terminate,
 }
@@ -335,8 +540,10 @@ enum ClientCommand {
 impl Display for ClientCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let message = match self {
-            ClientCommand::Envelope(_) => "event",
+            ClientCommand::Item(_) => "event",
             ClientCommand::Flush => "flush",
+            ClientCommand::FlushAndWait(..) => "flush and wait",
+            ClientCommand::SendImmediately(..) => "send immediately",
             ClientCommand::Stop => "stop",
             ClientCommand::Terminate => "terminate",
         };
@@ -357,13 +564,13 @@ mod tests {
 
     #[test]
     fn it_enabled_by_default() {
-        let client = TelemetryClient::new("key".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
         assert!(client.is_enabled())
     }
 
     #[test]
     fn it_disables_telemetry() {
-        let mut client = TelemetryClient::new("key".into());
+        let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
 
         client.enabled(false);
 
@@ -380,6 +587,71 @@ mod tests {
         assert_eq!(events.len(), 1)
     }
 
+    #[test]
+    fn it_sends_telemetry_immediately() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let result = client.send_immediately(TestTelemetry {}, Some(Duration::from_secs(1)));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(events.len(), 1)
+    }
+
+    #[test]
+    fn it_sends_sampled_out_telemetry_immediately() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .sampling_rate(0.0)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::create(config, {
+            let events = events.clone();
+            |_| TestChannel::new(events)
+        });
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), None);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(events.len(), 1)
+    }
+
+    #[test]
+    fn it_fails_to_send_discarded_telemetry_immediately() {
+        let events = Arc::new(SegQueue::default());
+        let mut client = create_client(events.clone());
+        client.add_processor(|_: &mut TelemetryItem| crate::ProcessResult::Drop);
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), None);
+
+        assert_eq!(result, Err(SendError::Discarded));
+        assert!(events.is_empty())
+    }
+
+    #[test]
+    fn it_fails_to_send_telemetry_immediately_when_rejected() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .transport(RejectingTransport)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::from_config(config);
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), Some(Duration::from_secs(1)));
+
+        assert_matches!(result, Err(SendError::Rejected(_)));
+    }
+
+    struct RejectingTransport;
+
+    #[async_trait::async_trait]
+    impl crate::Transport for RejectingTransport {
+        async fn send(&self, _: Vec<TelemetryItem>) -> Result<(), crate::TransportError> {
+            Err(crate::TransportError::Permanent("invalid payload".into()))
+        }
+    }
+
     #[test]
     fn it_swallows_telemetry_when_disabled() {
         let events = Arc::new(SegQueue::default());
@@ -391,9 +663,42 @@ mod tests {
         assert!(events.is_empty())
     }
 
+    #[test]
+    fn it_drops_sampled_out_telemetry() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .sampling_rate(0.0)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::create(config, {
+            let events = events.clone();
+            |_| TestChannel::new(events)
+        });
+
+        client.track_event("event");
+        client.track_metric("metric", 42.0);
+
+        assert_eq!(events.len(), 1);
+        assert_matches!(
+            events.pop().unwrap().name.as_str(),
+            "Microsoft.ApplicationInsights.Metric"
+        );
+    }
+
+    #[test]
+    fn it_tracks_telemetry_through_trait_object() {
+        let events = Arc::new(SegQueue::default());
+        let tracker: Arc<dyn TelemetryTracker> = Arc::new(create_client(events.clone()));
+
+        tracker.track_event("event".into());
+
+        assert_eq!(events.len(), 1)
+    }
+
     #[test]
     fn it_creates_client_with_default_tags() {
-        let client = TelemetryClient::new("instrumentation".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
 
         let tags = client.context().tags();
         assert_matches!(tags.internal().sdk_version(), Some(version) if version.starts_with("rust"));
@@ -402,12 +707,12 @@ mod tests {
 
     #[test]
     fn it_does_not_fail_with_tokio() {
-        let client = TelemetryClient::new("instrumentation".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
         assert!(client.is_enabled())
     }
 
     fn create_client(events: Arc<SegQueue<Envelope>>) -> TelemetryClient {
-        let config = TelemetryConfig::new("instrumentation".into());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
         TelemetryClient::create(config, |_| TestChannel::new(events))
     }
 }
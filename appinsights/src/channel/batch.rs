@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+
+use log::debug;
+
+use crate::contracts::Envelope;
+
+/// Takes the next batch of pending telemetry items to submit in a single request. A batch is limited
+/// by a number of items and by a size of the batch serialized to JSON, so the server does not reject
+/// the request. A batch always contains at least one item, even if it exceeds the size limit on its own.
+pub fn take(pending: &mut Vec<Envelope>, max_batch_size: usize, max_bytes: usize) -> Vec<Envelope> {
+    // items are serialized as a JSON array: enclosed in brackets and separated by commas
+    let mut size = 2;
+    let mut count = 0;
+
+    for item in pending.iter().take(max_batch_size) {
+        let item_size = serialized_len(item) + usize::from(count > 0);
+        if count > 0 && size + item_size > max_bytes {
+            debug!("Batch of {} items reached content limit of {} bytes", count, max_bytes);
+            break;
+        }
+
+        size += item_size;
+        count += 1;
+    }
+
+    pending.drain(..count.max(1).min(pending.len())).collect()
+}
+
+/// Returns a number of bytes a telemetry item takes when serialized to JSON.
+fn serialized_len(item: &Envelope) -> usize {
+    let mut counter = ByteCounter(0);
+    match serde_json::to_writer(&mut counter, item) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
+
+/// A writer that only counts bytes written into it.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_large_items_into_batches_within_content_limit() {
+        let mut pending: Vec<_> = (0..10).map(|i| envelope(i, 300 * 1024)).collect();
+        let max_bytes = 900 * 1024;
+
+        let mut batches = Vec::new();
+        while !pending.is_empty() {
+            batches.push(take(&mut pending, 256, max_bytes));
+        }
+
+        let sizes: Vec<_> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 2, 2, 2]);
+        for batch in batches {
+            assert!(serde_json::to_vec(&batch).unwrap().len() <= max_bytes);
+        }
+    }
+
+    #[test]
+    fn it_limits_batch_by_number_of_items() {
+        let mut pending: Vec<_> = (0..10).map(|i| envelope(i, 10)).collect();
+
+        let batch = take(&mut pending, 4, 900 * 1024);
+
+        let names: Vec<_> = batch.into_iter().map(|item| item.name).collect();
+        assert_eq!(names, vec!["0", "1", "2", "3"]);
+        assert_eq!(pending.len(), 6);
+    }
+
+    #[test]
+    fn it_takes_single_item_exceeding_content_limit() {
+        let mut pending = vec![envelope(0, 2 * 1024), envelope(1, 10)];
+
+        let batch = take(&mut pending, 256, 1024);
+
+        assert_eq!(batch.len(), 1);
+        assert_eq!(pending.len(), 1);
+    }
+
+    fn envelope(i: usize, size: usize) -> Envelope {
+        Envelope {
+            name: i.to_string(),
+            seq: Some("x".repeat(size)),
+            ..Envelope::default()
+        }
+    }
+}
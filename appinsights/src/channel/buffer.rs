@@ -0,0 +1,256 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+use crossbeam_queue::SegQueue;
+use log::debug;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::contracts::Envelope;
+
+/// Describes what happens to a new telemetry item when the number of pending telemetry items reaches
+/// [`max_pending_items`](struct.TelemetryConfigBuilder.html#method.max_pending_items) limit.
+///
+/// # Examples
+///
+/// ```rust
+/// # use appinsights::{BackpressurePolicy, TelemetryConfig};
+/// let config = TelemetryConfig::builder()
+///     .i_key("00000000-0000-0000-0000-000000000000")
+///     .max_pending_items(10_000)
+///     .backpressure(BackpressurePolicy::ReturnError)
+///     .build()
+///     .expect("valid instrumentation key");
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BackpressurePolicy {
+    /// The oldest pending telemetry item is discarded to make room for a new one.
+    #[default]
+    Drop,
+
+    /// The caller is blocked until pending telemetry items are submitted. A worker thread of a
+    /// multi-thread runtime hands its other tasks over to another thread before it is blocked. A thread
+    /// that drives a current-thread runtime is never blocked, since pending items would not be
+    /// submitted meanwhile, so the oldest pending telemetry item is discarded instead.
+    Block,
+
+    /// A new telemetry item is rejected. [`try_track`](struct.TelemetryClient.html#method.try_track)
+    /// returns a [`BufferFullError`](struct.BufferFullError.html), while other `track_*` methods
+    /// discard the item.
+    ReturnError,
+}
+
+/// Describes an error occurred when a telemetry item cannot be queued because the number of pending
+/// telemetry items reached the limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferFullError;
+
+impl Display for BufferFullError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "buffer of pending telemetry items is full")
+    }
+}
+
+impl Error for BufferFullError {}
+
+/// A queue of telemetry items waiting to be submitted with an optional limit of its size.
+pub struct Buffer {
+    items: SegQueue<Envelope>,
+    capacity: Option<usize>,
+    policy: BackpressurePolicy,
+    lock: Mutex<()>,
+    space: Condvar,
+    dropped: AtomicU64,
+}
+
+impl Buffer {
+    /// Creates a new buffer that holds at most specified number of items if any.
+    pub fn new(capacity: Option<usize>, policy: BackpressurePolicy) -> Self {
+        Self {
+            items: SegQueue::new(),
+            capacity,
+            policy,
+            lock: Mutex::default(),
+            space: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a number of pending telemetry items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns a maximum number of pending telemetry items if limited.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Returns the total number of telemetry items discarded so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Counts telemetry items discarded without being submitted.
+    pub fn discard(&self, count: usize) {
+        self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Returns `true` when the number of pending telemetry items reached the limit.
+    pub fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.items.len() >= capacity)
+    }
+
+    /// Queues a telemetry item according to back-pressure policy when the buffer is full.
+    pub fn push(&self, item: Envelope) -> Result<(), BufferFullError> {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => {
+                self.items.push(item);
+                return Ok(());
+            }
+        };
+
+        match self.policy {
+            BackpressurePolicy::Block if can_block() => block(|| {
+                let mut guard = self.lock.lock().unwrap();
+                while self.items.len() >= capacity {
+                    guard = self.space.wait(guard).unwrap();
+                }
+            }),
+            BackpressurePolicy::Drop | BackpressurePolicy::Block => {
+                while self.items.len() >= capacity && self.items.pop().is_some() {
+                    debug!("Buffer of {} items is full. Oldest telemetry item discarded", capacity);
+                    self.discard(1);
+                }
+            }
+            BackpressurePolicy::ReturnError => {
+                if self.items.len() >= capacity {
+                    self.discard(1);
+                    return Err(BufferFullError);
+                }
+            }
+        }
+
+        self.items.push(item);
+        Ok(())
+    }
+
+    /// Takes all pending telemetry items and wakes up callers waiting for free space.
+    pub fn drain(&self) -> Vec<Envelope> {
+        let mut items = Vec::with_capacity(self.items.len());
+        while let Some(item) = self.items.pop() {
+            items.push(item);
+        }
+
+        // lock prevents notification from being lost while a caller checks the size
+        let _guard = self.lock.lock().unwrap();
+        self.space.notify_all();
+
+        items
+    }
+}
+
+/// Determines whether the current thread can wait for pending items to be submitted. The thread that
+/// drives a current-thread runtime also runs the submission routine, so it must never be blocked.
+pub fn can_block() -> bool {
+    Handle::try_current().map_or(true, |handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread)
+}
+
+/// Runs a routine that blocks the current thread once [`can_block`](fn.can_block.html) allowed it. A
+/// worker thread of a multi-thread runtime is never parked while other tasks, including the submission
+/// routine, are waiting for it: they are handed over to another thread first.
+pub fn block<R>(f: impl FnOnce() -> R) -> R {
+    if Handle::try_current().is_ok() {
+        tokio::task::block_in_place(f)
+    } else {
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn it_discards_oldest_items_when_full() {
+        let buffer = Buffer::new(Some(2), BackpressurePolicy::Drop);
+
+        for i in 0..3 {
+            assert_eq!(buffer.push(envelope(i)), Ok(()));
+        }
+
+        let names: Vec<_> = buffer.drain().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, vec!["1", "2"]);
+        assert_eq!(buffer.dropped(), 1);
+    }
+
+    #[test]
+    fn it_rejects_new_items_when_full() {
+        let buffer = Buffer::new(Some(2), BackpressurePolicy::ReturnError);
+
+        assert_eq!(buffer.push(envelope(0)), Ok(()));
+        assert_eq!(buffer.push(envelope(1)), Ok(()));
+        assert_eq!(buffer.push(envelope(2)), Err(BufferFullError));
+
+        let names: Vec<_> = buffer.drain().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, vec!["0", "1"]);
+        assert_eq!(buffer.dropped(), 1);
+    }
+
+    #[test]
+    fn it_blocks_until_items_drained_when_full() {
+        let buffer = Arc::new(Buffer::new(Some(1), BackpressurePolicy::Block));
+        buffer.push(envelope(0)).unwrap();
+
+        let handle = {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.push(envelope(1)))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.drain().len(), 1);
+
+        assert_eq!(handle.join().unwrap(), Ok(()));
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_discards_oldest_items_instead_of_blocking_current_thread_runtime() {
+        let buffer = Buffer::new(Some(1), BackpressurePolicy::Block);
+
+        assert_eq!(buffer.push(envelope(0)), Ok(()));
+        assert_eq!(buffer.push(envelope(1)), Ok(()));
+
+        let names: Vec<_> = buffer.drain().into_iter().map(|item| item.name).collect();
+        assert_eq!(names, vec!["1"]);
+    }
+
+    #[test]
+    fn it_accepts_any_number_of_items_without_limit() {
+        let buffer = Buffer::new(None, BackpressurePolicy::ReturnError);
+
+        for i in 0..100 {
+            assert_eq!(buffer.push(envelope(i)), Ok(()));
+        }
+
+        assert!(!buffer.is_full());
+        assert_eq!(buffer.len(), 100);
+    }
+
+    fn envelope(i: usize) -> Envelope {
+        Envelope {
+            name: i.to_string(),
+            ..Envelope::default()
+        }
+    }
+}
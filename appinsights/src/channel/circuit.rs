@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+/// A state of a circuit breaker guarding submissions to the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Telemetry items are submitted as usual. Counts consecutive failed submissions.
+    Closed(u32),
+
+    /// Submissions are suspended until specified time.
+    Open(Instant),
+
+    /// A single trial submission is allowed to find out whether the server recovered.
+    HalfOpen,
+}
+
+/// Suspends submissions to the server after a number of consecutive failures, so an unresponsive
+/// endpoint is not hammered with retries.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    state: State,
+    failure_threshold: u32,
+    recovery_timeout: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, recovery_timeout: Duration) -> Self {
+        Self {
+            state: State::Closed(0),
+            failure_threshold,
+            recovery_timeout,
+        }
+    }
+
+    /// Determines whether telemetry items can be submitted now. An open circuit becomes half-open
+    /// once the recovery timeout expires, so a single trial submission is allowed.
+    pub fn allows(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Closed(_) | State::HalfOpen => true,
+            State::Open(until) if now >= until => {
+                debug!("Circuit breaker is half-open. Sending a trial batch");
+                self.state = State::HalfOpen;
+                true
+            }
+            State::Open(_) => false,
+        }
+    }
+
+    /// Returns `true` when submissions are suspended.
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, State::Open(_))
+    }
+
+    /// Closes the circuit after a successful submission.
+    pub fn record_success(&mut self) {
+        if self.state == State::HalfOpen {
+            debug!("Trial batch succeeded. Circuit breaker is closed");
+        }
+        self.state = State::Closed(0);
+    }
+
+    /// Counts a failed submission and opens the circuit when the threshold is reached or a trial
+    /// submission failed.
+    pub fn record_failure(&mut self, now: Instant) {
+        self.state = match self.state {
+            State::Closed(failures) if failures + 1 < self.failure_threshold => State::Closed(failures + 1),
+            State::Closed(_) | State::HalfOpen => {
+                warn!(
+                    "Circuit breaker is open. Sending is suspended for {:?}",
+                    self.recovery_timeout
+                );
+                State::Open(now + self.recovery_timeout)
+            }
+            open @ State::Open(_) => open,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_opens_after_consecutive_failures() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        breaker.record_success();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        assert!(breaker.allows(now));
+
+        breaker.record_failure(now);
+        assert!(breaker.is_open());
+        assert!(!breaker.allows(now + Duration::from_secs(29)));
+    }
+
+    #[test]
+    fn it_allows_trial_after_recovery_timeout() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(30));
+        breaker.record_failure(now);
+
+        let trial = now + Duration::from_secs(30);
+        assert!(breaker.allows(trial));
+        assert!(!breaker.is_open());
+
+        breaker.record_failure(trial);
+        assert!(!breaker.allows(trial + Duration::from_secs(1)));
+
+        assert!(breaker.allows(trial + Duration::from_secs(30)));
+        breaker.record_success();
+        assert!(!breaker.is_open());
+    }
+}
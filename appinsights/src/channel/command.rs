@@ -1,5 +1,15 @@
+use futures_channel::oneshot;
+
+use crate::channel::FlushError;
+
+/// A sender part of a channel to notify about completion of telemetry items submission.
+pub type FlushSender = oneshot::Sender<Result<(), FlushError>>;
+
+/// A sender part of a channel to answer a ping of a watchdog that checks whether a worker is alive.
+pub type PongSender = oneshot::Sender<()>;
+
 /// Describes command to be sent to internal channel.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug)]
 pub enum Command {
     /// A command to tear down the submission, close internal channels. All pending telemetry items to be discarded.
     Terminate,
@@ -7,6 +17,10 @@ pub enum Command {
     /// A command to force all pending telemetry items to be submitted.
     Flush,
 
+    /// A command to force all pending telemetry items to be submitted and notify when the server
+    /// acknowledges them.
+    FlushAndWait(FlushSender),
+
     /// A command to tear down the submission, close internal channels and wait until all pending telemetry items to be sent.
     Close,
 }
@@ -15,6 +29,7 @@ impl std::fmt::Display for Command {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let label = match self {
             Command::Flush => "flush",
+            Command::FlushAndWait(_) => "flush and wait",
             Command::Terminate => "terminate",
             Command::Close => "close",
         };
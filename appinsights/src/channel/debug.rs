@@ -0,0 +1,113 @@
+use std::{
+    env,
+    io::{self, Write},
+};
+
+use async_trait::async_trait;
+use futures_channel::oneshot;
+use log::warn;
+
+use crate::{
+    channel::{FlushError, TelemetryChannel},
+    TelemetryItem,
+};
+
+/// An environment variable that selects a [`DebugChannel`](struct.DebugChannel.html) for a client
+/// configured with [`debug_channel`](struct.TelemetryConfigBuilder.html#method.debug_channel).
+pub const DEBUG_CHANNEL_ENV: &str = "APPINSIGHTS_DEBUG";
+
+/// A telemetry channel that prints every telemetry item to the standard output or error stream instead
+/// of submitting it to the server. Each item is printed as a single line of the same JSON that is
+/// submitted to the server, so the output can be piped through tools like `jq` during local development.
+pub struct DebugChannel {
+    stream: Stream,
+}
+
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl DebugChannel {
+    /// Creates a new channel that prints telemetry items to the standard output stream.
+    pub fn stdout() -> Self {
+        Self { stream: Stream::Stdout }
+    }
+
+    /// Creates a new channel that prints telemetry items to the standard error stream.
+    pub fn stderr() -> Self {
+        Self { stream: Stream::Stderr }
+    }
+
+    /// Creates a new channel when `APPINSIGHTS_DEBUG` environment variable is set. Telemetry items are
+    /// printed to the standard error stream when it is set to `stderr` or to the standard output otherwise.
+    pub fn from_env() -> Option<Self> {
+        match env::var(DEBUG_CHANNEL_ENV) {
+            Ok(value) if value.eq_ignore_ascii_case("stderr") => Some(Self::stderr()),
+            Ok(value) if !value.is_empty() => Some(Self::stdout()),
+            _ => None,
+        }
+    }
+
+    fn write(&self, line: &str) -> io::Result<()> {
+        match self.stream {
+            Stream::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Stream::Stderr => writeln!(io::stderr().lock(), "{}", line),
+        }
+    }
+}
+
+#[async_trait]
+impl TelemetryChannel for DebugChannel {
+    fn send(&self, item: TelemetryItem) {
+        let result = serde_json::to_string(&item)
+            .map_err(io::Error::from)
+            .and_then(|line| self.write(&line));
+
+        if let Err(err) = result {
+            warn!("Unable to print telemetry item: {}", err);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = match self.stream {
+            Stream::Stdout => io::stdout().flush(),
+            Stream::Stderr => io::stderr().flush(),
+        };
+    }
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(Ok(()));
+        receiver
+    }
+
+    async fn close(&self) {}
+
+    async fn terminate(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_selects_stream_from_env() {
+        env::remove_var(DEBUG_CHANNEL_ENV);
+        assert!(DebugChannel::from_env().is_none());
+
+        env::set_var(DEBUG_CHANNEL_ENV, "stderr");
+        assert!(matches!(
+            DebugChannel::from_env(),
+            Some(DebugChannel { stream: Stream::Stderr })
+        ));
+
+        env::set_var(DEBUG_CHANNEL_ENV, "1");
+        assert!(matches!(
+            DebugChannel::from_env(),
+            Some(DebugChannel { stream: Stream::Stdout })
+        ));
+
+        env::remove_var(DEBUG_CHANNEL_ENV);
+    }
+}
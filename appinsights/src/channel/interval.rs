@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use log::debug;
+
+/// A submission with less than this number of telemetry items makes the interval longer.
+const FEW_ITEMS: usize = 10;
+
+/// Adjusts a time to wait until send a batch of telemetry to the traffic: the interval grows while only
+/// a few items are submitted at once and shrinks while batches are close to full.
+#[derive(Debug)]
+pub struct AdaptiveInterval {
+    current: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl AdaptiveInterval {
+    pub fn new(initial: Duration, min: Duration, max: Duration) -> Self {
+        Self {
+            current: initial.clamp(min, max),
+            min,
+            max,
+        }
+    }
+
+    /// Returns a time to wait until the next submission.
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Doubles the interval after a submission of a few items and halves it after a submission of more
+    /// than 80% of a full batch.
+    pub fn record(&mut self, sent: usize, max_batch_size: usize) {
+        let next = if sent < FEW_ITEMS {
+            (self.current * 2).min(self.max)
+        } else if sent * 5 > max_batch_size * 4 {
+            (self.current / 2).max(self.min)
+        } else {
+            self.current
+        };
+
+        if next != self.current {
+            debug!("Submission interval changed to {:?} after sending {} items", next, sent);
+            self.current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_grows_interval_up_to_max_when_traffic_is_low() {
+        let mut interval =
+            AdaptiveInterval::new(Duration::from_secs(2), Duration::from_secs(1), Duration::from_secs(5));
+
+        interval.record(3, 1024);
+        assert_eq!(interval.current(), Duration::from_secs(4));
+
+        interval.record(0, 1024);
+        assert_eq!(interval.current(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn it_shrinks_interval_down_to_min_when_batches_are_almost_full() {
+        let mut interval = AdaptiveInterval::new(
+            Duration::from_secs(2),
+            Duration::from_millis(800),
+            Duration::from_secs(5),
+        );
+
+        interval.record(900, 1024);
+        assert_eq!(interval.current(), Duration::from_secs(1));
+
+        interval.record(1024, 1024);
+        assert_eq!(interval.current(), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn it_keeps_interval_under_moderate_traffic() {
+        let mut interval =
+            AdaptiveInterval::new(Duration::from_secs(10), Duration::from_secs(1), Duration::from_secs(5));
+        assert_eq!(interval.current(), Duration::from_secs(5));
+
+        interval.record(500, 1024);
+        assert_eq!(interval.current(), Duration::from_secs(5));
+    }
+}
@@ -1,86 +1,587 @@
-use std::sync::Arc;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use crossbeam_queue::SegQueue;
-use futures_channel::mpsc::UnboundedSender;
-use log::{debug, trace, warn};
-use tokio::task::JoinHandle;
+use futures_channel::{mpsc::UnboundedSender, oneshot};
+use log::{debug, error, trace, warn};
+use tokio::{runtime::Handle, task::JoinHandle};
 
 use crate::{
-    channel::{command::Command, state::Worker, TelemetryChannel},
-    contracts::Envelope,
-    transmitter::Transmitter,
-    TelemetryConfig,
+    channel::{
+        buffer::{self, Buffer},
+        command::{Command, PongSender},
+        state::Worker,
+        storage::OfflineStorage,
+        BufferFullError, FlushError, RetryPolicy, SendError, TelemetryChannel,
+    },
+    client::SharedStatus,
+    transmitter::{Response, Transmitter},
+    ClientStatus, TelemetryConfig, TelemetryItem,
 };
 
-/// A telemetry channel that stores events exclusively in memory.
+/// How long a channel dropped without being closed waits for pending items to be submitted.
+const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The shortest period a watchdog pings a worker with, so a tiny submission interval does not make a
+/// busy worker look dead.
+const MIN_WATCHDOG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A telemetry channel that stores events in memory. Events that could not be delivered to the server
+/// are persisted to disk when offline storage is configured.
+///
+/// A channel dropped without being closed makes a best-effort attempt to submit pending items. It
+/// waits at most 5 seconds unless it is dropped by a thread that drives a current-thread runtime,
+/// which must never be blocked, so items are submitted in background as long as the runtime runs.
+///
+/// A watchdog pings the submission routine every interval. When the routine panicked or does not
+/// respond within two intervals, it is restarted with the same configuration. Pending items are kept,
+/// while items that were being sent at that moment are lost.
 pub struct InMemoryChannel {
-    items: Arc<SegQueue<Envelope>>,
-    command_sender: Option<UnboundedSender<Command>>,
-    join: Option<JoinHandle<()>>,
+    items: Arc<Buffer>,
+    command_sender: Arc<Mutex<Option<UnboundedSender<Command>>>>,
+    join: Arc<Mutex<Option<JoinHandle<()>>>>,
+    alive: Arc<AtomicBool>,
+    runtime: Handle,
+    max_batch_size: usize,
+    status: Arc<SharedStatus>,
+    last_request_id: Arc<Mutex<Option<String>>>,
+    transmitter: Transmitter,
 }
 
 impl InMemoryChannel {
     /// Creates a new instance of in-memory channel and starts a submission routine.
     pub fn new(config: &TelemetryConfig) -> Self {
-        let items = Arc::new(SegQueue::new());
+        let items = Arc::new(Buffer::new(Some(config.max_pending_items()), config.backpressure()));
 
-        let (command_sender, command_receiver) = futures_channel::mpsc::unbounded();
-        let worker = Worker::new(
-            Transmitter::new(config.endpoint()),
-            items.clone(),
-            command_receiver,
-            config.interval(),
-        );
+        let transmitter = Transmitter::from_config(config);
+        let last_request_id = transmitter.last_request_id();
+
+        // stored items are replayed to the server only
+        let storage = match config.offline_storage() {
+            Some(_) if transmitter.is_custom() => {
+                warn!("Offline storage is not supported with custom transport");
+                None
+            }
+            Some((path, max_size)) => Some((path.to_path_buf(), max_size)),
+            None => None,
+        };
+
+        let status = Arc::new(SharedStatus::default());
+        let factory = WorkerFactory {
+            transmitter: transmitter.clone(),
+            items: items.clone(),
+            storage,
+            interval: config.interval(),
+            adaptive_interval: config.adaptive_interval(),
+            max_batch_size: config.max_batch_size(),
+            retry_policy: *config.retry_policy(),
+            batch_content_limit: config.batch_content_limit(),
+            circuit_breaker: config.circuit_breaker(),
+            status: status.clone(),
+        };
 
-        let handle = tokio::spawn(worker.run());
+        let runtime = Handle::current();
+        let (command_sender, pings, handle) = factory.spawn(&runtime);
+
+        let command_sender = Arc::new(Mutex::new(Some(command_sender)));
+        let join = Arc::new(Mutex::new(Some(handle)));
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let watchdog = Watchdog {
+            factory,
+            runtime: runtime.clone(),
+            command_sender: command_sender.clone(),
+            join: join.clone(),
+            pings,
+            alive: alive.clone(),
+            interval: config.interval().max(MIN_WATCHDOG_INTERVAL),
+        };
+        runtime.spawn(watchdog.run());
 
         Self {
             items,
-            command_sender: Some(command_sender),
-            join: Some(handle),
+            command_sender,
+            join,
+            alive,
+            runtime,
+            max_batch_size: config.max_batch_size(),
+            status,
+            last_request_id,
+            transmitter,
         }
     }
 
-    async fn shutdown(&mut self, command: Command) {
+    async fn shutdown(&self, command: Command) {
         // send shutdown command
-        if let Some(sender) = self.command_sender.take() {
+        let sender = self.command_sender.lock().unwrap().take();
+        if let Some(sender) = sender {
             send_command(&sender, command);
         }
 
         // wait until worker is finished
-        if let Some(handle) = self.join.take() {
+        let handle = self.join.lock().unwrap().take();
+        if let Some(handle) = handle {
             debug!("Shutting down worker");
-            handle.await.unwrap();
+            if let Err(err) = handle.await {
+                warn!("Worker stopped unexpectedly: {}", err);
+            }
+        }
+    }
+}
+
+/// Starts a worker that submits telemetry items with the same configuration every time.
+struct WorkerFactory {
+    transmitter: Transmitter,
+    items: Arc<Buffer>,
+    storage: Option<(PathBuf, u64)>,
+    interval: Duration,
+    adaptive_interval: Option<(Duration, Duration)>,
+    max_batch_size: usize,
+    retry_policy: RetryPolicy,
+    batch_content_limit: usize,
+    circuit_breaker: Option<(u32, Duration)>,
+    status: Arc<SharedStatus>,
+}
+
+impl WorkerFactory {
+    /// Spawns a new worker. Returns senders to pass commands and pings to it and a handle to wait for it.
+    fn spawn(&self, runtime: &Handle) -> (UnboundedSender<Command>, UnboundedSender<PongSender>, JoinHandle<()>) {
+        let (command_sender, command_receiver) = futures_channel::mpsc::unbounded();
+        let (ping_sender, ping_receiver) = futures_channel::mpsc::unbounded();
+
+        let storage = self
+            .storage
+            .as_ref()
+            .map(|(path, max_size)| OfflineStorage::new(path, *max_size));
+        let worker = Worker::new(
+            self.transmitter.clone(),
+            self.items.clone(),
+            command_receiver,
+            self.interval,
+            self.max_batch_size,
+            self.retry_policy,
+            storage,
+        )
+        .adaptive_interval(self.adaptive_interval)
+        .batch_content_limit(self.batch_content_limit)
+        .circuit_breaker(self.circuit_breaker)
+        .status(self.status.clone())
+        .watchdog(ping_receiver);
+
+        (command_sender, ping_sender, runtime.spawn(worker.run()))
+    }
+}
+
+/// Periodically checks that a worker answers pings and restarts the one that panicked or got stuck.
+/// Stops as soon as the channel is closed.
+struct Watchdog {
+    factory: WorkerFactory,
+    runtime: Handle,
+    command_sender: Arc<Mutex<Option<UnboundedSender<Command>>>>,
+    join: Arc<Mutex<Option<JoinHandle<()>>>>,
+    pings: UnboundedSender<PongSender>,
+    alive: Arc<AtomicBool>,
+    interval: Duration,
+}
+
+impl Watchdog {
+    async fn run(mut self) {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            if self.command_sender.lock().unwrap().is_none() {
+                break;
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let alive = self.pings.unbounded_send(sender).is_ok()
+                && matches!(tokio::time::timeout(2 * self.interval, receiver).await, Ok(Ok(())));
+            self.alive.store(alive, Ordering::Relaxed);
+
+            if !alive && !self.restart() {
+                break;
+            }
+        }
+    }
+
+    /// Replaces a worker that does not respond with a new one. Returns `false` when the channel was
+    /// closed in the meantime, so there is nothing to restart.
+    fn restart(&mut self) -> bool {
+        let mut command_sender = self.command_sender.lock().unwrap();
+        if command_sender.is_none() {
+            return false;
+        }
+
+        error!(
+            "Telemetry worker did not respond within {:?}. Restarting it",
+            2 * self.interval
+        );
+        let (sender, pings, handle) = self.factory.spawn(&self.runtime);
+        if let Some(previous) = self.join.lock().unwrap().replace(handle) {
+            previous.abort();
         }
+        self.pings = pings;
+        *command_sender = Some(sender);
+        true
     }
 }
 
 #[async_trait]
 impl TelemetryChannel for InMemoryChannel {
-    fn send(&self, envelop: Envelope) {
+    fn send(&self, item: TelemetryItem) {
+        if let Err(err) = self.try_send(item) {
+            warn!("Telemetry item discarded: {}", err);
+        }
+    }
+
+    fn try_send(&self, item: TelemetryItem) -> Result<(), BufferFullError> {
         trace!("Sending telemetry to channel");
-        self.items.push(envelop);
+
+        // nobody is going to submit items queued after the channel is closed
+        if self.command_sender.lock().unwrap().is_none() {
+            debug!("Channel is closed. Telemetry item discarded");
+            self.items.discard(1);
+            return Ok(());
+        }
+
+        // make room for a new item as soon as possible
+        if self.items.is_full() {
+            self.flush();
+        }
+
+        self.items.push(item.into_envelope())?;
+
+        // trigger submission as soon as a batch is full
+        if self.items.len() == self.max_batch_size {
+            self.flush();
+        }
+
+        Ok(())
+    }
+
+    async fn send_immediately(&self, item: TelemetryItem) -> Result<(), SendError> {
+        trace!("Sending telemetry item immediately");
+
+        if self.command_sender.lock().unwrap().is_none() {
+            debug!("Channel is closed. Telemetry item discarded");
+            self.items.discard(1);
+            return Err(SendError::Closed);
+        }
+
+        // the item is posted on its own so that the result reflects this item only
+        let mut transmitter = self.transmitter.clone();
+        match transmitter.send_items(vec![item.into_envelope()]).await {
+            Ok(Response::Success) => Ok(()),
+            Ok(Response::NoRetry(_)) => {
+                self.items.discard(1);
+                Err(SendError::Rejected("telemetry item is invalid".into()))
+            }
+            Ok(Response::Retry(_)) => Err(SendError::Transport("server is not available".into())),
+            Ok(Response::Throttled(retry_after, _)) => Err(SendError::Transport(format!(
+                "requests are throttled until {}",
+                retry_after
+            ))),
+            Err(err) => Err(SendError::Transport(err.to_string())),
+        }
+    }
+
+    fn send_batch(&self, items: Vec<TelemetryItem>) {
+        trace!("Sending {} telemetry items to channel", items.len());
+
+        if self.command_sender.lock().unwrap().is_none() {
+            debug!("Channel is closed. {} telemetry items discarded", items.len());
+            self.items.discard(items.len());
+            return;
+        }
+
+        for item in items {
+            if self.items.is_full() {
+                self.flush();
+            }
+            if let Err(err) = self.items.push(item.into_envelope()) {
+                warn!("Telemetry item discarded: {}", err);
+            }
+        }
+
+        // submission is triggered once for the whole batch
+        if self.items.len() >= self.max_batch_size {
+            self.flush();
+        }
+    }
+
+    fn pending_count(&self) -> usize {
+        self.items.len()
+    }
+
+    fn dropped_count(&self) -> u64 {
+        self.items.dropped()
+    }
+
+    fn status(&self) -> ClientStatus {
+        self.status.get()
+    }
+
+    fn is_worker_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    fn last_request_id(&self) -> Option<String> {
+        self.last_request_id.lock().unwrap().clone()
     }
 
     fn flush(&self) {
-        if let Some(sender) = &self.command_sender {
+        if let Some(sender) = &*self.command_sender.lock().unwrap() {
             send_command(sender, Command::Flush);
         }
     }
 
-    async fn close(&mut self) {
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let (sender, receiver) = oneshot::channel();
+        if let Some(command_sender) = &*self.command_sender.lock().unwrap() {
+            send_command(command_sender, Command::FlushAndWait(sender));
+        }
+        receiver
+    }
+
+    async fn close(&self) {
         self.shutdown(Command::Close).await
     }
 
-    async fn terminate(&mut self) {
+    async fn terminate(&self) {
         self.shutdown(Command::Terminate).await;
     }
 }
 
+impl Drop for InMemoryChannel {
+    fn drop(&mut self) {
+        // nothing to do when the channel was closed or terminated explicitly
+        let sender = match self.command_sender.lock().ok().and_then(|mut sender| sender.take()) {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        let pending = self.items.len();
+        if pending == 0 {
+            return;
+        }
+
+        debug!(
+            "Channel dropped without being closed. Submitting {} pending items",
+            pending
+        );
+        send_command(&sender, Command::Close);
+
+        let join = self.join.lock().ok().and_then(|mut join| join.take());
+        if let Some(join) = join.filter(|_| buffer::can_block()) {
+            let (done_sender, done_receiver) = mpsc::channel();
+            self.runtime.spawn(async move {
+                let _ = join.await;
+                let _ = done_sender.send(());
+            });
+
+            if buffer::block(|| done_receiver.recv_timeout(DROP_TIMEOUT)).is_err() {
+                warn!(
+                    "Channel dropped without being closed. {} pending telemetry items may be lost",
+                    pending
+                );
+            }
+        }
+    }
+}
+
 fn send_command(sender: &UnboundedSender<Command>, command: Command) {
-    debug!("Sending {} command to channel", command);
-    if let Err(err) = sender.unbounded_send(command.clone()) {
-        warn!("Unable to send {} command to channel: {}", command, err);
+    let label = command.to_string();
+    debug!("Sending {} command to channel", label);
+    if let Err(err) = sender.unbounded_send(command) {
+        warn!("Unable to send {} command to channel: {}", label, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contracts::Envelope, processor::Pipeline, telemetry::SeverityLevel};
+
+    #[tokio::test]
+    async fn it_discards_items_sent_after_close() {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let channel = InMemoryChannel::new(&config);
+
+        channel.close().await;
+        channel.send(item());
+
+        assert_eq!(channel.pending_count(), 0);
+        assert_eq!(channel.dropped_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_restarts_worker_that_panicked() {
+        let submitted = Arc::new(Mutex::new(Vec::new()));
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .interval(Duration::from_millis(100))
+            .transport(PanicOnceTransport {
+                panicked: AtomicBool::new(false),
+                submitted: submitted.clone(),
+            })
+            .build()
+            .unwrap();
+        let channel = InMemoryChannel::new(&config);
+
+        channel.send(item());
+        channel.flush();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        channel.send(item());
+        let flushed = tokio::time::timeout(Duration::from_secs(5), channel.flush_and_wait()).await;
+
+        assert!(matches!(flushed, Ok(Ok(Ok(())))));
+        assert_eq!(submitted.lock().unwrap().len(), 1);
+        assert!(channel.is_worker_alive());
+
+        channel.close().await;
+    }
+
+    #[tokio::test]
+    async fn it_fails_flush_on_first_error_without_retries() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .retry_policy(RetryPolicy::none())
+            .transport(FailingTransport)
+            .build()
+            .unwrap();
+        let channel = InMemoryChannel::new(&config);
+
+        channel.send(item());
+        let flushed = tokio::time::timeout(Duration::from_secs(1), channel.flush_and_wait()).await;
+
+        assert!(matches!(flushed, Ok(Ok(Err(FlushError::Transport(_))))));
+        assert_eq!(channel.pending_count(), 0);
+
+        channel.close().await;
+    }
+
+    #[tokio::test]
+    async fn it_sends_item_immediately_without_pending_items() {
+        let submitted = Arc::new(Mutex::new(Vec::new()));
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .transport(RecordingTransport {
+                submitted: submitted.clone(),
+            })
+            .build()
+            .unwrap();
+        let channel = InMemoryChannel::new(&config);
+        channel.send(item());
+
+        let result = channel.send_immediately(item()).await;
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(submitted.lock().unwrap().len(), 1);
+        assert_eq!(channel.pending_count(), 1);
+
+        channel.terminate().await;
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_send_item_immediately_when_rejected() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .transport(RejectingTransport)
+            .build()
+            .unwrap();
+        let channel = InMemoryChannel::new(&config);
+
+        let result = channel.send_immediately(item()).await;
+
+        assert!(matches!(result, Err(SendError::Rejected(_))));
+        assert_eq!(channel.dropped_count(), 1);
+
+        channel.close().await;
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_send_item_immediately_when_unreachable() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .transport(FailingTransport)
+            .build()
+            .unwrap();
+        let channel = InMemoryChannel::new(&config);
+
+        let result = channel.send_immediately(item()).await;
+
+        assert!(matches!(result, Err(SendError::Transport(_))));
+
+        channel.close().await;
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_send_item_immediately_after_close() {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let channel = InMemoryChannel::new(&config);
+
+        channel.close().await;
+        let result = channel.send_immediately(item()).await;
+
+        assert_eq!(result, Err(SendError::Closed));
+        assert_eq!(channel.dropped_count(), 1);
+    }
+
+    struct RecordingTransport {
+        submitted: Arc<Mutex<Vec<TelemetryItem>>>,
+    }
+
+    #[async_trait]
+    impl crate::Transport for RecordingTransport {
+        async fn send(&self, items: Vec<TelemetryItem>) -> Result<(), crate::TransportError> {
+            self.submitted.lock().unwrap().extend(items);
+            Ok(())
+        }
+    }
+
+    struct RejectingTransport;
+
+    #[async_trait]
+    impl crate::Transport for RejectingTransport {
+        async fn send(&self, _: Vec<TelemetryItem>) -> Result<(), crate::TransportError> {
+            Err(crate::TransportError::Permanent("invalid payload".into()))
+        }
+    }
+
+    struct FailingTransport;
+
+    #[async_trait]
+    impl crate::Transport for FailingTransport {
+        async fn send(&self, _: Vec<TelemetryItem>) -> Result<(), crate::TransportError> {
+            Err(crate::TransportError::Transient("connection refused".into()))
+        }
+    }
+
+    struct PanicOnceTransport {
+        panicked: AtomicBool,
+        submitted: Arc<Mutex<Vec<TelemetryItem>>>,
+    }
+
+    #[async_trait]
+    impl crate::Transport for PanicOnceTransport {
+        async fn send(&self, items: Vec<TelemetryItem>) -> Result<(), crate::TransportError> {
+            if !self.panicked.swap(true, Ordering::Relaxed) {
+                panic!("transport failed");
+            }
+            self.submitted.lock().unwrap().extend(items);
+            Ok(())
+        }
+    }
+
+    fn item() -> TelemetryItem {
+        Pipeline::new(100.0, SeverityLevel::Verbose)
+            .process(Envelope::default(), true)
+            .unwrap()
     }
 }
@@ -0,0 +1,147 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures_channel::oneshot;
+
+use crate::{
+    channel::{FlushError, TelemetryChannel},
+    TelemetryItem,
+};
+
+/// A telemetry channel that captures telemetry items in memory instead of submitting them to the
+/// server. It is intended to verify telemetry produced by an application in tests.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use appinsights::{MockTelemetryChannel, TelemetryClient, TelemetryConfig};
+/// let channel = MockTelemetryChannel::new();
+///
+/// let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".to_string());
+/// let client = TelemetryClient::with_channel(&config, channel.clone());
+/// client.track_event("application started");
+///
+/// assert!(channel.wait_for_items(1, Duration::from_secs(1)));
+/// assert_eq!(channel.items()[0].name(), "Microsoft.ApplicationInsights.Event");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockTelemetryChannel {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    items: Mutex<Vec<TelemetryItem>>,
+    received: Condvar,
+}
+
+impl MockTelemetryChannel {
+    /// Creates a new channel without any captured telemetry items.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all telemetry items sent to the channel so far in the order they were sent.
+    pub fn items(&self) -> Vec<TelemetryItem> {
+        self.inner.items.lock().unwrap().clone()
+    }
+
+    /// Discards all captured telemetry items.
+    pub fn clear(&self) {
+        self.inner.items.lock().unwrap().clear();
+    }
+
+    /// Blocks the current thread until at least specified number of telemetry items captured or
+    /// timeout expires. Returns `true` when the channel received enough items in time.
+    pub fn wait_for_items(&self, count: usize, timeout: Duration) -> bool {
+        let items = self.inner.items.lock().unwrap();
+        let (items, _) = self
+            .inner
+            .received
+            .wait_timeout_while(items, timeout, |items| items.len() < count)
+            .unwrap();
+        items.len() >= count
+    }
+}
+
+#[async_trait]
+impl TelemetryChannel for MockTelemetryChannel {
+    fn send(&self, item: TelemetryItem) {
+        self.inner.items.lock().unwrap().push(item);
+        self.inner.received.notify_all();
+    }
+
+    fn flush(&self) {}
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(Ok(()));
+        receiver
+    }
+
+    async fn close(&self) {}
+
+    async fn terminate(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{telemetry::SeverityLevel, TelemetryClient, TelemetryConfig};
+
+    #[test]
+    fn it_captures_sent_items() {
+        let channel = MockTelemetryChannel::new();
+        let client = create_client(&channel);
+
+        client.track_event("event");
+        client.track_trace("trace", SeverityLevel::Warning);
+
+        let names: Vec<_> = channel.items().iter().map(|item| item.name().to_string()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Microsoft.ApplicationInsights.Event",
+                "Microsoft.ApplicationInsights.Message"
+            ]
+        );
+
+        channel.clear();
+        assert!(channel.items().is_empty());
+    }
+
+    #[test]
+    fn it_waits_for_items_sent_from_another_thread() {
+        let channel = MockTelemetryChannel::new();
+        let client = create_client(&channel);
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            client.track_event("event");
+        });
+
+        assert!(channel.wait_for_items(1, Duration::from_secs(5)));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn it_stops_waiting_for_items_when_timeout_expires() {
+        let channel = MockTelemetryChannel::new();
+        let client = create_client(&channel);
+        client.track_event("event");
+
+        assert!(!channel.wait_for_items(2, Duration::from_millis(50)));
+        assert_eq!(channel.items().len(), 1);
+    }
+
+    fn create_client(channel: &MockTelemetryChannel) -> TelemetryClient {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        TelemetryClient::with_channel(&config, channel.clone())
+    }
+}
@@ -1,35 +1,234 @@
+mod buffer;
+pub(crate) use buffer::{block, can_block};
+pub use buffer::{BackpressurePolicy, BufferFullError};
+
+mod batch;
+
+mod circuit;
+
 mod command;
 
+mod interval;
+
+mod debug;
+pub use debug::{DebugChannel, DEBUG_CHANNEL_ENV};
+
 mod memory;
 pub use memory::InMemoryChannel;
 
+#[cfg(feature = "test-utils")]
+mod mock;
+#[cfg(feature = "test-utils")]
+pub use mock::MockTelemetryChannel;
+
+mod multi;
+pub use multi::{MultiChannel, PartialSendError};
+
+mod noop;
+pub use noop::NoopChannel;
+
 mod retry;
+pub use retry::RetryPolicy;
+
+mod routing;
+pub use routing::RoutingChannel;
 
 mod state;
 
+mod storage;
+
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
 use async_trait::async_trait;
+use futures_channel::oneshot;
 
-use crate::contracts::Envelope;
+use crate::{ClientStatus, TelemetryItem};
 
 /// An implementation of [TelemetryChannel](trait.TelemetryChannel.html) is responsible for queueing
-/// and periodically submitting telemetry events.
+/// and periodically submitting telemetry events. A client can be created with a custom channel with
+/// [`TelemetryClient::with_channel`](struct.TelemetryClient.html#method.with_channel).
 #[async_trait]
-pub trait TelemetryChannel {
+pub trait TelemetryChannel: Send + Sync {
     /// Queues a single telemetry item.
-    fn send(&self, envelop: Envelope);
+    fn send(&self, item: TelemetryItem);
+
+    /// Queues a single telemetry item. Returns an error when the item was rejected because too many
+    /// telemetry items are pending submission.
+    fn try_send(&self, item: TelemetryItem) -> Result<(), BufferFullError> {
+        self.send(item);
+        Ok(())
+    }
+
+    /// Queues several telemetry items at once, e.g. when a burst of items is ingested in bulk. Items
+    /// are queued one by one unless a channel implements it more efficiently.
+    fn send_batch(&self, items: Vec<TelemetryItem>) {
+        for item in items {
+            self.send(item);
+        }
+    }
+
+    /// Returns the number of telemetry items waiting to be submitted.
+    fn pending_count(&self) -> usize {
+        0
+    }
+
+    /// Returns the total number of telemetry items discarded without being submitted, e.g. when too
+    /// many items are pending or retries are exhausted.
+    fn dropped_count(&self) -> u64 {
+        0
+    }
+
+    /// Returns what a worker that submits telemetry items is doing. Channels without a background worker
+    /// are always running.
+    fn status(&self) -> ClientStatus {
+        ClientStatus::Running
+    }
+
+    /// Returns `false` when a background worker that submits telemetry items did not answer the last
+    /// health check and had to be restarted. Channels without a background worker are always alive.
+    fn is_worker_alive(&self) -> bool {
+        true
+    }
+
+    /// Returns an identifier the server assigned to the last accepted batch of telemetry items, e.g. to
+    /// refer to it in a support request. Channels that do not submit items to the server have none.
+    fn last_request_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Submits a single telemetry item right away and waits until the server acknowledges it. By default
+    /// the item is queued and all pending telemetry items are flushed together with it, unless a channel
+    /// is able to post it on its own.
+    async fn send_immediately(&self, item: TelemetryItem) -> Result<(), SendError> {
+        self.try_send(item)
+            .map_err(|err| SendError::Transport(err.to_string()))?;
+        self.flush_and_wait()
+            .await
+            .unwrap_or(Err(FlushError::Closed))
+            .map_err(SendError::from)
+    }
 
     /// Forces all pending telemetry items to be submitted. The current task will not be blocked.
     fn flush(&self);
 
+    /// Forces all pending telemetry items to be submitted. Returns a receiver that resolves when
+    /// the server acknowledges submitted items or submission fails.
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>>;
+
     /// Flushes and tears down the submission flow and closes internal channels.
     /// It blocks the current task until all pending telemetry items have been submitted and it is safe to
     /// shutdown without losing telemetry.
-    async fn close(&mut self);
+    async fn close(&self);
 
     /// Flushes and tears down the submission flow and closes internal channels.
     /// It blocks the current task until all pending telemetry items have been submitted and it is safe to
     /// shutdown without losing telemetry.
     /// Tears down the submission flow and closes internal channels. Any telemetry waiting to be sent is discarded.
     /// This is a more abrupt version of [close](#method.close).
-    async fn terminate(&mut self);
+    async fn terminate(&self);
+}
+
+/// Waits until all pending telemetry items flushed, but not longer than specified timeout if any.
+pub async fn wait_flushed(
+    receiver: oneshot::Receiver<Result<(), FlushError>>,
+    timeout: Option<Duration>,
+) -> Result<(), FlushError> {
+    let flushed = async { receiver.await.unwrap_or(Err(FlushError::Closed)) };
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, flushed)
+            .await
+            .unwrap_or(Err(FlushError::Timeout)),
+        None => flushed.await,
+    }
+}
+
+/// Submits a single telemetry item immediately, but does not wait longer than specified timeout if any.
+pub(crate) async fn send_immediately<C>(
+    channel: &C,
+    item: TelemetryItem,
+    timeout: Option<Duration>,
+) -> Result<(), SendError>
+where
+    C: TelemetryChannel + ?Sized,
+{
+    let sent = channel.send_immediately(item);
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, sent)
+            .await
+            .unwrap_or(Err(SendError::Timeout)),
+        None => sent.await,
+    }
+}
+
+/// Describes an error occurred while waiting for pending telemetry items to be flushed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlushError {
+    /// Telemetry items were not accepted by the server or the server is unreachable.
+    Transport(String),
+
+    /// Telemetry items were not acknowledged by the server within the specified timeout.
+    Timeout,
+
+    /// The internal channel was closed before telemetry items were submitted.
+    Closed,
+}
+
+impl Display for FlushError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlushError::Transport(reason) => write!(f, "unable to submit telemetry items: {}", reason),
+            FlushError::Timeout => write!(f, "timeout expired while waiting for telemetry items to be submitted"),
+            FlushError::Closed => write!(f, "channel closed before telemetry items were submitted"),
+        }
+    }
+}
+
+impl Error for FlushError {}
+
+/// Describes an error occurred while sending a single telemetry item immediately.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SendError {
+    /// The telemetry item was not submitted: the client is disabled, or the item was filtered out by
+    /// severity or dropped by a processor.
+    Discarded,
+
+    /// The telemetry item was rejected by the server and must not be sent again.
+    Rejected(String),
+
+    /// The telemetry item was not accepted by the server or the server is unreachable.
+    Transport(String),
+
+    /// The telemetry item was not acknowledged by the server within the specified timeout.
+    Timeout,
+
+    /// The internal channel was closed before the telemetry item was submitted.
+    Closed,
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Discarded => write!(f, "telemetry item was discarded before submission"),
+            SendError::Rejected(reason) => write!(f, "telemetry item rejected by the server: {}", reason),
+            SendError::Transport(reason) => write!(f, "unable to submit telemetry item: {}", reason),
+            SendError::Timeout => write!(f, "timeout expired while waiting for telemetry item to be submitted"),
+            SendError::Closed => write!(f, "channel closed before telemetry item was submitted"),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+impl From<FlushError> for SendError {
+    fn from(err: FlushError) -> Self {
+        match err {
+            FlushError::Transport(reason) => SendError::Transport(reason),
+            FlushError::Timeout => SendError::Timeout,
+            FlushError::Closed => SendError::Closed,
+        }
+    }
 }
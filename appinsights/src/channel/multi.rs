@@ -0,0 +1,277 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures_channel::oneshot;
+use log::warn;
+
+use crate::{
+    channel::{self, BufferFullError, FlushError, TelemetryChannel},
+    TelemetryItem,
+};
+
+/// A telemetry channel that delivers every telemetry item to all inner channels, e.g. to submit
+/// telemetry to both development and production Application Insights resources during rollout.
+///
+/// Submission is considered successful when at least one of inner channels succeeds. Failures of
+/// other channels are reported as warnings by [`flush_and_wait`](trait.TelemetryChannel.html#tymethod.flush_and_wait)
+/// and can be inspected with [`flush_all`](#method.flush_all).
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::{InMemoryChannel, MultiChannel, TelemetryClient, TelemetryConfig};
+/// # #[tokio::main]
+/// # async fn main() {
+/// let development = TelemetryConfig::new("<development instrumentation key>".into());
+/// let production = TelemetryConfig::new("<production instrumentation key>".into());
+///
+/// let channel = MultiChannel::new(vec![
+///     Box::new(InMemoryChannel::new(&development)),
+///     Box::new(InMemoryChannel::new(&production)),
+/// ]);
+/// let client = TelemetryClient::with_channel(&production, channel);
+/// # }
+/// ```
+pub struct MultiChannel {
+    channels: Vec<Box<dyn TelemetryChannel>>,
+}
+
+impl MultiChannel {
+    /// Creates a new channel that fans telemetry items out to all specified channels.
+    pub fn new(channels: Vec<Box<dyn TelemetryChannel>>) -> Self {
+        Self { channels }
+    }
+
+    /// Forces all pending telemetry items to be submitted by every inner channel and waits until all
+    /// of them finish, but not longer than specified timeout if any. Returns an error that lists
+    /// every channel failed to submit telemetry items.
+    pub async fn flush_all(&self, timeout: Option<Duration>) -> Result<(), PartialSendError> {
+        let receivers = self.flush_channels();
+        collect_failures(receivers, timeout).await
+    }
+
+    fn flush_channels(&self) -> Vec<oneshot::Receiver<Result<(), FlushError>>> {
+        self.channels.iter().map(|channel| channel.flush_and_wait()).collect()
+    }
+}
+
+/// Waits for every inner channel to be flushed and collects errors they reported.
+pub(super) async fn collect_failures(
+    receivers: Vec<oneshot::Receiver<Result<(), FlushError>>>,
+    timeout: Option<Duration>,
+) -> Result<(), PartialSendError> {
+    let total = receivers.len();
+
+    let mut failures = Vec::default();
+    for (index, receiver) in receivers.into_iter().enumerate() {
+        if let Err(err) = channel::wait_flushed(receiver, timeout).await {
+            failures.push((index, err));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PartialSendError { failures, total })
+    }
+}
+
+#[async_trait]
+impl TelemetryChannel for MultiChannel {
+    fn send(&self, item: TelemetryItem) {
+        for channel in &self.channels {
+            channel.send(item.clone());
+        }
+    }
+
+    fn try_send(&self, item: TelemetryItem) -> Result<(), BufferFullError> {
+        let mut result = Ok(());
+        let mut accepted = false;
+        for channel in &self.channels {
+            match channel.try_send(item.clone()) {
+                Ok(()) => accepted = true,
+                Err(err) => result = Err(err),
+            }
+        }
+
+        if accepted {
+            Ok(())
+        } else {
+            result
+        }
+    }
+
+    fn pending_count(&self) -> usize {
+        self.channels.iter().map(|channel| channel.pending_count()).sum()
+    }
+
+    fn dropped_count(&self) -> u64 {
+        self.channels.iter().map(|channel| channel.dropped_count()).sum()
+    }
+
+    fn flush(&self) {
+        for channel in &self.channels {
+            channel.flush();
+        }
+    }
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let receivers = self.flush_channels();
+
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let result = match collect_failures(receivers, None).await {
+                Err(err) if err.is_total() => Err(FlushError::Transport(err.to_string())),
+                Err(err) => {
+                    warn!("{}", err);
+                    Ok(())
+                }
+                Ok(()) => Ok(()),
+            };
+            let _ = sender.send(result);
+        });
+        receiver
+    }
+
+    async fn close(&self) {
+        for channel in &self.channels {
+            channel.close().await;
+        }
+    }
+
+    async fn terminate(&self) {
+        for channel in &self.channels {
+            channel.terminate().await;
+        }
+    }
+}
+
+/// Describes inner channels of a [`MultiChannel`](struct.MultiChannel.html) that were not able to
+/// submit telemetry items.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialSendError {
+    failures: Vec<(usize, FlushError)>,
+    total: usize,
+}
+
+impl PartialSendError {
+    /// Returns indexes of failed channels in the order they were passed to
+    /// [`MultiChannel::new`](struct.MultiChannel.html#method.new) along with errors they reported.
+    pub fn failures(&self) -> &[(usize, FlushError)] {
+        &self.failures
+    }
+
+    /// Determines whether every inner channel failed.
+    pub fn is_total(&self) -> bool {
+        self.failures.len() == self.total
+    }
+}
+
+impl Display for PartialSendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} channels failed:", self.failures.len(), self.total)?;
+        for (index, err) in &self.failures {
+            write!(f, " channel {}: {};", index, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for PartialSendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{contracts::Envelope, NoopChannel};
+
+    #[tokio::test]
+    async fn it_succeeds_when_at_least_one_channel_succeeds() {
+        let channel = MultiChannel::new(vec![Box::new(FailingChannel), Box::new(NoopChannel)]);
+
+        channel.send(TelemetryItem::from_envelope(Envelope::default()));
+
+        assert_eq!(channel.flush_and_wait().await.unwrap(), Ok(()));
+        assert_eq!(
+            channel.flush_all(None).await,
+            Err(PartialSendError {
+                failures: vec![(0, FlushError::Transport("server is unreachable".into()))],
+                total: 2,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn it_fails_when_all_channels_fail() {
+        let channel = MultiChannel::new(vec![Box::new(FailingChannel), Box::new(FailingChannel)]);
+
+        let result = channel.flush_and_wait().await.unwrap();
+
+        assert_eq!(
+            result,
+            Err(FlushError::Transport(
+                "2 of 2 channels failed: channel 0: unable to submit telemetry items: server is unreachable; \
+                 channel 1: unable to submit telemetry items: server is unreachable;"
+                    .into()
+            ))
+        );
+    }
+
+    #[test]
+    fn it_accepts_item_when_at_least_one_channel_accepts() {
+        let channel = MultiChannel::new(vec![Box::new(FullChannel), Box::new(NoopChannel)]);
+        assert_eq!(
+            channel.try_send(TelemetryItem::from_envelope(Envelope::default())),
+            Ok(())
+        );
+
+        let channel = MultiChannel::new(vec![Box::new(FullChannel)]);
+        assert_eq!(
+            channel.try_send(TelemetryItem::from_envelope(Envelope::default())),
+            Err(BufferFullError)
+        );
+    }
+
+    struct FailingChannel;
+
+    #[async_trait]
+    impl TelemetryChannel for FailingChannel {
+        fn send(&self, _: TelemetryItem) {}
+
+        fn flush(&self) {}
+
+        fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+            let (sender, receiver) = oneshot::channel();
+            let _ = sender.send(Err(FlushError::Transport("server is unreachable".into())));
+            receiver
+        }
+
+        async fn close(&self) {}
+
+        async fn terminate(&self) {}
+    }
+
+    struct FullChannel;
+
+    #[async_trait]
+    impl TelemetryChannel for FullChannel {
+        fn send(&self, _: TelemetryItem) {}
+
+        fn try_send(&self, _: TelemetryItem) -> Result<(), BufferFullError> {
+            Err(BufferFullError)
+        }
+
+        fn flush(&self) {}
+
+        fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+            unimplemented!()
+        }
+
+        async fn close(&self) {}
+
+        async fn terminate(&self) {}
+    }
+}
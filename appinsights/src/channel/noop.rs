@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use futures_channel::oneshot;
+
+use crate::{
+    channel::{FlushError, TelemetryChannel},
+    TelemetryItem,
+};
+
+/// A telemetry channel that discards all telemetry items. It never spawns background tasks and
+/// never makes any network calls.
+#[derive(Default)]
+pub struct NoopChannel;
+
+#[async_trait]
+impl TelemetryChannel for NoopChannel {
+    fn send(&self, _: TelemetryItem) {}
+
+    fn flush(&self) {}
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(Ok(()));
+        receiver
+    }
+
+    async fn close(&self) {}
+
+    async fn terminate(&self) {}
+}
@@ -1,12 +1,85 @@
 use std::time::Duration;
 
+/// Describes how many times and how often failed telemetry submissions should be retried.
+///
+/// A delay before each subsequent attempt doubles starting from an initial delay, but never exceeds
+/// a maximum delay.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use appinsights::{RetryPolicy, TelemetryConfig};
+/// let config = TelemetryConfig::builder()
+///     .i_key("00000000-0000-0000-0000-000000000000")
+///     .retry_policy(RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(30)))
+///     .build()
+///     .expect("valid instrumentation key");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a new retry policy with a maximum number of submission attempts including the first one
+    /// and delays between them.
+    pub fn new(max_attempts: u32, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_delay,
+            max_delay,
+        }
+    }
+
+    /// Creates a retry policy that makes a single submission attempt. Telemetry items that could not be
+    /// submitted are discarded right away, or persisted when offline storage is configured, e.g. when
+    /// an application retries on its own.
+    pub fn none() -> Self {
+        Self::new(1, Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Returns a maximum number of submission attempts including the first one.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns a delay before the first retry.
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Returns a maximum delay between retries.
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(4, Duration::from_secs(2), Duration::from_secs(16))
+    }
+}
+
 /// Encapsulates retry logic for submit telemetry items operation.
 #[derive(Default, Debug)]
 pub struct Retry(Vec<Duration>);
 
 impl Retry {
-    pub fn exponential() -> Self {
-        let timeouts = vec![Duration::from_secs(16), Duration::from_secs(4), Duration::from_secs(2)];
+    pub fn exponential(policy: &RetryPolicy) -> Self {
+        let mut timeouts: Vec<_> = (0..policy.max_attempts.saturating_sub(1))
+            .map(|attempt| {
+                policy
+                    .initial_delay
+                    .checked_mul(2u32.saturating_pow(attempt))
+                    .map_or(policy.max_delay, |delay| delay.min(policy.max_delay))
+            })
+            .collect();
+
+        // timeouts are taken from the end
+        timeouts.reverse();
         Self(timeouts)
     }
 
@@ -18,3 +91,34 @@ impl Retry {
         self.0.pop()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(RetryPolicy::default(), &[2, 4, 8]; "default")]
+    #[test_case(RetryPolicy::new(6, Duration::from_secs(1), Duration::from_secs(10)), &[1, 2, 4, 8, 10]; "limited by max delay")]
+    #[test_case(RetryPolicy::new(1, Duration::from_secs(1), Duration::from_secs(10)), &[]; "single attempt")]
+    #[test_case(RetryPolicy::new(0, Duration::from_secs(1), Duration::from_secs(10)), &[]; "no attempts")]
+    #[test_case(RetryPolicy::none(), &[]; "none")]
+    fn it_creates_exponential_timeouts(policy: RetryPolicy, expected: &[u64]) {
+        let mut retry = Retry::exponential(&policy);
+
+        let timeouts: Vec<_> = std::iter::from_fn(|| retry.next()).collect();
+
+        let expected: Vec<_> = expected.iter().copied().map(Duration::from_secs).collect();
+        assert_eq!(timeouts, expected);
+    }
+
+    #[test]
+    fn it_does_not_overflow_when_many_attempts_allowed() {
+        let mut retry = Retry::exponential(&RetryPolicy::new(100, Duration::from_secs(1), Duration::from_secs(10)));
+
+        let timeouts: Vec<_> = std::iter::from_fn(|| retry.next()).collect();
+
+        assert_eq!(timeouts.len(), 99);
+        assert_eq!(timeouts.last(), Some(&Duration::from_secs(10)));
+    }
+}
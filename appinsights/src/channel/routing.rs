@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+use futures_channel::oneshot;
+
+use crate::{
+    channel::{multi, BufferFullError, FlushError, TelemetryChannel},
+    TelemetryItem,
+};
+
+/// A predicate that decides whether a telemetry item is sent to a channel of a route.
+type Predicate = Box<dyn Fn(&TelemetryItem) -> bool + Send + Sync>;
+
+/// A telemetry channel that sends every telemetry item to exactly one of inner channels, e.g. to
+/// submit error traces to a high-priority Application Insights resource and everything else to
+/// a standard one.
+///
+/// Routes are checked in the order they were added and an item goes to the channel of the first
+/// route whose predicate matches it. Items that match no route go to the default channel. A predicate
+/// can inspect [`base_type`](struct.TelemetryItem.html#method.base_type),
+/// [`severity_level`](struct.TelemetryItem.html#method.severity_level),
+/// [`properties`](struct.TelemetryItem.html#method.properties) or any other data of a telemetry item.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::{InMemoryChannel, RoutingChannel, TelemetryClient, TelemetryConfig, TelemetryItem};
+/// use appinsights::telemetry::SeverityLevel;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let standard = TelemetryConfig::new("<standard instrumentation key>".into());
+/// let priority = TelemetryConfig::new("<high-priority instrumentation key>".into());
+///
+/// let channel = RoutingChannel::new(Box::new(InMemoryChannel::new(&standard))).route(
+///     |item: &TelemetryItem| item.severity_level() >= Some(SeverityLevel::Error),
+///     Box::new(InMemoryChannel::new(&priority)),
+/// );
+/// let client = TelemetryClient::with_channel(&standard, channel);
+/// # }
+/// ```
+pub struct RoutingChannel {
+    default: Box<dyn TelemetryChannel>,
+    routes: Vec<(Predicate, Box<dyn TelemetryChannel>)>,
+}
+
+impl RoutingChannel {
+    /// Creates a new channel that sends all telemetry items to specified default channel.
+    pub fn new(default: Box<dyn TelemetryChannel>) -> Self {
+        Self {
+            default,
+            routes: Vec::default(),
+        }
+    }
+
+    /// Appends a route that sends telemetry items matching a predicate to specified channel.
+    pub fn route<P>(mut self, predicate: P, channel: Box<dyn TelemetryChannel>) -> Self
+    where
+        P: Fn(&TelemetryItem) -> bool + Send + Sync + 'static,
+    {
+        self.routes.push((Box::new(predicate), channel));
+        self
+    }
+
+    /// Returns a channel of the first route matching a telemetry item or the default one.
+    fn select(&self, item: &TelemetryItem) -> &dyn TelemetryChannel {
+        self.routes
+            .iter()
+            .find(|(predicate, _)| predicate(item))
+            .map_or(self.default.as_ref(), |(_, channel)| channel.as_ref())
+    }
+
+    /// Returns all inner channels starting with the default one.
+    fn channels(&self) -> impl Iterator<Item = &dyn TelemetryChannel> {
+        std::iter::once(self.default.as_ref()).chain(self.routes.iter().map(|(_, channel)| channel.as_ref()))
+    }
+}
+
+#[async_trait]
+impl TelemetryChannel for RoutingChannel {
+    fn send(&self, item: TelemetryItem) {
+        self.select(&item).send(item);
+    }
+
+    fn try_send(&self, item: TelemetryItem) -> Result<(), BufferFullError> {
+        self.select(&item).try_send(item)
+    }
+
+    fn pending_count(&self) -> usize {
+        self.channels().map(|channel| channel.pending_count()).sum()
+    }
+
+    fn dropped_count(&self) -> u64 {
+        self.channels().map(|channel| channel.dropped_count()).sum()
+    }
+
+    fn flush(&self) {
+        for channel in self.channels() {
+            channel.flush();
+        }
+    }
+
+    fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+        let receivers = self.channels().map(|channel| channel.flush_and_wait()).collect();
+
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let result = multi::collect_failures(receivers, None)
+                .await
+                .map_err(|err| FlushError::Transport(err.to_string()));
+            let _ = sender.send(result);
+        });
+        receiver
+    }
+
+    async fn close(&self) {
+        for channel in self.channels() {
+            channel.close().await;
+        }
+    }
+
+    async fn terminate(&self) {
+        for channel in self.channels() {
+            channel.terminate().await;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        channel::MockTelemetryChannel,
+        contracts::{Base, Data, Envelope, MessageData, SeverityLevel as ContractsSeverityLevel},
+        telemetry::SeverityLevel,
+    };
+
+    #[test]
+    fn it_sends_item_to_first_matching_route() {
+        let default = MockTelemetryChannel::new();
+        let errors = MockTelemetryChannel::new();
+        let traces = MockTelemetryChannel::new();
+
+        let channel = RoutingChannel::new(Box::new(default.clone()))
+            .route(
+                |item: &TelemetryItem| item.severity_level() >= Some(SeverityLevel::Error),
+                Box::new(errors.clone()),
+            )
+            .route(
+                |item: &TelemetryItem| item.base_type() == Some("MessageData"),
+                Box::new(traces.clone()),
+            );
+
+        channel.send(trace(ContractsSeverityLevel::Critical));
+        channel.send(trace(ContractsSeverityLevel::Information));
+        channel.send(TelemetryItem::from_envelope(Envelope::default()));
+
+        let timeout = Duration::from_secs(1);
+        assert!(errors.wait_for_items(1, timeout));
+        assert!(traces.wait_for_items(1, timeout));
+        assert!(default.wait_for_items(1, timeout));
+
+        assert_eq!(errors.items()[0].severity_level(), Some(SeverityLevel::Critical));
+        assert_eq!(traces.items()[0].severity_level(), Some(SeverityLevel::Information));
+        assert_eq!(default.items()[0].base_type(), None);
+    }
+
+    fn trace(severity: ContractsSeverityLevel) -> TelemetryItem {
+        TelemetryItem::from_envelope(Envelope {
+            data: Some(Base::Data(Data::MessageData(MessageData {
+                severity_level: Some(severity),
+                ..MessageData::default()
+            }))),
+            ..Envelope::default()
+        })
+    }
+}
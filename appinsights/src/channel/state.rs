@@ -1,18 +1,25 @@
-use std::{mem, sync::Arc, time::Duration};
+use std::{future::Future, mem, sync::Arc, time::Duration};
 
-use crossbeam_queue::SegQueue;
-use futures_channel::mpsc::UnboundedReceiver;
-use futures_util::{Future, Stream, StreamExt};
-use log::{debug, error, trace};
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::{self, UnboundedReceiver};
+use futures_util::StreamExt;
+use log::{debug, error, trace, warn};
 use sm::{sm, Event};
 
 use crate::{
-    channel::command::Command,
-    channel::retry::Retry,
+    channel::batch,
+    channel::buffer::Buffer,
+    channel::command::{Command, FlushSender, PongSender},
+    channel::retry::{Retry, RetryPolicy},
     channel::state::worker::{Variant::*, *},
+    channel::storage::OfflineStorage,
+    channel::FlushError,
+    channel::{circuit::CircuitBreaker, interval::AdaptiveInterval},
+    client::SharedStatus,
     contracts::Envelope,
-    timeout,
+    time, timeout,
     transmitter::{Response, Transmitter},
+    ClientStatus,
 };
 
 sm! {
@@ -59,26 +66,87 @@ sm! {
 
 pub struct Worker {
     transmitter: Transmitter,
-    items: Arc<SegQueue<Envelope>>,
+    items: Arc<Buffer>,
     command_receiver: UnboundedReceiver<Command>,
+    pings: UnboundedReceiver<PongSender>,
     interval: Duration,
+    adaptive_interval: Option<AdaptiveInterval>,
+    max_batch_size: usize,
+    batch_content_limit: usize,
+    retry_policy: RetryPolicy,
+    flush_waiters: Vec<FlushSender>,
+    last_error: Option<String>,
+    rejected: usize,
+    throttled_until: Option<DateTime<Utc>>,
+    storage: Option<OfflineStorage>,
+    breaker: Option<CircuitBreaker>,
+    held: Vec<Envelope>,
+    status: Arc<SharedStatus>,
 }
 
 impl Worker {
     pub fn new(
         transmitter: Transmitter,
-        items: Arc<SegQueue<Envelope>>,
+        items: Arc<Buffer>,
         command_receiver: UnboundedReceiver<Command>,
         interval: Duration,
+        max_batch_size: usize,
+        retry_policy: RetryPolicy,
+        storage: Option<OfflineStorage>,
     ) -> Self {
         Self {
             transmitter,
             items,
             command_receiver,
+            pings: mpsc::unbounded().1,
             interval,
+            adaptive_interval: None,
+            max_batch_size,
+            batch_content_limit: usize::MAX,
+            retry_policy,
+            flush_waiters: Vec::default(),
+            last_error: Option::default(),
+            rejected: 0,
+            throttled_until: None,
+            storage,
+            breaker: None,
+            held: Vec::default(),
+            status: Arc::default(),
         }
     }
 
+    /// Reports worker status to specified shared one.
+    pub fn status(mut self, status: Arc<SharedStatus>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Answers pings of a watchdog that checks whether the worker is still alive. Pings are answered
+    /// while waiting for commands and while telemetry items are being sent.
+    pub fn watchdog(mut self, pings: UnboundedReceiver<PongSender>) -> Self {
+        self.pings = pings;
+        self
+    }
+
+    /// Limits a size of serialized batches submitted in a single request.
+    pub fn batch_content_limit(mut self, bytes: usize) -> Self {
+        self.batch_content_limit = bytes;
+        self
+    }
+
+    /// Adjusts the interval within specified bounds depending on a number of items sent every time.
+    pub fn adaptive_interval(mut self, bounds: Option<(Duration, Duration)>) -> Self {
+        self.adaptive_interval = bounds.map(|(min, max)| AdaptiveInterval::new(self.interval, min, max));
+        self
+    }
+
+    /// Suspends sending after specified number of consecutive failed submissions for a recovery timeout.
+    pub fn circuit_breaker(mut self, circuit_breaker: Option<(u32, Duration)>) -> Self {
+        self.breaker = circuit_breaker
+            .map(|(failure_threshold, recovery_timeout)| CircuitBreaker::new(failure_threshold, recovery_timeout));
+        self
+    }
+
     pub async fn run(mut self) {
         let mut state = Machine::new(Receiving).as_enum();
 
@@ -87,11 +155,11 @@ impl Worker {
 
         loop {
             state = match state {
-                InitialReceiving(m) => self.handle_receiving(m, &mut items).await,
-                ReceivingByItemsSentAndContinue(m) => self.handle_receiving(m, &mut items).await,
-                ReceivingByRetryExhausted(m) => self.handle_receiving(m, &mut items).await,
-                SendingByTimeoutExpired(m) => self.handle_sending_with_retry(m, &mut items, &mut retry).await,
-                SendingByFlushRequested(m) => self.handle_sending_with_retry(m, &mut items, &mut retry).await,
+                InitialReceiving(m) => self.handle_receiving(m, &mut items, &mut retry).await,
+                ReceivingByItemsSentAndContinue(m) => self.handle_receiving(m, &mut items, &mut retry).await,
+                ReceivingByRetryExhausted(m) => self.handle_receiving(m, &mut items, &mut retry).await,
+                SendingByTimeoutExpired(m) => self.handle_sending(m, &mut items).await,
+                SendingByFlushRequested(m) => self.handle_sending(m, &mut items).await,
                 SendingByCloseRequested(m) => self.handle_sending_once_and_terminate(m, &mut items, &mut retry).await,
                 WaitingByRetryRequested(m) => self.handle_waiting(m, &mut retry).await,
                 StoppedByItemsSentAndStop(_) => break,
@@ -99,50 +167,70 @@ impl Worker {
                 StoppedByTerminateRequested(_) => break,
             }
         }
+
+        self.status.set(ClientStatus::Stopping);
     }
 
-    async fn handle_receiving<E: Event>(&mut self, m: Machine<Receiving, E>, items: &mut Vec<Envelope>) -> Variant {
+    async fn handle_receiving<E: Event>(
+        &mut self,
+        m: Machine<Receiving, E>,
+        items: &mut Vec<Envelope>,
+        retry: &mut Retry,
+    ) -> Variant {
         debug!("Receiving messages triggered by {:?}", m.trigger());
 
-        let timeout = timeout::sleep(self.interval);
+        // start a new submission attempt with a fresh retry budget
+        self.persist(items);
         items.clear();
+        *retry = Retry::exponential(&self.retry_policy);
+        self.throttled_until = None;
+
+        // a batch could be filled up while previous one was being sent
+        if self.items.len() >= self.max_batch_size {
+            debug!("Batch of {} items is full", self.max_batch_size);
+            return m.transition(FlushRequested).as_enum();
+        }
+
+        let interval = self
+            .adaptive_interval
+            .as_ref()
+            .map_or(self.interval, AdaptiveInterval::current);
+        let timeout = timeout::sleep(interval);
+        tokio::pin!(timeout);
 
         loop {
             tokio::select! {
                 command = self.command_receiver.next() => {
-                    match command {
+                    return match command {
                         Some(command) => {
                             trace!("Command received: {}", command);
                             match command {
-                                Command::Flush => return m.transition(FlushRequested).as_enum(),
-                                Command::Terminate => return m.transition(TerminateRequested).as_enum(),
-                                Command::Close => return m.transition(CloseRequested).as_enum(),
+                                Command::Flush => m.transition(FlushRequested).as_enum(),
+                                Command::FlushAndWait(sender) => {
+                                    self.flush_waiters.push(sender);
+                                    m.transition(FlushRequested).as_enum()
+                                }
+                                Command::Terminate => m.transition(TerminateRequested).as_enum(),
+                                Command::Close => m.transition(CloseRequested).as_enum(),
                             }
                         },
                         None => {
                             error!("commands channel closed");
-                            return m.transition(TerminateRequested).as_enum()
+                            m.transition(TerminateRequested).as_enum()
                         },
-                    }
+                    };
+                },
+                Some(pong) = self.pings.next() => {
+                    let _ = pong.send(());
                 },
-                _ = timeout => {
+                _ = &mut timeout => {
                     debug!("Timeout expired");
-                    return m.transition(TimeoutExpired).as_enum()
+                    return m.transition(TimeoutExpired).as_enum();
                 },
             }
         }
     }
 
-    async fn handle_sending_with_retry<E: Event>(
-        &mut self,
-        m: Machine<Sending, E>,
-        items: &mut Vec<Envelope>,
-        retry: &mut Retry,
-    ) -> Variant {
-        *retry = Retry::exponential();
-        self.handle_sending(m, items).await
-    }
-
     async fn handle_sending_once_and_terminate<E: Event>(
         &mut self,
         m: Machine<Sending, E>,
@@ -150,15 +238,32 @@ impl Worker {
         retry: &mut Retry,
     ) -> Variant {
         *retry = Retry::once();
+        self.status.set(ClientStatus::Stopping);
         let cloned = m.clone(); // clone here
         self.handle_sending(m, items).await;
+        self.persist(items);
+        let held = mem::take(&mut self.held);
+        self.persist(&held);
+        self.notify_flush_failed();
         cloned.transition(TerminateRequested).as_enum()
     }
 
     async fn handle_sending<E: Event>(&mut self, m: Machine<Sending, E>, items: &mut Vec<Envelope>) -> Variant {
         // read pending items from a channel
-        while let Some(item) = self.items.pop() {
-            items.push(item);
+        items.extend(self.items.drain());
+        if let Some(interval) = &mut self.adaptive_interval {
+            interval.record(items.len(), self.max_batch_size);
+        }
+
+        if let Some(breaker) = &mut self.breaker {
+            if !breaker.allows(timeout::now()) {
+                debug!("Circuit breaker is open. Holding {} telemetry items", items.len());
+                self.suspend(items, None);
+                return m.transition(ItemsSentAndContinue).as_enum();
+            }
+
+            // items held while the circuit was open are sent first
+            items.splice(0..0, self.held.drain(..));
         }
 
         debug!(
@@ -166,35 +271,74 @@ impl Worker {
             items.len(),
             m.trigger().unwrap()
         );
+        self.status.set(ClientStatus::Flushing);
 
         // submit items to the server if any
         if items.is_empty() {
             debug!("Nothing to send. Continue to wait");
+            self.notify_flushed(Ok(()));
+            self.replay().await;
             m.transition(ItemsSentAndContinue).as_enum()
         } else {
-            // attempt to send items
-            match self.transmitter.send(mem::take(items)).await {
-                Ok(Response::Success) => m.transition(ItemsSentAndContinue).as_enum(),
-                Ok(Response::Retry(retry_items)) => {
-                    *items = retry_items;
-                    m.transition(RetryRequested).as_enum()
-                }
-                Ok(Response::Throttled(_retry_after, retry_items)) => {
-                    *items = retry_items;
-                    // TODO implement throttling instead
-                    m.transition(RetryRequested).as_enum()
-                }
-                Ok(Response::NoRetry) => m.transition(ItemsSentAndContinue).as_enum(),
-                Err(err) => {
-                    debug!("Error occurred during sending telemetry items: {}", err);
-                    m.transition(RetryRequested).as_enum()
+            // attempt to send items in batches of limited number of items and content size
+            let mut pending = mem::take(items);
+            let mut last_error = None;
+
+            while !pending.is_empty() {
+                let batch = batch::take(&mut pending, self.max_batch_size, self.batch_content_limit);
+                let delivered = match answering(&mut self.pings, self.transmitter.send_items(batch)).await {
+                    Ok(Response::Success) => true,
+                    Ok(Response::NoRetry(rejected)) => {
+                        self.reject(rejected);
+                        true
+                    }
+                    Ok(Response::Retry(retry_items)) => {
+                        last_error = Some(format!("{} items were not delivered to the server", retry_items.len()));
+                        items.extend(retry_items);
+                        false
+                    }
+                    Ok(Response::Throttled(retry_after, retry_items)) => {
+                        last_error = Some(format!("{} items were throttled by the server", retry_items.len()));
+                        self.throttled_until = self.throttled_until.max(Some(retry_after));
+                        items.extend(retry_items);
+                        false
+                    }
+                    Err(err) => {
+                        debug!("Error occurred during sending telemetry items: {}", err);
+                        last_error = Some(err.to_string());
+                        false
+                    }
+                };
+
+                // stop sending remaining batches as soon as the circuit opens
+                if record(&mut self.breaker, delivered) {
+                    items.append(&mut pending);
+                    break;
                 }
             }
+
+            if self.breaker.as_ref().is_some_and(CircuitBreaker::is_open) {
+                self.suspend(items, last_error);
+                m.transition(ItemsSentAndContinue).as_enum()
+            } else if let Some(reason) = last_error {
+                self.status.set(ClientStatus::Errored(reason.clone()));
+                self.last_error = Some(reason);
+                m.transition(RetryRequested).as_enum()
+            } else {
+                self.notify_flushed(Ok(()));
+                self.replay().await;
+                m.transition(ItemsSentAndContinue).as_enum()
+            }
         }
     }
 
     async fn handle_waiting<E: Event>(&mut self, m: Machine<Waiting, E>, retry: &mut Retry) -> Variant {
         if let Some(timeout) = retry.next() {
+            // the server may ask to wait longer than the retry policy does
+            let timeout = match self.throttled_until.take() {
+                Some(until) => (until - time::now()).to_std().map_or(timeout, |wait| wait.max(timeout)),
+                None => timeout,
+            };
             debug!(
                 "Waiting for retry timeout {:?} or stop command triggered by {:?}",
                 timeout,
@@ -202,48 +346,204 @@ impl Worker {
             );
             // sleep until next sending attempt
             let timeout = timeout::sleep(timeout);
+            tokio::pin!(timeout);
 
             // wait for either retry timeout expired or stop command received
-            tokio::select! {
-                command = skip_flush(&mut self.command_receiver) => {
-                    match command {
-                        Some(Command::Terminate) => m.transition(TerminateRequested).as_enum(),
-                        Some(Command::Close) => m.transition(CloseRequested).as_enum(),
-                        Some(Command::Flush) => panic!("whoops Flush is not supported here"),
-                        None => {
-                            error!("commands channel closed");
-                            m.transition(TerminateRequested).as_enum()
+            loop {
+                tokio::select! {
+                    command = self.command_receiver.next() => {
+                        match command {
+                            Some(Command::Terminate) => return m.transition(TerminateRequested).as_enum(),
+                            Some(Command::Close) => return m.transition(CloseRequested).as_enum(),
+                            Some(Command::Flush) => trace!("Flush is ignored while waiting for retry"),
+                            Some(Command::FlushAndWait(sender)) => self.flush_waiters.push(sender),
+                            None => {
+                                error!("commands channel closed");
+                                return m.transition(TerminateRequested).as_enum()
+                            }
                         }
-                    }
-                },
-                _ = timeout => {
-                    debug!("Retry timeout expired");
-                    m.transition(TimeoutExpired).as_enum()
-                },
+                    },
+                    Some(pong) = self.pings.next() => {
+                        let _ = pong.send(());
+                    },
+                    _ = &mut timeout => {
+                        debug!("Retry timeout expired");
+                        return m.transition(TimeoutExpired).as_enum()
+                    },
+                }
             }
         } else {
             debug!("All retries exhausted by {:?}", m.state());
+            error!(
+                "Unable to submit telemetry items in {} attempts: {}",
+                self.retry_policy.max_attempts().max(1),
+                self.last_error.as_deref().unwrap_or("unknown error")
+            );
+            self.notify_flush_failed();
             m.transition(RetryExhausted).as_enum()
         }
     }
-}
 
-fn skip_flush<St>(stream: &mut St) -> SkipFlush<'_, St> {
-    SkipFlush { stream }
-}
+    /// Persists telemetry items that were not delivered to the server, so they can be sent later.
+    fn persist(&mut self, items: &[Envelope]) {
+        if items.is_empty() {
+            return;
+        }
+
+        match &mut self.storage {
+            Some(storage) => {
+                debug!("Storing {} undelivered telemetry items", items.len());
+                if let Err(err) = storage.store(items) {
+                    warn!("Unable to store {} undelivered telemetry items: {}", items.len(), err);
+                    self.items.discard(items.len());
+                }
+            }
+            None => self.items.discard(items.len()),
+        }
+    }
+
+    /// Sends telemetry items stored earlier, starting from the oldest batch, until the server stops
+    /// accepting them.
+    async fn replay(&mut self) {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return,
+        };
 
-struct SkipFlush<'a, St: ?Sized> {
-    stream: &'a mut St,
+        loop {
+            let (path, items) = match storage.oldest() {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("Unable to read stored telemetry items: {}", err);
+                    break;
+                }
+            };
+
+            debug!("Sending {} stored telemetry items from {}", items.len(), path.display());
+            let result = match answering(&mut self.pings, self.transmitter.send(items)).await {
+                Ok(Response::Success) => {
+                    record(&mut self.breaker, true);
+                    storage.remove(&path)
+                }
+                Ok(Response::NoRetry(rejected)) => {
+                    self.items.discard(rejected);
+                    record(&mut self.breaker, true);
+                    storage.remove(&path)
+                }
+                Ok(Response::Retry(items)) | Ok(Response::Throttled(_, items)) => {
+                    if let Err(err) = storage.replace(&path, &items) {
+                        warn!("Unable to update stored telemetry items in {}: {}", path.display(), err);
+                    }
+                    record(&mut self.breaker, false);
+                    break;
+                }
+                Err(err) => {
+                    debug!("Error occurred during sending stored telemetry items: {}", err);
+                    record(&mut self.breaker, false);
+                    break;
+                }
+            };
+
+            if let Err(err) = result {
+                warn!("Unable to remove stored telemetry items in {}: {}", path.display(), err);
+                break;
+            }
+        }
+    }
+
+    /// Keeps telemetry items while the circuit breaker is open, so they are sent once it closes. Items
+    /// are persisted when offline storage is configured, otherwise at most as many items as the buffer
+    /// holds are kept in memory and the oldest ones are discarded. Callers waiting for a flush are
+    /// notified that submission failed.
+    fn suspend(&mut self, items: &mut Vec<Envelope>, last_error: Option<String>) {
+        if self.storage.is_some() {
+            self.persist(items);
+            items.clear();
+        } else {
+            self.held.append(items);
+            let excess = self
+                .held
+                .len()
+                .saturating_sub(self.items.capacity().unwrap_or(usize::MAX));
+            if excess > 0 {
+                debug!("Too many telemetry items held. {} oldest items discarded", excess);
+                self.held.drain(..excess);
+                self.items.discard(excess);
+            }
+        }
+
+        let reason = last_error.unwrap_or_else(|| "circuit breaker is open".into());
+        self.status.set(ClientStatus::Errored(reason.clone()));
+        self.last_error = Some(reason);
+        self.notify_flush_failed();
+    }
+
+    /// Notifies all callers waiting for pending telemetry items to be flushed that submission failed.
+    fn notify_flush_failed(&mut self) {
+        if let Some(reason) = self.last_error.take() {
+            self.notify_flushed(Err(FlushError::Transport(reason)));
+        }
+    }
+
+    /// Counts telemetry items the server rejected permanently as discarded. Callers waiting for a flush
+    /// are notified that submission failed once remaining items are sent.
+    fn reject(&mut self, count: usize) {
+        self.items.discard(count);
+        self.rejected += count;
+    }
+
+    /// Notifies all callers waiting for pending telemetry items to be flushed with submission result.
+    /// Submission fails when any telemetry items were rejected since the last notification.
+    fn notify_flushed(&mut self, result: Result<(), FlushError>) {
+        let result = match (result, mem::take(&mut self.rejected)) {
+            (Ok(()), 0) => Ok(()),
+            (Ok(()), rejected) => {
+                let reason = format!("{} telemetry items were rejected by the server", rejected);
+                self.status.set(ClientStatus::Errored(reason.clone()));
+                Err(FlushError::Transport(reason))
+            }
+            (result, _) => result,
+        };
+
+        if result.is_ok() {
+            self.last_error = None;
+            self.status.set(ClientStatus::Running);
+        }
+
+        for sender in self.flush_waiters.drain(..) {
+            // a caller may not wait for the result anymore
+            let _ = sender.send(result.clone());
+        }
+    }
 }
 
-impl<St: ?Sized + Stream<Item = Command> + Unpin> Future for SkipFlush<'_, St> {
-    type Output = Option<St::Item>;
+/// Drives a future to completion answering pings of a watchdog in the meantime, so a worker waiting
+/// for a slow server is not considered dead.
+async fn answering<F: Future>(pings: &mut UnboundedReceiver<PongSender>, future: F) -> F::Output {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            Some(pong) = pings.next() => {
+                let _ = pong.send(());
+            },
+        }
+    }
+}
 
-    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
-        match self.stream.poll_next_unpin(cx) {
-            std::task::Poll::Ready(Some(Command::Flush)) => std::task::Poll::Pending,
-            std::task::Poll::Ready(command) => std::task::Poll::Ready(command),
-            std::task::Poll::Pending => std::task::Poll::Pending,
+/// Records a result of a submission with the circuit breaker if any. Returns `true` when the circuit is
+/// open, so sending should be suspended.
+fn record(breaker: &mut Option<CircuitBreaker>, delivered: bool) -> bool {
+    match breaker {
+        Some(breaker) => {
+            if delivered {
+                breaker.record_success();
+            } else {
+                breaker.record_failure(timeout::now());
+            }
+            breaker.is_open()
         }
+        None => false,
     }
 }
@@ -0,0 +1,220 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Extension of files that contain persisted telemetry items.
+const FILE_EXTENSION: &str = "ndjson";
+
+/// Persists batches of telemetry items that could not be delivered to the server in a directory, so
+/// that they can be sent later. Each batch is stored in a separate file with one telemetry item per line.
+pub struct OfflineStorage {
+    path: PathBuf,
+    max_size: u64,
+    sequence: u64,
+}
+
+impl OfflineStorage {
+    /// Creates a new storage that keeps at most specified number of bytes in a directory.
+    pub fn new(path: impl Into<PathBuf>, max_size: u64) -> Self {
+        Self {
+            path: path.into(),
+            max_size,
+            sequence: 0,
+        }
+    }
+
+    /// Persists a batch of telemetry items. The oldest batches are deleted when total size of stored
+    /// batches exceeds the limit.
+    pub fn store<T: Serialize>(&mut self, items: &[T]) -> io::Result<()> {
+        fs::create_dir_all(&self.path)?;
+
+        // file names are sortable, so that batches can be sent in the order they were stored
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let name = format!("{:020}-{:010}", timestamp.as_nanos(), self.sequence);
+        self.sequence += 1;
+
+        self.write(&self.path.join(format!("{}.{}", name, FILE_EXTENSION)), items)?;
+        self.enforce_limit()
+    }
+
+    /// Returns a path to the oldest stored batch together with telemetry items it contains.
+    pub fn oldest(&self) -> io::Result<Option<(PathBuf, Vec<Value>)>> {
+        match self.files()?.into_iter().next() {
+            Some((path, _)) => {
+                let content = fs::read_to_string(&path)?;
+                let items = content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter_map(|line| match serde_json::from_str(line) {
+                        Ok(item) => Some(item),
+                        Err(err) => {
+                            warn!(
+                                "Skipping malformed telemetry item stored in {}: {}",
+                                path.display(),
+                                err
+                            );
+                            None
+                        }
+                    })
+                    .collect();
+                Ok(Some((path, items)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces a content of a stored batch with specified telemetry items. A batch is deleted when
+    /// there are no items left.
+    pub fn replace(&self, path: &Path, items: &[Value]) -> io::Result<()> {
+        if items.is_empty() {
+            self.remove(path)
+        } else {
+            self.write(path, items)
+        }
+    }
+
+    /// Deletes a stored batch.
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes telemetry items to a temporary file first and then moves it to a specified path, so that
+    /// partially written batches are never read.
+    fn write<T: Serialize>(&self, path: &Path, items: &[T]) -> io::Result<()> {
+        let mut content = Vec::default();
+        for item in items {
+            serde_json::to_writer(&mut content, item)?;
+            content.push(b'\n');
+        }
+
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, content)?;
+        fs::rename(temp, path)
+    }
+
+    /// Deletes the oldest batches until total size of stored batches fits the limit.
+    fn enforce_limit(&self) -> io::Result<()> {
+        let files = self.files()?;
+        let mut size: u64 = files.iter().map(|(_, size)| size).sum();
+
+        for (path, file_size) in files {
+            if size <= self.max_size {
+                break;
+            }
+
+            warn!(
+                "Offline storage exceeded {} bytes. Deleting telemetry items stored in {}",
+                self.max_size,
+                path.display()
+            );
+            self.remove(&path)?;
+            size -= file_size;
+        }
+
+        Ok(())
+    }
+
+    /// Returns paths and sizes of all stored batches starting from the oldest one.
+    fn files(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::default()),
+            Err(err) => return Err(err),
+        };
+
+        let mut files = Vec::default();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == FILE_EXTENSION) {
+                files.push((path, entry.metadata()?.len()));
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::uuid::Uuid;
+
+    #[test]
+    fn it_returns_batches_oldest_first() {
+        let path = temp_dir();
+        let mut storage = OfflineStorage::new(&path, u64::MAX);
+
+        storage
+            .store(&[json!({"name": "first"}), json!({"name": "second"})])
+            .unwrap();
+        storage.store(&[json!({"name": "third"})]).unwrap();
+
+        let (oldest, items) = storage.oldest().unwrap().unwrap();
+        assert_eq!(items, vec![json!({"name": "first"}), json!({"name": "second"})]);
+
+        storage.remove(&oldest).unwrap();
+        let (_, items) = storage.oldest().unwrap().unwrap();
+        assert_eq!(items, vec![json!({"name": "third"})]);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn it_deletes_oldest_batches_when_limit_exceeded() {
+        let path = temp_dir();
+        let mut storage = OfflineStorage::new(&path, 40);
+
+        for i in 0..3 {
+            storage.store(&[json!({ "name": format!("event {}", i) })]).unwrap();
+        }
+
+        assert_eq!(storage.files().unwrap().len(), 2);
+        let (_, items) = storage.oldest().unwrap().unwrap();
+        assert_eq!(items, vec![json!({"name": "event 1"})]);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn it_replaces_batch_with_items_left() {
+        let path = temp_dir();
+        let mut storage = OfflineStorage::new(&path, u64::MAX);
+        storage
+            .store(&[json!({"name": "first"}), json!({"name": "second"})])
+            .unwrap();
+
+        let (oldest, _) = storage.oldest().unwrap().unwrap();
+        storage.replace(&oldest, &[json!({"name": "second"})]).unwrap();
+        assert_eq!(storage.oldest().unwrap().unwrap().1, vec![json!({"name": "second"})]);
+
+        storage.replace(&oldest, &[]).unwrap();
+        assert_eq!(storage.oldest().unwrap(), None);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn it_returns_nothing_when_directory_does_not_exist() {
+        let storage = OfflineStorage::new(temp_dir(), u64::MAX);
+
+        assert_eq!(storage.oldest().unwrap(), None);
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("appinsights-{}", Uuid::new_v4().to_simple()))
+    }
+}
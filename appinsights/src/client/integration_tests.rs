@@ -21,11 +21,15 @@ use tokio::sync::{
     oneshot,
 };
 
-use crate::{timeout, TelemetryClient, TelemetryConfig};
+use crate::{
+    telemetry::EventTelemetry, timeout, BackpressurePolicy, BufferFullError, ClientStatus, FlushError, RetryPolicy,
+    TelemetryClient, TelemetryConfig,
+};
 
 lazy_static! {
-    /// A global lock since most tests need to run in serial.
-    static ref SERIAL_TEST_MUTEX: Mutex<()> = Mutex::new(());
+    /// A global lock since most tests need to run in serial. It is shared with blocking client tests
+    /// because all of them use the same manual timeout channel.
+    pub(crate) static ref SERIAL_TEST_MUTEX: Mutex<()> = Mutex::new(());
 }
 
 macro_rules! manual_timeout_test {
@@ -126,6 +130,48 @@ manual_timeout_test! {
     }
 }
 
+manual_timeout_test! {
+    async fn it_sends_telemetry_items_in_batches_of_limited_size() {
+        let mut server = server().status(StatusCode::OK).status(StatusCode::OK).create();
+
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(server.url())
+            .interval(Duration::from_millis(300))
+            .max_batch_size(10)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::from_config(config);
+
+        // send 11 items and force client to send all of them
+        for i in 0..11 {
+            client.track_event(format!("--event {}--", i));
+        }
+        client.flush_channel();
+
+        // NOTE no timeout expired
+        // verify that items were sent in 2 batches
+        let requests = server.wait_for_requests(2).await;
+        assert_eq!(requests.len(), 2);
+
+        let counts: Vec<_> = requests
+            .iter()
+            .map(|body| (0..11).filter(|i| body.contains(&format!("--event {}--", i))).count())
+            .collect();
+        assert!(counts.iter().all(|count| *count <= 10));
+        assert_eq!(counts.iter().sum::<usize>(), 11);
+
+        // verify no other requests were sent
+        assert_matches!(
+            server.next_request_timeout().await,
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
 manual_timeout_test! {
     async fn it_flushes_all_pending_telemetry_items() {
         let mut server = server().status(StatusCode::OK).status(StatusCode::OK).create();
@@ -161,12 +207,93 @@ manual_timeout_test! {
 }
 
 manual_timeout_test! {
-    async fn it_does_not_send_any_pending_telemetry_items_when_drop_client() {
+    async fn it_waits_until_all_pending_telemetry_items_flushed() {
+        let mut server = server().status(StatusCode::OK).create();
+
+        let client = create_client(server.url());
+
+        // send 15 items and then wait until they are submitted
+        for i in 0..15 {
+            client.track_event(format!("--event {}--", i));
+        }
+        assert_eq!(client.flush(None).await, Ok(()));
+
+        // NOTE no timeout expired
+        // verify request with all items has been already received by the server
+        let requests = server.wait_for_requests(1).await;
+        assert_eq!(requests.len(), 1);
+
+        let items_count = (0..15)
+            .filter(|i| requests[0].contains(&format!("--event {}--", i)))
+            .count();
+        assert_eq!(items_count, 15);
+
+        // verify client is still able to submit telemetry
+        client.track_event("--event after flush--");
+        client.flush_channel();
+
+        let requests = server.wait_for_requests(1).await;
+        assert_eq!(requests.len(), 1);
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+#[test]
+fn it_flushes_pending_telemetry_items_on_current_thread_runtime() {
+    let _guard = SERIAL_TEST_MUTEX.lock();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("runtime");
+    rt.block_on(async {
+        timeout::init();
+
+        let mut server = server().status(StatusCode::OK).create();
+
+        let client = create_client(server.url());
+        client.track_event("--event--");
+        assert_eq!(client.flush(None).await, Ok(()));
+
+        let requests = server.wait_for_requests(1).await;
+        assert_eq!(requests.len(), 1);
+
+        server.terminate().await;
+
+        timeout::reset();
+    });
+}
+
+manual_timeout_test! {
+    async fn it_stops_waiting_for_flush_when_timeout_expired() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .create();
+
+        let client = create_client(server.url());
+        client.track_event("--event--");
+
+        // NOTE retry timeout never expires
+        let result = client.flush(Some(Duration::from_millis(100))).await;
+        assert_eq!(result, Err(FlushError::Timeout));
+
+        // verify items were submitted
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_sends_pending_telemetry_items_when_drop_client() {
         let mut server = server().status(StatusCode::OK).status(StatusCode::OK).create();
 
         let client = create_client(server.url());
 
-        // send 15 items and then interval expired
+        // send 15 items without waiting for interval to expire
         for i in 0..15 {
             client.track_event(format!("--event {}--", i));
         }
@@ -174,11 +301,13 @@ manual_timeout_test! {
         // drop client
         drop(client);
 
-        // verify that nothing has been sent to the server
-        assert_matches!(
-            server.next_request_timeout().await,
-            Err(RecvTimeoutError::Timeout)
-        );
+        // verify that all items were sent to the server
+        let requests = server.wait_for_requests(2).await;
+        let content = requests.concat();
+        let items_count = (0..15)
+            .filter(|i| content.contains(&format!("--event {}--", i)))
+            .count();
+        assert_eq!(items_count, 15);
 
         // terminate server
         server.terminate().await;
@@ -283,6 +412,123 @@ manual_timeout_test! {
     }
 }
 
+manual_timeout_test! {
+    async fn it_waits_for_retry_after_when_throttled() {
+        let retry_after = Utc::now() + chrono::Duration::seconds(60);
+        let mut server = server()
+            .response(StatusCode::TOO_MANY_REQUESTS, json!({}), Some(retry_after))
+            .status(StatusCode::OK)
+            .create();
+
+        let client = create_client(server.url());
+        client.track_event("--event--");
+
+        // "wait" until interval expired
+        timeout::expire();
+
+        // "wait" until retry requested by the server expired
+        timeout::expire();
+
+        let requests = server.wait_for_requests(2).await;
+        assert_eq!(requests.len(), 2);
+
+        // the worker waited longer than the default retry policy asks for
+        let longest = timeout::durations().into_iter().max().unwrap();
+        assert!(longest > Duration::from_secs(50), "waited for {:?}", longest);
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_stops_retrying_when_max_attempts_reached() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(server.url())
+            .interval(Duration::from_millis(300))
+            .retry_policy(RetryPolicy::new(2, Duration::from_millis(100), Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        let client = TelemetryClient::from_config(config);
+        client.track_event("--event--");
+
+        // "wait" until interval expired
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // "wait" until retry timeout expired
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // verify that items were not sent once again
+        timeout::expire();
+        assert_matches!(
+            server.next_request_timeout().await,
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_does_not_retry_when_request_rejected() {
+        let mut server = server()
+            .response(StatusCode::BAD_REQUEST, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let client = create_client(server.url());
+        client.track_event("--event--");
+
+        // "wait" until interval expired
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // verify that items were not sent once again
+        timeout::expire();
+        assert_matches!(
+            server.next_request_timeout().await,
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_fails_to_flush_when_request_rejected() {
+        let mut server = server()
+            .response(StatusCode::BAD_REQUEST, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let client = create_client(server.url());
+        client.track_event("--event--");
+
+        // verify that waiters are notified that items were not accepted
+        assert_matches!(client.flush(None).await, Err(FlushError::Transport(_)));
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+        assert_eq!(client.dropped_count(), 1);
+
+        // verify that next flush succeeds once items are accepted
+        client.track_event("--event--");
+        assert_eq!(client.flush(None).await, Ok(()));
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
 manual_timeout_test! {
     async fn it_retries_when_partial_content() {
         let mut server = server()
@@ -369,14 +615,221 @@ manual_timeout_test! {
     }
 }
 
-// TODO Check case when all retries exhausted. Pending items should not be lost
+manual_timeout_test! {
+    async fn it_sends_stored_items_when_server_accepts_telemetry_again() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let storage = std::env::temp_dir().join(format!("appinsights-{}", uuid::Uuid::new_v4().to_simple()));
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(server.url())
+            .interval(Duration::from_millis(300))
+            .retry_policy(RetryPolicy::new(2, Duration::from_millis(100), Duration::from_millis(100)))
+            .offline_storage(&storage, 1024 * 1024)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::from_config(config);
+        client.track_event("--event 1--");
+
+        // "wait" until interval expired and all retries exhausted
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // "wait" until interval expired for a new item
+        client.track_event("--event 2--");
+        timeout::expire();
+
+        // verify that stored item was sent after a new item delivered
+        let requests = server.wait_for_requests(2).await;
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].contains("--event 2--"));
+        assert!(requests[1].contains("--event 1--"));
+
+        // verify that stored item was deleted once delivered
+        for _ in 0..10 {
+            if std::fs::read_dir(&storage).unwrap().count() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_dir(&storage).unwrap().count(), 0);
+
+        // terminate server
+        std::fs::remove_dir_all(storage).unwrap();
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_holds_items_while_circuit_breaker_is_open() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(server.url())
+            .interval(Duration::from_millis(300))
+            .circuit_breaker(1, Duration::from_secs(30))
+            .build()
+            .unwrap();
+        let client = TelemetryClient::from_config(config);
+        client.track_event("--event 1--");
+
+        // "wait" until interval expired and submission failed, so the circuit opens
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // verify that nothing is sent while the circuit is open
+        client.track_event("--event 2--");
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Err(_));
+
+        // "wait" until recovery timeout expired and verify that held items are sent with a trial batch
+        timeout::advance(Duration::from_secs(30));
+        client.track_event("--event 3--");
+        timeout::expire();
+        let request = server.next_request_timeout().await.unwrap();
+        assert!(request.contains("--event 1--"));
+        assert!(request.contains("--event 2--"));
+        assert!(request.contains("--event 3--"));
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_reports_worker_status() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let client = create_client(server.url());
+        assert!(client.wait_until_running(Duration::from_secs(1)).await);
+        client.track_event("--event 1--");
+
+        // "wait" until interval expired and verify that failed submission is reported
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+        assert!(!client.wait_until_running(Duration::from_secs(1)).await);
+        assert_matches!(client.status(), ClientStatus::Errored(_));
+
+        // "wait" until retry timeout expired and verify that worker is running again
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+        assert!(client.wait_until_running(Duration::from_secs(1)).await);
+
+        let clone = client.clone();
+        client.close_channel().await;
+        assert_eq!(clone.status(), ClientStatus::Stopping);
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+manual_timeout_test! {
+    async fn it_rejects_items_when_too_many_items_pending() {
+        let mut server = server()
+            .response(StatusCode::INTERNAL_SERVER_ERROR, json!({}), None)
+            .status(StatusCode::OK)
+            .create();
+
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(server.url())
+            .interval(Duration::from_millis(300))
+            .max_pending_items(1)
+            .backpressure(BackpressurePolicy::ReturnError)
+            .build_unchecked();
+        let client = TelemetryClient::from_config(config);
+        client.track_event("--event 1--");
+
+        // "wait" until interval expired and submission failed
+        timeout::expire();
+        assert_matches!(server.next_request_timeout().await, Ok(_));
+
+        // verify that new items are rejected while waiting for retry
+        assert_eq!(client.try_track(EventTelemetry::new("--event 2--")), Ok(()));
+        assert_eq!(client.try_track(EventTelemetry::new("--event 3--")), Err(BufferFullError));
+
+        // "wait" until retry timeout expired
+        timeout::expire();
+
+        // verify that only accepted items were sent
+        let request = server.next_request_timeout().await.unwrap();
+        assert!(request.contains("--event 1--"));
+        assert!(request.contains("--event 2--"));
+        assert!(!request.contains("--event 3--"));
+
+        // terminate server
+        server.terminate().await;
+    }
+}
+
+#[test]
+fn it_does_not_park_runtime_worker_when_too_many_items_pending() {
+    let _guard = SERIAL_TEST_MUTEX.lock();
+
+    let (done_sender, done_receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("runtime");
+
+        rt.block_on(async {
+            let server = server().status(StatusCode::OK).create();
+
+            let config = TelemetryConfig::builder()
+                .i_key("00000000-0000-0000-0000-000000000000")
+                .endpoint(server.url())
+                .interval(Duration::from_millis(300))
+                .max_pending_items(2)
+                .max_batch_size(2)
+                .backpressure(BackpressurePolicy::Block)
+                .build_unchecked();
+            let client = TelemetryClient::from_config(config);
+
+            // track items from a task that runs on the only worker thread
+            tokio::spawn(async move {
+                for i in 0..10 {
+                    client.track_event(format!("--event {}--", i));
+                }
+            })
+            .await
+            .unwrap();
+
+            // terminate server
+            server.terminate().await;
+        });
+
+        let _ = done_sender.send(());
+    });
+
+    assert!(
+        done_receiver.recv_timeout(Duration::from_secs(10)).is_ok(),
+        "runtime worker is blocked"
+    );
+}
 
 fn create_client(endpoint: &str) -> TelemetryClient {
     let config = TelemetryConfig::builder()
-        .i_key("instrumentation key")
+        .i_key("00000000-0000-0000-0000-000000000000")
         .endpoint(endpoint)
         .interval(Duration::from_millis(300))
-        .build();
+        .build()
+        .unwrap();
 
     TelemetryClient::from_config(config)
 }
@@ -493,10 +946,11 @@ impl Builder {
                         let count = counter.fetch_add(1, Ordering::AcqRel);
 
                         let response = if let Some(response) = responses.get(count) {
-                            Response::builder()
-                                .status(response.status())
-                                .body(Body::from(response.body().clone()))
-                                .unwrap()
+                            let mut builder = Response::builder().status(response.status());
+                            for (name, value) in response.headers() {
+                                builder = builder.header(name, value);
+                            }
+                            builder.body(Body::from(response.body().clone())).unwrap()
                         } else {
                             Response::builder()
                                 .status(StatusCode::NOT_FOUND)
@@ -510,7 +964,7 @@ impl Builder {
             }
         });
 
-        let server = Server::bind(&([0, 0, 0, 0], 0).into()).serve(make_service);
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
 
         let url = format!("http://{}", server.local_addr());
 
@@ -1,23 +1,147 @@
-use std::time::Duration;
+mod stats;
+pub use stats::ClientStats;
+pub(crate) use stats::StatsCallback;
+
+mod span;
+pub use span::{DependencySpan, DependencyTimer};
+
+mod status;
+pub use status::ClientStatus;
+pub(crate) use status::SharedStatus;
+
+use std::{
+    fmt::Display,
+    future::Future,
+    ops::{Deref, DerefMut},
+    panic::{self, PanicHookInfo},
+    sync::{mpsc, Arc, RwLock},
+    time::Duration,
+};
 
 use http::{Method, Uri};
+use tokio::runtime::Handle;
 
+#[cfg(feature = "perf-counters")]
+use crate::performance::PerformanceCounterCollector;
 use crate::{
-    channel::{InMemoryChannel, TelemetryChannel},
-    context::TelemetryContext,
+    aggregator::MetricsAggregator,
+    channel::{
+        self, BufferFullError, DebugChannel, FlushError, InMemoryChannel, NoopChannel, SendError, TelemetryChannel,
+    },
+    context::{self, TelemetryContext},
     contracts::Envelope,
+    heartbeat::Heartbeat,
+    live_metrics::{LiveMetrics, LiveMetricsService},
+    processor::{Pipeline, TelemetryItem, TelemetryProcessor},
+    rate_limit::{RateLimiter, DROPPED_ITEMS},
+    sampling::SamplingStrategy,
     telemetry::{
-        AvailabilityTelemetry, EventTelemetry, MetricTelemetry, RemoteDependencyTelemetry, RequestTelemetry,
-        SeverityLevel, Telemetry, TraceTelemetry,
+        http_target, AnyTelemetry, AvailabilityTelemetry, EventTelemetry, ExceptionTelemetry, MetricTelemetry,
+        OperationContext, RemoteDependencyTelemetry, RequestTelemetry, SeverityLevel, Telemetry, TraceTelemetry,
     },
-    TelemetryConfig,
+    time, TelemetryConfig,
 };
 
+/// How long a panic hook waits for pending items to be submitted.
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a status is checked while waiting for a worker to run.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A panic hook as it is returned by `std::panic::take_hook`.
+type PanicHook = dyn Fn(&PanicHookInfo<'_>) + Send + Sync + 'static;
+
 /// Application Insights telemetry client provides an interface to track telemetry items.
+///
+/// A client is cheap to clone. All clones share a single channel, so telemetry items tracked with any of
+/// them are submitted by one background worker using one HTTP connection pool. Each clone has its own
+/// copy of [context](#method.context), processors and enabled flag taken at the moment of cloning.
+/// Closing or terminating a channel with any clone stops submission for all of them. Pending telemetry
+/// items may be lost when the last clone is dropped without [closing](#method.close_channel) the channel.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # async fn run() {
+/// let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+///
+/// let handle = tokio::spawn({
+///     let client = client.clone();
+///     async move { client.track_event("handled in a separate task") }
+/// });
+/// handle.await.unwrap();
+///
+/// client.close_channel().await;
+/// # }
+/// ```
 pub struct TelemetryClient {
     enabled: bool,
+    pipeline: Pipeline,
     context: TelemetryContext,
-    channel: Box<dyn TelemetryChannel>,
+    shared: Arc<RwLock<SharedState>>,
+    channel: Arc<dyn TelemetryChannel>,
+    aggregator: Arc<MetricsAggregator>,
+    limiter: Option<Arc<RateLimiter>>,
+    live_metrics: Option<Arc<LiveMetrics>>,
+}
+
+/// A copy of an enabled flag, processors and context of a client that tasks it spawned read each time
+/// they run, so they follow changes made to the client after they were spawned.
+#[derive(Clone)]
+struct SharedState {
+    enabled: bool,
+    pipeline: Pipeline,
+    context: TelemetryContext,
+}
+
+impl SharedState {
+    fn new(enabled: bool, pipeline: &Pipeline, context: &TelemetryContext) -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(Self {
+            enabled,
+            pipeline: pipeline.clone(),
+            context: context.clone(),
+        }))
+    }
+}
+
+impl Clone for TelemetryClient {
+    fn clone(&self) -> Self {
+        Self {
+            enabled: self.enabled,
+            pipeline: self.pipeline.clone(),
+            context: self.context.clone(),
+            shared: SharedState::new(self.enabled, &self.pipeline, &self.context),
+            channel: self.channel.clone(),
+            aggregator: self.aggregator.clone(),
+            limiter: self.limiter.clone(),
+            live_metrics: self.live_metrics.clone(),
+        }
+    }
+}
+
+/// A mutable reference to a context of a client that shares changes with tasks spawned by the client
+/// once dropped.
+struct ContextMut<'a>(&'a mut TelemetryClient);
+
+impl Deref for ContextMut<'_> {
+    type Target = TelemetryContext;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.context
+    }
+}
+
+impl DerefMut for ContextMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.context
+    }
+}
+
+impl Drop for ContextMut<'_> {
+    fn drop(&mut self) {
+        self.0.share();
+    }
 }
 
 impl TelemetryClient {
@@ -28,16 +152,95 @@ impl TelemetryClient {
 
     /// Creates a new telemetry client configured with specified configuration.
     pub fn from_config(config: TelemetryConfig) -> Self {
-        Self::create(&config, InMemoryChannel::new(&config))
+        match DebugChannel::from_env().filter(|_| config.debug_channel()) {
+            Some(channel) => Self::with_channel(&config, channel),
+            None => Self::with_channel(&config, InMemoryChannel::new(&config)),
+        }
     }
 
-    /// Creates a new telemetry client with custom telemetry channel.
-    pub(crate) fn create<C: TelemetryChannel + 'static>(config: &TelemetryConfig, channel: C) -> Self {
-        Self {
+    /// Creates a new telemetry client that accepts all telemetry items and immediately discards them.
+    /// It neither spawns any background tasks nor makes any network calls, so it can be used in unit tests
+    /// instead of a real client.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use appinsights::TelemetryClient;
+    /// let client = TelemetryClient::new_noop();
+    /// client.track_event("discarded");
+    /// ```
+    pub fn new_noop() -> Self {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .build_unchecked();
+        Self::with_channel(&config, NoopChannel)
+    }
+
+    /// Creates a new telemetry client configured with specified configuration that submits telemetry
+    /// items with a custom telemetry channel.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use appinsights::{NoopChannel, TelemetryClient, TelemetryConfig};
+    /// let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".to_string());
+    /// let client = TelemetryClient::with_channel(&config, NoopChannel);
+    /// client.track_event("discarded");
+    /// ```
+    pub fn with_channel<C: TelemetryChannel + 'static>(config: &TelemetryConfig, channel: C) -> Self {
+        let context = TelemetryContext::from_config(config);
+
+        // live metrics are streamed until all clones of the client are dropped
+        let live_metrics = match Handle::try_current() {
+            Ok(runtime) if config.live_metrics() => {
+                let live_metrics = Arc::new(LiveMetrics::new());
+                let service = LiveMetricsService::new(config, &context);
+                runtime.spawn(service.run(Arc::downgrade(&live_metrics)));
+                Some(live_metrics)
+            }
+            _ => None,
+        };
+
+        let pipeline = Pipeline::new(config.sampling_rate(), config.min_trace_severity());
+        let client = Self {
             enabled: true,
-            context: TelemetryContext::from_config(config),
-            channel: Box::new(channel),
+            shared: SharedState::new(true, &pipeline, &context),
+            pipeline,
+            context,
+            channel: Arc::new(channel),
+            aggregator: Arc::new(MetricsAggregator::new(config.aggregation_window())),
+            limiter: config.rate_limit().map(|rate| Arc::new(RateLimiter::new(rate))),
+            live_metrics,
+        };
+
+        // held items are released and dropped items are reported once a second
+        if let (Some(_), Ok(runtime)) = (&client.limiter, Handle::try_current()) {
+            client.spawn_periodic(&runtime, Duration::from_secs(1), TelemetryClient::release_limited);
+        }
+
+        if let (Some(callback), Ok(runtime)) = (config.internal_metrics(), Handle::try_current()) {
+            let callback = callback.clone();
+            client.spawn_periodic(&runtime, config.interval(), move |client| callback(client.stats()));
+        }
+
+        if let (Some(interval), Ok(runtime)) = (config.heartbeat(), Handle::try_current()) {
+            let heartbeat = Heartbeat::new(interval);
+            client.spawn_periodic(&runtime, interval, move |client| {
+                client.track(heartbeat.collect());
+            });
+        }
+
+        #[cfg(feature = "perf-counters")]
+        if let (Some(interval), Ok(runtime)) = (config.perf_counters(), Handle::try_current()) {
+            let mut collector = PerformanceCounterCollector::new();
+            client.spawn_periodic(&runtime, interval, move |client| {
+                for telemetry in collector.collect() {
+                    client.track(telemetry);
+                }
+            });
         }
+
+        client
     }
 
     /// Determines whether this client is enabled and will accept telemetry.
@@ -46,7 +249,7 @@ impl TelemetryClient {
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
-    /// let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
     /// assert!(client.is_enabled());
     /// ```
     pub fn is_enabled(&self) -> bool {
@@ -59,7 +262,7 @@ impl TelemetryClient {
     ///
     /// ```rust
     /// # use appinsights::TelemetryClient;
-    /// let mut client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
     /// assert!(client.is_enabled());
     ///
     /// client.enabled(false);
@@ -67,6 +270,27 @@ impl TelemetryClient {
     /// ```
     pub fn enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
+        self.share();
+    }
+
+    /// Changes a minimum severity of trace telemetry items to submit for this client and all its clones.
+    /// Trace telemetry items of lower severity are silently swallowed by the client. Defaults to
+    /// [`min_trace_severity`](struct.TelemetryConfigBuilder.html#method.min_trace_severity) configured.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// use appinsights::telemetry::SeverityLevel;
+    ///
+    /// let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+    /// client.set_min_trace_severity(SeverityLevel::Warning);
+    ///
+    /// // discarded by the client
+    /// client.track_trace("connection established", SeverityLevel::Information);
+    /// ```
+    pub fn set_min_trace_severity(&self, severity: SeverityLevel) {
+        self.pipeline.set_min_trace_severity(severity);
     }
 
     /// Returns an immutable reference to a collection of tag data to attach to the telemetry item.
@@ -75,7 +299,7 @@ impl TelemetryClient {
     ///
     /// ```rust
     /// # use appinsights::TelemetryClient;
-    /// let mut client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
     /// client.context_mut().tags_mut().cloud_mut().set_role("rust_server".to_string());
     ///
     /// assert_eq!(client.context().tags().cloud().role(), Some("rust_server"));
@@ -97,8 +321,76 @@ impl TelemetryClient {
     /// assert_eq!(client.context().tags().get("app_version"), Some(&"v0.1.1".to_string()));
     /// assert_eq!(client.context().properties().get("Resource Group"), Some(&"my-rg".to_string()));
     /// ```
-    pub fn context_mut(&mut self) -> &mut TelemetryContext {
-        &mut self.context
+    pub fn context_mut(&mut self) -> impl DerefMut<Target = TelemetryContext> + '_ {
+        ContextMut(self)
+    }
+
+    /// Adds custom properties attached to every telemetry item tracked by this client, e.g. to tag items
+    /// emitted by a component with its name. It is a shortcut for inserting properties into the
+    /// [`context`](#method.context_mut), so a clone of the client can be tagged without affecting
+    /// the others. Properties set on a telemetry item take precedence over the ones added here.
+    ///
+    /// Clones share the channel, so clones tagged for every subsystem submit their telemetry with the
+    /// same worker and HTTP client as the original one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let auth = client.clone().with_default_properties([("subsystem", "auth")]);
+    /// let payments = client
+    ///     .clone()
+    ///     .with_default_properties([("subsystem", "payment"), ("region", "us-east-1")]);
+    /// auth.track_event("user signed in");
+    /// payments.track_event("payment accepted");
+    /// ```
+    pub fn with_default_properties<K, V>(mut self, properties: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (key, value) in properties {
+            self.context.properties_mut().insert(key.into(), value.into());
+        }
+        self.share();
+        self
+    }
+
+    /// Appends a telemetry processor to the end of processors chain. Every telemetry item tracked
+    /// by this client passes through all processors in the order they were added before it is queued
+    /// for submission.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::{ProcessResult, TelemetryClient, TelemetryItem};
+    /// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+    /// client.add_processor(|item: &mut TelemetryItem| {
+    ///     item.tags_mut().insert("ai.cloud.role".to_string(), "rust_server".to_string());
+    ///     ProcessResult::Continue
+    /// });
+    /// ```
+    pub fn add_processor(&mut self, processor: impl TelemetryProcessor + 'static) {
+        self.pipeline.add_processor(processor);
+        self.share();
+    }
+
+    /// Replaces a fixed sampling rate configured by
+    /// [`sampling_rate`](struct.TelemetryConfigBuilder.html#method.sampling_rate) with a custom
+    /// sampling strategy. Telemetry items pass through the strategy after all processors.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::{AdaptiveSampling, TelemetryClient};
+    /// let client = TelemetryClient::new("<instrumentation key>".to_string())
+    ///     .with_sampling_strategy(Box::new(AdaptiveSampling::new(5)));
+    /// ```
+    pub fn with_sampling_strategy(mut self, strategy: Box<dyn SamplingStrategy>) -> Self {
+        self.pipeline.set_sampling_strategy(strategy);
+        self.share();
+        self
     }
 
     /// Logs a user action with the specified name.
@@ -115,6 +407,38 @@ impl TelemetryClient {
         self.track(event)
     }
 
+    /// Logs a user action with the specified name, custom properties and measurements. Measurements
+    /// that are `NaN` or infinite are discarded with a warning.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// client.track_event_with_measurements("Purchase", [("product", "Widget")], [("amount", 9.99)]);
+    /// ```
+    pub fn track_event_with_measurements<K, V, M>(
+        &self,
+        name: impl Into<String>,
+        properties: impl IntoIterator<Item = (K, V)>,
+        measurements: impl IntoIterator<Item = (M, f64)>,
+    ) where
+        K: Into<String>,
+        V: Into<String>,
+        M: Into<String>,
+    {
+        let mut event = EventTelemetry::new(name);
+        for (key, value) in properties {
+            event.properties_mut().insert(key.into(), value.into());
+        }
+        for (key, value) in measurements {
+            if let Err(err) = event.measurements_mut().insert(key, value) {
+                log::warn!("Discarding {}", err);
+            }
+        }
+        self.track(event)
+    }
+
     /// Logs a trace message with a specified severity level.
     ///
     /// # Examples
@@ -130,180 +454,887 @@ impl TelemetryClient {
         self.track(event)
     }
 
-    /// Logs a numeric value that is not specified with a specific event.
-    /// Typically used to send regular reports of performance indicators.
+    /// Logs an error with its chain of sources.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
-    /// # use appinsights::telemetry::SeverityLevel;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// client.track_metric("gateway_latency_ms", 113.0);
-    /// ```    
-    pub fn track_metric(&self, name: impl Into<String>, value: f64) {
-        let event = MetricTelemetry::new(name, value);
+    /// if let Err(err) = "not a number".parse::<i32>() {
+    ///     client.track_exception(&err);
+    /// }
+    /// ```
+    pub fn track_exception(&self, error: &dyn std::error::Error) {
+        let event = ExceptionTelemetry::from_std_error(error);
         self.track(event)
     }
 
-    /// Logs a HTTP request with the specified method, URL, duration and response code.
+    /// Logs an error with its chain of causes as an exception that belongs to the specified operation,
+    /// e.g. to correlate it with a failed dependency call.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// use http::{Method, Uri};
-    /// use std::time::Duration;
+    /// use appinsights::telemetry::OperationContext;
     ///
-    /// let uri: Uri = "https://api.github.com/dmolokanov/appinsights-rs".parse().unwrap();
-    /// client.track_request(Method::GET, uri, Duration::from_millis(100), "200");
+    /// let operation = OperationContext::new_root();
+    /// if let Err(err) = "not a number".parse::<i32>() {
+    ///     client.track_exception_with_context(&err, &operation);
+    /// }
     /// ```
-    pub fn track_request(&self, method: Method, uri: Uri, duration: Duration, response_code: impl Into<String>) {
-        let event = RequestTelemetry::new(method, uri, duration, response_code);
+    pub fn track_exception_with_context(&self, error: &dyn std::error::Error, operation: &OperationContext) {
+        let mut event = ExceptionTelemetry::from_std_error(error);
+        event.set_operation(operation);
         self.track(event)
     }
 
-    /// Logs a dependency with the specified name, type, target, and success status.
+    /// Logs a numeric value that is not specified with a specific event.
+    /// Typically used to send regular reports of performance indicators.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
+    /// # use appinsights::telemetry::SeverityLevel;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// client.track_remote_dependency(
-    ///     "GET https://api.github.com/dmolokanov/appinsights-rs",
-    ///     "HTTP",
-    ///     "api.github.com",
-    ///     true
-    /// );
-    /// ```
-    pub fn track_remote_dependency(
-        &self,
-        name: impl Into<String>,
-        dependency_type: impl Into<String>,
-        target: impl Into<String>,
-        success: bool,
-    ) {
-        let event = RemoteDependencyTelemetry::new(name, dependency_type, Default::default(), target, success);
+    /// client.track_metric("gateway_latency_ms", 113.0);
+    /// ```    
+    pub fn track_metric(&self, name: impl Into<String>, value: f64) {
+        let event = MetricTelemetry::new(name, value);
         self.track(event)
     }
 
-    /// Logs an availability test result with the specified test name, duration, and success status.
+    /// Logs a duration as a metric value in milliseconds, e.g. to report how long an operation took
+    /// without converting the duration by hand.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// use std::time::Duration;
+    /// use std::time::Instant;
     ///
-    /// client.track_availability(
-    ///     "GET https://api.github.com/dmolokanov/appinsights-rs",
-    ///     Duration::from_millis(100),
-    ///     true
-    /// );
+    /// let started = Instant::now();
+    /// // ... run the code to measure
+    /// client.track_metric_timing("cache_refresh_ms", started.elapsed());
     /// ```
-    pub fn track_availability(&self, name: impl Into<String>, duration: Duration, success: bool) {
-        let event = AvailabilityTelemetry::new(name, duration, success);
-        self.track(event)
+    pub fn track_metric_timing(&self, name: impl Into<String>, duration: Duration) {
+        self.track_metric(name, duration.as_secs_f64() * 1000.0)
     }
 
-    /// Submits a specific telemetry event.
+    /// Records a numeric value of a metric with the specified name. Unlike
+    /// [`track_metric`](#method.track_metric) values are not submitted one by one but accumulated
+    /// with an [`aggregator`](#method.aggregator) and submitted once per aggregation window.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
+    /// # async fn run() {
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// use appinsights::telemetry::AggregateMetricTelemetry;
-    ///
-    /// let mut telemetry = AggregateMetricTelemetry::new("device_message_latency_per_min");
-    /// telemetry.stats_mut().add_data(&[113.0, 250.0, 316.0]);
-    ///
-    /// client.track(telemetry);
+    /// client.track_aggregated_metric("request_latency_ms", 42.0);
+    /// # }
     /// ```
-    pub fn track<E>(&self, event: E)
-    where
-        E: Telemetry,
-        (TelemetryContext, E): Into<Envelope>,
-    {
+    pub fn track_aggregated_metric(&self, name: &str, value: f64) {
         if self.is_enabled() {
-            let envelop = (self.context.clone(), event).into();
-            self.channel.send(envelop);
+            self.aggregator().record(name, value);
         }
     }
 
-    /// Forces all pending telemetry items to be submitted. The current task will not be blocked.
+    /// Returns an aggregator that accumulates metric values and submits them as aggregated metric
+    /// telemetry items at the end of each [aggregation window](struct.TelemetryConfigBuilder.html#method.aggregation_window).
+    /// Aggregated metrics are also submitted whenever the channel is flushed or closed. The aggregator
+    /// is shared between clones of the client and submits aggregated metrics with context, processors
+    /// and enabled flag of the client it was accessed with for the first time.
+    ///
+    /// Periodic submission starts when an aggregator is accessed within a Tokio runtime. Otherwise
+    /// aggregated metrics are only submitted with the next flush.
+    pub fn aggregator(&self) -> &MetricsAggregator {
+        if let Ok(runtime) = Handle::try_current() {
+            if self.aggregator.start() {
+                self.spawn_periodic(&runtime, self.aggregator.window(), TelemetryClient::track_aggregated);
+            }
+        }
+
+        &self.aggregator
+    }
+
+    /// Shares an enabled flag, processors and context of this client with tasks it spawned.
+    fn share(&mut self) {
+        let mut shared = self.shared.write().unwrap();
+        shared.enabled = self.enabled;
+        shared.pipeline = self.pipeline.clone();
+        shared.context = self.context.clone();
+    }
+
+    /// Spawns a task that runs an action with a copy of this client at specified interval. Each time
+    /// the task runs, the copy takes an enabled flag, processors and context this client has at the
+    /// moment. The task stops as soon as all clones of the client are dropped.
+    fn spawn_periodic<F>(&self, runtime: &Handle, interval: Duration, mut action: F)
+    where
+        F: FnMut(&TelemetryClient) + Send + 'static,
+    {
+        let aggregator = Arc::downgrade(&self.aggregator);
+        let channel = Arc::downgrade(&self.channel);
+        let shared = self.shared.clone();
+        let (limiter, live_metrics) = (self.limiter.clone(), self.live_metrics.clone());
+
+        runtime.spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let client = match (aggregator.upgrade(), channel.upgrade()) {
+                    (Some(aggregator), Some(channel)) => {
+                        let SharedState {
+                            enabled,
+                            pipeline,
+                            context,
+                        } = shared.read().unwrap().clone();
+                        TelemetryClient {
+                            enabled,
+                            pipeline,
+                            context,
+                            shared: shared.clone(),
+                            channel,
+                            aggregator,
+                            limiter: limiter.clone(),
+                            live_metrics: live_metrics.clone(),
+                        }
+                    }
+                    _ => break,
+                };
+                action(&client);
+            }
+        });
+    }
+
+    /// Installs a process-wide panic hook that tracks every panic as a critical
+    /// [`ExceptionTelemetry`](telemetry/struct.ExceptionTelemetry.html) and waits up to 5 seconds for
+    /// pending telemetry items to be submitted before the panic unwinds. A previously installed hook is
+    /// called first, so the panic message is still printed. Waiting is skipped when the hook is installed
+    /// outside of a Tokio runtime or the panic happens on a thread that drives a current-thread runtime.
+    ///
+    /// The previous hook is restored when the returned guard is dropped.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
-    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// let mut counter = 0;
-    ///
-    /// // send heartbeats while application is running
-    /// let running = true;
-    /// while running {
-    ///     client.track_event("app is running");
-    ///     counter += 1;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let _guard = client.install_panic_hook();
     ///
-    ///     // if the rate is bigger than submission interval you can make sure that data is
-    ///     // triggered for submission (each 100 items)
-    ///     if counter == 100 {
-    ///         // trigger submission of all pending items
-    ///         client.flush_channel();
-    ///         counter = 0;
-    ///     }
-    /// }
+    /// // panic is submitted to the server before the application exits
+    /// panic!("unrecoverable state");
+    /// # }
     /// ```
-    pub fn flush_channel(&self) {
-        self.channel.flush();
+    pub fn install_panic_hook(&self) -> PanicHookGuard {
+        let previous: Arc<PanicHook> = Arc::from(panic::take_hook());
+
+        let client = self.clone();
+        let runtime = Handle::try_current().ok();
+        let chained = previous.clone();
+        panic::set_hook(Box::new(move |info| {
+            chained(info);
+            client.track(ExceptionTelemetry::from_panic(info));
+            client.flush_before_exit(runtime.as_ref());
+        }));
+
+        PanicHookGuard { previous }
     }
 
-    /// Flushes and tears down the submission flow and closes internal channels.
-    /// It blocks the current task until all pending telemetry items have been submitted and it is safe to
-    /// shutdown without losing telemetry.
-    /// This method consumes the value of client so it makes impossible to use a client with close
-    /// channel.
+    /// Forces all pending telemetry items to be submitted and waits for the server to acknowledge them
+    /// when the current thread can be blocked.
+    fn flush_before_exit(&self, runtime: Option<&Handle>) {
+        match runtime {
+            Some(runtime) if channel::can_block() => {
+                let receiver = self.channel.flush_and_wait();
+
+                let (sender, done) = mpsc::channel();
+                runtime.spawn(async move {
+                    let _ = sender.send(channel::wait_flushed(receiver, Some(PANIC_FLUSH_TIMEOUT)).await);
+                });
+                let _ = channel::block(|| done.recv_timeout(PANIC_FLUSH_TIMEOUT));
+            }
+            _ => self.channel.flush(),
+        }
+    }
+
+    /// Returns the number of telemetry items waiting to be submitted.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// // send heartbeats while application is running
-    /// let running = true;
-    /// while running {
-    ///     client.track_event("app is running");
+    /// if client.pending_count() > 10_000 {
+    ///     eprintln!("telemetry is not submitted fast enough");
     /// }
-    ///
-    /// // wait until pending telemetry is sent at most once and tear down submission flow
-    /// client.close_channel().await;
-    ///
-    /// // unable to sent any telemetry after client closes its channel
-    /// // client.track_event("app is stopped".to_string());
     /// ```
-    pub async fn close_channel(mut self) {
-        self.channel.close().await;
+    pub fn pending_count(&self) -> usize {
+        self.channel.pending_count()
     }
 
-    /// Tears down the submission flow and closes internal channels.
-    /// Any telemetry waiting to be sent is discarded. This is a more abrupt version of [`close_channel`](#method.close_channel).
-    /// This method consumes the value of client so it makes impossible to use a client with close
-    /// channel.
-    ///
-    /// This method should be used in cases when the client should be stopped. It is a separate function until
-    /// `async_drop` is implemented in rust.
+    /// Returns the total number of telemetry items discarded without being submitted, e.g. when too
+    /// many items are pending, retries are exhausted or the [rate limit](struct.TelemetryConfigBuilder.html#method.rate_limit)
+    /// is exceeded.
+    pub fn dropped_count(&self) -> u64 {
+        let limited = self.limiter.as_ref().map_or(0, |limiter| limiter.dropped());
+        self.channel.dropped_count() + limited
+    }
+
+    /// Returns the number of pending and dropped telemetry items at once.
+    pub fn stats(&self) -> ClientStats {
+        ClientStats::new(self.pending_count(), self.dropped_count())
+    }
+
+    /// Returns what a background worker that submits telemetry items is doing, e.g. to report whether
+    /// telemetry reaches the server.
+    pub fn status(&self) -> ClientStatus {
+        self.channel.status()
+    }
+
+    /// Returns `false` when a background worker that submits telemetry items did not answer the last
+    /// periodic health check. Such a worker is restarted with the same configuration, so telemetry
+    /// tracked afterwards is submitted again.
+    pub fn is_worker_alive(&self) -> bool {
+        self.channel.is_worker_alive()
+    }
+
+    /// Returns an identifier the server assigned to the last batch of telemetry items it accepted, taken
+    /// from the `x-ms-request-id` response header. Include it in a support request when telemetry does
+    /// not show up in the portal.
+    pub fn last_request_id(&self) -> Option<String> {
+        self.channel.last_request_id()
+    }
+
+    /// Waits until a background worker finishes submitting pending telemetry items, but not longer than
+    /// specified timeout. Returns `true` when the worker is running afterwards, or `false` when the last
+    /// submission failed, the channel is stopping or the timeout expired. It is useful for tests that
+    /// need a client to be ready before sending telemetry.
+    pub async fn wait_until_running(&self, timeout: Duration) -> bool {
+        let running = async {
+            loop {
+                match self.status() {
+                    ClientStatus::Flushing => tokio::time::sleep(STATUS_POLL_INTERVAL).await,
+                    status => return status == ClientStatus::Running,
+                }
+            }
+        };
+        tokio::time::timeout(timeout, running).await.unwrap_or(false)
+    }
+
+    /// Passes telemetry items held by the rate limiter to the channel and reports the number of
+    /// items it discarded.
+    fn release_limited(&self) {
+        if let Some(limiter) = &self.limiter {
+            for item in limiter.release() {
+                self.channel.send(item);
+            }
+
+            // the report itself is never held, otherwise it would be lost when the limiter is busy
+            let dropped = limiter.take_dropped();
+            if dropped > 0 {
+                if let Some(item) = self.prepare(MetricTelemetry::new(DROPPED_ITEMS, dropped as f64)) {
+                    self.channel.send(item);
+                }
+            }
+        }
+    }
+
+    /// Submits metrics accumulated by the aggregator so far.
+    fn track_aggregated(&self) {
+        self.aggregator.flush_to_client(self);
+    }
+
+    /// Logs a HTTP request with the specified method, URL, duration and response code.
     ///
     /// # Examples
     ///
     /// ```rust, no_run
     /// # use appinsights::TelemetryClient;
     /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
-    /// // send heartbeats while application is running
-    /// let running = true;
+    /// use http::{Method, Uri};
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// let uri: Uri = "https://api.github.com/dmolokanov/appinsights-rs".parse().unwrap();
+    /// client.track_request(Method::GET, uri, Duration::from_millis(100), "200");
+    /// ```
+    pub fn track_request(&self, method: Method, uri: Uri, duration: Duration, response_code: impl Into<String>) {
+        let event = RequestTelemetry::new(method, uri, duration, response_code);
+        self.track(event)
+    }
+
+    /// Logs a HTTP request served by the application with the method and URL of the request, the status
+    /// code of the response and the specified duration. Success is inferred from the status code the
+    /// same way as for [`RequestTelemetry`](telemetry/struct.RequestTelemetry.html#method.is_success).
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use http::{Request, Response};
+    /// use std::time::Duration;
+    ///
+    /// let request = Request::get("https://example.com/orders").body(()).unwrap();
+    /// let response = Response::builder().status(201).body(()).unwrap();
+    /// client.track_request_from_http(&request, &response, Duration::from_millis(100));
+    /// ```
+    pub fn track_request_from_http<B, C>(
+        &self,
+        request: &http::Request<B>,
+        response: &http::Response<C>,
+        duration: Duration,
+    ) {
+        self.track_request(
+            request.method().clone(),
+            request.uri().clone(),
+            duration,
+            response.status().as_str(),
+        )
+    }
+
+    /// Runs a closure that serves a request and logs the request with the duration of the call. The
+    /// closure returns its result together with a response code and an indication of success of the
+    /// request. Returns the result of the closure.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// # fn load_orders() -> Result<Vec<u32>, std::io::Error> { Ok(vec![42]) }
+    /// use http::{Method, Uri};
+    ///
+    /// let orders = client.observe_request(Method::GET, Uri::from_static("https://example.com/orders"), || {
+    ///     let orders = load_orders();
+    ///     let (code, success) = if orders.is_ok() { ("200", true) } else { ("500", false) };
+    ///     (orders, code, success)
+    /// });
+    /// ```
+    pub fn observe_request<R, S>(&self, method: Method, uri: Uri, f: impl FnOnce() -> (R, S, bool)) -> R
+    where
+        S: Into<String>,
+    {
+        let request = RequestTelemetry::start(method, uri);
+        let (result, response_code, success) = f();
+        self.track(request.stop(response_code, success));
+        result
+    }
+
+    /// Awaits a future that serves a request and logs the request with the duration until the future
+    /// completed. The future resolves to its result together with a response code and an indication of
+    /// success of the request. Returns the result of the future.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # async fn run(client: TelemetryClient) {
+    /// # async fn load_orders() -> Result<Vec<u32>, std::io::Error> { Ok(vec![42]) }
+    /// use http::{Method, Uri};
+    ///
+    /// let uri = Uri::from_static("https://example.com/orders");
+    /// let orders = client
+    ///     .async_observe_request(Method::GET, uri, async {
+    ///         let orders = load_orders().await;
+    ///         let (code, success) = if orders.is_ok() { ("200", true) } else { ("500", false) };
+    ///         (orders, code, success)
+    ///     })
+    ///     .await;
+    /// # }
+    /// ```
+    pub async fn async_observe_request<R, S, F>(&self, method: Method, uri: Uri, future: F) -> R
+    where
+        S: Into<String>,
+        F: Future<Output = (R, S, bool)>,
+    {
+        let request = RequestTelemetry::start(method, uri);
+        let (result, response_code, success) = future.await;
+        self.track(request.stop(response_code, success));
+        result
+    }
+
+    /// Logs a dependency with the specified name, type, target, and success status.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// client.track_remote_dependency(
+    ///     "GET https://api.github.com/dmolokanov/appinsights-rs",
+    ///     "HTTP",
+    ///     "api.github.com",
+    ///     true
+    /// );
+    /// ```
+    pub fn track_remote_dependency(
+        &self,
+        name: impl Into<String>,
+        dependency_type: impl Into<String>,
+        target: impl Into<String>,
+        success: bool,
+    ) {
+        let event = RemoteDependencyTelemetry::new(name, dependency_type, Default::default(), target, success);
+        self.track(event)
+    }
+
+    /// Logs a HTTP dependency call made with `reqwest` once its response is received. A response does not
+    /// carry the method of the request, so the dependency is named after the URL path. The target is a
+    /// host of the URL, and the call is considered successful when the status code is below `400`.
+    /// Use [`AppInsightsMiddleware`](middleware/struct.AppInsightsMiddleware.html) to track all calls of
+    /// a client together with their methods and operation context.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # async fn run(client: TelemetryClient) -> reqwest::Result<()> {
+    /// use std::time::Instant;
+    ///
+    /// let started = Instant::now();
+    /// let response = reqwest::get("https://api.github.com/repos/dmolokanov/appinsights-rs").await?;
+    /// client.track_dependency_from_reqwest(&response, started.elapsed());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn track_dependency_from_reqwest(&self, response: &reqwest::Response, duration: Duration) {
+        let url = response.url();
+        let status = response.status();
+
+        let mut telemetry = RemoteDependencyTelemetry::new(
+            url.path(),
+            "HTTP",
+            duration,
+            http_target(url),
+            status < http::StatusCode::BAD_REQUEST,
+        );
+        telemetry.set_data(url.as_str());
+        telemetry.set_result_code(status.as_str());
+        self.track(telemetry)
+    }
+
+    /// Starts measuring a dependency call with the specified name and type. The returned guard tracks the
+    /// dependency with the elapsed duration when it is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// {
+    ///     let _span = client.measure_dependency("process orders", "InProc");
+    ///     // ... run the code to measure
+    /// }
+    /// ```
+    pub fn measure_dependency(&self, name: &str, dependency_type: &str) -> DependencySpan<'_> {
+        DependencySpan::new(self, name, dependency_type)
+    }
+
+    /// Starts measuring a dependency call with the specified name and type. Unlike
+    /// [`measure_dependency`](#method.measure_dependency), the returned timer does not borrow the client
+    /// and can be sent to another thread. It produces a telemetry item to track once stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let timer = client.start_dependency_timer("process orders", "InProc");
+    /// // ... run the code to measure
+    /// client.track(timer.stop(true, "0"));
+    /// ```
+    pub fn start_dependency_timer(&self, name: &str, dependency_type: &str) -> DependencyTimer {
+        DependencyTimer::new(name, dependency_type)
+    }
+
+    /// Stops a dependency timer with an outcome of the measured call and tracks the dependency. An `Ok`
+    /// result is tracked as a successful call with `0` result code, while an `Err` is tracked as a failed
+    /// call with the error message as a result code. The result is returned unchanged, so failures are
+    /// never left unreported on an early return.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let timer = client.start_dependency_timer("read settings", "FileSystem");
+    /// let settings = client.record_dependency_result(timer, std::fs::read_to_string("settings.toml"));
+    /// ```
+    pub fn record_dependency_result<T, E>(&self, timer: DependencyTimer, result: Result<T, E>) -> Result<T, E>
+    where
+        E: Display,
+    {
+        let telemetry = match &result {
+            Ok(_) => timer.stop(true, "0"),
+            Err(err) => timer.stop(false, &err.to_string()),
+        };
+        self.track(telemetry);
+        result
+    }
+
+    /// Logs an availability test result with the specified test name, duration, and success status.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// client.track_availability(
+    ///     "GET https://api.github.com/dmolokanov/appinsights-rs",
+    ///     Duration::from_millis(100),
+    ///     true
+    /// );
+    /// ```
+    pub fn track_availability(&self, name: impl Into<String>, duration: Duration, success: bool) {
+        let event = AvailabilityTelemetry::new(name, duration, success);
+        self.track(event)
+    }
+
+    /// Submits a specific telemetry event.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use appinsights::telemetry::AggregateMetricTelemetry;
+    ///
+    /// let mut telemetry = AggregateMetricTelemetry::new("device_message_latency_per_min");
+    /// telemetry.stats_mut().add_data(&[113.0, 250.0, 316.0]);
+    ///
+    /// client.track(telemetry);
+    /// ```
+    pub fn track<E>(&self, event: E)
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        if let Some(item) = self.prepare(event) {
+            self.dispatch(item);
+        }
+    }
+
+    /// Submits telemetry items of any type at once, e.g. when log files or event streams are ingested in
+    /// bulk. All items are passed to the channel together, so submission is triggered once for the whole
+    /// batch instead of once per item.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use appinsights::telemetry::{AnyTelemetry, EventTelemetry};
+    ///
+    /// let lines = vec!["started", "finished"];
+    /// client.track_batch(
+    ///     lines
+    ///         .into_iter()
+    ///         .map(|line| Box::new(EventTelemetry::new(line)) as Box<dyn AnyTelemetry>),
+    /// );
+    /// ```
+    pub fn track_batch<I>(&self, items: I)
+    where
+        I: IntoIterator<Item = Box<dyn AnyTelemetry>>,
+    {
+        let items: Vec<_> = items.into_iter().filter_map(|event| self.prepare(event)).collect();
+        let items = match &self.limiter {
+            Some(limiter) => items.into_iter().flat_map(|item| limiter.admit(item)).collect(),
+            None => items,
+        };
+
+        if !items.is_empty() {
+            self.channel.send_batch(items);
+        }
+    }
+
+    /// Submits a specific telemetry event. Returns an error when the event was rejected because
+    /// [`max_pending_items`](struct.TelemetryConfigBuilder.html#method.max_pending_items) limit is reached
+    /// and the client is configured with
+    /// [`BackpressurePolicy::ReturnError`](enum.BackpressurePolicy.html#variant.ReturnError).
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use appinsights::telemetry::EventTelemetry;
+    ///
+    /// if let Err(err) = client.try_track(EventTelemetry::new("app is running")) {
+    ///     eprintln!("telemetry is not submitted: {}", err);
+    /// }
+    /// ```
+    pub fn try_track<E>(&self, event: E) -> Result<(), BufferFullError>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        match self.prepare(event) {
+            Some(item) => self.try_dispatch(item),
+            None => Ok(()),
+        }
+    }
+
+    /// Passes a telemetry item to the channel unless it exceeds the rate limit.
+    fn dispatch(&self, item: TelemetryItem) {
+        match &self.limiter {
+            Some(limiter) => {
+                for item in limiter.admit(item) {
+                    self.channel.send(item);
+                }
+            }
+            None => self.channel.send(item),
+        }
+    }
+
+    /// Passes a telemetry item to the channel unless it exceeds the rate limit. Returns an error when
+    /// the channel rejected any of released items.
+    fn try_dispatch(&self, item: TelemetryItem) -> Result<(), BufferFullError> {
+        match &self.limiter {
+            Some(limiter) => {
+                let mut result = Ok(());
+                for item in limiter.admit(item) {
+                    if let Err(err) = self.channel.try_send(item) {
+                        result = Err(err);
+                    }
+                }
+                result
+            }
+            None => self.channel.try_send(item),
+        }
+    }
+
+    /// Converts a telemetry event to a telemetry item and passes it through processors. Returns nothing
+    /// when the client is disabled or the event is filtered out.
+    fn prepare<E>(&self, event: E) -> Option<TelemetryItem>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        self.prepare_with(event, false)
+    }
+
+    /// Converts a telemetry event to a telemetry item like [`prepare`](#method.prepare) does, but never
+    /// samples it out when `never_sample` is set.
+    fn prepare_with<E>(&self, event: E, never_sample: bool) -> Option<TelemetryItem>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        if !self.is_enabled() || !self.pipeline.accepts(&event) {
+            return None;
+        }
+
+        time::check_timestamp(event.timestamp());
+
+        // telemetry without an operation of its own belongs to the ambient one
+        let mut event = event;
+        if let Some(operation) = context::current() {
+            if event.tags().operation().id().is_none() {
+                event.set_operation(&operation);
+            }
+        }
+
+        let never_sample = never_sample || event.never_sample();
+        let envelop = (self.context.next(), event).into();
+        self.process(envelop, never_sample)
+    }
+
+    /// Submits a raw envelope, e.g. telemetry of a custom schema or received from a non-Rust component.
+    /// The envelope is submitted with the instrumentation key of the client unless it specifies its own
+    /// one, and passes through processors and sampling like any other telemetry item. Context tags of
+    /// the client are not applied. In debug builds every violation of the schema the server expects is
+    /// logged as a warning.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use appinsights::schema::{Base, Data, Envelope, EventData};
+    ///
+    /// client.track_envelope(Envelope {
+    ///     name: "Microsoft.ApplicationInsights.Event".into(),
+    ///     time: "2019-01-02T03:04:05.678Z".into(),
+    ///     data: Some(Base::Data(Data::EventData(EventData {
+    ///         name: "job completed".into(),
+    ///         ..EventData::default()
+    ///     }))),
+    ///     ..Envelope::default()
+    /// });
+    /// ```
+    pub fn track_envelope(&self, envelope: Envelope) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        for err in crate::validation::validate_envelope(&envelope) {
+            log::warn!("Envelope {} violates the schema: {}", envelope.name, err);
+        }
+
+        if let Some(item) = self.process(envelope, false) {
+            self.dispatch(item);
+        }
+    }
+
+    /// Passes an envelope through processors and sampling. Live metrics account every envelope
+    /// before it is sampled.
+    fn process(&self, mut envelope: Envelope, never_sample: bool) -> Option<TelemetryItem> {
+        if envelope.i_key.is_none() {
+            envelope.i_key = Some(self.context.i_key.clone());
+        }
+        if let Some(live_metrics) = &self.live_metrics {
+            live_metrics.record(&envelope);
+        }
+        self.pipeline.process(envelope, never_sample)
+    }
+
+    /// Forces all pending telemetry items to be submitted. The current task will not be blocked.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let mut counter = 0;
+    ///
+    /// // send heartbeats while application is running
+    /// let running = true;
+    /// while running {
+    ///     client.track_event("app is running");
+    ///     counter += 1;
+    ///
+    ///     // if the rate is bigger than submission interval you can make sure that data is
+    ///     // triggered for submission (each 100 items)
+    ///     if counter == 100 {
+    ///         // trigger submission of all pending items
+    ///         client.flush_channel();
+    ///         counter = 0;
+    ///     }
+    /// }
+    /// ```
+    pub fn flush_channel(&self) {
+        self.track_aggregated();
+        self.channel.flush();
+    }
+
+    /// Forces all pending telemetry items to be submitted and waits until the server acknowledges them.
+    /// When a timeout is specified it returns [`FlushError::Timeout`](enum.FlushError.html) if items
+    /// were not acknowledged in time and [`FlushError::Transport`](enum.FlushError.html) if the server
+    /// rejected any of them. Unlike [`close_channel`](#method.close_channel) the client remains usable
+    /// afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # use std::{sync::Arc, time::Duration};
+    /// # async fn run() {
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// client.track_event("job completed");
+    ///
+    /// // make sure the event is delivered before a short-living process exits
+    /// if let Err(err) = client.flush(Some(Duration::from_secs(5))).await {
+    ///     eprintln!("telemetry was not submitted: {}", err);
+    /// }
+    /// # }
+    /// ```
+    pub async fn flush(&self, timeout: Option<Duration>) -> Result<(), FlushError> {
+        self.track_aggregated();
+        channel::wait_flushed(self.channel.flush_and_wait(), timeout).await
+    }
+
+    /// Submits a telemetry item without waiting for the next submission interval and waits until the
+    /// server acknowledges it, e.g. to report a fatal error right before a process exits. The item goes
+    /// through processors, but it is never sampled out nor held by the rate limiter, although it counts
+    /// toward the rate. Items pending in the channel are not affected. Returns
+    /// [`SendError::Discarded`](enum.SendError.html#variant.Discarded) when the item was not submitted,
+    /// e.g. because the client is disabled or a processor dropped it, or an error the submission failed
+    /// with otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::{telemetry::{SeverityLevel, TraceTelemetry}, TelemetryClient};
+    /// # use std::time::Duration;
+    /// # async fn run() {
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let trace = TraceTelemetry::new("configuration is corrupted", SeverityLevel::Critical);
+    /// if let Err(err) = client.send_immediately(trace, Some(Duration::from_secs(5))).await {
+    ///     eprintln!("telemetry was not submitted: {}", err);
+    /// }
+    /// # }
+    /// ```
+    pub async fn send_immediately<E>(&self, event: E, timeout: Option<Duration>) -> Result<(), SendError>
+    where
+        E: Telemetry,
+        (TelemetryContext, E): Into<Envelope>,
+    {
+        let item = self.prepare_with(event, true).ok_or(SendError::Discarded)?;
+        if let Some(limiter) = &self.limiter {
+            limiter.charge();
+        }
+
+        channel::send_immediately(&*self.channel, item, timeout).await
+    }
+
+    /// Flushes and tears down the submission flow and closes internal channels.
+    /// It blocks the current task until all pending telemetry items have been submitted and it is safe to
+    /// shutdown without losing telemetry.
+    /// This method consumes the value of client so it makes impossible to use a client with close
+    /// channel. Clones of the client share the channel, so telemetry they track afterwards is discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// // send heartbeats while application is running
+    /// let running = true;
+    /// while running {
+    ///     client.track_event("app is running");
+    /// }
+    ///
+    /// // wait until pending telemetry is sent at most once and tear down submission flow
+    /// client.close_channel().await;
+    ///
+    /// // unable to sent any telemetry after client closes its channel
+    /// // client.track_event("app is stopped".to_string());
+    /// ```
+    pub async fn close_channel(self) {
+        self.track_aggregated();
+        if let Some(limiter) = &self.limiter {
+            for item in limiter.drain() {
+                self.channel.send(item);
+            }
+        }
+        self.channel.close().await;
+    }
+
+    /// Tears down the submission flow and closes internal channels.
+    /// Any telemetry waiting to be sent is discarded. This is a more abrupt version of [`close_channel`](#method.close_channel).
+    /// This method consumes the value of client so it makes impossible to use a client with close
+    /// channel. Clones of the client share the channel, so telemetry they track afterwards is discarded.
+    ///
+    /// This method should be used in cases when the client should be stopped. It is a separate function until
+    /// `async_drop` is implemented in rust.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// // send heartbeats while application is running
+    /// let running = true;
     /// while running {
     ///     client.track_event("app is running");
     /// }
@@ -314,18 +1345,30 @@ impl TelemetryClient {
     /// // unable to sent any telemetry after client closes its channel
     /// // client.track_event("app is stopped".to_string());
     /// ```
-    pub async fn terminate(mut self) {
+    pub async fn terminate(self) {
         self.channel.terminate().await;
     }
 }
 
+/// Restores a panic hook that was installed before
+/// [`install_panic_hook`](struct.TelemetryClient.html#method.install_panic_hook) when dropped.
+#[must_use = "the panic hook is uninstalled as soon as the guard is dropped"]
+pub struct PanicHookGuard {
+    previous: Arc<PanicHook>,
+}
+
+impl Drop for PanicHookGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.clone();
+        panic::set_hook(Box::new(move |info| previous(info)));
+    }
+}
+
 impl From<(TelemetryConfig, TelemetryContext)> for TelemetryClient {
     fn from((config, context): (TelemetryConfig, TelemetryContext)) -> Self {
-        Self {
-            enabled: true,
-            context,
-            channel: Box::new(InMemoryChannel::new(&config)),
-        }
+        let mut client = Self::from_config(config);
+        *client.context_mut() = context;
+        client
     }
 }
 
@@ -336,20 +1379,26 @@ pub(crate) mod tests {
     use async_trait::async_trait;
     use chrono::{DateTime, Utc};
     use crossbeam_queue::SegQueue;
+    use futures_channel::oneshot;
     use matches::assert_matches;
+    use test_case::test_case;
 
     use super::*;
-    use crate::telemetry::{ContextTags, Properties};
+    use crate::{
+        contracts::{Base, Data},
+        telemetry::{ContextTags, OperationContext, Properties},
+        ProcessResult, TelemetryItem,
+    };
 
     #[tokio::test]
     async fn it_enabled_by_default() {
-        let client = TelemetryClient::new("key".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
         assert!(client.is_enabled())
     }
 
     #[tokio::test]
     async fn it_disables_telemetry() {
-        let mut client = TelemetryClient::new("key".into());
+        let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
 
         client.enabled(false);
 
@@ -366,6 +1415,29 @@ pub(crate) mod tests {
         assert_eq!(events.len(), 1)
     }
 
+    #[tokio::test]
+    async fn it_submits_telemetry_of_different_types_in_batch() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let items: Vec<Box<dyn AnyTelemetry>> = vec![
+            Box::new(EventTelemetry::new("event")),
+            Box::new(TraceTelemetry::new("trace", SeverityLevel::Information)),
+            Box::new(TestTelemetry {}),
+        ];
+        client.track_batch(items);
+
+        assert_eq!(events.len(), 3);
+        assert_matches!(
+            events.pop().and_then(|envelope| envelope.data),
+            Some(Base::Data(Data::EventData(_)))
+        );
+        assert_matches!(
+            events.pop().and_then(|envelope| envelope.data),
+            Some(Base::Data(Data::MessageData(_)))
+        );
+    }
+
     #[tokio::test]
     async fn it_swallows_telemetry_when_disabled() {
         let events = Arc::new(SegQueue::default());
@@ -379,28 +1451,693 @@ pub(crate) mod tests {
 
     #[tokio::test]
     async fn it_creates_client_with_default_tags() {
-        let client = TelemetryClient::new("instrumentation".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
 
         let tags = client.context().tags();
         assert_matches!(tags.internal().sdk_version(), Some(version) if version.starts_with("rust"));
         assert_matches!(tags.device().os_version(), Some(_))
     }
 
+    #[tokio::test]
+    async fn it_attaches_common_context_to_every_telemetry_item() {
+        let events = Arc::new(SegQueue::default());
+        let mut client = create_client(events.clone());
+        client
+            .context_mut()
+            .tags_mut()
+            .cloud_mut()
+            .set_role("rust_server".into());
+        client.context_mut().tags_mut().session_mut().set_id("session-1".into());
+        client
+            .context_mut()
+            .properties_mut()
+            .insert("Resource Group".into(), "my-rg".into());
+
+        client.track_event("first");
+        client.track_event("second");
+
+        assert_eq!(events.len(), 2);
+        while let Some(envelope) = events.pop() {
+            let tags = envelope.tags.expect("tags");
+            assert_eq!(tags.get("ai.cloud.role"), Some(&"rust_server".to_string()));
+            assert_eq!(tags.get("ai.session.id"), Some(&"session-1".to_string()));
+
+            let properties = match envelope.data {
+                Some(Base::Data(Data::EventData(data))) => data.properties.expect("properties"),
+                _ => panic!("unexpected telemetry data"),
+            };
+            assert_eq!(properties.get("Resource Group"), Some(&"my-rg".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_drops_telemetry_rejected_by_processor() {
+        let events = Arc::new(SegQueue::default());
+        let mut client = create_client(events.clone());
+        client.add_processor(|item: &mut TelemetryItem| {
+            if item.name() == "Microsoft.ApplicationInsights.Event" {
+                ProcessResult::Drop
+            } else {
+                ProcessResult::Continue
+            }
+        });
+
+        client.track_event("event");
+        client.track_trace("trace", SeverityLevel::Information);
+
+        assert_eq!(events.len(), 1);
+        assert_matches!(
+            events.pop().unwrap().name.as_str(),
+            "Microsoft.ApplicationInsights.Message"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_submits_timing_in_milliseconds() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        client.track_metric_timing("cache_refresh_ms", Duration::from_micros(1500));
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::MetricData(data))) => {
+                assert_eq!(data.metrics[0].name, "cache_refresh_ms");
+                assert_eq!(data.metrics[0].value, 1.5);
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_drops_sampled_out_telemetry() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .sampling_rate(0.0)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        client.track_event("event");
+        client.track_metric("metric", 42.0);
+
+        assert_eq!(events.len(), 1);
+        assert_matches!(
+            events.pop().unwrap().name.as_str(),
+            "Microsoft.ApplicationInsights.Metric"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_drops_traces_below_min_severity() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .min_trace_severity(SeverityLevel::Warning)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        client.track_trace("verbose", SeverityLevel::Verbose);
+        client.track(TraceTelemetry::new("information", SeverityLevel::Information));
+        client.track_trace("warning", SeverityLevel::Warning);
+        client.track(ExceptionTelemetry::new("Error", "message"));
+        client.set_min_trace_severity(SeverityLevel::Error);
+        client.clone().track_trace("another warning", SeverityLevel::Warning);
+        client.track_trace("error", SeverityLevel::Error);
+
+        let messages: Vec<_> = (0..events.len())
+            .map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::MessageData(data))) => data.message,
+                Some(Base::Data(Data::ExceptionData(_))) => "exception".into(),
+                data => panic!("unexpected telemetry data {:?}", data),
+            })
+            .collect();
+        assert_eq!(messages, vec!["warning", "exception", "error"]);
+    }
+
+    #[tokio::test]
+    async fn it_submits_raw_envelope_with_client_instrumentation_key() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        client.track_envelope(Envelope {
+            name: "Custom.Schema".into(),
+            ..Envelope::default()
+        });
+        client.track_envelope(Envelope {
+            i_key: Some("00000000-0000-0000-0000-000000000001".into()),
+            ..Envelope::default()
+        });
+
+        let first = events.pop().unwrap();
+        assert_eq!(first.name, "Custom.Schema");
+        assert_eq!(first.i_key, Some("00000000-0000-0000-0000-000000000000".into()));
+
+        let second = events.pop().unwrap();
+        assert_eq!(second.i_key, Some("00000000-0000-0000-0000-000000000001".into()));
+    }
+
+    #[test_case(201, true; "created")]
+    #[test_case(401, true; "unauthorized")]
+    #[test_case(503, false; "service unavailable")]
+    #[tokio::test]
+    async fn it_submits_request_from_http_request_and_response(status: u16, success: bool) {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let request = http::Request::post("https://example.com/orders?id=1").body(()).unwrap();
+        let response = http::Response::builder().status(status).body(()).unwrap();
+        client.track_request_from_http(&request, &response, Duration::from_millis(100));
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.name, Some("POST https://example.com/orders".into()));
+                assert_eq!(data.response_code, status.to_string());
+                assert_eq!(data.success, success);
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_submits_event_with_properties_and_measurements() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        client.track_event_with_measurements(
+            "Purchase",
+            [("product", "Widget")],
+            [("amount", 9.99), ("discount", f64::NAN)],
+        );
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::EventData(data))) => {
+                assert_eq!(data.name, "Purchase");
+                assert_eq!(
+                    data.properties.unwrap().get("product").map(String::as_str),
+                    Some("Widget")
+                );
+                let measurements = data.measurements.unwrap();
+                assert_eq!(measurements.get("amount"), Some(&9.99));
+                assert_eq!(measurements.len(), 1);
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_submits_observed_requests() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let uri = Uri::from_static("https://example.com/orders");
+        let orders = client.observe_request(Method::GET, uri.clone(), || (vec![42], "200", true));
+        let created = client
+            .async_observe_request(Method::POST, uri, async { (Err::<(), _>("conflict"), "409", false) })
+            .await;
+
+        assert_eq!(orders, vec![42]);
+        assert_eq!(created, Err("conflict"));
+
+        let requests: Vec<_> = (0..events.len())
+            .map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::RequestData(data))) => (data.name, data.response_code, data.success),
+                data => panic!("unexpected telemetry data {:?}", data),
+            })
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                (Some("GET https://example.com/orders".into()), "200".into(), true),
+                (Some("POST https://example.com/orders".into()), "409".into(), false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_submits_dependency_from_reqwest_response() {
+        let make_service = hyper::service::make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(hyper::service::service_fn(|_| async {
+                hyper::Response::builder()
+                    .status(http::StatusCode::SERVICE_UNAVAILABLE)
+                    .body(hyper::Body::empty())
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let url = format!("http://{}/users?id=1", addr);
+        let response = reqwest::get(&url).await.unwrap();
+        client.track_dependency_from_reqwest(&response, Duration::from_millis(100));
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.name, "/users");
+                assert_eq!(data.type_, Some("HTTP".into()));
+                assert_eq!(data.target, Some(addr.to_string()));
+                assert_eq!(data.data, Some(url));
+                assert_eq!(data.result_code, Some("503".into()));
+                assert_eq!(data.success, Some(false));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_submits_exception_attached_to_operation() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let mut operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+        operation.set_parent_id("b7ad6b7169203331");
+        let err = "not a number".parse::<i32>().unwrap_err();
+        client.track_exception_with_context(&err, &operation);
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(
+            tags.get("ai.operation.id"),
+            Some(&"0af7651916cd43dd8448eb211c80319c".into())
+        );
+        assert_eq!(tags.get("ai.operation.parentId"), Some(&"b7ad6b7169203331".into()));
+        assert_matches!(envelope.data, Some(Base::Data(Data::ExceptionData(_))));
+    }
+
+    #[tokio::test]
+    async fn it_attaches_ambient_operation_to_telemetry_without_one() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let mut operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+        operation.set_parent_id("b7ad6b7169203331");
+        context::scope(operation, async {
+            client.track_event("order placed");
+
+            let mut event = EventTelemetry::new("order shipped");
+            event.set_operation(&OperationContext::new("4bf92f3577b34da6a3ce929d0e0e4736"));
+            client.track(event);
+        })
+        .await;
+        client.track_event("order delivered");
+
+        let operation_ids: Vec<_> = (0..events.len())
+            .map(|_| events.pop().unwrap().tags.unwrap().get("ai.operation.id").cloned())
+            .collect();
+        assert_eq!(
+            operation_ids,
+            vec![
+                Some("0af7651916cd43dd8448eb211c80319c".to_string()),
+                Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_submits_dependency_when_span_is_dropped() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        {
+            let mut span = client.measure_dependency("SELECT orders", "SQL");
+            span.set_target("orders.db").set_success(false).set_result_code("1205");
+            assert!(events.is_empty());
+        }
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.name, "SELECT orders");
+                assert_eq!(data.type_, Some("SQL".into()));
+                assert_eq!(data.target, Some("orders.db".into()));
+                assert_eq!(data.result_code, Some("1205".into()));
+                assert_eq!(data.success, Some(false));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_submits_dependency_measured_by_timer_on_another_thread() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let mut timer = client.start_dependency_timer("GET /orders", "HTTP");
+        timer.set_target("api.contoso.com");
+        let telemetry = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            timer.stop(false, "503")
+        })
+        .join()
+        .unwrap();
+        client.track(telemetry);
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.name, "GET /orders");
+                assert_eq!(data.type_, Some("HTTP".into()));
+                assert_eq!(data.target, Some("api.contoso.com".into()));
+                assert_eq!(data.result_code, Some("503".into()));
+                assert_eq!(data.success, Some(false));
+                assert_ne!(data.duration, "0.00:00:00.0000000");
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_records_dependency_result() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let timer = client.start_dependency_timer("parse", "InProc");
+        let result = client.record_dependency_result(timer, "forty two".parse::<i32>());
+        assert!(result.is_err());
+
+        let timer = client.start_dependency_timer("parse", "InProc");
+        let result = client.record_dependency_result(timer, "42".parse::<i32>());
+        assert_eq!(result, Ok(42));
+
+        let outcomes: Vec<_> = (0..2)
+            .map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::RemoteDependencyData(data))) => (data.success, data.result_code),
+                data => panic!("unexpected telemetry data {:?}", data),
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (Some(false), Some("invalid digit found in string".into())),
+                (Some(true), Some("0".into()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_limits_rate_of_telemetry_items() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .rate_limit(2)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        for i in 0..5 {
+            client.track_event(format!("event {}", i));
+        }
+        assert_eq!(events.len(), 2);
+
+        client.release_limited();
+        let names: Vec<_> = (0..events.len())
+            .map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::EventData(data))) => data.name,
+                Some(Base::Data(Data::MetricData(data))) => {
+                    format!("{} {}", data.metrics[0].name, data.metrics[0].value)
+                }
+                data => panic!("unexpected telemetry data {:?}", data),
+            })
+            .collect();
+        assert_eq!(names, vec!["event 0", "event 1", "DroppedItems 1"]);
+    }
+
+    #[tokio::test]
+    async fn it_sends_telemetry_immediately_bypassing_rate_limit() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .rate_limit(1)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+        client.track_event("event 0");
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), None).await;
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(events.len(), 2);
+
+        // verify that the item counts toward the rate
+        client.track_event("event 1");
+        client.release_limited();
+        assert_eq!(events.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_sends_sampled_out_telemetry_immediately() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .sampling_rate(0.0)
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), None).await;
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_send_telemetry_immediately_when_disabled() {
+        let events = Arc::new(SegQueue::default());
+        let mut client = create_client(events.clone());
+        client.enabled(false);
+
+        let result = client.send_immediately(EventTelemetry::new("fatal"), None).await;
+
+        assert_eq!(result, Err(SendError::Discarded));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn it_tracks_panic_with_installed_hook() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let guard = client.install_panic_hook();
+        let result = panic::catch_unwind(|| panic!("unrecoverable state"));
+        drop(guard);
+
+        assert!(result.is_err());
+        let exception = (0..events.len())
+            .filter_map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::ExceptionData(data))) => Some(data.exceptions.into_iter().next().unwrap()),
+                _ => None,
+            })
+            .find(|exception| exception.message == "unrecoverable state")
+            .unwrap();
+        assert_eq!(exception.type_name, "panic");
+        assert_matches!(exception.stack, Some(stack) if stack.starts_with("at appinsights/src/client/mod.rs"));
+    }
+
+    #[tokio::test]
+    async fn it_shares_channel_between_clones() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let client = client.clone();
+                std::thread::spawn(move || client.track_event(format!("event {}", i)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        client.track_event("event");
+
+        assert_eq!(events.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_context_of_clones_independent() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        let mut other = client.clone();
+        other
+            .context_mut()
+            .properties_mut()
+            .insert("clone".into(), "yes".into());
+
+        client.track_event("event");
+        other.track_event("event");
+
+        let properties: Vec<_> = (0..2)
+            .map(|_| match events.pop().unwrap().data {
+                Some(Base::Data(Data::EventData(data))) => data.properties.unwrap_or_default(),
+                data => panic!("unexpected telemetry data {:?}", data),
+            })
+            .collect();
+        assert_eq!(properties[0].get("clone"), None);
+        assert_eq!(properties[1].get("clone"), Some(&"yes".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_attaches_default_properties_overridden_by_item_properties() {
+        let events = Arc::new(SegQueue::default());
+        let client =
+            create_client(events.clone()).with_default_properties([("component", "payments"), ("region", "us-east-1")]);
+
+        client.track_event_with_measurements("event", [("region", "eu-west-1")], Vec::<(String, f64)>::new());
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::EventData(data))) => {
+                let properties = data.properties.unwrap();
+                assert_eq!(properties.get("component"), Some(&"payments".to_string()));
+                assert_eq!(properties.get("region"), Some(&"eu-west-1".to_string()));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_submits_aggregated_metrics_when_flushed() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        for value in &[1.0, 2.0, 3.0] {
+            client.track_aggregated_metric("latency", *value);
+        }
+        assert!(events.is_empty());
+
+        client.flush_channel();
+
+        assert_eq!(events.len(), 1);
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::MetricData(data))) => {
+                assert_eq!(data.metrics[0].name, "latency");
+                assert_eq!(data.metrics[0].value, 6.0);
+                assert_eq!(data.metrics[0].count, Some(3));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_flushes_aggregated_metrics_to_client_on_demand() {
+        let events = Arc::new(SegQueue::default());
+        let client = create_client(events.clone());
+
+        client.aggregator().record("latency", 4.0);
+        client.aggregator().record("latency", 8.0);
+        let metrics = client.aggregator().flush_to_client(&client);
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].stats().value, 12.0);
+        assert_eq!(events.len(), 1);
+        assert!(client.aggregator().flush_to_client(&client).is_empty());
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_submits_aggregated_metrics_at_the_end_of_window() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .aggregation_window(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        client.track_aggregated_metric("latency", 42.0);
+
+        for _ in 0..100 {
+            if !events.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn it_discards_telemetry_without_runtime_when_noop() {
+        let client = TelemetryClient::new_noop();
+
+        client.track_event("event");
+        client.flush_channel();
+
+        assert!(client.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn it_flushes_immediately_when_noop() {
+        let client = TelemetryClient::new_noop();
+        client.track_event("event");
+
+        assert_eq!(client.flush(None).await, Ok(()));
+
+        client.close_channel().await;
+    }
+
     #[tokio::test]
     async fn it_does_not_fail_with_tokio() {
-        let client = TelemetryClient::new("instrumentation".into());
+        let client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".into());
         assert!(client.is_enabled())
     }
 
+    #[tokio::test]
+    async fn it_stops_sending_heartbeats_when_disabled() {
+        let events = Arc::new(SegQueue::new());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .heartbeat(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let mut client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!events.is_empty());
+
+        // a heartbeat task reads the enabled flag each time it runs
+        client.enabled(false);
+        while events.pop().is_some() {}
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_creates_client_with_context_and_all_features_configured() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .rate_limit(10)
+            .build()
+            .unwrap();
+        let mut context = TelemetryContext::from_config(&config);
+        context
+            .properties_mut()
+            .insert("component".into(), "data_processor".into());
+
+        let client = TelemetryClient::from((config, context));
+
+        assert!(client.limiter.is_some());
+        assert_eq!(
+            client.context().properties().get("component"),
+            Some(&"data_processor".to_string())
+        );
+    }
+
     fn create_client(events: Arc<SegQueue<Envelope>>) -> TelemetryClient {
-        let config = TelemetryConfig::new("instrumentation".into());
-        TelemetryClient::create(&config, TestChannel::new(events))
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        TelemetryClient::with_channel(&config, TestChannel::new(events))
     }
 
     pub(crate) struct TestTelemetry {}
 
     impl Telemetry for TestTelemetry {
         fn timestamp(&self) -> DateTime<Utc> {
+            time::now()
+        }
+
+        fn set_timestamp(&mut self, _: DateTime<Utc>) {
             unimplemented!()
         }
 
@@ -421,9 +2158,6 @@ pub(crate) mod tests {
         }
     }
 
-    #[derive(Clone)]
-    pub(crate) struct TestData;
-
     impl From<(TelemetryContext, TestTelemetry)> for Envelope {
         fn from((_, _): (TelemetryContext, TestTelemetry)) -> Self {
             Envelope::default()
@@ -442,21 +2176,25 @@ pub(crate) mod tests {
 
     #[async_trait]
     impl TelemetryChannel for TestChannel {
-        fn send(&self, envelop: Envelope) {
-            self.events.push(envelop);
+        fn send(&self, item: TelemetryItem) {
+            self.events.push(item.into_envelope());
         }
 
-        fn flush(&self) {
-            unimplemented!()
+        fn flush(&self) {}
+
+        fn flush_and_wait(&self) -> oneshot::Receiver<Result<(), FlushError>> {
+            let (sender, receiver) = oneshot::channel();
+            let _ = sender.send(Ok(()));
+            receiver
         }
 
-        async fn close(&mut self) {
+        async fn close(&self) {
             unimplemented!()
         }
 
-        async fn terminate(&mut self) {}
+        async fn terminate(&self) {}
     }
 }
 
 #[cfg(test)]
-mod integration_tests;
+pub(crate) mod integration_tests;
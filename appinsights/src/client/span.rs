@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    telemetry::{RemoteDependencyTelemetry, Telemetry},
+    time, TelemetryClient,
+};
+
+/// A dependency call being measured. Created by
+/// [`measure_dependency`](struct.TelemetryClient.html#method.measure_dependency), it tracks a
+/// [`RemoteDependencyTelemetry`](telemetry/struct.RemoteDependencyTelemetry.html) with duration
+/// elapsed since then as soon as it goes out of scope. The call is considered successful unless
+/// specified otherwise.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+/// # fn query() -> Result<u32, std::io::Error> { Ok(42) }
+/// let mut span = client.measure_dependency("SELECT orders", "SQL");
+/// span.set_target("orders.database.windows.net");
+///
+/// if let Err(err) = query() {
+///     span.set_success(false).set_result_code(&err.to_string());
+/// }
+/// // the dependency is tracked here
+/// ```
+#[must_use = "a dependency is tracked as soon as the span is dropped"]
+pub struct DependencySpan<'a> {
+    client: &'a TelemetryClient,
+    name: String,
+    dependency_type: String,
+    target: String,
+    result_code: Option<String>,
+    success: bool,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+}
+
+impl<'a> DependencySpan<'a> {
+    pub(crate) fn new(client: &'a TelemetryClient, name: &str, dependency_type: &str) -> Self {
+        Self {
+            client,
+            name: name.into(),
+            dependency_type: dependency_type.into(),
+            target: String::default(),
+            result_code: None,
+            success: true,
+            timestamp: time::now(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Sets an indication of successful or unsuccessful call.
+    pub fn set_success(&mut self, success: bool) -> &mut Self {
+        self.success = success;
+        self
+    }
+
+    /// Sets a result code of the call, e.g. a status code of a HTTP response or an error code of a database.
+    pub fn set_result_code(&mut self, result_code: &str) -> &mut Self {
+        self.result_code = Some(result_code.into());
+        self
+    }
+
+    /// Sets a target site of the call, e.g. a server name or a host address.
+    pub fn set_target(&mut self, target: &str) -> &mut Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Returns time elapsed since the call was started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Drop for DependencySpan<'_> {
+    fn drop(&mut self) {
+        let mut telemetry = RemoteDependencyTelemetry::new(
+            std::mem::take(&mut self.name),
+            std::mem::take(&mut self.dependency_type),
+            self.started.elapsed(),
+            std::mem::take(&mut self.target),
+            self.success,
+        );
+        if let Some(result_code) = self.result_code.take() {
+            telemetry.set_result_code(result_code);
+        }
+        telemetry.set_timestamp(self.timestamp);
+        self.client.track(telemetry);
+    }
+}
+
+/// A dependency call being measured that is not bound to a client. Created by
+/// [`start_dependency_timer`](struct.TelemetryClient.html#method.start_dependency_timer), it can be
+/// moved to another thread or task and stopped there to get a
+/// [`RemoteDependencyTelemetry`](telemetry/struct.RemoteDependencyTelemetry.html) to track.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+/// let mut timer = client.start_dependency_timer("GET /orders", "HTTP");
+/// timer.set_target("api.contoso.com");
+///
+/// let telemetry = std::thread::spawn(move || {
+///     // ... run the call to measure
+///     timer.stop(true, "200")
+/// })
+/// .join()
+/// .unwrap();
+/// client.track(telemetry);
+/// ```
+#[must_use = "a dependency is measured until the timer is stopped"]
+#[derive(Debug)]
+pub struct DependencyTimer {
+    name: String,
+    dependency_type: String,
+    target: String,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+}
+
+impl DependencyTimer {
+    pub(crate) fn new(name: &str, dependency_type: &str) -> Self {
+        Self {
+            name: name.into(),
+            dependency_type: dependency_type.into(),
+            target: String::default(),
+            timestamp: time::now(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Sets a target site of the call, e.g. a server name or a host address.
+    pub fn set_target(&mut self, target: &str) -> &mut Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Returns time elapsed since the call was started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Stops measuring the call and returns a telemetry item with duration elapsed since the timer was
+    /// started. The telemetry item time stamp is the time the timer was started at.
+    pub fn stop(self, success: bool, result_code: &str) -> RemoteDependencyTelemetry {
+        let mut telemetry = RemoteDependencyTelemetry::new(
+            self.name,
+            self.dependency_type,
+            self.started.elapsed(),
+            self.target,
+            success,
+        );
+        telemetry.set_result_code(result_code);
+        telemetry.set_timestamp(self.timestamp);
+        telemetry
+    }
+}
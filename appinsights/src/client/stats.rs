@@ -0,0 +1,60 @@
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
+};
+
+/// Describes the health of a telemetry client, e.g. to export it to a metrics system.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::{ClientStats, TelemetryClient, TelemetryConfig};
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = TelemetryConfig::builder()
+///     .i_key("<instrumentation key>")
+///     .internal_metrics(|stats: ClientStats| {
+///         println!("pending: {}, dropped: {}", stats.pending(), stats.dropped());
+///     })
+///     .build_unchecked();
+///
+/// let client = TelemetryClient::from_config(config);
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pending: usize,
+    dropped: u64,
+}
+
+impl ClientStats {
+    pub(crate) fn new(pending: usize, dropped: u64) -> Self {
+        Self { pending, dropped }
+    }
+
+    /// Returns the number of telemetry items waiting to be submitted.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns the total number of telemetry items discarded without being submitted.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// A shared reference to a callback that receives client stats kept by configuration.
+#[derive(Clone)]
+pub(crate) struct StatsCallback(pub(crate) Arc<dyn Fn(ClientStats) + Send + Sync>);
+
+impl Debug for StatsCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StatsCallback")
+    }
+}
+
+impl PartialEq for StatsCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
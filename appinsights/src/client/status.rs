@@ -0,0 +1,88 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Mutex,
+};
+
+const RUNNING: u8 = 0;
+const FLUSHING: u8 = 1;
+const STOPPING: u8 = 2;
+const ERRORED: u8 = 3;
+
+/// Describes what a background worker that submits telemetry items is doing, e.g. to report its health.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::{ClientStatus, TelemetryClient};
+/// # async fn run(client: TelemetryClient) {
+/// if let ClientStatus::Errored(reason) = client.status() {
+///     eprintln!("telemetry is not submitted: {}", reason);
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientStatus {
+    /// A worker waits for telemetry items to submit.
+    Running,
+
+    /// A worker submits pending telemetry items.
+    Flushing,
+
+    /// A channel was closed or terminated, so telemetry items are not submitted anymore.
+    Stopping,
+
+    /// The last submission failed with specified reason. A worker retries it or waits for new items.
+    Errored(String),
+}
+
+/// A status of a worker shared with a channel that reports it.
+#[derive(Debug, Default)]
+pub(crate) struct SharedStatus {
+    state: AtomicU8,
+    reason: Mutex<String>,
+}
+
+impl SharedStatus {
+    /// Returns the current status.
+    pub(crate) fn get(&self) -> ClientStatus {
+        match self.state.load(Ordering::Acquire) {
+            FLUSHING => ClientStatus::Flushing,
+            STOPPING => ClientStatus::Stopping,
+            ERRORED => ClientStatus::Errored(self.reason.lock().unwrap().clone()),
+            _ => ClientStatus::Running,
+        }
+    }
+
+    /// Updates the current status.
+    pub(crate) fn set(&self, status: ClientStatus) {
+        let state = match status {
+            ClientStatus::Running => RUNNING,
+            ClientStatus::Flushing => FLUSHING,
+            ClientStatus::Stopping => STOPPING,
+            ClientStatus::Errored(reason) => {
+                *self.reason.lock().unwrap() = reason;
+                ERRORED
+            }
+        };
+        self.state.store(state, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(ClientStatus::Running ; "running")]
+    #[test_case(ClientStatus::Flushing ; "flushing")]
+    #[test_case(ClientStatus::Stopping ; "stopping")]
+    #[test_case(ClientStatus::Errored("server is unavailable".into()) ; "errored")]
+    fn it_shares_status(status: ClientStatus) {
+        let shared = SharedStatus::default();
+
+        shared.set(status.clone());
+
+        assert_eq!(shared.get(), status);
+    }
+}
@@ -0,0 +1,346 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    channel::{BackpressurePolicy, RetryPolicy},
+    telemetry::SeverityLevel,
+    TelemetryConfig,
+};
+
+/// A representation of a telemetry configuration stored in a configuration file. All durations are
+/// in milliseconds. Settings that are missing take their default values. A custom HTTP client,
+/// transport and internal metrics callback cannot be stored and have to be set in code.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    i_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_batch_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role_instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdk_version_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sampling_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression_threshold: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    send_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregation_window: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_pending_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backpressure: Option<BackpressurePolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_trace_severity: Option<SeverityLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    heartbeat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_channel: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    live_metrics: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    live_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    perf_counters: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_pool_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_content_limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_keys: Option<Vec<String>>,
+    // tables go last, since TOML requires plain values to precede them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_policy: Option<RetryPolicyFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offline_storage: Option<OfflineStorageFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<CircuitBreakerFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adaptive_interval: Option<AdaptiveIntervalFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryPolicyFile {
+    max_attempts: u32,
+    initial_delay: u64,
+    max_delay: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OfflineStorageFile {
+    path: PathBuf,
+    max_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CircuitBreakerFile {
+    failure_threshold: u32,
+    recovery_timeout: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdaptiveIntervalFile {
+    min: u64,
+    max: u64,
+}
+
+impl From<&TelemetryConfig> for ConfigFile {
+    fn from(config: &TelemetryConfig) -> Self {
+        let retry_policy = config.retry_policy();
+        Self {
+            i_key: config.i_key().to_string(),
+            endpoint: Some(config.endpoint().into()),
+            interval: Some(millis(config.interval())),
+            max_batch_size: Some(config.max_batch_size()),
+            role_name: config.role_name().map(Into::into),
+            role_instance: config.role_instance().map(Into::into),
+            application_version: config.application_version().map(Into::into),
+            sdk_version_prefix: config.sdk_version_prefix().map(Into::into),
+            sampling_rate: Some(config.sampling_rate()),
+            compression: Some(config.compression()),
+            compression_threshold: Some(config.compression_threshold()),
+            send_timeout: Some(millis(config.send_timeout())),
+            proxy: config.proxy().map(Into::into),
+            aggregation_window: Some(millis(config.aggregation_window())),
+            max_pending_items: Some(config.max_pending_items()),
+            backpressure: Some(config.backpressure()),
+            min_trace_severity: Some(config.min_trace_severity()),
+            heartbeat: config.heartbeat().map(millis),
+            debug_channel: Some(config.debug_channel()),
+            rate_limit: config.rate_limit(),
+            live_metrics: Some(config.live_metrics()),
+            live_endpoint: Some(config.live_endpoint().into()),
+            #[cfg(feature = "perf-counters")]
+            perf_counters: config.perf_counters().map(millis),
+            #[cfg(not(feature = "perf-counters"))]
+            perf_counters: None,
+            connection_pool_size: Some(config.connection_pool_size()),
+            batch_content_limit: Some(config.batch_content_limit()),
+            correlation_keys: Some(config.correlation_keys().to_vec()),
+            retry_policy: Some(RetryPolicyFile {
+                max_attempts: retry_policy.max_attempts(),
+                initial_delay: millis(retry_policy.initial_delay()),
+                max_delay: millis(retry_policy.max_delay()),
+            }),
+            offline_storage: config.offline_storage().map(|(path, max_size)| OfflineStorageFile {
+                path: path.into(),
+                max_size,
+            }),
+            circuit_breaker: config
+                .circuit_breaker()
+                .map(|(failure_threshold, recovery_timeout)| CircuitBreakerFile {
+                    failure_threshold,
+                    recovery_timeout: millis(recovery_timeout),
+                }),
+            adaptive_interval: config.adaptive_interval().map(|(min, max)| AdaptiveIntervalFile {
+                min: millis(min),
+                max: millis(max),
+            }),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Applies all stored settings on top of default ones and validates the result.
+    fn into_config<E: de::Error>(self) -> Result<TelemetryConfig, E> {
+        let mut builder = TelemetryConfig::builder().i_key(self.i_key);
+
+        if let Some(endpoint) = self.endpoint {
+            builder.endpoint = endpoint;
+        }
+        if let Some(interval) = self.interval {
+            builder = builder.interval(Duration::from_millis(interval));
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            builder = builder.max_batch_size(max_batch_size);
+        }
+        builder.role_name = self.role_name;
+        builder.role_instance = self.role_instance;
+        builder.application_version = self.application_version;
+        builder.sdk_version_prefix = self.sdk_version_prefix;
+        if let Some(sampling_rate) = self.sampling_rate {
+            builder = builder.sampling_rate(sampling_rate);
+        }
+        if let Some(compression) = self.compression {
+            builder.compression = compression;
+        }
+        if let Some(compression_threshold) = self.compression_threshold {
+            builder.compression_threshold = compression_threshold;
+        }
+        if let Some(send_timeout) = self.send_timeout {
+            builder.send_timeout = Duration::from_millis(send_timeout);
+        }
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy).map_err(E::custom)?;
+        }
+        if let Some(aggregation_window) = self.aggregation_window {
+            builder = builder.aggregation_window(Duration::from_millis(aggregation_window));
+        }
+        if let Some(max_pending_items) = self.max_pending_items {
+            builder.max_pending_items = max_pending_items;
+        }
+        if let Some(backpressure) = self.backpressure {
+            builder.backpressure = backpressure;
+        }
+        if let Some(min_trace_severity) = self.min_trace_severity {
+            builder.min_trace_severity = min_trace_severity;
+        }
+        builder.heartbeat = self.heartbeat.map(Duration::from_millis);
+        if let Some(debug_channel) = self.debug_channel {
+            builder.debug_channel = debug_channel;
+        }
+        builder.rate_limit = self.rate_limit;
+        if let Some(live_metrics) = self.live_metrics {
+            builder.live_metrics = live_metrics;
+        }
+        if let Some(live_endpoint) = self.live_endpoint {
+            builder.live_endpoint = live_endpoint;
+        }
+        #[cfg(feature = "perf-counters")]
+        {
+            builder.perf_counters = self.perf_counters.map(Duration::from_millis);
+        }
+        if let Some(connection_pool_size) = self.connection_pool_size {
+            builder.connection_pool_size = connection_pool_size;
+        }
+        if let Some(batch_content_limit) = self.batch_content_limit {
+            builder.batch_content_limit = batch_content_limit;
+        }
+        if let Some(correlation_keys) = self.correlation_keys {
+            builder.correlation_keys = correlation_keys;
+        }
+        if let Some(retry_policy) = self.retry_policy {
+            builder.retry_policy = RetryPolicy::new(
+                retry_policy.max_attempts,
+                Duration::from_millis(retry_policy.initial_delay),
+                Duration::from_millis(retry_policy.max_delay),
+            );
+        }
+        if let Some(storage) = self.offline_storage {
+            builder = builder.offline_storage(storage.path, storage.max_size);
+        }
+        if let Some(breaker) = self.circuit_breaker {
+            builder = builder.circuit_breaker(
+                breaker.failure_threshold,
+                Duration::from_millis(breaker.recovery_timeout),
+            );
+        }
+        if let Some(adaptive) = self.adaptive_interval {
+            builder =
+                builder.adaptive_interval(Duration::from_millis(adaptive.min), Duration::from_millis(adaptive.max));
+        }
+
+        builder.build().map_err(E::custom)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+impl Serialize for TelemetryConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ConfigFile::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TelemetryConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ConfigFile::deserialize(deserializer)?.into_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_deserializes_config_with_defaults_for_missing_settings() {
+        let config: TelemetryConfig = serde_json::from_value(json!({
+            "i_key": "00000000-0000-0000-0000-000000000000",
+            "interval": 5000,
+            "backpressure": "Block",
+            "retry_policy": { "max_attempts": 2, "initial_delay": 100, "max_delay": 1000 }
+        }))
+        .unwrap();
+
+        let expected = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .interval(Duration::from_secs(5))
+            .backpressure(BackpressurePolicy::Block)
+            .retry_policy(RetryPolicy::new(2, Duration::from_millis(100), Duration::from_secs(1)))
+            .build()
+            .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn it_roundtrips_config() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .role_name("server")
+            .heartbeat(Duration::from_secs(900))
+            .offline_storage("/var/lib/appinsights", 1024)
+            .min_trace_severity(SeverityLevel::Warning)
+            .adaptive_interval(Duration::from_millis(500), Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["heartbeat"], json!(900_000));
+        assert_eq!(serde_json::from_value::<TelemetryConfig>(json).unwrap(), config);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn it_reads_config_from_toml() {
+        let config = TelemetryConfig::from_toml_str(
+            r#"
+            i_key = "00000000-0000-0000-0000-000000000000"
+            sampling_rate = 50.0
+
+            [offline_storage]
+            path = "/var/lib/appinsights"
+            max_size = 1024
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.sampling_rate(), 50.0);
+        assert_eq!(
+            config.offline_storage(),
+            Some((std::path::Path::new("/var/lib/appinsights"), 1024))
+        );
+        assert!(toml::to_string(&config).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_config_with_invalid_instrumentation_key() {
+        let err = serde_json::from_value::<TelemetryConfig>(json!({ "i_key": "foo" })).unwrap_err();
+
+        assert!(err.to_string().contains("'foo'"));
+    }
+}
@@ -1,8 +1,124 @@
+//! Contextual data attached to telemetry items: a [`TelemetryContext`](struct.TelemetryContext.html)
+//! common to all telemetry of a client, and an ambient [`OperationContext`](../telemetry/struct.OperationContext.html)
+//! of the operation being executed.
+//!
+//! An ambient operation is attached to every telemetry item tracked while it is current, unless the
+//! item has its own operation id, so it does not have to be passed through every function call. Each
+//! task gets its own operation with [`scope`](fn.scope.html), while [`set_current`](fn.set_current.html)
+//! outside of a scope makes an operation current for the calling thread in synchronous code. Within a
+//! Tokio runtime tasks share worker threads, so an operation can be made current only in a scope. A spawned
+//! task keeps the operation that was current when it was spawned with [`bind`](fn.bind.html).
+//!
+//! # Examples
+//! ```rust, no_run
+//! # use appinsights::TelemetryClient;
+//! # async fn run(client: TelemetryClient) {
+//! use appinsights::{context, telemetry::OperationContext};
+//!
+//! let operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+//! context::scope(operation, async {
+//!     // attached to the operation
+//!     client.track_event("order placed");
+//! })
+//! .await;
+//! # }
+//! ```
+use std::{
+    cell::RefCell,
+    env, fs,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use log::warn;
+use tokio::runtime::Handle;
+
 use crate::{
-    telemetry::{ContextTags, Properties},
-    TelemetryConfig,
+    telemetry::{ContextTags, ErrorPolicy, OperationContext, Properties},
+    uuid, TelemetryConfig,
 };
 
+tokio::task_local! {
+    /// An operation of the current task set by a scope.
+    static TASK_OPERATION: RefCell<Option<OperationContext>>;
+}
+
+thread_local! {
+    /// An operation of the current thread used outside of any scope.
+    static THREAD_OPERATION: RefCell<Option<OperationContext>> = const { RefCell::new(None) };
+}
+
+/// Runs a future with specified operation as current one. Nested futures spawned as separate tasks do
+/// not inherit the operation, since it is bound to the task that runs the scope.
+pub async fn scope<F: Future>(operation: OperationContext, future: F) -> F::Output {
+    TASK_OPERATION.scope(RefCell::new(Some(operation)), future).await
+}
+
+/// Binds a future to the operation that is current at the moment of the call, so it keeps reporting
+/// telemetry for that operation wherever it runs, e.g. when it is spawned as a separate task, even if
+/// the current operation changes meanwhile.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # async fn run(client: TelemetryClient) {
+/// use appinsights::context;
+///
+/// tokio::spawn(context::bind(async move {
+///     // attached to the operation of the spawning task
+///     client.track_event("report generated");
+/// }));
+/// # }
+/// ```
+pub fn bind<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    TASK_OPERATION.scope(RefCell::new(current()), future)
+}
+
+/// Makes an operation current for the rest of the enclosing [`scope`](fn.scope.html), or for the
+/// calling thread when there is none. Without a scope the call is ignored with a warning within a
+/// Tokio runtime, since an operation of a thread would leak to unrelated tasks running on it.
+pub fn set_current(operation: OperationContext) {
+    replace_current(Some(operation));
+}
+
+/// Clears the current operation of the enclosing [`scope`](fn.scope.html), or of the calling thread
+/// when there is none and the thread does not run a Tokio runtime.
+pub fn clear_current() {
+    replace_current(None);
+}
+
+/// Returns the current operation if any.
+pub fn current() -> Option<OperationContext> {
+    TASK_OPERATION
+        .try_with(|operation| operation.borrow().clone())
+        .unwrap_or_else(|_| match Handle::try_current() {
+            Ok(_) => None,
+            Err(_) => THREAD_OPERATION.with(|operation| operation.borrow().clone()),
+        })
+}
+
+fn replace_current(operation: Option<OperationContext>) {
+    let mut operation = Some(operation);
+    let scoped = TASK_OPERATION
+        .try_with(|current| *current.borrow_mut() = operation.take().flatten())
+        .is_ok();
+    if scoped {
+        return;
+    }
+
+    if Handle::try_current().is_ok() {
+        warn!("Current operation can be changed only within context::scope in a Tokio runtime");
+    } else {
+        THREAD_OPERATION.with(|current| *current.borrow_mut() = operation.flatten());
+    }
+}
+
+/// Anonymous user id generated once per process.
+static ANONYMOUS_USER_ID: OnceLock<String> = OnceLock::new();
+
 /// Encapsulates contextual data common to all telemetry submitted through a telemetry client.
 /// # Examples
 /// ```rust
@@ -30,35 +146,58 @@ pub struct TelemetryContext {
 
     // A collection of common properties to attach to telemetry event.
     pub(crate) properties: Properties,
+
+    // Determines whether the next telemetry event is the first one in a session.
+    session_start: Arc<AtomicBool>,
+
+    // Keys of correlation context entries accepted from incoming requests.
+    correlation_keys: Arc<[String]>,
+
+    // Determines what happens to properties that are too long when a telemetry item is submitted.
+    pub(crate) error_policy: ErrorPolicy,
 }
 
 impl TelemetryContext {
     /// Creates a new instance of telemetry context from config
     pub fn from_config(config: &TelemetryConfig) -> Self {
-        let i_key = config.i_key().into();
-
-        let sdk_version = format!("rust:{}", env!("CARGO_PKG_VERSION"));
-        let os_version = if cfg!(target_os = "linux") {
-            "linux"
-        } else if cfg!(target_os = "windows") {
-            "windows"
-        } else if cfg!(target_os = "macos") {
-            "macos"
-        } else {
-            "unknown"
-        };
+        let i_key = config.i_key().to_string();
+
+        let sdk_version = format!(
+            "{}rust:{}",
+            config.sdk_version_prefix().unwrap_or_default(),
+            env!("CARGO_PKG_VERSION")
+        );
 
         let mut tags = ContextTags::default();
         tags.internal_mut().set_sdk_version(sdk_version);
-        tags.device_mut().set_os_version(os_version.into());
+        tags.device_mut().set_os_version(os_version());
+        tags.user_mut().set_id(anonymous_user_id());
 
-        if let Ok(Ok(host)) = &hostname::get().map(|host| host.into_string()) {
-            tags.device_mut().set_id(host.into());
-            tags.cloud_mut().set_role_instance(host.into());
+        if let Some(locale) = locale() {
+            tags.device_mut().set_locale(locale);
+        }
+
+        if let Some(host) = host_name() {
+            tags.device_mut().set_id(host.clone());
+            tags.cloud_mut().set_role_instance(host);
+        }
+
+        if let Some(role_name) = config.role_name() {
+            tags.cloud_mut().set_role(role_name.into());
+        }
+
+        if let Some(role_instance) = config.role_instance() {
+            tags.cloud_mut().set_role_instance(role_instance.into());
+        }
+
+        if let Some(version) = config.application_version() {
+            tags.application_mut().set_version(version.into());
         }
 
         let properties = Properties::default();
-        Self::new(i_key, tags, properties)
+        let mut context = Self::new(i_key, tags, properties);
+        context.correlation_keys = config.correlation_keys().into();
+        context
     }
 
     /// Creates a new instance of telemetry context.
@@ -67,7 +206,54 @@ impl TelemetryContext {
             i_key,
             tags,
             properties,
+            session_start: Arc::default(),
+            correlation_keys: Arc::new([]),
+            error_policy: ErrorPolicy::default(),
+        }
+    }
+
+    /// Starts a new session with a random id. The first telemetry item tracked afterwards with this
+    /// context or any of its clones is marked as the session start with `ai.session.isFirst` tag.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use appinsights::{telemetry::{ContextTags, Properties}, TelemetryContext};
+    ///
+    /// let i_key = "00000000-0000-0000-0000-000000000000".to_string();
+    /// let mut context = TelemetryContext::new(i_key, ContextTags::default(), Properties::default());
+    /// context.start_session();
+    ///
+    /// assert!(context.tags().session().id().is_some());
+    /// ```
+    pub fn start_session(&mut self) {
+        self.start_session_with_id(uuid::new().to_hyphenated().to_string());
+    }
+
+    /// Starts a new session with specified id, e.g. taken from a cookie of a web application.
+    pub fn start_session_with_id(&mut self, id: String) {
+        let mut tags = self.tags.session_mut();
+        tags.clear();
+        tags.set_id(id);
+
+        // context clones taken before keep their own session state
+        self.session_start = Arc::new(AtomicBool::new(true));
+    }
+
+    /// Returns a copy of the context to attach to the next telemetry item. Only the first item of a
+    /// session is marked as the session start.
+    pub(crate) fn next(&self) -> Self {
+        let mut context = self.clone();
+        if self.session_start.swap(false, Ordering::Relaxed) {
+            context.tags.session_mut().set_is_first("true".into());
         }
+        context
+    }
+
+    /// Returns keys of correlation context entries accepted from incoming requests.
+    #[cfg(feature = "tower")]
+    pub(crate) fn correlation_keys(&self) -> &[String] {
+        &self.correlation_keys
     }
 
     /// Returns mutable reference to a collection of common properties to attach to telemetry event.
@@ -80,6 +266,18 @@ impl TelemetryContext {
         &self.properties
     }
 
+    /// Returns a policy that determines what happens to custom properties of a telemetry item, including
+    /// common ones, whose keys or values are longer than the server accepts.
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.error_policy
+    }
+
+    /// Changes a policy that determines what happens to custom properties of a telemetry item,
+    /// including common ones, whose keys or values are longer than the server accepts.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
     /// Returns mutable reference to a collection of common tags to attach to telemetry event.
     pub fn tags_mut(&mut self) -> &mut ContextTags {
         &mut self.tags
@@ -91,15 +289,79 @@ impl TelemetryContext {
     }
 }
 
+/// Returns an anonymous user id that stays the same for all clients created by the current process.
+/// It can be replaced or removed with user tags of the context.
+fn anonymous_user_id() -> String {
+    ANONYMOUS_USER_ID
+        .get_or_init(|| uuid::new().to_hyphenated().to_string())
+        .clone()
+}
+
+/// Detects a name of operating system family together with a distribution name and version if known,
+/// e.g. `linux (Ubuntu 22.04.3 LTS)`.
+fn os_version() -> String {
+    let os = std::env::consts::OS;
+    let release = if cfg!(target_os = "linux") {
+        fs::read_to_string("/etc/os-release")
+            .ok()
+            .and_then(|content| parse_os_release(&content))
+    } else {
+        None
+    };
+
+    match release {
+        Some(release) => format!("{} ({})", os, release),
+        None => os.into(),
+    }
+}
+
+/// Extracts a human readable name of Linux distribution from `/etc/os-release` content.
+fn parse_os_release(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim().trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Detects a name of the host the process runs on. `HOSTNAME` environment variable takes precedence
+/// over a system host name, so a pod name can be provided with Kubernetes downward API.
+pub(crate) fn host_name() -> Option<String> {
+    env::var("HOSTNAME")
+        .ok()
+        .filter(|host| !host.is_empty())
+        .or_else(|| hostname::get().ok().and_then(|host| host.into_string().ok()))
+}
+
+/// Detects a locale of the current process from environment variables used on Unix-like systems.
+fn locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| parse_locale(&value))
+}
+
+/// Converts a POSIX locale like `en_US.UTF-8` to RFC 5646 language tag like `en-US`.
+fn parse_locale(value: &str) -> Option<String> {
+    let locale = value.split(['.', '@']).next().unwrap_or_default();
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return None;
+    }
+
+    Some(locale.replace('_', "-"))
+}
+
 #[cfg(test)]
 mod tests {
     use matches::assert_matches;
+    use test_case::test_case;
 
     use super::*;
 
     #[test]
     fn it_updates_common_properties() {
-        let config = TelemetryConfig::new("instrumentation".into());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
         let mut context = TelemetryContext::from_config(&config);
         context.properties_mut().insert("Resource Group".into(), "my-rg".into());
 
@@ -109,15 +371,175 @@ mod tests {
 
     #[test]
     fn it_creates_a_context_with_default_values() {
-        let config = TelemetryConfig::new("instrumentation".into());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
 
         let context = TelemetryContext::from_config(&config);
 
-        assert_eq!(&context.i_key, "instrumentation");
+        assert_eq!(&context.i_key, "00000000-0000-0000-0000-000000000000");
         assert_matches!(&context.tags().internal().sdk_version(), Some(_));
         assert_matches!(&context.tags().device().os_version(), Some(_));
         assert_matches!(&context.tags().device().id(), Some(_));
         assert_matches!(&context.tags().cloud().role_instance(), Some(_));
+        assert_eq!(context.tags().application().version(), None);
+        assert_matches!(&context.tags().user().id(), Some(_));
+        assert_eq!(context.tags().user().auth_user_id(), None);
         assert!(context.properties().is_empty());
     }
+
+    #[test]
+    fn it_creates_a_context_with_sdk_version_prefix_from_config() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .sdk_version_prefix("myframework:1.0_")
+            .build()
+            .unwrap();
+
+        let context = TelemetryContext::from_config(&config);
+
+        assert_eq!(
+            context.tags().internal().sdk_version(),
+            Some(format!("myframework:1.0_rust:{}", env!("CARGO_PKG_VERSION")).as_str())
+        );
+    }
+
+    #[test]
+    fn it_keeps_anonymous_user_id_for_all_contexts() {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+
+        let first = TelemetryContext::from_config(&config);
+        let mut second = TelemetryContext::from_config(&config);
+        assert_eq!(first.tags().user().id(), second.tags().user().id());
+
+        second.tags_mut().user_mut().set_auth_user_id("user@example.com".into());
+        assert_eq!(second.tags().user().auth_user_id(), Some("user@example.com"));
+
+        second.tags_mut().user_mut().clear();
+        assert_eq!(second.tags().user().id(), None);
+        assert_eq!(second.tags().user().auth_user_id(), None);
+        assert_matches!(&second.tags().device().id(), Some(_));
+    }
+
+    #[test]
+    fn it_marks_only_first_item_of_session() {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let mut context = TelemetryContext::from_config(&config);
+        assert_eq!(context.next().tags().session().id(), None);
+
+        context.start_session_with_id("session-1".into());
+        let clone = context.clone();
+
+        let first = context.next();
+        assert_eq!(first.tags().session().id(), Some("session-1"));
+        assert_eq!(first.tags().session().is_first(), Some("true"));
+
+        let second = clone.next();
+        assert_eq!(second.tags().session().id(), Some("session-1"));
+        assert_eq!(second.tags().session().is_first(), None);
+
+        context.start_session();
+        assert_ne!(context.tags().session().id(), Some("session-1"));
+        assert_eq!(context.next().tags().session().is_first(), Some("true"));
+    }
+
+    #[test_case("en_US.UTF-8", Some("en-US"); "language and region")]
+    #[test_case("de_DE@euro", Some("de-DE"); "modifier")]
+    #[test_case("fr", Some("fr"); "language only")]
+    #[test_case("C.UTF-8", None; "c locale")]
+    #[test_case("POSIX", None; "posix locale")]
+    fn it_parses_locale(value: &str, expected: Option<&str>) {
+        assert_eq!(parse_locale(value).as_deref(), expected);
+    }
+
+    #[test]
+    fn it_parses_distribution_name_from_os_release() {
+        let content = "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\nID=ubuntu\n";
+
+        assert_eq!(parse_os_release(content), Some("Ubuntu 22.04.3 LTS".into()));
+        assert_eq!(parse_os_release("ID=ubuntu"), None);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_current_operation_within_scope() {
+        let outer = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+        let inner = OperationContext::new("4bf92f3577b34da6a3ce929d0e0e4736");
+
+        let ids = scope(outer, async move {
+            let before = current().map(|operation| operation.id().to_string());
+            set_current(inner);
+            let after = current().map(|operation| operation.id().to_string());
+            (before, after)
+        })
+        .await;
+
+        assert_eq!(ids.0.as_deref(), Some("0af7651916cd43dd8448eb211c80319c"));
+        assert_eq!(ids.1.as_deref(), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert!(current().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_keeps_operation_bound_to_spawned_task() {
+        let operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+
+        let id = scope(operation, async {
+            let task = tokio::spawn(bind(async { current().map(|operation| operation.id().to_string()) }));
+            set_current(OperationContext::new("4bf92f3577b34da6a3ce929d0e0e4736"));
+            task.await.unwrap()
+        })
+        .await;
+
+        assert_eq!(id.as_deref(), Some("0af7651916cd43dd8448eb211c80319c"));
+    }
+
+    #[tokio::test]
+    async fn it_ignores_current_operation_set_outside_of_scope_in_runtime() {
+        set_current(OperationContext::new("0af7651916cd43dd8448eb211c80319c"));
+
+        assert!(current().is_none());
+        assert!(THREAD_OPERATION.with(|operation| operation.borrow().is_none()));
+    }
+
+    #[test]
+    fn it_keeps_current_operation_of_thread_outside_of_scope() {
+        set_current(OperationContext::new("0af7651916cd43dd8448eb211c80319c"));
+        let other = std::thread::spawn(|| current().is_some()).join().unwrap();
+
+        assert_eq!(
+            current().map(|operation| operation.id().to_string()).as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert!(!other);
+
+        clear_current();
+        assert!(current().is_none());
+    }
+
+    #[test]
+    fn it_creates_a_context_with_cloud_role_from_config() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .role_name("rust_server")
+            .role_instance("rust_server_1")
+            .build()
+            .unwrap();
+
+        let context = TelemetryContext::from_config(&config);
+
+        assert_eq!(context.tags().cloud().role(), Some("rust_server"));
+        assert_eq!(context.tags().cloud().role_instance(), Some("rust_server_1"));
+    }
+
+    #[test]
+    fn it_creates_a_context_with_application_version_from_config() {
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .application_version("1.2.3")
+            .build()
+            .unwrap();
+
+        let mut context = TelemetryContext::from_config(&config);
+        assert_eq!(context.tags().application().version(), Some("1.2.3"));
+
+        context.tags_mut().application_mut().set_version("1.2.4".into());
+        assert_eq!(context.tags().application().version(), Some("1.2.4"));
+    }
 }
@@ -1,10 +1,11 @@
 use crate::contracts::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 // NOTE: This file was automatically generated.
 
 /// Instances of AvailabilityData represent the result of executing an availability test.
 #[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 #[serde(rename_all = "camelCase")]
 pub struct AvailabilityData {
     pub ver: i32,
@@ -1,10 +1,11 @@
 use crate::contracts::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 // NOTE: This file was automatically generated.
 
 /// Data struct to contain only C section with custom fields.
 #[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 #[serde(untagged)]
 #[serde(rename_all = "camelCase")]
 pub enum Base {
@@ -1,10 +1,11 @@
 use crate::contracts::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 // NOTE: This file was automatically generated.
 
 /// Instances of Event represent structured event records that can be grouped and searched by their properties. Event data item also creates a metric of event count by name.
 #[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
 #[serde(rename_all = "camelCase")]
 pub struct EventData {
     pub ver: i32,
@@ -1,6 +1,6 @@
 // NOTE: This file was automatically generated.
 
-#![allow(unused_imports)]
+#![allow(missing_docs, unused_imports, clippy::enum_variant_names, clippy::derivable_impls)]
 
 mod availability_data;
 mod base;
@@ -0,0 +1,101 @@
+//! Module for sending SDK heartbeat metric.
+//!
+//! Heartbeat is submitted as a [`MetricTelemetry`](../telemetry/struct.MetricTelemetry.html) item named
+//! [`HEARTBEAT`](constant.HEARTBEAT.html) that carries SDK and runtime versions, the same way other
+//! Application Insights SDKs do. It powers SDK statistics displayed in the portal. A client sends
+//! heartbeat periodically when it is configured with
+//! [`heartbeat`](../struct.TelemetryConfigBuilder.html#method.heartbeat) interval.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{heartbeat, TelemetryClient, TelemetryConfig};
+//!
+//! # async fn run() {
+//! let config = TelemetryConfig::builder()
+//!     .i_key("<instrumentation key>")
+//!     .heartbeat(heartbeat::DEFAULT_INTERVAL)
+//!     .build_unchecked();
+//!
+//! // heartbeat is submitted every 15 minutes while the client is alive
+//! let client = TelemetryClient::from_config(config);
+//! # }
+//! ```
+use std::time::Duration;
+
+use crate::telemetry::{MetricTelemetry, Telemetry};
+
+/// A name of SDK heartbeat metric.
+pub const HEARTBEAT: &str = "HeartBeat";
+
+/// An interval other Application Insights SDKs send heartbeat with.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Produces SDK heartbeat metric.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    interval: Duration,
+}
+
+impl Heartbeat {
+    /// Creates a new heartbeat that is sent with specified interval.
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+
+    /// Returns an interval heartbeat is sent with.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns heartbeat metric telemetry item. Its value is 0 that means the SDK is healthy.
+    pub fn collect(&self) -> MetricTelemetry {
+        let mut telemetry = MetricTelemetry::new(HEARTBEAT, 0.0);
+
+        let properties = telemetry.properties_mut();
+        properties.insert("sdk".into(), format!("rust:{}", env!("CARGO_PKG_VERSION")));
+        properties.insert("runtimeVersion".into(), env!("APPINSIGHTS_RUSTC_VERSION").into());
+        properties.insert("interval".into(), self.interval.as_secs().to_string());
+
+        telemetry
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        context::TelemetryContext,
+        contracts::{Base, Data, Envelope},
+        telemetry::{ContextTags, Properties},
+    };
+
+    #[test]
+    fn it_collects_heartbeat_metric() {
+        let heartbeat = Heartbeat::new(Duration::from_secs(60));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let data = match Envelope::from((context, heartbeat.collect())).data {
+            Some(Base::Data(Data::MetricData(data))) => data,
+            data => panic!("unexpected telemetry data {:?}", data),
+        };
+
+        assert_eq!(data.metrics[0].name, HEARTBEAT);
+        assert_eq!(data.metrics[0].value, 0.0);
+
+        let properties = data.properties.unwrap();
+        assert_eq!(
+            properties.get("sdk"),
+            Some(&format!("rust:{}", env!("CARGO_PKG_VERSION")))
+        );
+        assert_matches!(properties.get("runtimeVersion"), Some(version) if version.starts_with('1'));
+        assert_eq!(properties.get("interval"), Some(&"60".to_string()));
+    }
+}
@@ -6,9 +6,36 @@
 //! However there is also a [`blocking`](blocking) which intended to preserve
 //! backward compatibility whenever needed.
 //!
+//! In async mode telemetry items are submitted by a task spawned on the current Tokio runtime, so
+//! both multi-threaded and current-thread runtimes are supported. Tracking telemetry never blocks
+//! and does not need to be awaited, only [`flush`](struct.TelemetryClient.html#method.flush) and
+//! [`close_channel`](struct.TelemetryClient.html#method.close_channel) are async.
+//!
+//! Applications instrumented with OpenTelemetry can export spans to Application Insights with
+//! [`opentelemetry`](opentelemetry) module enabled by `opentelemetry` feature. Similarly, events
+//! emitted with `tracing` crate can be forwarded with [`tracing`](tracing) module enabled by `tracing` feature.
+//! Records of `log` crate can be forwarded with [`AppInsightsLogger`](logger/struct.AppInsightsLogger.html).
+//! Outgoing HTTP calls made with `reqwest_middleware` client are tracked as dependencies by the
+//! [`middleware`](middleware) module enabled by `reqwest-middleware` feature. Incoming requests of any
+//! `tower` based HTTP server are tracked with [`tower`](tower) module enabled by `tower` feature, and
+//! Axum handlers get the request operation from [`axum`](axum) module enabled by `axum` feature.
+//! CPU and memory usage can be collected as [`performance`](performance) counters enabled by `perf-counters` feature.
+//! SDK and runtime versions can be reported with a periodic [`heartbeat`](heartbeat) metric.
+//! With `serde` feature enabled all telemetry items implement `Serialize` and `Deserialize` using the same
+//! JSON schema that is submitted to the server, so they can be inspected or stored for later replay.
+//! A [`TelemetryConfig`](struct.TelemetryConfig.html) can be stored in a configuration file with `serde`
+//! feature and read from TOML with `toml` feature.
+//! Telemetry produced by an application can be verified in tests with [`MockTelemetryChannel`](struct.MockTelemetryChannel.html)
+//! enabled by `test-utils` feature.
+//! Domain structs can be converted into custom events with `#[derive(Telemetry)]` enabled by `derive` feature.
+//! Errors of `anyhow` crate can be tracked with their whole chain of context enabled by `anyhow` feature.
+//! Telemetry is submitted over TLS provided by the platform with default `native-tls` feature. Use
+//! `rustls-tls` feature with default features disabled to build with [rustls](https://docs.rs/rustls) instead.
+//!
 //! The following Application Insights telemetry items are supported:
 //! * [Availability telemetry](telemetry/struct.AvailabilityTelemetry.html)
 //! * [Event telemetry](telemetry/struct.EventTelemetry.html)
+//! * [Exception telemetry](telemetry/struct.ExceptionTelemetry.html)
 //! * [Page view telemetry](telemetry/struct.PageViewTelemetry.html)
 //! * [Remote dependency telemetry](telemetry/struct.RemoteDependencyTelemetry.html)
 //! * [Request telemetry](telemetry/struct.RequestTelemetry.html)
@@ -29,8 +56,8 @@
 //! ## Examples
 //!
 //! 1. Create an new instance of [`TelemetryClient`](struct.TelemetryClient.html) with an
-//! Instrumentation Key and default settings. To get more control over client behavior please visit
-//! [`TelemetryConfig`](struct.TelemetryConfig.html).
+//!    Instrumentation Key and default settings. To get more control over client behavior please visit
+//!    [`TelemetryConfig`](struct.TelemetryConfig.html).
 //! 2. Send an event telemetry to the Application Insights service.
 //!
 //! ```rust
@@ -62,7 +89,9 @@
 //!     // set a new maximum time to wait until data will be sent to the server
 //!     .interval(Duration::from_secs(5))
 //!     // construct a new instance of telemetry configuration
-//!     .build();
+//!     .build()
+//!     // instrumentation key is validated to be a GUID
+//!     .expect("valid instrumentation key");
 //!
 //! // configure telemetry client with default settings
 //! let client = TelemetryClient::from_config(config);
@@ -108,7 +137,7 @@
 //! use appinsights::telemetry::{RequestTelemetry, Telemetry};
 //!
 //! // configure telemetry with default settings
-//! let mut client = TelemetryClient::new("instrumentation".to_string());
+//! let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
 //!
 //! // set role instance name globally. This is usually the name of the service submitting the telemetry
 //! client.context_mut().tags_mut().cloud_mut().set_role("rust_server".to_string());
@@ -145,7 +174,7 @@
 //! use appinsights::telemetry::{RequestTelemetry, Telemetry};
 //!
 //! // configure telemetry with default settings
-//! let mut client = TelemetryClient::new("instrumentation".to_string());
+//! let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
 //!
 //! // set custom telemetry item property globally
 //! client.context_mut().properties_mut().insert("Resource Group".to_string(), "my-rg".to_string());
@@ -160,37 +189,106 @@
 //! worker stores it in memory, so when application crashes the data will be lost. Luckily SDK
 //! provides several convenient methods to deal with this issue.
 //! * [`flush_channel`](struct.TelemetryClient.html#method.flush_channel) will trigger telemetry submission
-//! as soon as possible. It returns immediately and telemetry is no guaranteed to be sent.
+//!   as soon as possible. It returns immediately and telemetry is no guaranteed to be sent.
+//! * [`flush`](struct.TelemetryClient.html#method.flush) will trigger telemetry submission and block
+//!   current task until the server acknowledges pending items, submission fails or an optional timeout expires.
+//!   The client remains usable afterwards.
 //! * [`close_channel`](struct.TelemetryClient.html#method.close_channel) will cause the channel to
-//! stop accepting any new telemetry items, submit all pending ones, block current task and
-//! wait until data will be sent at most once. If telemetry submission fails, it will not retry.
-//! This method consumes the value of client so it makes impossible to use a client with close channel.
+//!   stop accepting any new telemetry items, submit all pending ones, block current task and
+//!   wait until data will be sent at most once. If telemetry submission fails, it will not retry.
+//!   This method consumes the value of client so it makes impossible to use a client with close channel.
 //! * [`terminate`](struct.TelemetryClient.html#method.terminate) will trigger termination of submission flow, all pending items discarded and
-//! current task will be blocked until all resources freed.
+//!   current task will be blocked until all resources freed.
 #![deny(unused_extern_crates)]
 #![deny(missing_docs)]
 
+mod aggregator;
+pub use aggregator::MetricsAggregator;
+#[cfg(feature = "axum")]
+pub mod axum;
+
 #[cfg(feature = "blocking")]
 pub mod blocking;
 
 mod channel;
+#[cfg(feature = "test-utils")]
+pub use channel::MockTelemetryChannel;
+pub use channel::{
+    BackpressurePolicy, BufferFullError, DebugChannel, FlushError, InMemoryChannel, MultiChannel, NoopChannel,
+    PartialSendError, RetryPolicy, RoutingChannel, SendError, TelemetryChannel, DEBUG_CHANNEL_ENV,
+};
 
 mod client;
-pub use client::TelemetryClient;
+pub use client::{ClientStats, ClientStatus, DependencySpan, DependencyTimer, PanicHookGuard, TelemetryClient};
 
 mod config;
 #[doc(inline)]
-pub use config::TelemetryConfig;
+pub use config::{
+    CloudEnvironment, ConfigBuildError, ConfigEnvError, ConnectionStringError, InstrumentationKey, InvalidKeyError,
+    ProxyError, TelemetryConfig,
+};
 
-mod context;
+pub mod context;
 pub use context::TelemetryContext;
 
 mod contracts;
+
+#[cfg(feature = "derive")]
+pub use appinsights_derive::Telemetry;
+
+/// Raw data contracts of telemetry items as they are submitted to the server. Telemetry that does
+/// not fit any type of the [`telemetry`](telemetry/index.html) module can be constructed as an
+/// [`Envelope`](schema/struct.Envelope.html) and submitted with
+/// [`track_envelope`](struct.TelemetryClient.html#method.track_envelope).
+pub mod schema {
+    pub use crate::contracts::*;
+
+    /// Helpers to produce values in formats the server expects.
+    pub mod utils {
+        pub use crate::time::{format_duration, parse_duration};
+    }
+
+    /// Checks envelopes against the schema the server expects.
+    pub mod validation {
+        pub use crate::validation::{validate_envelope, ValidationError};
+    }
+
+    /// Produces the payload submitted to the server, e.g. to inspect it in tests.
+    pub mod serialization {
+        pub use crate::transmitter::{serialize_batch, to_json_value};
+    }
+}
+
+pub mod heartbeat;
+mod live_metrics;
+pub mod logger;
+#[cfg(feature = "reqwest-middleware")]
+pub mod middleware;
+#[cfg(feature = "opentelemetry")]
+pub mod opentelemetry;
+#[cfg(feature = "perf-counters")]
+pub mod performance;
+mod processor;
+pub use processor::{
+    ExceptionSeverity, ProcessResult, RequestFilter, TelemetryItem, TelemetryKind, TelemetryProcessor,
+};
+mod rate_limit;
+mod sampling;
+pub use sampling::{AdaptiveSampling, AlwaysSample, FixedRateSampling, NeverSample, SamplingStrategy};
 pub mod telemetry;
 mod time;
 mod timeout;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+mod tracker;
+pub use tracker::TelemetryTracker;
 mod transmitter;
+mod transport;
+pub use transport::{Transport, TransportError};
 mod uuid;
+mod validation;
 
 use std::error::Error;
 
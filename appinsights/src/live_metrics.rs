@@ -0,0 +1,425 @@
+use std::{
+    mem,
+    sync::{Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use http::{header::CONTENT_TYPE, HeaderMap};
+use log::{debug, warn};
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::{
+    config,
+    context::{self, TelemetryContext},
+    contracts::{Base, Data, Envelope},
+    time, transmitter, uuid, TelemetryConfig,
+};
+
+/// How often the service is polled while nobody watches live metrics.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A time window live metrics are aggregated over and posted with while somebody watches them.
+const POST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time to wait for a response from the service.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A version of the protocol the service expects.
+const INVARIANT_VERSION: u32 = 1;
+
+/// Number of .NET ticks between 0001-01-01 and 1970-01-01 the service expects transmission time in.
+const TICKS_AT_EPOCH: i64 = 621_355_968_000_000_000;
+
+const HEADER_TRANSMISSION_TIME: &str = "x-ms-qps-transmission-time";
+const HEADER_STREAM_ID: &str = "x-ms-qps-stream-id";
+const HEADER_MACHINE_NAME: &str = "x-ms-qps-machine-name";
+const HEADER_INSTANCE_NAME: &str = "x-ms-qps-instance-name";
+const HEADER_ROLE_NAME: &str = "x-ms-qps-role-name";
+const HEADER_INVARIANT_VERSION: &str = "x-ms-qps-invariant-version";
+const HEADER_SUBSCRIBED: &str = "x-ms-qps-subscribed";
+const HEADER_ENDPOINT_REDIRECT: &str = "x-ms-qps-service-endpoint-redirect-v2";
+const HEADER_POLLING_INTERVAL_HINT: &str = "x-ms-qps-service-polling-interval-hint";
+
+/// Aggregates request, dependency and exception rates and durations of tracked telemetry items to be
+/// streamed to the Live Metrics service.
+pub struct LiveMetrics {
+    window: Mutex<Window>,
+}
+
+struct Window {
+    started: Instant,
+    counters: Counters,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Counters {
+    requests: u64,
+    failed_requests: u64,
+    request_duration: f64,
+    dependencies: u64,
+    failed_dependencies: u64,
+    dependency_duration: f64,
+    exceptions: u64,
+}
+
+impl LiveMetrics {
+    /// Creates a new empty live metrics aggregator.
+    pub fn new() -> Self {
+        Self {
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                counters: Counters::default(),
+            }),
+        }
+    }
+
+    /// Accounts a telemetry item in the current window.
+    pub fn record(&self, envelope: &Envelope) {
+        let duration = |duration: &str| time::parse_duration(duration).map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+
+        let mut window = self.window.lock().unwrap();
+        let counters = &mut window.counters;
+        match &envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                counters.requests += 1;
+                counters.failed_requests += u64::from(!data.success);
+                counters.request_duration += duration(&data.duration);
+            }
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                counters.dependencies += 1;
+                counters.failed_dependencies += u64::from(data.success == Some(false));
+                counters.dependency_duration += duration(&data.duration);
+            }
+            Some(Base::Data(Data::ExceptionData(_))) => counters.exceptions += 1,
+            _ => {}
+        }
+    }
+
+    /// Returns metrics aggregated in the current window and starts a new one.
+    fn take(&self) -> Vec<Metric> {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&self, now: Instant) -> Vec<Metric> {
+        let mut window = self.window.lock().unwrap();
+        let elapsed = now
+            .saturating_duration_since(window.started)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let counters = mem::take(&mut window.counters);
+        window.started = now;
+
+        let rate = |count: u64| count as f64 / elapsed;
+        let average = |total: f64, count: u64| if count == 0 { 0.0 } else { total / count as f64 };
+
+        vec![
+            Metric::new("\\ApplicationInsights\\Requests/Sec", rate(counters.requests)),
+            Metric::new(
+                "\\ApplicationInsights\\Request Duration",
+                average(counters.request_duration, counters.requests),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Requests Failed/Sec",
+                rate(counters.failed_requests),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Requests Succeeded/Sec",
+                rate(counters.requests - counters.failed_requests),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Dependency Calls/Sec",
+                rate(counters.dependencies),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Dependency Call Duration",
+                average(counters.dependency_duration, counters.dependencies),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Dependency Calls Failed/Sec",
+                rate(counters.failed_dependencies),
+            ),
+            Metric::new(
+                "\\ApplicationInsights\\Dependency Calls Succeeded/Sec",
+                rate(counters.dependencies - counters.failed_dependencies),
+            ),
+            Metric::new("\\ApplicationInsights\\Exceptions/Sec", rate(counters.exceptions)),
+        ]
+    }
+}
+
+/// A single live metric value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Metric {
+    name: &'static str,
+    value: f64,
+    weight: u32,
+}
+
+impl Metric {
+    fn new(name: &'static str, value: f64) -> Self {
+        Self { name, value, weight: 1 }
+    }
+}
+
+/// A payload of ping and post requests to the Live Metrics service.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MonitoringDataPoint<'a> {
+    version: &'a str,
+    invariant_version: u32,
+    instrumentation_key: &'a str,
+    instance: &'a str,
+    role_name: &'a str,
+    machine_name: &'a str,
+    stream_id: &'a str,
+    timestamp: String,
+    is_web_app: bool,
+    performance_collection_supported: bool,
+    metrics: Vec<Metric>,
+}
+
+/// Polls the Live Metrics service and posts aggregated live metrics while somebody watches them.
+pub struct LiveMetricsService {
+    client: Client,
+    endpoint: String,
+    i_key: String,
+    version: String,
+    stream_id: String,
+    machine_name: String,
+    instance: String,
+    role_name: String,
+    ping_interval: Duration,
+}
+
+impl LiveMetricsService {
+    /// Creates a new service client configured with specified configuration and context.
+    pub fn new(config: &TelemetryConfig, context: &TelemetryContext) -> Self {
+        let machine_name = context::host_name().unwrap_or_default();
+        let instance = context.tags().cloud().role_instance().unwrap_or(&machine_name).into();
+
+        Self {
+            client: transmitter::http_client(config),
+            endpoint: config.live_endpoint().trim_end_matches('/').into(),
+            i_key: config.i_key().to_string(),
+            version: format!("rust:{}", env!("CARGO_PKG_VERSION")),
+            stream_id: uuid::new().to_simple().to_string(),
+            machine_name,
+            instance,
+            role_name: context.tags().cloud().role().unwrap_or_default().into(),
+            ping_interval: PING_INTERVAL,
+        }
+    }
+
+    /// Pings the service until somebody starts watching live metrics, then posts metrics aggregated
+    /// over every second until the service tells to stop. Runs until live metrics are dropped.
+    pub async fn run(mut self, metrics: Weak<LiveMetrics>) {
+        let mut subscribed = false;
+        loop {
+            tokio::time::sleep(if subscribed { POST_INTERVAL } else { self.ping_interval }).await;
+
+            let metrics = match metrics.upgrade() {
+                Some(metrics) => metrics.take(),
+                None => break,
+            };
+
+            let streaming = if subscribed {
+                let payload = serde_json::to_vec(&[self.data_point(metrics)]);
+                self.send("post", payload).await
+            } else {
+                let payload = serde_json::to_vec(&self.data_point(Vec::default()));
+                self.send("ping", payload).await
+            };
+
+            if streaming != subscribed {
+                debug!(
+                    "Live metrics streaming {}",
+                    if streaming { "started" } else { "stopped" }
+                );
+                subscribed = streaming;
+            }
+        }
+    }
+
+    fn data_point(&self, metrics: Vec<Metric>) -> MonitoringDataPoint<'_> {
+        MonitoringDataPoint {
+            version: &self.version,
+            invariant_version: INVARIANT_VERSION,
+            instrumentation_key: &self.i_key,
+            instance: &self.instance,
+            role_name: &self.role_name,
+            machine_name: &self.machine_name,
+            stream_id: &self.stream_id,
+            timestamp: format!("/Date({})/", time::now().timestamp_millis()),
+            is_web_app: false,
+            performance_collection_supported: false,
+            metrics,
+        }
+    }
+
+    /// Sends a request to the service and returns whether somebody watches live metrics.
+    async fn send(&mut self, method: &str, payload: serde_json::Result<Vec<u8>>) -> bool {
+        let url = format!("{}/QuickPulseService.svc/{}?ikey={}", self.endpoint, method, self.i_key);
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Unable to serialize live metrics: {}", err);
+                return false;
+            }
+        };
+
+        let response = self
+            .client
+            .post(&url)
+            .timeout(TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header(HEADER_TRANSMISSION_TIME, transmission_time(time::now()))
+            .header(HEADER_STREAM_ID, &self.stream_id)
+            .header(HEADER_MACHINE_NAME, &self.machine_name)
+            .header(HEADER_INSTANCE_NAME, &self.instance)
+            .header(HEADER_ROLE_NAME, &self.role_name)
+            .header(HEADER_INVARIANT_VERSION, INVARIANT_VERSION)
+            .body(payload)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => self.update(response.headers()),
+            Ok(response) => {
+                debug!("Live Metrics service responded with {}", response.status());
+                false
+            }
+            Err(err) => {
+                // an error message must not contain a URL with an instrumentation key
+                debug!("Unable to reach Live Metrics service: {}", err.without_url());
+                false
+            }
+        }
+    }
+
+    /// Applies endpoint and polling interval the service asked for and returns whether somebody
+    /// watches live metrics. The service is never polled more often than every 5 seconds, and it can
+    /// redirect to an endpoint a configured one could be set to only.
+    fn update(&mut self, headers: &HeaderMap) -> bool {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(endpoint) = header(HEADER_ENDPOINT_REDIRECT).filter(|endpoint| !endpoint.is_empty()) {
+            match config::validate_endpoint(endpoint) {
+                Ok(()) => self.endpoint = endpoint.trim_end_matches('/').into(),
+                Err(err) => warn!("Live Metrics service redirect ignored: {}", err),
+            }
+        }
+
+        if let Some(interval) = header(HEADER_POLLING_INTERVAL_HINT).and_then(|ms| ms.parse().ok()) {
+            self.ping_interval = Duration::from_millis(interval).max(PING_INTERVAL);
+        }
+
+        header(HEADER_SUBSCRIBED).is_some_and(|subscribed| subscribed.eq_ignore_ascii_case("true"))
+    }
+}
+
+/// Returns time in .NET ticks, 100 nanosecond intervals since 0001-01-01.
+fn transmission_time(now: DateTime<Utc>) -> i64 {
+    TICKS_AT_EPOCH + now.timestamp_millis() * 10_000
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::contracts::{ExceptionData, RemoteDependencyData, RequestData};
+
+    #[test]
+    fn it_aggregates_rates_and_durations_over_window() {
+        let metrics = LiveMetrics::new();
+        let started = metrics.window.lock().unwrap().started;
+
+        metrics.record(&request(true, "0.00:00:00.1000000"));
+        metrics.record(&request(false, "0.00:00:00.3000000"));
+        metrics.record(&dependency(Some(false), "0.00:00:01.0000000"));
+        metrics.record(&envelope(Data::ExceptionData(ExceptionData::default())));
+
+        let values: Vec<_> = metrics
+            .take_at(started + Duration::from_secs(2))
+            .into_iter()
+            .map(|metric| metric.value)
+            .collect();
+
+        assert_eq!(values, vec![1.0, 200.0, 0.5, 0.5, 0.5, 1000.0, 0.5, 0.0, 0.5]);
+        assert_eq!(metrics.window.lock().unwrap().counters, Counters::default());
+    }
+
+    #[test]
+    fn it_follows_service_redirect_and_polling_hint() {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let mut service = LiveMetricsService::new(&config, &TelemetryContext::from_config(&config));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_SUBSCRIBED, HeaderValue::from_static("true"));
+        headers.insert(
+            HEADER_ENDPOINT_REDIRECT,
+            HeaderValue::from_static("https://westus.live.local/"),
+        );
+        headers.insert(HEADER_POLLING_INTERVAL_HINT, HeaderValue::from_static("10000"));
+
+        assert!(service.update(&headers));
+        assert_eq!(service.endpoint, "https://westus.live.local");
+        assert_eq!(service.ping_interval, Duration::from_secs(10));
+        assert!(!service.update(&HeaderMap::new()));
+    }
+
+    #[test_case("http://westus.live.local/"; "insecure")]
+    #[test_case("westus.live.local"; "malformed")]
+    fn it_ignores_redirect_to_invalid_endpoint(redirect: &'static str) {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let mut service = LiveMetricsService::new(&config, &TelemetryContext::from_config(&config));
+        let endpoint = service.endpoint.clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_ENDPOINT_REDIRECT, HeaderValue::from_static(redirect));
+        service.update(&headers);
+
+        assert_eq!(service.endpoint, endpoint);
+    }
+
+    #[test_case("0", PING_INTERVAL; "zero")]
+    #[test_case("1000", PING_INTERVAL; "shorter than default")]
+    #[test_case("10000", Duration::from_secs(10); "longer than default")]
+    fn it_polls_service_no_more_often_than_default_interval(hint: &'static str, expected: Duration) {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let mut service = LiveMetricsService::new(&config, &TelemetryContext::from_config(&config));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_POLLING_INTERVAL_HINT, HeaderValue::from_static(hint));
+        service.update(&headers);
+
+        assert_eq!(service.ping_interval, expected);
+    }
+
+    fn request(success: bool, duration: &str) -> Envelope {
+        envelope(Data::RequestData(RequestData {
+            success,
+            duration: duration.into(),
+            ..RequestData::default()
+        }))
+    }
+
+    fn dependency(success: Option<bool>, duration: &str) -> Envelope {
+        envelope(Data::RemoteDependencyData(RemoteDependencyData {
+            success,
+            duration: duration.into(),
+            ..RemoteDependencyData::default()
+        }))
+    }
+
+    fn envelope(data: Data) -> Envelope {
+        Envelope {
+            data: Some(Base::Data(data)),
+            ..Envelope::default()
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Module for forwarding records of [log](https://docs.rs/log) crate to Application Insights.
+//!
+//! Records are converted to [`TraceTelemetry`](../telemetry/struct.TraceTelemetry.html) items and
+//! submitted with a [`TelemetryClient`](../struct.TelemetryClient.html). Records emitted by this crate
+//! and the HTTP stack it uses to submit telemetry are ignored to avoid feedback loops.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{logger::AppInsightsLogger, TelemetryClient};
+//! use log::LevelFilter;
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let logger = AppInsightsLogger::new(client).max_level(LevelFilter::Info);
+//!
+//! log::set_max_level(logger.level());
+//! log::set_boxed_logger(Box::new(logger)).expect("logger set");
+//!
+//! log::warn!("unable to process {} records", 42);
+//! ```
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    telemetry::{SeverityLevel, Telemetry, TraceTelemetry},
+    TelemetryClient,
+};
+
+/// Targets of records that are never submitted because they are emitted while submitting telemetry.
+const IGNORED_TARGETS: &[&str] = &["appinsights", "reqwest", "hyper", "h2", "rustls", "want", "mio"];
+
+/// A logger that submits log records as trace telemetry items. A target and a module path of each
+/// record are attached as custom properties.
+pub struct AppInsightsLogger {
+    client: TelemetryClient,
+    max_level: LevelFilter,
+}
+
+impl AppInsightsLogger {
+    /// Creates a new logger that submits records with specified telemetry client.
+    pub fn new(client: TelemetryClient) -> Self {
+        Self {
+            client,
+            max_level: LevelFilter::Trace,
+        }
+    }
+
+    /// Sets a maximum level of records to submit. Records above this level are discarded.
+    pub fn max_level(mut self, max_level: LevelFilter) -> Self {
+        self.max_level = max_level;
+        self
+    }
+
+    /// Returns a maximum level of records to submit.
+    pub fn level(&self) -> LevelFilter {
+        self.max_level
+    }
+}
+
+impl Log for AppInsightsLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.max_level && !is_ignored(metadata.target())
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut telemetry = TraceTelemetry::new(record.args().to_string(), severity(record.level()));
+        telemetry
+            .properties_mut()
+            .insert("target".into(), record.target().into());
+        if let Some(module_path) = record.module_path() {
+            telemetry
+                .properties_mut()
+                .insert("module_path".into(), module_path.into());
+        }
+
+        self.client.track(telemetry);
+    }
+
+    fn flush(&self) {
+        self.client.flush_channel();
+    }
+}
+
+/// Determines whether a record with specified target should not be submitted.
+fn is_ignored(target: &str) -> bool {
+    IGNORED_TARGETS.iter().any(|ignored| {
+        target
+            .strip_prefix(ignored)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+/// Maps log level to Application Insights severity level.
+fn severity(level: Level) -> SeverityLevel {
+    match level {
+        Level::Trace | Level::Debug => SeverityLevel::Verbose,
+        Level::Info => SeverityLevel::Information,
+        Level::Warn => SeverityLevel::Warning,
+        Level::Error => SeverityLevel::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_queue::SegQueue;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data, Envelope, SeverityLevel as ContractsSeverityLevel},
+        TelemetryConfig,
+    };
+
+    #[test]
+    fn it_submits_records_as_traces() {
+        let events = Arc::new(SegQueue::default());
+        let logger = create_logger(events.clone());
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("unable to process {} records", 42))
+                .level(Level::Warn)
+                .target("server")
+                .module_path(Some("server::handlers"))
+                .build(),
+        );
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::MessageData(data))) => {
+                assert_eq!(data.message, "unable to process 42 records");
+                assert_eq!(data.severity_level, Some(ContractsSeverityLevel::Warning));
+
+                let properties = data.properties.unwrap();
+                assert_eq!(properties.get("target"), Some(&"server".to_string()));
+                assert_eq!(properties.get("module_path"), Some(&"server::handlers".to_string()));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[test]
+    fn it_discards_records_above_max_level() {
+        let events = Arc::new(SegQueue::default());
+        let logger = create_logger(events.clone()).max_level(LevelFilter::Warn);
+
+        for level in &[Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error] {
+            logger.log(&Record::builder().args(format_args!("message")).level(*level).build());
+        }
+
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test_case("appinsights", true; "crate itself")]
+    #[test_case("appinsights::channel", true; "crate module")]
+    #[test_case("hyper::client", true; "http client")]
+    #[test_case("appinsights_demo", false; "similar name")]
+    #[test_case("server", false; "application")]
+    fn it_ignores_records_emitted_while_submitting_telemetry(target: &str, expected: bool) {
+        assert_eq!(is_ignored(target), expected);
+    }
+
+    fn create_logger(events: Arc<SegQueue<Envelope>>) -> AppInsightsLogger {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        AppInsightsLogger::new(TelemetryClient::with_channel(&config, TestChannel::new(events)))
+    }
+}
@@ -0,0 +1,185 @@
+//! Module for tracking outgoing HTTP calls made with a [`reqwest_middleware`](https://docs.rs/reqwest-middleware)
+//! client as dependencies.
+//!
+//! Every call is submitted as a [`RemoteDependencyTelemetry`](../telemetry/struct.RemoteDependencyTelemetry.html)
+//! of `HTTP` type with a [`TelemetryClient`](../struct.TelemetryClient.html). W3C Trace Context
+//! `traceparent` and legacy `Request-Id` headers are injected into each request, so the downstream
+//! service can correlate its telemetry with the dependency. A correlation context of the operation
+//! is propagated with `Correlation-Context` and `baggage` headers. An operation the call belongs to is taken
+//! from the request extensions, a new root operation is started otherwise.
+//!
+//! # Examples
+//! ```rust, no_run
+//! # async fn run() -> reqwest_middleware::Result<()> {
+//! use appinsights::{middleware::AppInsightsMiddleware, telemetry::OperationContext, TelemetryClient};
+//! use reqwest_middleware::ClientBuilder;
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let http = ClientBuilder::new(reqwest::Client::new())
+//!     .with(AppInsightsMiddleware::new(client))
+//!     .build();
+//!
+//! // attach the call to an operation of an incoming request
+//! let operation = OperationContext::new_root();
+//! http.get("https://api.github.com").with_extension(operation).send().await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    fmt::{Debug, Formatter},
+    time::Instant,
+};
+
+use http::StatusCode;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use task_local_extensions::Extensions;
+
+use crate::{
+    telemetry::{http_target, OperationContext, RemoteDependencyTelemetry, Telemetry},
+    time, uuid, TelemetryClient,
+};
+
+/// A `reqwest_middleware` middleware that tracks every outgoing HTTP call as a dependency.
+pub struct AppInsightsMiddleware {
+    client: TelemetryClient,
+}
+
+impl AppInsightsMiddleware {
+    /// Creates a new middleware that submits dependencies with specified telemetry client.
+    pub fn new(client: TelemetryClient) -> Self {
+        Self { client }
+    }
+
+    /// Returns a telemetry client dependencies are submitted with.
+    pub fn client(&self) -> &TelemetryClient {
+        &self.client
+    }
+}
+
+impl Debug for AppInsightsMiddleware {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppInsightsMiddleware")
+            .field("i_key", &self.client.context().i_key)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for AppInsightsMiddleware {
+    async fn handle(&self, mut req: Request, extensions: &mut Extensions, next: Next<'_>) -> Result<Response> {
+        let operation = extensions
+            .get::<OperationContext>()
+            .cloned()
+            .unwrap_or_else(OperationContext::new_root);
+
+        // the dependency id becomes a parent id of the downstream request
+        let id = uuid::new().to_simple().to_string()[..16].to_string();
+        req.headers_mut().extend(operation.outgoing_headers(&id));
+
+        let url = req.url();
+        let name = format!("{} {}", req.method(), url.path());
+        let target = http_target(url);
+        let data = url.to_string();
+
+        let timestamp = time::now();
+        let started = Instant::now();
+        let result = next.run(req, extensions).await;
+
+        let status = result.as_ref().ok().map(Response::status);
+        let success = status.is_some_and(|status| status < StatusCode::BAD_REQUEST);
+        let mut telemetry = RemoteDependencyTelemetry::new(name, "HTTP", started.elapsed(), target, success);
+        telemetry.set_id(id);
+        telemetry.set_data(data);
+        if let Some(status) = status {
+            telemetry.set_result_code(status.as_str());
+        }
+        telemetry.set_timestamp(timestamp);
+        telemetry.set_operation(&operation);
+        self.client.track(telemetry);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_queue::SegQueue;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Server,
+    };
+    use parking_lot::Mutex;
+    use reqwest::header::HeaderValue;
+    use reqwest_middleware::ClientBuilder;
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data},
+        TelemetryConfig,
+    };
+
+    #[tokio::test]
+    async fn it_tracks_http_call_as_dependency() {
+        let traceparent = Arc::new(Mutex::new(None));
+        let headers = traceparent.clone();
+        let make_service = make_service_fn(move |_| {
+            let headers = headers.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<Body>| {
+                    *headers.lock() = req.headers().get("traceparent").cloned();
+                    async {
+                        hyper::Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+        let http = ClientBuilder::new(reqwest::Client::new())
+            .with(AppInsightsMiddleware::new(client))
+            .build();
+
+        let mut operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+        operation.set_parent_id("b7ad6b7169203331");
+        let url = format!("http://{}/users?id=1", addr);
+        let response = http.get(&url).with_extension(operation).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(
+            tags.get("ai.operation.id"),
+            Some(&"0af7651916cd43dd8448eb211c80319c".to_string())
+        );
+        assert_eq!(tags.get("ai.operation.parentId"), Some(&"b7ad6b7169203331".to_string()));
+
+        match envelope.data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.name, "GET /users");
+                assert_eq!(data.type_, Some("HTTP".into()));
+                assert_eq!(data.target, Some(addr.to_string()));
+                assert_eq!(data.data, Some(url));
+                assert_eq!(data.result_code, Some("404".into()));
+                assert_eq!(data.success, Some(false));
+
+                let expected = format!("00-0af7651916cd43dd8448eb211c80319c-{}-01", data.id.unwrap());
+                assert_eq!(
+                    traceparent.lock().clone(),
+                    Some(HeaderValue::from_str(&expected).unwrap())
+                );
+            }
+            _ => panic!("unexpected data"),
+        }
+    }
+}
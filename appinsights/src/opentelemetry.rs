@@ -0,0 +1,367 @@
+//! Module for exporting [OpenTelemetry](https://opentelemetry.io) spans to Application Insights.
+//!
+//! Spans are converted to telemetry items and submitted with a [`TelemetryClient`](../struct.TelemetryClient.html)
+//! so all batching and retry settings of the client apply. `SERVER` and `CONSUMER` spans become
+//! [`RequestTelemetry`](../telemetry/struct.RequestTelemetry.html), all others become
+//! [`RemoteDependencyTelemetry`](../telemetry/struct.RemoteDependencyTelemetry.html). Span attributes
+//! are attached to telemetry items as custom properties.
+//!
+//! Well-known [semantic convention](https://opentelemetry.io/docs/specs/semconv/) attributes are
+//! translated to telemetry fields the same way Azure Monitor exporters do it. E.g. `http.request.method`
+//! and `http.route` make up a request name, `http.response.status_code` becomes a response code,
+//! `db.system` and `db.query.text` become a dependency type and data. So spans of instrumented
+//! libraries as well as of `tracing` spans exported with `tracing-opentelemetry` are displayed in the
+//! portal as if they were tracked with an Application Insights SDK.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{opentelemetry::AppInsightsExporter, TelemetryClient};
+//! use opentelemetry_sdk::trace::SdkTracerProvider;
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let provider = SdkTracerProvider::builder()
+//!     .with_simple_exporter(AppInsightsExporter::new(client))
+//!     .build();
+//! ```
+use std::fmt::{Debug, Formatter};
+
+use ::opentelemetry::trace::{SpanId, SpanKind, Status};
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    trace::{SpanData, SpanExporter},
+};
+
+use crate::{
+    telemetry::{OperationContext, RemoteDependencyTelemetry, RequestTelemetry, Telemetry},
+    TelemetryClient,
+};
+
+mod conventions;
+
+/// An OpenTelemetry span exporter that submits spans as Application Insights telemetry items.
+pub struct AppInsightsExporter {
+    client: TelemetryClient,
+}
+
+impl AppInsightsExporter {
+    /// Creates a new exporter that submits spans with specified telemetry client.
+    pub fn new(client: TelemetryClient) -> Self {
+        Self { client }
+    }
+
+    /// Returns a telemetry client spans are submitted with.
+    pub fn client(&self) -> &TelemetryClient {
+        &self.client
+    }
+}
+
+impl Debug for AppInsightsExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppInsightsExporter")
+            .field("i_key", &self.client.context().i_key)
+            .finish()
+    }
+}
+
+impl SpanExporter for AppInsightsExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        for span in batch {
+            match span.span_kind {
+                SpanKind::Server | SpanKind::Consumer => self.client.track(request(span)),
+                _ => self.client.track(dependency(span)),
+            }
+        }
+        Ok(())
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.client.flush_channel();
+        Ok(())
+    }
+}
+
+/// Converts a span that represents an incoming call to a request telemetry item.
+fn request(span: SpanData) -> RequestTelemetry {
+    let mut telemetry = RequestTelemetry::new(
+        conventions::method(&span),
+        conventions::request_uri(&span),
+        duration(&span),
+        conventions::response_code(&span),
+    );
+    telemetry.set_name(conventions::request_name(&span));
+    telemetry.set_id(span.span_context.span_id().to_string());
+    if let Some(ip) = conventions::client_address(&span) {
+        telemetry.tags_mut().location_mut().set_ip(ip.into_owned());
+    }
+    match span.status {
+        Status::Ok => telemetry.set_success(true),
+        Status::Error { .. } => telemetry.set_success(false),
+        Status::Unset => {}
+    }
+
+    attach(telemetry, span)
+}
+
+/// Converts a span that represents an outgoing or internal call to a dependency telemetry item.
+fn dependency(span: SpanData) -> RemoteDependencyTelemetry {
+    let success = !matches!(span.status, Status::Error { .. });
+    let mut telemetry = RemoteDependencyTelemetry::new(
+        conventions::dependency_name(&span),
+        conventions::dependency_type(&span),
+        duration(&span),
+        conventions::dependency_target(&span),
+        success,
+    );
+    telemetry.set_id(span.span_context.span_id().to_string());
+    if let Some(result_code) = conventions::dependency_result_code(&span) {
+        telemetry.set_result_code(result_code);
+    }
+    if let Some(data) = conventions::dependency_data(&span) {
+        telemetry.set_data(data);
+    }
+
+    attach(telemetry, span)
+}
+
+/// Attaches span timestamp, operation and attributes to a telemetry item.
+fn attach<T: Telemetry>(mut telemetry: T, span: SpanData) -> T {
+    telemetry.set_timestamp(span.start_time.into());
+
+    let mut operation = OperationContext::new(span.span_context.trace_id().to_string());
+    if span.parent_span_id != SpanId::INVALID {
+        operation.set_parent_id(span.parent_span_id.to_string());
+    }
+    telemetry.set_operation(&operation);
+
+    for attribute in span.attributes {
+        telemetry
+            .properties_mut()
+            .insert(attribute.key.as_str().into(), attribute.value.as_str().into_owned());
+    }
+
+    telemetry
+}
+
+/// Returns a time elapsed between span start and end.
+fn duration(span: &SpanData) -> std::time::Duration {
+    span.end_time.duration_since(span.start_time).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use ::opentelemetry::{
+        trace::{SpanContext, TraceFlags, TraceId, TraceState},
+        InstrumentationScope, KeyValue,
+    };
+    use crossbeam_queue::SegQueue;
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data, Envelope},
+        TelemetryConfig,
+    };
+
+    #[tokio::test]
+    async fn it_exports_server_span_as_request() {
+        let events = Arc::new(SegQueue::default());
+        let exporter = create_exporter(events.clone());
+
+        let span = create_span(
+            SpanKind::Server,
+            Status::Unset,
+            vec![
+                KeyValue::new("http.request.method", "POST"),
+                KeyValue::new("url.full", "https://example.com/users?id=1"),
+                KeyValue::new("http.response.status_code", 500),
+            ],
+        );
+        exporter.export(vec![span]).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(envelope.name, "Microsoft.ApplicationInsights.Request");
+        assert_eq!(envelope.time, "2019-01-02T03:04:05.000Z");
+        assert_eq!(
+            tags.get("ai.operation.id"),
+            Some(&"0af7651916cd43dd8448eb211c80319c".to_string())
+        );
+        assert_eq!(tags.get("ai.operation.parentId"), Some(&"00f067aa0ba902b7".to_string()));
+        assert_eq!(tags.get("ai.operation.name"), Some(&"POST /users".to_string()));
+
+        match envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.id, "b7ad6b7169203331");
+                assert_eq!(data.name, Some("POST /users".into()));
+                assert_eq!(data.duration, "0.00:00:00.1500000");
+                assert_eq!(data.response_code, "500");
+                assert!(!data.success);
+                assert_eq!(data.url, Some("https://example.com/users".into()));
+                assert_eq!(
+                    data.properties.unwrap().get("http.request.method"),
+                    Some(&"POST".into())
+                );
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_exports_client_span_as_dependency() {
+        let events = Arc::new(SegQueue::default());
+        let exporter = create_exporter(events.clone());
+
+        let span = create_span(
+            SpanKind::Client,
+            Status::error("timeout"),
+            vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("server.address", "db.example.com"),
+                KeyValue::new("db.query.text", "SELECT 1"),
+            ],
+        );
+        exporter.export(vec![span]).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        assert_eq!(envelope.name, "Microsoft.ApplicationInsights.RemoteDependency");
+
+        match envelope.data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.id, Some("b7ad6b7169203331".into()));
+                assert_eq!(data.name, "POST /users");
+                assert_eq!(data.type_, Some("postgresql".into()));
+                assert_eq!(data.target, Some("db.example.com".into()));
+                assert_eq!(data.data, Some("SELECT 1".into()));
+                assert_eq!(data.success, Some(false));
+                assert_eq!(data.properties.unwrap().len(), 3);
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_maps_legacy_http_attributes_of_server_span() {
+        let events = Arc::new(SegQueue::default());
+        let exporter = create_exporter(events.clone());
+
+        let span = create_span(
+            SpanKind::Server,
+            Status::Unset,
+            vec![
+                KeyValue::new("http.method", "GET"),
+                KeyValue::new("http.scheme", "http"),
+                KeyValue::new("http.host", "example.com:8080"),
+                KeyValue::new("http.target", "/users/42?details=true"),
+                KeyValue::new("http.route", "/users/{id}"),
+                KeyValue::new("http.status_code", 200),
+                KeyValue::new("http.client_ip", "10.0.0.1"),
+            ],
+        );
+        exporter.export(vec![span]).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(tags.get("ai.operation.name"), Some(&"GET /users/{id}".to_string()));
+        assert_eq!(tags.get("ai.location.ip"), Some(&"10.0.0.1".to_string()));
+
+        match envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.name, Some("GET /users/{id}".into()));
+                assert_eq!(data.url, Some("http://example.com:8080/users/42".into()));
+                assert_eq!(data.response_code, "200");
+                assert!(data.success);
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[test_case(vec![KeyValue::new("http.request.method", "GET"), KeyValue::new("url.full", "https://api.example.com:443/users?id=1"), KeyValue::new("http.response.status_code", 404)], SpanKind::Client, "GET /users", "HTTP", "api.example.com", Some("https://api.example.com:443/users?id=1"), Some("404"); "http")]
+    #[test_case(vec![KeyValue::new("db.system", "mssql"), KeyValue::new("db.name", "users"), KeyValue::new("net.peer.name", "db.local"), KeyValue::new("net.peer.port", 1433), KeyValue::new("db.statement", "SELECT 1")], SpanKind::Client, "POST /users", "SQL", "db.local:1433|users", Some("SELECT 1"), None; "database")]
+    #[test_case(vec![KeyValue::new("rpc.system", "grpc"), KeyValue::new("rpc.service", "users.UserService"), KeyValue::new("rpc.grpc.status_code", 5)], SpanKind::Client, "POST /users", "grpc", "users.UserService", None, Some("5"); "rpc")]
+    #[test_case(vec![KeyValue::new("messaging.system", "kafka"), KeyValue::new("messaging.destination.name", "users"), KeyValue::new("server.address", "broker.local")], SpanKind::Producer, "POST /users", "Queue Message | kafka", "broker.local/users", None, None; "messaging")]
+    #[tokio::test]
+    async fn it_maps_semantic_conventions_of_dependency(
+        attributes: Vec<KeyValue>,
+        span_kind: SpanKind,
+        name: &str,
+        dependency_type: &str,
+        target: &str,
+        data: Option<&str>,
+        result_code: Option<&str>,
+    ) {
+        let events = Arc::new(SegQueue::default());
+        let exporter = create_exporter(events.clone());
+
+        exporter
+            .export(vec![create_span(span_kind, Status::Unset, attributes)])
+            .await
+            .unwrap();
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RemoteDependencyData(telemetry))) => {
+                assert_eq!(telemetry.name, name);
+                assert_eq!(telemetry.type_, Some(dependency_type.into()));
+                assert_eq!(telemetry.target, Some(target.into()));
+                assert_eq!(telemetry.data, data.map(Into::into));
+                assert_eq!(telemetry.result_code, result_code.map(Into::into));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_exports_internal_span_as_in_proc_dependency() {
+        let events = Arc::new(SegQueue::default());
+        let exporter = create_exporter(events.clone());
+
+        exporter
+            .export(vec![create_span(SpanKind::Internal, Status::Ok, vec![])])
+            .await
+            .unwrap();
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RemoteDependencyData(data))) => {
+                assert_eq!(data.type_, Some("InProc".into()));
+                assert_eq!(data.success, Some(true));
+            }
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+
+    fn create_exporter(events: Arc<SegQueue<Envelope>>) -> AppInsightsExporter {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        AppInsightsExporter::new(TelemetryClient::with_channel(&config, TestChannel::new(events)))
+    }
+
+    fn create_span(span_kind: SpanKind, status: Status, attributes: Vec<KeyValue>) -> SpanData {
+        let start_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_546_398_245);
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap(),
+                SpanId::from_hex("b7ad6b7169203331").unwrap(),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            parent_span_is_remote: false,
+            span_kind,
+            name: "POST /users".into(),
+            start_time,
+            end_time: start_time + Duration::from_millis(150),
+            attributes,
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status,
+            instrumentation_scope: InstrumentationScope::default(),
+        }
+    }
+}
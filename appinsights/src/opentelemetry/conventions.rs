@@ -0,0 +1,233 @@
+//! Translation of OpenTelemetry semantic convention attributes to Application Insights fields as
+//! Azure Monitor exporters do it. Both stable attribute names and the ones they replaced are
+//! recognized, so spans of older instrumentations are mapped as well.
+use std::borrow::Cow;
+
+use ::opentelemetry::trace::SpanKind;
+use http::{Method, Uri};
+use opentelemetry_sdk::trace::SpanData;
+
+const HTTP_METHOD: &[&str] = &["http.request.method", "http.method"];
+const HTTP_STATUS_CODE: &[&str] = &["http.response.status_code", "http.status_code"];
+const HTTP_ROUTE: &[&str] = &["http.route"];
+const URL_FULL: &[&str] = &["url.full", "http.url"];
+const URL_SCHEME: &[&str] = &["url.scheme", "http.scheme"];
+const URL_PATH: &[&str] = &["url.path"];
+const URL_QUERY: &[&str] = &["url.query"];
+const HTTP_TARGET: &[&str] = &["http.target"];
+const HOST_ADDRESS: &[&str] = &["server.address", "http.host", "net.host.name"];
+const PEER_ADDRESS: &[&str] = &["server.address", "net.peer.name", "net.sock.peer.addr", "net.peer.ip"];
+const PEER_PORT: &[&str] = &["server.port", "net.peer.port"];
+const CLIENT_ADDRESS: &[&str] = &["client.address", "http.client_ip", "net.sock.peer.addr", "net.peer.ip"];
+const DB_SYSTEM: &[&str] = &["db.system.name", "db.system"];
+const DB_STATEMENT: &[&str] = &["db.query.text", "db.statement", "db.operation.name", "db.operation"];
+const DB_NAME: &[&str] = &["db.namespace", "db.name"];
+const RPC_SYSTEM: &[&str] = &["rpc.system"];
+const RPC_SERVICE: &[&str] = &["rpc.service"];
+const RPC_GRPC_STATUS_CODE: &[&str] = &["rpc.grpc.status_code"];
+const MESSAGING_SYSTEM: &[&str] = &["messaging.system"];
+const MESSAGING_DESTINATION: &[&str] = &["messaging.destination.name", "messaging.destination"];
+
+/// Database systems reported as `SQL` dependencies.
+const SQL_SYSTEMS: &[&str] = &[
+    "db2",
+    "derby",
+    "h2",
+    "hsqldb",
+    "mariadb",
+    "mssql",
+    "microsoft.sql_server",
+    "oracle",
+    "oracle.db",
+    "other_sql",
+    "sqlite",
+];
+
+/// A semantic convention a span follows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Convention {
+    Http,
+    Database,
+    Rpc,
+    Messaging,
+    Other,
+}
+
+impl Convention {
+    fn of(span: &SpanData) -> Self {
+        if attribute(span, HTTP_METHOD).is_some() {
+            Convention::Http
+        } else if attribute(span, DB_SYSTEM).is_some() {
+            Convention::Database
+        } else if attribute(span, RPC_SYSTEM).is_some() {
+            Convention::Rpc
+        } else if attribute(span, MESSAGING_SYSTEM).is_some() {
+            Convention::Messaging
+        } else {
+            Convention::Other
+        }
+    }
+}
+
+/// Returns an HTTP method of a span, or `GET` when it is not an HTTP span.
+pub(super) fn method(span: &SpanData) -> Method {
+    attribute(span, HTTP_METHOD)
+        .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        .unwrap_or(Method::GET)
+}
+
+/// Returns a URL of an incoming request. It is either taken as is or assembled from a scheme, host
+/// and path attributes.
+pub(super) fn request_uri(span: &SpanData) -> Uri {
+    if let Some(uri) = attribute(span, URL_FULL).and_then(|uri| uri.parse().ok()) {
+        return uri;
+    }
+
+    let target = match attribute(span, URL_PATH) {
+        Some(path) => match attribute(span, URL_QUERY) {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.into_owned(),
+        },
+        None => attribute(span, HTTP_TARGET).map(Cow::into_owned).unwrap_or_default(),
+    };
+    let uri = match (attribute(span, URL_SCHEME), attribute(span, HOST_ADDRESS)) {
+        (Some(scheme), Some(host)) => format!("{}://{}{}", scheme, host, target),
+        _ => target,
+    };
+    uri.parse().unwrap_or_else(|_| Uri::from_static("/"))
+}
+
+/// Returns a name of an incoming request. HTTP requests are named after a method and a route
+/// template or a path, all other requests are named after the span.
+pub(super) fn request_name(span: &SpanData) -> String {
+    if Convention::of(span) != Convention::Http {
+        return span.name.to_string();
+    }
+
+    let method = method(span);
+    match attribute(span, HTTP_ROUTE) {
+        Some(route) => format!("{} {}", method, route),
+        None => format!("{} {}", method, request_uri(span).path()),
+    }
+}
+
+/// Returns a response code of an incoming request, or `0` when the span has none.
+pub(super) fn response_code(span: &SpanData) -> String {
+    attribute(span, HTTP_STATUS_CODE)
+        .or_else(|| attribute(span, RPC_GRPC_STATUS_CODE))
+        .map(Cow::into_owned)
+        .unwrap_or_else(|| "0".into())
+}
+
+/// Returns an IP address of a client that made an incoming request.
+pub(super) fn client_address(span: &SpanData) -> Option<Cow<'_, str>> {
+    attribute(span, CLIENT_ADDRESS)
+}
+
+/// Returns a name of an outgoing call. HTTP calls are named after a method and a path, all other
+/// calls are named after the span.
+pub(super) fn dependency_name(span: &SpanData) -> String {
+    match (Convention::of(span), attribute(span, URL_FULL)) {
+        (Convention::Http, Some(url)) => match url.parse::<Uri>() {
+            Ok(uri) => format!("{} {}", method(span), uri.path()),
+            Err(_) => span.name.to_string(),
+        },
+        _ => span.name.to_string(),
+    }
+}
+
+/// Returns a dependency type of an outgoing or internal call.
+pub(super) fn dependency_type(span: &SpanData) -> String {
+    match Convention::of(span) {
+        Convention::Http => "HTTP".into(),
+        Convention::Database => {
+            let system = attribute(span, DB_SYSTEM).unwrap_or_default();
+            if SQL_SYSTEMS.contains(&system.as_ref()) {
+                "SQL".into()
+            } else {
+                system.into_owned()
+            }
+        }
+        Convention::Rpc => attribute(span, RPC_SYSTEM).unwrap_or_default().into_owned(),
+        Convention::Messaging => {
+            let system = attribute(span, MESSAGING_SYSTEM).unwrap_or_default();
+            if span.span_kind == SpanKind::Producer {
+                format!("Queue Message | {}", system)
+            } else {
+                system.into_owned()
+            }
+        }
+        Convention::Other if span.span_kind == SpanKind::Internal => "InProc".into(),
+        Convention::Other => "Other".into(),
+    }
+}
+
+/// Returns a target of an outgoing call: a host with a non-default port of an HTTP call, a server
+/// with a database name, a server with a queue name and so on.
+pub(super) fn dependency_target(span: &SpanData) -> String {
+    let peer = attribute(span, PEER_ADDRESS).map(|host| match attribute(span, PEER_PORT) {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.into_owned(),
+    });
+
+    match Convention::of(span) {
+        Convention::Http => attribute(span, URL_FULL)
+            .and_then(|url| url.parse::<Uri>().ok())
+            .and_then(|uri| authority(&uri))
+            .or(peer)
+            .unwrap_or_default(),
+        Convention::Database => match (peer, attribute(span, DB_NAME)) {
+            (Some(peer), Some(name)) => format!("{}|{}", peer, name),
+            (Some(peer), None) => peer,
+            (None, Some(name)) => name.into_owned(),
+            (None, None) => attribute(span, DB_SYSTEM).unwrap_or_default().into_owned(),
+        },
+        Convention::Rpc => peer
+            .or_else(|| attribute(span, RPC_SERVICE).map(Cow::into_owned))
+            .unwrap_or_else(|| attribute(span, RPC_SYSTEM).unwrap_or_default().into_owned()),
+        Convention::Messaging => match (peer, attribute(span, MESSAGING_DESTINATION)) {
+            (Some(peer), Some(destination)) => format!("{}/{}", peer, destination),
+            (Some(peer), None) => peer,
+            (None, Some(destination)) => destination.into_owned(),
+            (None, None) => attribute(span, MESSAGING_SYSTEM).unwrap_or_default().into_owned(),
+        },
+        Convention::Other => peer.unwrap_or_default(),
+    }
+}
+
+/// Returns a command of an outgoing call: a URL of an HTTP call or a database statement.
+pub(super) fn dependency_data(span: &SpanData) -> Option<Cow<'_, str>> {
+    match Convention::of(span) {
+        Convention::Http => attribute(span, URL_FULL),
+        Convention::Database => attribute(span, DB_STATEMENT),
+        _ => None,
+    }
+}
+
+/// Returns a result code of an outgoing call.
+pub(super) fn dependency_result_code(span: &SpanData) -> Option<Cow<'_, str>> {
+    match Convention::of(span) {
+        Convention::Http => attribute(span, HTTP_STATUS_CODE),
+        Convention::Rpc => attribute(span, RPC_GRPC_STATUS_CODE),
+        _ => None,
+    }
+}
+
+/// Returns a host of a URL with a port unless it is the default one of the scheme.
+fn authority(uri: &Uri) -> Option<String> {
+    let host = uri.host()?;
+    match (uri.scheme_str(), uri.port_u16()) {
+        (Some("http"), Some(80)) | (Some("https"), Some(443)) | (_, None) => Some(host.into()),
+        (_, Some(port)) => Some(format!("{}:{}", host, port)),
+    }
+}
+
+/// Returns a value of the first attribute found among specified keys.
+fn attribute<'a>(span: &'a SpanData, keys: &[&str]) -> Option<Cow<'a, str>> {
+    keys.iter().find_map(|key| {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == *key)
+            .map(|attribute| attribute.value.as_str())
+    })
+}
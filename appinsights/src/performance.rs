@@ -0,0 +1,137 @@
+//! Module for collecting performance counters of the current process and the machine it runs on.
+//!
+//! Counters are submitted as [`MetricTelemetry`](../telemetry/struct.MetricTelemetry.html) items with
+//! standard Application Insights names, so they are displayed in the performance counters section of
+//! the portal. A client samples counters periodically when it is configured with
+//! [`perf_counters`](../struct.TelemetryConfigBuilder.html#method.perf_counters) interval.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use std::time::Duration;
+//! use appinsights::{TelemetryClient, TelemetryConfig};
+//!
+//! # async fn run() {
+//! let config = TelemetryConfig::builder()
+//!     .i_key("<instrumentation key>")
+//!     .perf_counters(Duration::from_secs(60))
+//!     .build_unchecked();
+//!
+//! // counters are submitted every minute while the client is alive
+//! let client = TelemetryClient::from_config(config);
+//! # }
+//! ```
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::telemetry::MetricTelemetry;
+
+/// A percentage of CPU time consumed by the current process across all processors.
+pub const PROCESS_CPU_TIME: &str = r"\Process(??APP_WIN32_PROC??)\% Processor Time";
+
+/// A percentage of CPU time consumed by the current process normalized by the number of processors.
+pub const PROCESS_CPU_TIME_NORMALIZED: &str = r"\Process(??APP_WIN32_PROC??)\% Processor Time Normalized";
+
+/// Memory in bytes used by the current process.
+pub const PROCESS_PRIVATE_BYTES: &str = r"\Process(??APP_WIN32_PROC??)\Private Bytes";
+
+/// A percentage of CPU time consumed by all processes on the machine.
+pub const PROCESSOR_TIME: &str = r"\Processor(_Total)\% Processor Time";
+
+/// Physical memory in bytes available on the machine.
+pub const AVAILABLE_MEMORY: &str = r"\Memory\Available Bytes";
+
+/// Samples performance counters of the current process and the machine it runs on.
+pub struct PerformanceCounterCollector {
+    system: System,
+    pid: Option<Pid>,
+}
+
+impl PerformanceCounterCollector {
+    /// Creates a new collector. CPU usage is measured between consecutive calls to
+    /// [`collect`](#method.collect), starting from the moment the collector is created.
+    pub fn new() -> Self {
+        let mut collector = Self {
+            system: System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+        };
+        collector.refresh();
+        collector
+    }
+
+    /// Samples counters and returns them as metric telemetry items. Counters that are not available on
+    /// the current platform are omitted.
+    pub fn collect(&mut self) -> Vec<MetricTelemetry> {
+        self.refresh();
+
+        let mut metrics = vec![
+            MetricTelemetry::new(PROCESSOR_TIME, f64::from(self.system.global_cpu_usage())),
+            MetricTelemetry::new(AVAILABLE_MEMORY, self.system.available_memory() as f64),
+        ];
+
+        if let Some(process) = self.pid.and_then(|pid| self.system.process(pid)) {
+            let cpu_usage = f64::from(process.cpu_usage());
+            let cpus = self.system.cpus().len().max(1) as f64;
+            metrics.push(MetricTelemetry::new(PROCESS_CPU_TIME, cpu_usage));
+            metrics.push(MetricTelemetry::new(PROCESS_CPU_TIME_NORMALIZED, cpu_usage / cpus));
+            metrics.push(MetricTelemetry::new(PROCESS_PRIVATE_BYTES, process.memory() as f64));
+        }
+
+        metrics
+    }
+
+    fn refresh(&mut self) {
+        self.system.refresh_cpu_usage();
+        self.system.refresh_memory();
+        if let Some(pid) = self.pid {
+            self.system.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                true,
+                ProcessRefreshKind::nothing().with_cpu().with_memory(),
+            );
+        }
+    }
+}
+
+impl Default for PerformanceCounterCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::TelemetryContext,
+        contracts::{Base, Data, Envelope},
+        telemetry::{ContextTags, Properties},
+    };
+
+    #[test]
+    fn it_collects_counters_of_current_process() {
+        let mut collector = PerformanceCounterCollector::new();
+
+        let names: Vec<_> = collector
+            .collect()
+            .into_iter()
+            .map(|telemetry| {
+                let context =
+                    TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+                match Envelope::from((context, telemetry)).data {
+                    Some(Base::Data(Data::MetricData(data))) => data.metrics[0].name.clone(),
+                    data => panic!("unexpected telemetry data {:?}", data),
+                }
+            })
+            .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                PROCESSOR_TIME,
+                AVAILABLE_MEMORY,
+                PROCESS_CPU_TIME,
+                PROCESS_CPU_TIME_NORMALIZED,
+                PROCESS_PRIVATE_BYTES
+            ]
+        );
+    }
+}
@@ -0,0 +1,641 @@
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::Uri;
+use log::error;
+use serde::Serialize;
+
+use crate::{
+    contracts::{Base, Data, Envelope},
+    sampling::{self, SamplingStrategy},
+    telemetry::{panic_message, SeverityLevel, Telemetry},
+};
+
+/// A telemetry processor receives every telemetry item tracked by a client before it is queued
+/// for submission. It can modify an item, for example strip personal data from URLs or attach
+/// extra properties, or drop it entirely.
+///
+/// Any closure that accepts a mutable reference to a [`TelemetryItem`](struct.TelemetryItem.html)
+/// and returns a [`ProcessResult`](enum.ProcessResult.html) is a telemetry processor.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// use appinsights::{ProcessResult, TelemetryItem};
+///
+/// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+///
+/// // drop health check requests
+/// client.add_processor(|item: &mut TelemetryItem| match item.url() {
+///     Some(url) if url.ends_with("/health") => ProcessResult::Drop,
+///     _ => ProcessResult::Continue,
+/// });
+///
+/// // attach a custom property to every telemetry item
+/// client.add_processor(|item: &mut TelemetryItem| {
+///     if let Some(properties) = item.properties_mut() {
+///         properties.insert("deployment".to_string(), "canary".to_string());
+///     }
+///     ProcessResult::Continue
+/// });
+/// ```
+pub trait TelemetryProcessor: Send + Sync {
+    /// Processes a telemetry item and decides whether it should be submitted.
+    fn process(&self, item: &mut TelemetryItem) -> ProcessResult;
+}
+
+impl<F> TelemetryProcessor for F
+where
+    F: Fn(&mut TelemetryItem) -> ProcessResult + Send + Sync,
+{
+    fn process(&self, item: &mut TelemetryItem) -> ProcessResult {
+        self(item)
+    }
+}
+
+/// A telemetry processor that drops request telemetry items by URL path, e.g. to get rid of health
+/// checks a load balancer sends every few seconds. Query strings are ignored. Other telemetry items
+/// are passed through.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// use appinsights::RequestFilter;
+///
+/// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+/// client.add_processor(RequestFilter::exclude_url_paths(&["/health", "/ready"]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestFilter {
+    excluded_paths: Vec<String>,
+}
+
+impl RequestFilter {
+    /// Creates a filter that drops requests to any of specified URL paths.
+    pub fn exclude_url_paths(paths: &[&str]) -> Self {
+        Self {
+            excluded_paths: paths.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+}
+
+impl TelemetryProcessor for RequestFilter {
+    fn process(&self, item: &mut TelemetryItem) -> ProcessResult {
+        let url = match &item.0.data {
+            Some(Base::Data(Data::RequestData(data))) => data.url.as_deref(),
+            _ => None,
+        };
+        let path = url.and_then(|url| url.parse::<Uri>().ok());
+
+        match path {
+            Some(uri) if self.excluded_paths.iter().any(|path| path == uri.path()) => ProcessResult::Drop,
+            _ => ProcessResult::Continue,
+        }
+    }
+}
+
+/// A telemetry processor that assigns a severity level to exception telemetry items by type name of
+/// their exceptions, e.g. to report errors of `NotFound` kind as warnings. The first rule with a pattern
+/// contained in a type name of any exception in the chain wins. Exceptions with a severity level set
+/// explicitly are left as is, as well as other telemetry items.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// use appinsights::{telemetry::SeverityLevel, ExceptionSeverity};
+///
+/// let mut client = TelemetryClient::new("00000000-0000-0000-0000-000000000000".to_string());
+/// client.add_processor(
+///     ExceptionSeverity::default()
+///         .rule("NotFound", SeverityLevel::Warning)
+///         .rule("Timeout", SeverityLevel::Error),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ExceptionSeverity {
+    rules: Vec<(String, SeverityLevel)>,
+}
+
+impl ExceptionSeverity {
+    /// Adds a rule that assigns specified severity level to exceptions with type name containing the pattern.
+    pub fn rule(mut self, pattern: &str, severity: SeverityLevel) -> Self {
+        self.rules.push((pattern.into(), severity));
+        self
+    }
+}
+
+impl TelemetryProcessor for ExceptionSeverity {
+    fn process(&self, item: &mut TelemetryItem) -> ProcessResult {
+        if let Some(Base::Data(Data::ExceptionData(data))) = &mut item.0.data {
+            if data.severity_level.is_none() {
+                let severity = self.rules.iter().find_map(|(pattern, severity)| {
+                    data.exceptions
+                        .iter()
+                        .any(|exception| exception.type_name.contains(pattern.as_str()))
+                        .then_some(*severity)
+                });
+                data.severity_level = severity.map(Into::into);
+            }
+        }
+        ProcessResult::Continue
+    }
+}
+
+/// Describes a decision made by a telemetry processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessResult {
+    /// A telemetry item is passed to the next processor and eventually submitted.
+    Continue,
+
+    /// A telemetry item is discarded. Subsequent processors will not receive it.
+    Drop,
+}
+
+/// A telemetry item as it is going to be submitted to the server. It serializes to the same JSON that
+/// is submitted to the server.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct TelemetryItem(Envelope);
+
+impl TelemetryItem {
+    /// Wraps an envelope to submit to the server.
+    pub(crate) fn from_envelope(envelope: Envelope) -> Self {
+        Self(envelope)
+    }
+
+    /// Returns an envelope to submit to the server.
+    pub(crate) fn into_envelope(self) -> Envelope {
+        self.0
+    }
+
+    /// Returns a sampling percentage the telemetry item was sampled with if any.
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        self.0.sample_rate
+    }
+
+    /// Sets a sampling percentage the telemetry item was sampled with.
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.0.sample_rate = Some(sample_rate);
+    }
+
+    /// Returns a name of the telemetry item type, e.g. `Microsoft.ApplicationInsights.Request`.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Returns a type of telemetry data the item carries, e.g. `MessageData` or `RequestData`.
+    pub fn base_type(&self) -> Option<&str> {
+        match &self.0.data {
+            Some(Base::Data(data)) => Some(match data {
+                Data::AvailabilityData(_) => "AvailabilityData",
+                Data::EventData(_) => "EventData",
+                Data::ExceptionData(_) => "ExceptionData",
+                Data::MessageData(_) => "MessageData",
+                Data::MetricData(_) => "MetricData",
+                Data::PageViewData(_) => "PageViewData",
+                Data::RemoteDependencyData(_) => "RemoteDependencyData",
+                Data::RequestData(_) => "RequestData",
+            }),
+            None => None,
+        }
+    }
+
+    /// Returns a kind of telemetry the item carries or `None` when an envelope has no data, so processors
+    /// can tell items apart without matching on their data.
+    pub fn kind(&self) -> Option<TelemetryKind> {
+        self.0.data.as_ref().map(|Base::Data(data)| match data {
+            Data::AvailabilityData(_) => TelemetryKind::Availability,
+            Data::EventData(_) => TelemetryKind::Event,
+            Data::ExceptionData(_) => TelemetryKind::Exception,
+            Data::MessageData(_) => TelemetryKind::Trace,
+            Data::MetricData(_) => TelemetryKind::Metric,
+            Data::PageViewData(_) => TelemetryKind::PageView,
+            Data::RemoteDependencyData(_) => TelemetryKind::Dependency,
+            Data::RequestData(_) => TelemetryKind::Request,
+        })
+    }
+
+    /// Returns a severity level of trace and exception telemetry items.
+    pub fn severity_level(&self) -> Option<SeverityLevel> {
+        let severity = match &self.0.data {
+            Some(Base::Data(Data::MessageData(data))) => data.severity_level.clone(),
+            Some(Base::Data(Data::ExceptionData(data))) => data.severity_level.clone(),
+            _ => None,
+        };
+        severity.map(SeverityLevel::from)
+    }
+
+    /// Returns the time when the telemetry item was measured or `None` when it is malformed, e.g. in an
+    /// envelope submitted with [`track_envelope`](struct.TelemetryClient.html#method.track_envelope).
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.0.time)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+    }
+
+    /// Overrides the time when the telemetry item was measured.
+    pub fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.0.time = timestamp.to_rfc3339_opts(SecondsFormat::Millis, true);
+    }
+
+    /// Returns an instrumentation key the telemetry item is submitted with.
+    pub fn i_key(&self) -> Option<&str> {
+        self.0.i_key.as_deref()
+    }
+
+    /// Sets an instrumentation key the telemetry item is submitted with.
+    pub fn set_i_key(&mut self, i_key: impl Into<String>) {
+        self.0.i_key = Some(i_key.into());
+    }
+
+    /// Returns context tags attached to the telemetry item.
+    pub fn tags(&self) -> Option<&BTreeMap<String, String>> {
+        self.0.tags.as_ref()
+    }
+
+    /// Returns mutable reference to context tags attached to the telemetry item.
+    pub fn tags_mut(&mut self) -> &mut BTreeMap<String, String> {
+        self.0.tags.get_or_insert_with(BTreeMap::default)
+    }
+
+    /// Returns custom properties attached to the telemetry item.
+    pub fn properties(&self) -> Option<&BTreeMap<String, String>> {
+        match &self.0.data {
+            Some(Base::Data(data)) => match data {
+                Data::AvailabilityData(data) => data.properties.as_ref(),
+                Data::EventData(data) => data.properties.as_ref(),
+                Data::ExceptionData(data) => data.properties.as_ref(),
+                Data::MessageData(data) => data.properties.as_ref(),
+                Data::MetricData(data) => data.properties.as_ref(),
+                Data::PageViewData(data) => data.properties.as_ref(),
+                Data::RemoteDependencyData(data) => data.properties.as_ref(),
+                Data::RequestData(data) => data.properties.as_ref(),
+            },
+            None => None,
+        }
+    }
+
+    /// Returns mutable reference to custom properties attached to the telemetry item.
+    pub fn properties_mut(&mut self) -> Option<&mut BTreeMap<String, String>> {
+        let properties = match &mut self.0.data {
+            Some(Base::Data(data)) => match data {
+                Data::AvailabilityData(data) => &mut data.properties,
+                Data::EventData(data) => &mut data.properties,
+                Data::ExceptionData(data) => &mut data.properties,
+                Data::MessageData(data) => &mut data.properties,
+                Data::MetricData(data) => &mut data.properties,
+                Data::PageViewData(data) => &mut data.properties,
+                Data::RemoteDependencyData(data) => &mut data.properties,
+                Data::RequestData(data) => &mut data.properties,
+            },
+            None => return None,
+        };
+        Some(properties.get_or_insert_with(BTreeMap::default))
+    }
+
+    /// Returns a URL of request and page view telemetry items.
+    pub fn url(&self) -> Option<&str> {
+        match &self.0.data {
+            Some(Base::Data(Data::RequestData(data))) => data.url.as_deref(),
+            Some(Base::Data(Data::PageViewData(data))) => data.url.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Sets a URL of request and page view telemetry items. Other telemetry items remain unchanged.
+    pub fn set_url(&mut self, url: impl Into<String>) {
+        match &mut self.0.data {
+            Some(Base::Data(Data::RequestData(data))) => data.url = Some(url.into()),
+            Some(Base::Data(Data::PageViewData(data))) => data.url = Some(url.into()),
+            _ => {}
+        }
+    }
+}
+
+/// A kind of telemetry a [`TelemetryItem`](struct.TelemetryItem.html) carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryKind {
+    /// An availability test result.
+    Availability,
+
+    /// A custom event.
+    Event,
+
+    /// An exception.
+    Exception,
+
+    /// A trace message.
+    Trace,
+
+    /// A metric.
+    Metric,
+
+    /// A page view.
+    PageView,
+
+    /// A remote dependency call.
+    Dependency,
+
+    /// An incoming request.
+    Request,
+}
+
+/// Prepares telemetry items for submission: discards trace telemetry items of low severity, runs all
+/// registered processors and applies sampling.
+#[derive(Clone)]
+pub struct Pipeline {
+    processors: Vec<Arc<dyn TelemetryProcessor>>,
+    sampling_rate: f64,
+    sampling_strategy: Option<Arc<dyn SamplingStrategy>>,
+    min_trace_severity: Arc<AtomicU8>,
+}
+
+impl Pipeline {
+    /// Creates a new pipeline that sends only specified percentage of telemetry items and trace
+    /// telemetry items of at least specified severity.
+    pub fn new(sampling_rate: f64, min_trace_severity: SeverityLevel) -> Self {
+        Self {
+            processors: Vec::default(),
+            sampling_rate,
+            sampling_strategy: None,
+            min_trace_severity: Arc::new(AtomicU8::new(min_trace_severity as u8)),
+        }
+    }
+
+    /// Changes a minimum severity of trace telemetry items for this pipeline and all its clones.
+    pub fn set_min_trace_severity(&self, severity: SeverityLevel) {
+        self.min_trace_severity.store(severity as u8, Ordering::Relaxed);
+    }
+
+    /// Determines whether a telemetry event passes severity filter. It is cheap enough to be called
+    /// before an event is converted to a telemetry item.
+    pub fn accepts<E: Telemetry>(&self, event: &E) -> bool {
+        let min_severity = self.min_trace_severity.load(Ordering::Relaxed);
+        event
+            .trace_severity()
+            .is_none_or(|severity| severity as u8 >= min_severity)
+    }
+
+    /// Appends a processor to the end of processors chain.
+    pub fn add_processor(&mut self, processor: impl TelemetryProcessor + 'static) {
+        self.processors.push(Arc::new(processor));
+    }
+
+    /// Replaces a fixed sampling rate with a custom sampling strategy.
+    pub fn set_sampling_strategy(&mut self, strategy: Box<dyn SamplingStrategy>) {
+        self.sampling_strategy = Some(Arc::from(strategy));
+    }
+
+    /// Returns a telemetry item to submit or `None` when it was dropped. An item a processor panicked on
+    /// may be left half-modified, so it is dropped too, while the panic is reported by a panic hook if any.
+    pub fn process(&self, envelope: Envelope, never_sample: bool) -> Option<TelemetryItem> {
+        let mut item = TelemetryItem(envelope);
+        for processor in &self.processors {
+            match panic::catch_unwind(AssertUnwindSafe(|| processor.process(&mut item))) {
+                Ok(ProcessResult::Continue) => {}
+                Ok(ProcessResult::Drop) => return None,
+                Err(payload) => {
+                    error!(
+                        "Telemetry processor panicked: {}. Dropping telemetry item {}",
+                        panic_message(payload.as_ref()),
+                        item.name()
+                    );
+                    return None;
+                }
+            }
+        }
+
+        let sampled = match &self.sampling_strategy {
+            Some(strategy) => sampling::sample_with(&mut item, strategy.as_ref()),
+            None => sampling::sample(&mut item.0, self.sampling_rate),
+        };
+        if never_sample || sampled {
+            Some(item)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::contracts::{self, EventData, ExceptionData, ExceptionDetails, MessageData, RequestData};
+
+    #[test]
+    fn it_runs_processors_in_order() {
+        let mut pipeline = Pipeline::new(100.0, SeverityLevel::Verbose);
+        pipeline.add_processor(|item: &mut TelemetryItem| {
+            item.properties_mut().unwrap().insert("step".into(), "first".into());
+            ProcessResult::Continue
+        });
+        pipeline.add_processor(|item: &mut TelemetryItem| {
+            item.properties_mut().unwrap().insert("step".into(), "second".into());
+            item.set_i_key("00000000-0000-0000-0000-000000000001");
+            ProcessResult::Continue
+        });
+
+        let item = pipeline.process(event_envelope(), false).unwrap();
+
+        assert_eq!(item.i_key(), Some("00000000-0000-0000-0000-000000000001"));
+        assert_eq!(item.properties().unwrap().get("step"), Some(&"second".to_string()));
+    }
+
+    #[test]
+    fn it_stops_processing_when_item_dropped() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut pipeline = Pipeline::new(100.0, SeverityLevel::Verbose);
+        pipeline.add_processor(|_: &mut TelemetryItem| ProcessResult::Drop);
+        pipeline.add_processor({
+            let calls = calls.clone();
+            move |_: &mut TelemetryItem| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                ProcessResult::Continue
+            }
+        });
+
+        assert!(pipeline.process(event_envelope(), false).is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_assigns_exception_severity_by_type_name() {
+        let processor = ExceptionSeverity::default()
+            .rule("NotFound", SeverityLevel::Warning)
+            .rule("Error", SeverityLevel::Critical);
+        let exception = |type_name: &str| {
+            TelemetryItem(Envelope {
+                data: Some(Base::Data(Data::ExceptionData(ExceptionData {
+                    exceptions: vec![ExceptionDetails {
+                        type_name: type_name.into(),
+                        ..ExceptionDetails::default()
+                    }],
+                    ..ExceptionData::default()
+                }))),
+                ..Envelope::default()
+            })
+        };
+        let severity = |mut item: TelemetryItem| {
+            assert_eq!(processor.process(&mut item), ProcessResult::Continue);
+            match item.0.data {
+                Some(Base::Data(Data::ExceptionData(data))) => data.severity_level,
+                data => panic!("unexpected telemetry data {:?}", data),
+            }
+        };
+
+        assert_eq!(
+            severity(exception("UserNotFoundError")),
+            Some(contracts::SeverityLevel::Warning)
+        );
+        assert_eq!(
+            severity(exception("ParseIntError")),
+            Some(contracts::SeverityLevel::Critical)
+        );
+        assert_eq!(severity(exception("Panic")), None);
+    }
+
+    #[test]
+    fn it_returns_kind_of_telemetry() {
+        let pipeline = Pipeline::new(100.0, SeverityLevel::Verbose);
+
+        let item = pipeline.process(event_envelope(), false).unwrap();
+
+        assert_eq!(item.kind(), Some(TelemetryKind::Event));
+        assert_eq!(TelemetryItem(Envelope::default()).kind(), None);
+    }
+
+    #[test]
+    fn it_drops_item_when_processor_panics() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut pipeline = Pipeline::new(100.0, SeverityLevel::Verbose);
+        pipeline.add_processor({
+            let calls = calls.clone();
+            move |_: &mut TelemetryItem| {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("processor failed");
+                }
+                ProcessResult::Continue
+            }
+        });
+
+        assert!(pipeline.process(event_envelope(), false).is_none());
+        assert!(pipeline.process(event_envelope(), false).is_some());
+    }
+
+    #[test]
+    fn it_drops_requests_to_excluded_paths() {
+        let filter = RequestFilter::exclude_url_paths(&["/health"]);
+        let request = |url: &str| {
+            TelemetryItem(Envelope {
+                data: Some(Base::Data(Data::RequestData(RequestData {
+                    url: Some(url.into()),
+                    ..RequestData::default()
+                }))),
+                ..Envelope::default()
+            })
+        };
+
+        assert_eq!(
+            filter.process(&mut request("https://example.com/health?probe=lb")),
+            ProcessResult::Drop
+        );
+        assert_eq!(
+            filter.process(&mut request("https://example.com/users")),
+            ProcessResult::Continue
+        );
+        assert_eq!(
+            filter.process(&mut TelemetryItem(event_envelope())),
+            ProcessResult::Continue
+        );
+    }
+
+    #[test]
+    fn it_applies_sampling_after_processors() {
+        let pipeline = Pipeline::new(0.0, SeverityLevel::Verbose);
+
+        assert!(pipeline.process(event_envelope(), false).is_none());
+        assert!(pipeline.process(event_envelope(), true).is_some());
+    }
+
+    #[test]
+    fn it_applies_sampling_strategy_instead_of_sampling_rate() {
+        let mut pipeline = Pipeline::new(100.0, SeverityLevel::Verbose);
+        pipeline.set_sampling_strategy(Box::new(crate::NeverSample));
+
+        assert!(pipeline.process(event_envelope(), false).is_none());
+        assert!(pipeline.process(event_envelope(), true).is_some());
+    }
+
+    #[test]
+    fn it_overrides_timestamp() {
+        let mut item = TelemetryItem(Envelope {
+            time: "2019-01-02T03:04:05.678+02:00".into(),
+            ..event_envelope()
+        });
+        assert_eq!(item.timestamp(), Some(Utc.ymd(2019, 1, 2).and_hms_milli(1, 4, 5, 678)));
+
+        item.set_timestamp(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        assert_eq!(item.0.time, "2019-01-02T03:04:05.800Z");
+        assert_eq!(TelemetryItem(event_envelope()).timestamp(), None);
+    }
+
+    #[test]
+    fn it_updates_url_of_request() {
+        let mut item = TelemetryItem(Envelope {
+            data: Some(Base::Data(Data::RequestData(RequestData {
+                url: Some("https://example.com/users?email=john@example.com".into()),
+                ..RequestData::default()
+            }))),
+            ..Envelope::default()
+        });
+
+        item.set_url("https://example.com/users");
+
+        assert_eq!(item.url(), Some("https://example.com/users"));
+    }
+
+    #[test]
+    fn it_ignores_url_of_event() {
+        let mut item = TelemetryItem(event_envelope());
+
+        item.set_url("https://example.com/users");
+
+        assert_eq!(item.url(), None);
+    }
+
+    #[test]
+    fn it_returns_base_type_and_severity_of_trace() {
+        let item = TelemetryItem(Envelope {
+            data: Some(Base::Data(Data::MessageData(MessageData {
+                severity_level: Some(crate::contracts::SeverityLevel::Error),
+                ..MessageData::default()
+            }))),
+            ..Envelope::default()
+        });
+
+        assert_eq!(item.base_type(), Some("MessageData"));
+        assert_eq!(item.severity_level(), Some(SeverityLevel::Error));
+    }
+
+    fn event_envelope() -> Envelope {
+        Envelope {
+            name: "Microsoft.ApplicationInsights.Event".into(),
+            data: Some(Base::Data(Data::EventData(EventData::default()))),
+            ..Envelope::default()
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use log::debug;
+
+use crate::TelemetryItem;
+
+/// A name of the metric that reports how many telemetry items were discarded by a rate limiter.
+pub const DROPPED_ITEMS: &str = "DroppedItems";
+
+/// Limits the number of telemetry items passed to a channel per second with a token bucket.
+///
+/// The bucket holds at most one second worth of tokens, so short bursts are allowed. Items that exceed
+/// the rate are held in a queue of the same size and released as soon as tokens become available.
+/// Items that do not fit into the queue are discarded and counted.
+pub struct RateLimiter {
+    rate: f64,
+    capacity: usize,
+    bucket: Mutex<Bucket>,
+    held: Mutex<VecDeque<TelemetryItem>>,
+    dropped: AtomicU64,
+    reported: AtomicU64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter that passes specified number of items per second.
+    pub fn new(items_per_second: u32) -> Self {
+        let rate = f64::from(items_per_second.max(1));
+        Self {
+            rate,
+            capacity: items_per_second.max(1) as usize,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+            held: Mutex::default(),
+            dropped: AtomicU64::new(0),
+            reported: AtomicU64::new(0),
+        }
+    }
+
+    /// Accepts a new telemetry item and returns all items that can be passed to a channel right now
+    /// in the order they were accepted.
+    pub fn admit(&self, item: TelemetryItem) -> Vec<TelemetryItem> {
+        self.admit_at(item, Instant::now())
+    }
+
+    /// Returns held telemetry items that can be passed to a channel right now.
+    pub fn release(&self) -> Vec<TelemetryItem> {
+        self.release_at(Instant::now())
+    }
+
+    /// Spends a token for a telemetry item passed to a channel bypassing the limiter, so that it counts
+    /// toward the rate. The bucket may run into debt, which holds next items until it is refilled.
+    pub fn charge(&self) {
+        self.charge_at(Instant::now())
+    }
+
+    /// Returns all held telemetry items regardless of the rate, e.g. when a channel is closed.
+    pub fn drain(&self) -> Vec<TelemetryItem> {
+        self.held.lock().unwrap().drain(..).collect()
+    }
+
+    /// Returns the total number of telemetry items discarded so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of telemetry items discarded since the previous call.
+    pub fn take_dropped(&self) -> u64 {
+        let dropped = self.dropped();
+        dropped - self.reported.swap(dropped, Ordering::Relaxed)
+    }
+
+    fn admit_at(&self, item: TelemetryItem, now: Instant) -> Vec<TelemetryItem> {
+        let mut held = self.held.lock().unwrap();
+        if held.len() < self.capacity {
+            held.push_back(item);
+        } else {
+            debug!("Rate limit exceeded. Telemetry item discarded");
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.take_allowed(&mut held, now)
+    }
+
+    fn release_at(&self, now: Instant) -> Vec<TelemetryItem> {
+        let mut held = self.held.lock().unwrap();
+        self.take_allowed(&mut held, now)
+    }
+
+    fn charge_at(&self, now: Instant) {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket, now);
+        bucket.tokens -= 1.0;
+    }
+
+    /// Takes as many held items as there are tokens available.
+    fn take_allowed(&self, held: &mut VecDeque<TelemetryItem>, now: Instant) -> Vec<TelemetryItem> {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket, now);
+
+        let count = (bucket.tokens.max(0.0) as usize).min(held.len());
+        bucket.tokens -= count as f64;
+
+        let rest = held.split_off(count);
+        mem::replace(held, rest).into()
+    }
+
+    /// Adds tokens accumulated since the bucket was updated last time.
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::contracts::Envelope;
+
+    #[test]
+    fn it_holds_items_exceeding_rate_and_drops_the_rest() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+
+        let passed: usize = (0..5).map(|_| limiter.admit_at(item(), now).len()).sum();
+
+        assert_eq!(passed, 2);
+        assert_eq!(limiter.held.lock().unwrap().len(), 2);
+        assert_eq!(limiter.take_dropped(), 1);
+        assert_eq!(limiter.take_dropped(), 0);
+        assert_eq!(limiter.dropped(), 1);
+    }
+
+    #[test]
+    fn it_releases_held_items_when_tokens_refilled() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        for _ in 0..4 {
+            limiter.admit_at(item(), now);
+        }
+
+        assert_eq!(limiter.release_at(now + Duration::from_millis(500)).len(), 1);
+        assert_eq!(limiter.release_at(now + Duration::from_secs(5)).len(), 1);
+        assert!(limiter.drain().is_empty());
+    }
+
+    #[test]
+    fn it_counts_charged_items_toward_rate() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        for _ in 0..3 {
+            limiter.charge_at(now);
+        }
+
+        assert!(limiter.admit_at(item(), now).is_empty());
+        assert!(limiter.release_at(now + Duration::from_millis(500)).is_empty());
+        assert_eq!(limiter.release_at(now + Duration::from_secs(1)).len(), 1);
+    }
+
+    fn item() -> TelemetryItem {
+        TelemetryItem::from_envelope(Envelope::default())
+    }
+}
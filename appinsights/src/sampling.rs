@@ -0,0 +1,369 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{contracts::Envelope, telemetry::OperationContext, uuid, TelemetryItem};
+
+/// A tag telemetry items are correlated with.
+const OPERATION_ID_TAG: &str = "ai.operation.id";
+
+/// A tag with an identifier of the telemetry item's immediate parent.
+const OPERATION_PARENT_ID_TAG: &str = "ai.operation.parentId";
+
+/// A lowest sampling percentage adaptive sampling goes down to, so that some telemetry is sent anyway.
+const MIN_ADAPTIVE_SAMPLING_RATE: f64 = 0.1;
+
+/// Decides which telemetry items are sent when a fixed sampling rate is not enough, e.g. to keep all
+/// failed requests or sample differently per tenant.
+///
+/// A strategy returns whether to keep an item and an effective sampling percentage it was kept with,
+/// so that the portal can compensate counts. A strategy can rely on an operation identifier to keep
+/// or drop correlated telemetry items together. Items never sampled, such as metrics, and items
+/// already sampled by the application do not reach a strategy.
+///
+/// # Examples
+/// ```rust, no_run
+/// use appinsights::{
+///     telemetry::OperationContext, FixedRateSampling, SamplingStrategy, TelemetryClient, TelemetryItem,
+///     TelemetryKind,
+/// };
+///
+/// struct KeepExceptions(FixedRateSampling);
+///
+/// impl SamplingStrategy for KeepExceptions {
+///     fn should_sample(&self, item: &TelemetryItem, operation_context: &OperationContext) -> (bool, f64) {
+///         match item.kind() {
+///             Some(TelemetryKind::Exception) => (true, 100.0),
+///             _ => self.0.should_sample(item, operation_context),
+///         }
+///     }
+/// }
+///
+/// let client = TelemetryClient::new("<instrumentation key>".to_string())
+///     .with_sampling_strategy(Box::new(KeepExceptions(FixedRateSampling(10.0))));
+/// ```
+pub trait SamplingStrategy: Send + Sync {
+    /// Returns whether a telemetry item should be sent and a sampling percentage it is sent with.
+    fn should_sample(&self, item: &TelemetryItem, operation_context: &OperationContext) -> (bool, f64);
+}
+
+/// Sends specified percentage of operations. All telemetry items of an operation are either sent or
+/// discarded together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedRateSampling(pub f64);
+
+impl SamplingStrategy for FixedRateSampling {
+    fn should_sample(&self, _item: &TelemetryItem, operation_context: &OperationContext) -> (bool, f64) {
+        (self.0 >= 100.0 || score(operation_context.id()) < self.0, self.0)
+    }
+}
+
+/// Sends all telemetry items.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlwaysSample;
+
+impl SamplingStrategy for AlwaysSample {
+    fn should_sample(&self, _item: &TelemetryItem, _operation_context: &OperationContext) -> (bool, f64) {
+        (true, 100.0)
+    }
+}
+
+/// Discards all telemetry items that are subject to sampling.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NeverSample;
+
+impl SamplingStrategy for NeverSample {
+    fn should_sample(&self, _item: &TelemetryItem, _operation_context: &OperationContext) -> (bool, f64) {
+        (false, 0.0)
+    }
+}
+
+/// Adjusts a sampling percentage once a second to keep the number of sent telemetry items close to
+/// the target. It starts by sending all items and never goes below 0.1 percent.
+#[derive(Debug)]
+pub struct AdaptiveSampling {
+    target_items_per_second: u32,
+    window: Mutex<Window>,
+}
+
+/// Telemetry items observed since the sampling percentage was adjusted last time.
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    items: u32,
+    sampling_rate: f64,
+}
+
+impl AdaptiveSampling {
+    /// Creates a strategy that aims to send specified number of telemetry items per second.
+    pub fn new(target_items_per_second: u32) -> Self {
+        Self {
+            target_items_per_second,
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                items: 0,
+                sampling_rate: 100.0,
+            }),
+        }
+    }
+
+    /// Returns a target number of telemetry items sent per second.
+    pub fn target_items_per_second(&self) -> u32 {
+        self.target_items_per_second
+    }
+
+    /// Returns a current sampling percentage.
+    pub fn sampling_rate(&self) -> f64 {
+        self.window.lock().unwrap().sampling_rate
+    }
+
+    /// Accounts a telemetry item observed at specified time and returns a sampling percentage to apply.
+    fn observe_at(&self, now: Instant) -> f64 {
+        let mut window = self.window.lock().unwrap();
+        window.items = window.items.saturating_add(1);
+
+        let elapsed = now.saturating_duration_since(window.started);
+        if elapsed >= Duration::from_secs(1) {
+            let observed = f64::from(window.items) / elapsed.as_secs_f64();
+            let rate = f64::from(self.target_items_per_second) / observed * 100.0;
+            window.sampling_rate = rate.clamp(MIN_ADAPTIVE_SAMPLING_RATE, 100.0);
+            window.started = now;
+            window.items = 0;
+        }
+
+        window.sampling_rate
+    }
+}
+
+impl SamplingStrategy for AdaptiveSampling {
+    fn should_sample(&self, item: &TelemetryItem, operation_context: &OperationContext) -> (bool, f64) {
+        let rate = self.observe_at(Instant::now());
+        FixedRateSampling(rate).should_sample(item, operation_context)
+    }
+}
+
+/// Decides whether a telemetry item should be sent according to specified strategy. An item that
+/// passes is marked with the sampling rate returned by the strategy. An item already sampled by the
+/// application keeps its sampling rate and is always sent.
+pub fn sample_with(item: &mut TelemetryItem, strategy: &dyn SamplingStrategy) -> bool {
+    if item.sample_rate().is_some_and(|rate| rate < 100.0) {
+        return true;
+    }
+
+    let tags = item.tags();
+    let mut operation = match tags.and_then(|tags| tags.get(OPERATION_ID_TAG)) {
+        Some(operation_id) => OperationContext::new(operation_id.as_str()),
+        None => OperationContext::new_root(),
+    };
+    if let Some(parent_id) = tags.and_then(|tags| tags.get(OPERATION_PARENT_ID_TAG)) {
+        operation.set_parent_id(parent_id.as_str());
+    }
+
+    let (keep, sampling_rate) = strategy.should_sample(item, &operation);
+    if keep && sampling_rate < 100.0 {
+        item.set_sample_rate(sampling_rate);
+    }
+    keep
+}
+
+/// Decides whether a telemetry item should be sent when only specified percentage of items is sent.
+/// An item that passes is marked with the sampling rate, so that the portal can compensate counts.
+/// An item already sampled by the application keeps its sampling rate and is always sent.
+pub fn sample(envelope: &mut Envelope, sampling_rate: f64) -> bool {
+    if sampling_rate >= 100.0 || envelope.sample_rate.is_some_and(|rate| rate < 100.0) {
+        return true;
+    }
+
+    let operation_id = envelope.tags.as_ref().and_then(|tags| tags.get(OPERATION_ID_TAG));
+    let score = match operation_id {
+        Some(operation_id) => score(operation_id),
+        None => score(&uuid::new().to_simple().to_string()),
+    };
+
+    if score < sampling_rate {
+        envelope.sample_rate = Some(sampling_rate);
+        true
+    } else {
+        false
+    }
+}
+
+/// Calculates a stable sampling score in range from `0` to `100` for specified operation id. It uses
+/// the same algorithm as other Application Insights SDKs do, so that correlated telemetry items sent by
+/// different services get the same score.
+fn score(operation_id: &str) -> f64 {
+    if operation_id.is_empty() {
+        return 0.0;
+    }
+
+    let mut input = operation_id.to_string();
+    while input.len() < 8 {
+        input.push_str(operation_id);
+    }
+
+    let hash = input.encode_utf16().fold(5381_i32, |hash, c| {
+        (hash << 5).wrapping_add(hash).wrapping_add(i32::from(c))
+    });
+
+    f64::from(hash).abs() / f64::from(i32::MAX) * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, str::FromStr};
+
+    use test_case::test_case;
+
+    use super::*;
+    use crate::uuid::Uuid;
+
+    #[test_case("", 0.0; "empty")]
+    #[test_case("a", score("aaaaaaaa"); "short id repeated")]
+    fn it_calculates_score(operation_id: &str, expected: f64) {
+        assert_eq!(score(operation_id), expected);
+    }
+
+    #[test]
+    fn it_calculates_score_within_range() {
+        for i in 0..1000 {
+            let score = score(&format!("operation{}", i));
+            assert!((0.0..=100.0).contains(&score), "{}", score);
+        }
+    }
+
+    #[test]
+    fn it_samples_correlated_items_together() {
+        let sampled: Vec<_> = (0..100)
+            .map(|_| {
+                let id = Uuid::new_v4().to_simple().to_string();
+                let first = sample(&mut envelope(Some(&id)), 50.0);
+                let second = sample(&mut envelope(Some(&id)), 50.0);
+                assert_eq!(first, second);
+                first
+            })
+            .collect();
+
+        let kept = sampled.iter().filter(|sampled| **sampled).count();
+        assert!(kept > 20 && kept < 80, "{}", kept);
+    }
+
+    #[test]
+    fn it_marks_sampled_items_with_sampling_rate() {
+        uuid::set(Uuid::from_str("910b414a-f368-4b3a-aff6-326632aac566").unwrap());
+        let mut envelope = envelope(None);
+
+        assert!(sample(&mut envelope, 99.999));
+        assert_eq!(envelope.sample_rate, Some(99.999));
+    }
+
+    #[test]
+    fn it_keeps_items_sampled_by_application() {
+        let mut envelope = envelope(Some("0af7651916cd43dd8448eb211c80319c"));
+        envelope.sample_rate = Some(10.0);
+
+        assert!(sample(&mut envelope, 0.0));
+        assert_eq!(envelope.sample_rate, Some(10.0));
+    }
+
+    #[test_case(100.0, true; "all")]
+    #[test_case(0.0, false; "none")]
+    fn it_samples_items(sampling_rate: f64, expected: bool) {
+        let mut envelope = envelope(Some("0af7651916cd43dd8448eb211c80319c"));
+
+        assert_eq!(sample(&mut envelope, sampling_rate), expected);
+        if expected {
+            assert_eq!(envelope.sample_rate, Some(100.0));
+        }
+    }
+
+    #[test_case(&AlwaysSample, true, Some(100.0); "always")]
+    #[test_case(&NeverSample, false, None; "never")]
+    #[test_case(&FixedRateSampling(0.0), false, None; "fixed rate none")]
+    #[test_case(&FixedRateSampling(100.0), true, Some(100.0); "fixed rate all")]
+    fn it_samples_items_with_strategy(strategy: &dyn SamplingStrategy, expected: bool, sample_rate: Option<f64>) {
+        let mut item = TelemetryItem::from_envelope(envelope(Some("0af7651916cd43dd8448eb211c80319c")));
+        item.set_sample_rate(100.0);
+
+        assert_eq!(sample_with(&mut item, strategy), expected);
+        if expected {
+            assert_eq!(item.sample_rate(), sample_rate);
+        }
+    }
+
+    #[test]
+    fn it_passes_sampled_operation_to_strategy() {
+        struct KeepOperation;
+
+        impl SamplingStrategy for KeepOperation {
+            fn should_sample(&self, _item: &TelemetryItem, operation_context: &OperationContext) -> (bool, f64) {
+                let keep = operation_context.id() == "0af7651916cd43dd8448eb211c80319c"
+                    && operation_context.parent_id() == Some("b7ad6b7169203331");
+                (keep, 25.0)
+            }
+        }
+
+        let mut item = TelemetryItem::from_envelope(envelope(Some("0af7651916cd43dd8448eb211c80319c")));
+        item.tags_mut()
+            .insert(OPERATION_PARENT_ID_TAG.into(), "b7ad6b7169203331".into());
+
+        assert!(sample_with(&mut item, &KeepOperation));
+        assert_eq!(item.sample_rate(), Some(25.0));
+        assert!(!sample_with(
+            &mut TelemetryItem::from_envelope(envelope(None)),
+            &KeepOperation
+        ));
+    }
+
+    #[test]
+    fn it_keeps_items_sampled_by_application_with_strategy() {
+        let mut item = TelemetryItem::from_envelope(envelope(None));
+        item.set_sample_rate(10.0);
+
+        assert!(sample_with(&mut item, &NeverSample));
+        assert_eq!(item.sample_rate(), Some(10.0));
+    }
+
+    #[test]
+    fn it_adjusts_adaptive_sampling_rate_to_observed_throughput() {
+        let sampling = AdaptiveSampling::new(10);
+        let started = sampling.window.lock().unwrap().started;
+
+        for _ in 0..39 {
+            assert_eq!(sampling.observe_at(started + Duration::from_millis(500)), 100.0);
+        }
+        assert_eq!(sampling.observe_at(started + Duration::from_secs(2)), 50.0);
+
+        for _ in 0..4999 {
+            sampling.observe_at(started + Duration::from_millis(2500));
+        }
+        let rate = sampling.observe_at(started + Duration::from_secs(3));
+        assert!((rate - 0.2).abs() < 1e-9, "{}", rate);
+
+        assert_eq!(sampling.observe_at(started + Duration::from_secs(13)), 100.0);
+        assert_eq!(sampling.sampling_rate(), 100.0);
+    }
+
+    #[test]
+    fn it_keeps_adaptive_sampling_rate_above_minimum() {
+        let sampling = AdaptiveSampling::new(0);
+        let started = sampling.window.lock().unwrap().started;
+
+        assert_eq!(
+            sampling.observe_at(started + Duration::from_secs(1)),
+            MIN_ADAPTIVE_SAMPLING_RATE
+        );
+    }
+
+    fn envelope(operation_id: Option<&str>) -> Envelope {
+        let mut tags = BTreeMap::default();
+        if let Some(operation_id) = operation_id {
+            tags.insert(OPERATION_ID_TAG.to_string(), operation_id.to_string());
+        }
+
+        Envelope {
+            tags: Some(tags),
+            ..Envelope::default()
+        }
+    }
+}
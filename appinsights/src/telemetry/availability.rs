@@ -7,7 +7,7 @@ use crate::{
     contracts::{AvailabilityData, Base, Data, Envelope},
     telemetry::{ContextTags, Measurements, Properties, Telemetry},
     time::{self, Duration},
-    uuid::Uuid,
+    uuid,
 };
 
 /// Represents the result of executing an availability test.
@@ -26,19 +26,22 @@ use crate::{
 ///      true,
 /// );
 ///
+/// // specify where the test was run from
+/// telemetry.set_run_location("West US");
+///
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("body_size".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("body_size", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AvailabilityTelemetry {
     /// Identifier of a test run.
     /// It is used to correlate steps of test run and telemetry generated by the service.
-    id: Option<Uuid>,
+    id: Option<String>,
 
     /// Name of the test that this result represents.
     name: String,
@@ -85,6 +88,21 @@ impl AvailabilityTelemetry {
         }
     }
 
+    /// Sets the test run id. A new unique identifier is generated when it is not set.
+    pub fn set_id(&mut self, id: impl Into<String>) {
+        self.id = Some(id.into());
+    }
+
+    /// Sets name of the location where the test was run.
+    pub fn set_run_location(&mut self, run_location: impl Into<String>) {
+        self.run_location = Some(run_location.into());
+    }
+
+    /// Sets diagnostic message for the result.
+    pub fn set_message(&mut self, message: impl Into<String>) {
+        self.message = Some(message.into());
+    }
+
     /// Returns custom measurements to submit with the telemetry item.
     pub fn measurements(&self) -> &Measurements {
         &self.measurements
@@ -102,6 +120,11 @@ impl Telemetry for AvailabilityTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -121,6 +144,19 @@ impl Telemetry for AvailabilityTelemetry {
     fn tags_mut(&mut self) -> &mut ContextTags {
         &mut self.tags
     }
+
+    /// Availability test results are never sampled to preserve availability figures.
+    fn never_sample(&self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Display for AvailabilityTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = if self.success { "succeeded" } else { "failed" };
+        write!(f, "[AVAILABILITY] {} {} in {:?}", self.name, result, *self.duration)?;
+        self.properties.fmt_summary(f)
+    }
 }
 
 impl From<(TelemetryContext, AvailabilityTelemetry)> for Envelope {
@@ -131,16 +167,15 @@ impl From<(TelemetryContext, AvailabilityTelemetry)> for Envelope {
             i_key: Some(context.i_key),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::AvailabilityData(AvailabilityData {
-                id: telemetry
-                    .id
-                    .map(|id| id.to_hyphenated().to_string())
-                    .unwrap_or_default(),
+                id: telemetry.id.unwrap_or_else(|| uuid::new().to_hyphenated().to_string()),
                 name: telemetry.name,
                 duration: telemetry.duration.to_string(),
                 success: telemetry.success,
                 run_location: telemetry.run_location,
                 message: telemetry.message,
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..AvailabilityData::default()
             }))),
@@ -149,17 +184,80 @@ impl From<(TelemetryContext, AvailabilityTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for AvailabilityTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::AvailabilityData(data) => Ok(Self {
+                id: Some(data.id),
+                name: data.name,
+                duration: data.duration.parse()?,
+                success: data.success,
+                timestamp,
+                run_location: data.run_location,
+                message: data.message,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("AvailabilityData")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
+    use std::str::FromStr;
+
     use chrono::TimeZone;
 
     use super::*;
+    use crate::uuid::Uuid;
+
+    #[test]
+    fn it_uses_specified_id_run_location_and_message() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry =
+            AvailabilityTelemetry::new("GET https://example.com/main.html", StdDuration::from_secs(2), false);
+        telemetry.set_id("specified-id");
+        telemetry.set_run_location("West US");
+        telemetry.set_message("connection refused");
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = Envelope {
+            name: "Microsoft.ApplicationInsights.Availability".into(),
+            time: "2019-01-02T03:04:05.800Z".into(),
+            i_key: Some("instrumentation".into()),
+            tags: Some(BTreeMap::default()),
+            data: Some(Base::Data(Data::AvailabilityData(AvailabilityData {
+                id: "specified-id".into(),
+                name: "GET https://example.com/main.html".into(),
+                duration: "0.00:00:02.0000000".into(),
+                success: false,
+                run_location: Some("West US".into()),
+                message: Some("connection refused".into()),
+                properties: Some(BTreeMap::default()),
+                measurements: Some(BTreeMap::default()),
+                ..AvailabilityData::default()
+            }))),
+            ..Envelope::default()
+        };
+
+        assert_eq!(envelop, expected)
+    }
 
     #[test]
     fn it_overrides_properties_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+        uuid::set(Uuid::from_str("910b414a-f368-4b3a-aff6-326632aac566").unwrap());
 
         let mut context =
             TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
@@ -169,7 +267,7 @@ mod tests {
         let mut telemetry =
             AvailabilityTelemetry::new("GET https://example.com/main.html", StdDuration::from_secs(2), true);
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("latency".into(), 200.0);
+        telemetry.measurements_mut().insert("latency", 200.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
@@ -179,6 +277,7 @@ mod tests {
             i_key: Some("instrumentation".into()),
             tags: Some(BTreeMap::default()),
             data: Some(Base::Data(Data::AvailabilityData(AvailabilityData {
+                id: "910b414a-f368-4b3a-aff6-326632aac566".into(),
                 name: "GET https://example.com/main.html".into(),
                 duration: "0.00:00:02.0000000".into(),
                 success: true,
@@ -205,6 +304,7 @@ mod tests {
     #[test]
     fn it_overrides_tags_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 700));
+        uuid::set(Uuid::from_str("910b414a-f368-4b3a-aff6-326632aac566").unwrap());
 
         let mut context =
             TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
@@ -227,6 +327,7 @@ mod tests {
                 tags
             }),
             data: Some(Base::Data(Data::AvailabilityData(AvailabilityData {
+                id: "910b414a-f368-4b3a-aff6-326632aac566".into(),
                 name: "GET https://example.com/main.html".into(),
                 duration: "0.00:00:02.0000000".into(),
                 success: true,
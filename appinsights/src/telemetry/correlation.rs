@@ -0,0 +1,132 @@
+/// Custom properties propagated across service boundaries together with an operation by
+/// `Correlation-Context` or W3C `baggage` headers. Both headers carry comma-separated `key=value`
+/// entries.
+///
+/// Entries of a correlation context attached to an [`OperationContext`](struct.OperationContext.html)
+/// are recorded as custom properties of all telemetry items attached to the operation.
+///
+/// # Examples
+/// ```rust
+/// use appinsights::telemetry::CorrelationContext;
+///
+/// let mut correlation = CorrelationContext::from_header("tenant=contoso, region=westus");
+/// correlation.insert("plan", "premium");
+///
+/// assert_eq!(correlation.get("tenant"), Some("contoso"));
+/// assert_eq!(correlation.to_header(), "tenant=contoso,region=westus,plan=premium");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CorrelationContext {
+    entries: Vec<(String, String)>,
+}
+
+impl CorrelationContext {
+    /// Creates an empty correlation context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a `Correlation-Context` or `baggage` header value. Entries that are not in `key=value`
+    /// format are skipped, as well as `baggage` entry metadata that follows a `;`. Values are kept as is.
+    pub fn from_header(header: &str) -> Self {
+        let mut correlation = Self::new();
+        correlation.extend_from_header(header);
+        correlation
+    }
+
+    /// Adds entries of a `Correlation-Context` or `baggage` header value overriding the ones with the
+    /// same keys, e.g. to merge both headers of a request.
+    pub fn extend_from_header(&mut self, header: &str) {
+        let entries = header
+            .split(',')
+            .filter_map(|entry| entry.split(';').next())
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty());
+
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    /// Returns a header value with all entries to propagate the context to downstream services.
+    pub fn to_header(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Returns a value of an entry with specified key if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Adds an entry or replaces a value of an existing one with the same key.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        match self.entries.iter_mut().find(|(entry, _)| *entry == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    /// Keeps only entries with specified keys.
+    pub fn retain_keys<K: AsRef<str>>(&mut self, keys: &[K]) {
+        self.entries
+            .retain(|(entry, _)| keys.iter().any(|key| key.as_ref() == entry));
+    }
+
+    /// Returns an iterator over keys and values of all entries in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns a number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` when the context has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("tenant=contoso,region=westus", &[("tenant", "contoso"), ("region", "westus")]; "correlation context")]
+    #[test_case(" tenant = contoso ;ttl=30, region=westus", &[("tenant", "contoso"), ("region", "westus")]; "baggage with metadata")]
+    #[test_case("tenant=contoso,,malformed,=empty,tenant=fabrikam", &[("tenant", "fabrikam")]; "malformed and duplicate entries")]
+    #[test_case("", &[]; "empty")]
+    fn it_parses_header(header: &str, expected: &[(&str, &str)]) {
+        let correlation = CorrelationContext::from_header(header);
+
+        assert_eq!(correlation.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn it_serializes_entries_back_to_header() {
+        let mut correlation = CorrelationContext::from_header("tenant=contoso, region=westus");
+        correlation.extend_from_header("region=eastus,plan=premium");
+
+        assert_eq!(correlation.to_header(), "tenant=contoso,region=eastus,plan=premium");
+    }
+
+    #[test]
+    fn it_retains_allowed_keys() {
+        let mut correlation = CorrelationContext::from_header("tenant=contoso,secret=42,region=westus");
+
+        correlation.retain_keys(&["region", "tenant"]);
+
+        assert_eq!(correlation.to_header(), "tenant=contoso,region=westus");
+    }
+}
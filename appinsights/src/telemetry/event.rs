@@ -1,12 +1,20 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+};
+
 use chrono::{DateTime, SecondsFormat, Utc};
 
 use crate::{
     context::TelemetryContext,
     contracts::{Base, Data, Envelope, EventData},
-    telemetry::{ContextTags, Measurements, Properties, Telemetry},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, Telemetry},
     time,
 };
 
+/// Maximum number of characters in an event name accepted by the server.
+pub(crate) const MAX_EVENT_NAME_LENGTH: usize = 512;
+
 /// Represents structured event records.
 ///
 /// # Examples
@@ -21,12 +29,12 @@ use crate::{
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("records_count".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("records_count", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EventTelemetry {
     /// Event name.
     name: String,
@@ -40,22 +48,37 @@ pub struct EventTelemetry {
     /// Telemetry context containing extra, optional tags.
     tags: ContextTags,
 
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
     /// Custom measurements.
     measurements: Measurements,
 }
 
 impl EventTelemetry {
-    /// Creates an event telemetry item with specified name.
+    /// Creates an event telemetry item with specified name. The server discards events with names
+    /// longer than 512 characters, use [`try_new`](#method.try_new) to check the name in advance.
     pub fn new(name: impl Into<String>) -> Self {
         Self {
             name: name.into(),
             timestamp: time::now(),
             properties: Properties::default(),
             tags: ContextTags::default(),
+            sampling_percentage: None,
             measurements: Measurements::default(),
         }
     }
 
+    /// Creates an event telemetry item with specified name. Returns an error when the name is longer
+    /// than 512 characters, so the server would discard the event.
+    pub fn try_new(name: impl Into<String>) -> Result<Self, NameTooLongError> {
+        let name = name.into();
+        match name.chars().count() {
+            length if length > MAX_EVENT_NAME_LENGTH => Err(NameTooLongError(length)),
+            _ => Ok(Self::new(name)),
+        }
+    }
+
     /// Returns custom measurements to submit with the telemetry item.
     pub fn measurements(&self) -> &Measurements {
         &self.measurements
@@ -65,14 +88,50 @@ impl EventTelemetry {
     pub fn measurements_mut(&mut self) -> &mut Measurements {
         &mut self.measurements
     }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
+}
+
+/// Describes an error occurred when an event name is longer than 512 characters accepted by the server.
+/// Contains the number of characters in the name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTooLongError(pub usize);
+
+impl Display for NameTooLongError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "event name of {} characters exceeds {} characters",
+            self.0, MAX_EVENT_NAME_LENGTH
+        )
+    }
 }
 
+impl Error for NameTooLongError {}
+
 impl Telemetry for EventTelemetry {
     /// Returns the time when this telemetry was measured.
     fn timestamp(&self) -> DateTime<Utc> {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -94,16 +153,26 @@ impl Telemetry for EventTelemetry {
     }
 }
 
+impl std::fmt::Display for EventTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[EVENT] {}", self.name)?;
+        self.properties.fmt_summary(f)
+    }
+}
+
 impl From<(TelemetryContext, EventTelemetry)> for Envelope {
     fn from((context, telemetry): (TelemetryContext, EventTelemetry)) -> Self {
         Self {
             name: "Microsoft.ApplicationInsights.Event".into(),
             time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
             i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::EventData(EventData {
                 name: telemetry.name,
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..EventData::default()
             }))),
@@ -112,14 +181,36 @@ impl From<(TelemetryContext, EventTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for EventTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::EventData(data) => Ok(Self {
+                name: data.name,
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("EventData")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use chrono::TimeZone;
+    use serde_json::json;
 
     use super::*;
-    use crate::time;
+    use crate::{telemetry::ErrorPolicy, time};
 
     #[test]
     fn it_overrides_properties_from_context() {
@@ -132,7 +223,7 @@ mod tests {
 
         let mut telemetry = EventTelemetry::new("test");
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("value".into(), 5.0);
+        telemetry.measurements_mut().insert("value", 5.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
@@ -162,6 +253,27 @@ mod tests {
         assert_eq!(envelop, expected)
     }
 
+    #[test]
+    fn it_discards_long_properties_when_context_rejects_them() {
+        let mut context =
+            TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        context.set_error_policy(ErrorPolicy::Reject);
+        context.properties_mut().insert("k".repeat(200), "fail".into());
+
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry.properties_mut().insert("test".into(), "ok".into());
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let properties = match envelop.data {
+            Some(Base::Data(Data::EventData(data))) => data.properties,
+            _ => None,
+        };
+        let mut expected = BTreeMap::default();
+        expected.insert("test".to_string(), "ok".to_string());
+        assert_eq!(properties, Some(expected));
+    }
+
     #[test]
     fn it_overrides_tags_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 700));
@@ -197,4 +309,89 @@ mod tests {
 
         assert_eq!(envelop, expected)
     }
+
+    #[test]
+    fn it_uses_custom_timestamp() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry.set_timestamp(Utc.ymd(2019, 1, 1).and_hms_milli(23, 59, 59, 100));
+
+        let envelop = Envelope::from((context, telemetry));
+
+        assert_eq!(envelop.time, "2019-01-01T23:59:59.100Z");
+    }
+
+    #[test]
+    fn it_rejects_too_long_name() {
+        assert!(EventTelemetry::try_new("a".repeat(512)).is_ok());
+        assert_eq!(
+            EventTelemetry::try_new("ä".repeat(513)).unwrap_err(),
+            NameTooLongError(513)
+        );
+    }
+
+    #[test]
+    fn it_uses_custom_sampling_percentage() {
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = EventTelemetry::new("test");
+        assert_eq!(
+            telemetry.set_sampling_percentage(0.0),
+            Err(SamplingPercentageError(0.0))
+        );
+        assert_eq!(
+            telemetry.set_sampling_percentage(100.1),
+            Err(SamplingPercentageError(100.1))
+        );
+        telemetry.set_sampling_percentage(25.0).unwrap();
+
+        let envelop = Envelope::from((context, telemetry));
+
+        assert_eq!(envelop.sample_rate, Some(25.0));
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            std::convert::TryFrom::try_from(envelop).map(|telemetry: EventTelemetry| telemetry.sampling_percentage()),
+            Ok(Some(25.0))
+        );
+    }
+
+    #[test]
+    fn it_serializes_to_event_data_schema() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry
+            .properties_mut()
+            .insert("component".into(), "data_processor".into());
+        telemetry.measurements_mut().insert("records_count", 115.0).unwrap();
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = json!({
+            "ver": 1,
+            "name": "Microsoft.ApplicationInsights.Event",
+            "time": "2019-01-02T03:04:05.600Z",
+            "sampleRate": 100.0,
+            "seq": null,
+            "iKey": "instrumentation",
+            "flags": null,
+            "tags": {},
+            "data": {
+                "baseType": "EventData",
+                "baseData": {
+                    "ver": 2,
+                    "name": "test",
+                    "properties": {
+                        "component": "data_processor"
+                    },
+                    "measurements": {
+                        "records_count": 115.0
+                    }
+                }
+            }
+        });
+        assert_eq!(serde_json::to_value(envelop).unwrap(), expected)
+    }
 }
@@ -1 +1,519 @@
-// TODO implement exception collection telemetry item
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    error::Error,
+    panic::PanicHookInfo,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{
+    context::TelemetryContext,
+    contracts::{Base, Data, Envelope, ExceptionData, ExceptionDetails},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, SeverityLevel, Telemetry},
+    time,
+};
+
+/// Represents a handled or unhandled exception that occurred during execution of the monitored
+/// application. An exception telemetry item has a chain of [`ExceptionDetail`](struct.ExceptionDetail.html)
+/// starting from the outermost one.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+/// use appinsights::telemetry::{ExceptionTelemetry, SeverityLevel, Telemetry};
+///
+/// let err = "not a number".parse::<i32>().unwrap_err();
+///
+/// // create a telemetry item
+/// let mut telemetry = ExceptionTelemetry::from_std_error(&err);
+/// telemetry.set_severity(SeverityLevel::Error);
+///
+/// // attach custom properties, measurements and context tags
+/// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
+/// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
+/// telemetry.measurements_mut().insert("records_count", 115.0).unwrap();
+///
+/// // submit telemetry item to server
+/// client.track(telemetry);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExceptionTelemetry {
+    /// A chain of exceptions starting from the outermost one.
+    exceptions: Vec<ExceptionDetail>,
+
+    /// Severity level.
+    severity: Option<SeverityLevel>,
+
+    /// The time stamp when this telemetry was measured.
+    timestamp: DateTime<Utc>,
+
+    /// Custom properties.
+    properties: Properties,
+
+    /// Telemetry context containing extra, optional tags.
+    tags: ContextTags,
+
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
+    /// Custom measurements.
+    measurements: Measurements,
+}
+
+impl ExceptionTelemetry {
+    /// Creates an exception telemetry item with specified type name and message.
+    pub fn new(type_name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::with_exceptions(vec![ExceptionDetail::new(type_name, message)])
+    }
+
+    /// Creates an exception telemetry item from an error. It walks the error's source chain
+    /// and produces one [`ExceptionDetail`](struct.ExceptionDetail.html) per level. A type name of each
+    /// level is derived from its `Debug` representation.
+    pub fn from_std_error(err: &dyn Error) -> Self {
+        let mut exceptions = vec![ExceptionDetail::from_std_error(err)];
+
+        let mut source = err.source();
+        while let Some(err) = source {
+            exceptions.push(ExceptionDetail::from_std_error(err));
+            source = err.source();
+        }
+
+        Self::with_exceptions(exceptions)
+    }
+
+    /// Creates an exception telemetry item from an `anyhow` error. It walks the error's chain of context
+    /// and sources and produces one [`ExceptionDetail`](struct.ExceptionDetail.html) per level. The
+    /// outermost one carries a backtrace as its stack trace when it was captured.
+    #[cfg(feature = "anyhow")]
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        let mut exceptions: Vec<_> = err
+            .chain()
+            .map(|cause| ExceptionDetail::from_std_error(cause as &dyn Error))
+            .collect();
+
+        let backtrace = err.backtrace();
+        if backtrace.status() == BacktraceStatus::Captured {
+            if let Some(exception) = exceptions.first_mut() {
+                exception.set_stack_trace(backtrace.to_string());
+            }
+        }
+
+        Self::with_exceptions(exceptions)
+    }
+
+    /// Creates a critical exception telemetry item from a panic. Its message is the panic payload and
+    /// its stack trace contains the location of the panic followed by a backtrace when backtraces are
+    /// enabled with `RUST_BACKTRACE` environment variable.
+    pub fn from_panic(info: &PanicHookInfo<'_>) -> Self {
+        let message = panic_message(info.payload());
+
+        let mut stack_trace: Vec<_> = info
+            .location()
+            .map(|location| format!("at {}", location))
+            .into_iter()
+            .collect();
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            stack_trace.push(backtrace.to_string());
+        }
+
+        let mut exception = ExceptionDetail::new("panic", message);
+        if !stack_trace.is_empty() {
+            exception.set_stack_trace(stack_trace.join("\n"));
+        }
+
+        let mut telemetry = Self::with_exceptions(vec![exception]);
+        telemetry.set_severity(SeverityLevel::Critical);
+        telemetry
+    }
+
+    fn with_exceptions(exceptions: Vec<ExceptionDetail>) -> Self {
+        Self {
+            exceptions,
+            severity: Option::default(),
+            timestamp: time::now(),
+            properties: Properties::default(),
+            tags: ContextTags::default(),
+            sampling_percentage: None,
+            measurements: Measurements::default(),
+        }
+    }
+
+    /// Returns a chain of exceptions starting from the outermost one.
+    pub fn exceptions(&self) -> &[ExceptionDetail] {
+        &self.exceptions
+    }
+
+    /// Returns mutable reference to a chain of exceptions.
+    pub fn exceptions_mut(&mut self) -> &mut Vec<ExceptionDetail> {
+        &mut self.exceptions
+    }
+
+    /// Sets severity level of the exception.
+    pub fn set_severity(&mut self, severity: SeverityLevel) {
+        self.severity = Some(severity);
+    }
+
+    /// Returns custom measurements to submit with the telemetry item.
+    pub fn measurements(&self) -> &Measurements {
+        &self.measurements
+    }
+
+    /// Returns mutable reference to custom measurements.
+    pub fn measurements_mut(&mut self) -> &mut Measurements {
+        &mut self.measurements
+    }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
+}
+
+/// Returns a message a panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => (*message).to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "Box<dyn Any>".into(),
+    }
+}
+
+impl Telemetry for ExceptionTelemetry {
+    /// Returns the time when this telemetry was measured.
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
+    /// Returns custom properties to submit with the telemetry item.
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Returns mutable reference to custom properties.
+    fn properties_mut(&mut self) -> &mut Properties {
+        &mut self.properties
+    }
+
+    /// Returns context data containing extra, optional tags. Overrides values found on client telemetry context.
+    fn tags(&self) -> &ContextTags {
+        &self.tags
+    }
+
+    /// Returns mutable reference to custom tags.
+    fn tags_mut(&mut self) -> &mut ContextTags {
+        &mut self.tags
+    }
+}
+
+impl std::fmt::Display for ExceptionTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[EXCEPTION]")?;
+        if let Some(exception) = self.exceptions.first() {
+            write!(f, " {}: {}", exception.type_name, exception.message)?;
+        }
+        self.properties.fmt_summary(f)
+    }
+}
+
+impl From<(TelemetryContext, ExceptionTelemetry)> for Envelope {
+    fn from((context, telemetry): (TelemetryContext, ExceptionTelemetry)) -> Self {
+        let exceptions = telemetry
+            .exceptions
+            .into_iter()
+            .enumerate()
+            .map(|(id, exception)| ExceptionDetails {
+                id: Some(id as i32),
+                outer_id: if id > 0 { Some(id as i32 - 1) } else { None },
+                type_name: exception.type_name,
+                message: exception.message,
+                has_full_stack: Some(exception.stack_trace.is_some()),
+                stack: exception.stack_trace,
+                ..ExceptionDetails::default()
+            })
+            .collect();
+
+        Self {
+            name: "Microsoft.ApplicationInsights.Exception".into(),
+            time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
+            tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
+            data: Some(Base::Data(Data::ExceptionData(ExceptionData {
+                exceptions,
+                severity_level: telemetry.severity.map(Into::into),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
+                measurements: Some(telemetry.measurements.into()),
+                ..ExceptionData::default()
+            }))),
+            ..Envelope::default()
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for ExceptionTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::ExceptionData(data) => Ok(Self {
+                exceptions: data
+                    .exceptions
+                    .into_iter()
+                    .map(|exception| ExceptionDetail {
+                        type_name: exception.type_name,
+                        message: exception.message,
+                        stack_trace: exception.stack,
+                    })
+                    .collect(),
+                severity: data.severity_level.map(Into::into),
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("ExceptionData")),
+        }
+    }
+}
+
+/// Describes a single exception in a chain of exceptions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExceptionDetail {
+    /// Exception type name.
+    type_name: String,
+
+    /// Exception message.
+    message: String,
+
+    /// Text describing the stack.
+    stack_trace: Option<String>,
+}
+
+impl ExceptionDetail {
+    /// Creates a new exception details with specified type name and message.
+    pub fn new(type_name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            type_name: type_name.into(),
+            message: message.into(),
+            stack_trace: Option::default(),
+        }
+    }
+
+    fn from_std_error(err: &dyn Error) -> Self {
+        let debug = format!("{:?}", err);
+        let type_name: String = debug
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == ':')
+            .collect();
+        let type_name = if type_name.is_empty() {
+            "Error".into()
+        } else {
+            type_name
+        };
+
+        Self::new(type_name, err.to_string())
+    }
+
+    /// Returns exception type name.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// Returns exception message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns text describing the stack if any.
+    pub fn stack_trace(&self) -> Option<&str> {
+        self.stack_trace.as_deref()
+    }
+
+    /// Sets text describing the stack.
+    pub fn set_stack_trace(&mut self, stack_trace: impl Into<String>) {
+        self.stack_trace = Some(stack_trace.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt::{Display, Formatter},
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::contracts::SeverityLevel as ContractsSeverityLevel;
+
+    #[derive(Debug)]
+    struct ConfigError {
+        source: std::num::ParseIntError,
+    }
+
+    impl Display for ConfigError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "unable to read config")
+        }
+    }
+
+    impl Error for ConfigError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.source)
+        }
+    }
+
+    #[test]
+    fn it_walks_error_source_chain() {
+        let err = ConfigError {
+            source: "value".parse::<i32>().unwrap_err(),
+        };
+
+        let telemetry = ExceptionTelemetry::from_std_error(&err);
+
+        assert_eq!(
+            telemetry.exceptions(),
+            &[
+                ExceptionDetail::new("ConfigError", "unable to read config"),
+                ExceptionDetail::new("ParseIntError", "invalid digit found in string"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn it_walks_anyhow_error_chain() {
+        let err = anyhow::Error::new(ConfigError {
+            source: "value".parse::<i32>().unwrap_err(),
+        })
+        .context("unable to start");
+
+        let telemetry = ExceptionTelemetry::from_anyhow(&err);
+
+        let messages: Vec<_> = telemetry.exceptions().iter().map(ExceptionDetail::message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unable to start",
+                "unable to read config",
+                "invalid digit found in string"
+            ]
+        );
+        assert_eq!(telemetry.exceptions()[2].type_name(), "ParseIntError");
+    }
+
+    #[test]
+    fn it_creates_exception_data_envelope() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = ExceptionTelemetry::new("ConfigError", "unable to read config");
+        telemetry.set_severity(SeverityLevel::Critical);
+        let mut inner = ExceptionDetail::new("ParseIntError", "invalid digit found in string");
+        inner.set_stack_trace("at main.rs:10");
+        telemetry.exceptions_mut().push(inner);
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = Envelope {
+            name: "Microsoft.ApplicationInsights.Exception".into(),
+            time: "2019-01-02T03:04:05.800Z".into(),
+            i_key: Some("instrumentation".into()),
+            tags: Some(BTreeMap::default()),
+            data: Some(Base::Data(Data::ExceptionData(ExceptionData {
+                exceptions: vec![
+                    ExceptionDetails {
+                        id: Some(0),
+                        outer_id: None,
+                        type_name: "ConfigError".into(),
+                        message: "unable to read config".into(),
+                        has_full_stack: Some(false),
+                        ..ExceptionDetails::default()
+                    },
+                    ExceptionDetails {
+                        id: Some(1),
+                        outer_id: Some(0),
+                        type_name: "ParseIntError".into(),
+                        message: "invalid digit found in string".into(),
+                        has_full_stack: Some(true),
+                        stack: Some("at main.rs:10".into()),
+                        ..ExceptionDetails::default()
+                    },
+                ],
+                severity_level: Some(ContractsSeverityLevel::Critical),
+                properties: Some(BTreeMap::default()),
+                measurements: Some(BTreeMap::default()),
+                ..ExceptionData::default()
+            }))),
+            ..Envelope::default()
+        };
+
+        assert_eq!(envelop, expected)
+    }
+
+    #[test]
+    fn it_overrides_properties_from_context() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let mut context =
+            TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        context.properties_mut().insert("test".into(), "ok".into());
+        context.properties_mut().insert("no-write".into(), "fail".into());
+
+        let mut telemetry = ExceptionTelemetry::new("ConfigError", "unable to read config");
+        telemetry.properties_mut().insert("no-write".into(), "ok".into());
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = Envelope {
+            name: "Microsoft.ApplicationInsights.Exception".into(),
+            time: "2019-01-02T03:04:05.800Z".into(),
+            i_key: Some("instrumentation".into()),
+            tags: Some(BTreeMap::default()),
+            data: Some(Base::Data(Data::ExceptionData(ExceptionData {
+                exceptions: vec![ExceptionDetails {
+                    id: Some(0),
+                    type_name: "ConfigError".into(),
+                    message: "unable to read config".into(),
+                    has_full_stack: Some(false),
+                    ..ExceptionDetails::default()
+                }],
+                properties: Some({
+                    let mut properties = BTreeMap::default();
+                    properties.insert("test".into(), "ok".into());
+                    properties.insert("no-write".into(), "ok".into());
+                    properties
+                }),
+                measurements: Some(BTreeMap::default()),
+                ..ExceptionData::default()
+            }))),
+            ..Envelope::default()
+        };
+
+        assert_eq!(envelop, expected)
+    }
+}
@@ -1,15 +1,58 @@
 use std::{
     collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter},
     ops::{Deref, DerefMut},
 };
 
+use log::warn;
+
 /// Contains all measurements for telemetry to submit.
+///
+/// The server rejects measurements that are `NaN` or infinite, so such values are refused by
+/// [`insert`](#method.insert) and discarded with a warning when measurements are converted from a map.
 #[derive(Debug, Clone, Default)]
 pub struct Measurements(BTreeMap<String, f64>);
 
+impl Measurements {
+    /// Inserts a measurement. Returns an error when the value is `NaN` or infinite.
+    pub fn insert(&mut self, name: impl Into<String>, value: f64) -> Result<(), MeasurementError> {
+        let name = name.into();
+        if value.is_finite() {
+            self.0.insert(name, value);
+            Ok(())
+        } else {
+            Err(MeasurementError::NotFinite { name, value })
+        }
+    }
+}
+
+/// Removes measurements that are `NaN` or infinite.
+fn validate(measurements: BTreeMap<String, f64>) -> BTreeMap<String, f64> {
+    measurements
+        .into_iter()
+        .filter(|(name, value)| {
+            let finite = value.is_finite();
+            if !finite {
+                warn!(
+                    "Measurement {} is not a finite number and was discarded: {}",
+                    name, value
+                );
+            }
+            finite
+        })
+        .collect()
+}
+
+impl From<BTreeMap<String, f64>> for Measurements {
+    fn from(measurements: BTreeMap<String, f64>) -> Self {
+        Self(validate(measurements))
+    }
+}
+
 impl From<Measurements> for BTreeMap<String, f64> {
     fn from(measurements: Measurements) -> Self {
-        measurements.0
+        validate(measurements.0)
     }
 }
 
@@ -26,3 +69,64 @@ impl DerefMut for Measurements {
         &mut self.0
     }
 }
+
+/// Describes an error occurred when a measurement value is not accepted by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeasurementError {
+    /// A measurement value is `NaN` or infinite.
+    NotFinite {
+        /// A name of the measurement.
+        name: String,
+
+        /// A rejected value.
+        value: f64,
+    },
+}
+
+impl Display for MeasurementError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeasurementError::NotFinite { name, value } => {
+                write!(f, "measurement {} is not a finite number: {}", name, value)
+            }
+        }
+    }
+}
+
+impl Error for MeasurementError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rejects_non_finite_values_on_insert() {
+        let mut measurements = Measurements::default();
+
+        assert_eq!(measurements.insert("latency", 42.0), Ok(()));
+        assert_eq!(
+            measurements.insert("ratio", f64::INFINITY),
+            Err(MeasurementError::NotFinite {
+                name: "ratio".into(),
+                value: f64::INFINITY
+            })
+        );
+        assert!(measurements.insert("average", f64::NAN).is_err());
+
+        assert_eq!(measurements.keys().collect::<Vec<_>>(), vec!["latency"]);
+    }
+
+    #[test]
+    fn it_discards_non_finite_values_on_conversion() {
+        let mut measurements = BTreeMap::default();
+        measurements.insert("latency".to_string(), 42.0);
+        measurements.insert("ratio".to_string(), f64::NEG_INFINITY);
+
+        let mut measurements = Measurements::from(measurements);
+        assert_eq!(measurements.len(), 1);
+
+        measurements.entry("average".into()).or_insert(f64::NAN);
+        let measurements: BTreeMap<_, _> = measurements.into();
+        assert_eq!(measurements.keys().collect::<Vec<_>>(), vec!["latency"]);
+    }
+}
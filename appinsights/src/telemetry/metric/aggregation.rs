@@ -28,7 +28,7 @@ use crate::{
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AggregateMetricTelemetry {
     /// Metric name.
     name: String,
@@ -75,6 +75,11 @@ impl Telemetry for AggregateMetricTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -94,6 +99,22 @@ impl Telemetry for AggregateMetricTelemetry {
     fn tags_mut(&mut self) -> &mut ContextTags {
         &mut self.tags
     }
+
+    /// Metrics are never sampled to preserve aggregated values.
+    fn never_sample(&self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Display for AggregateMetricTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[METRIC] {} = {} (count: {}, min: {}, max: {})",
+            self.name, self.stats.value, self.stats.count, self.stats.min, self.stats.max
+        )?;
+        self.properties.fmt_summary(f)
+    }
 }
 
 impl From<(TelemetryContext, AggregateMetricTelemetry)> for Envelope {
@@ -114,7 +135,9 @@ impl From<(TelemetryContext, AggregateMetricTelemetry)> for Envelope {
                     std_dev: Some(telemetry.stats.std_dev),
                     ..DataPoint::default()
                 }],
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 ..MetricData::default()
             }))),
             ..Envelope::default()
@@ -122,11 +145,42 @@ impl From<(TelemetryContext, AggregateMetricTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for AggregateMetricTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        let mut data = match data {
+            Data::MetricData(data) => data,
+            _ => return Err(crate::telemetry::schema::unexpected("MetricData")),
+        };
+
+        match data.metrics.pop() {
+            Some(metric) if data.metrics.is_empty() && metric.kind == Some(DataPointType::Aggregation) => Ok(Self {
+                name: metric.name,
+                stats: Stats {
+                    value: metric.value,
+                    min: metric.min.unwrap_or(metric.value),
+                    max: metric.max.unwrap_or(metric.value),
+                    count: metric.count.unwrap_or(1),
+                    std_dev: metric.std_dev.unwrap_or_default(),
+                },
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+            }),
+            _ => Err("expected a single aggregation metric".into()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use chrono::TimeZone;
+    use serde_json::json;
 
     use super::*;
     use crate::time;
@@ -229,7 +283,7 @@ mod tests {
         let mut telemetry = AggregateMetricTelemetry::new("stats");
         *telemetry.stats_mut() = stats;
 
-        assert!((telemetry.stats().value - 15.0).abs() < std::f64::EPSILON);
+        assert!((telemetry.stats().value - 15.0).abs() < f64::EPSILON);
     }
 
     #[test]
@@ -253,4 +307,36 @@ mod tests {
 
         assert_eq!(telemetry.tags().len(), 1);
     }
+
+    #[test]
+    fn it_serializes_to_metric_data_schema_with_aggregation() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = AggregateMetricTelemetry::new("test");
+        telemetry.stats_mut().add_data(&[1.0, 3.0]);
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = json!({
+            "baseType": "MetricData",
+            "baseData": {
+                "ver": 2,
+                "metrics": [
+                    {
+                        "ns": null,
+                        "name": "test",
+                        "kind": "Aggregation",
+                        "value": 4.0,
+                        "count": 2,
+                        "min": 1.0,
+                        "max": 3.0,
+                        "stdDev": 1.0
+                    }
+                ],
+                "properties": {}
+            }
+        });
+        assert_eq!(serde_json::to_value(envelop).unwrap()["data"], expected)
+    }
 }
@@ -25,7 +25,7 @@ use crate::{
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MetricTelemetry {
     /// Metric name.
     name: String,
@@ -62,6 +62,11 @@ impl Telemetry for MetricTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -81,6 +86,18 @@ impl Telemetry for MetricTelemetry {
     fn tags_mut(&mut self) -> &mut ContextTags {
         &mut self.tags
     }
+
+    /// Metrics are never sampled to preserve aggregated values.
+    fn never_sample(&self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Display for MetricTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[METRIC] {} = {}", self.name, self.value)?;
+        self.properties.fmt_summary(f)
+    }
 }
 
 impl From<(TelemetryContext, MetricTelemetry)> for Envelope {
@@ -98,7 +115,9 @@ impl From<(TelemetryContext, MetricTelemetry)> for Envelope {
                     count: Some(1),
                     ..DataPoint::default()
                 }],
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 ..MetricData::default()
             }))),
             ..Envelope::default()
@@ -106,11 +125,36 @@ impl From<(TelemetryContext, MetricTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for MetricTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        let mut data = match data {
+            Data::MetricData(data) => data,
+            _ => return Err(crate::telemetry::schema::unexpected("MetricData")),
+        };
+
+        match data.metrics.pop() {
+            Some(metric) if data.metrics.is_empty() && metric.kind == Some(DataPointType::Measurement) => Ok(Self {
+                name: metric.name,
+                value: metric.value,
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+            }),
+            _ => Err("expected a single measurement metric".into()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use chrono::TimeZone;
+    use serde_json::json;
 
     use super::*;
     use crate::time;
@@ -196,4 +240,35 @@ mod tests {
 
         assert_eq!(envelop, expected)
     }
+
+    #[test]
+    fn it_serializes_to_metric_data_schema_with_measurement() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let telemetry = MetricTelemetry::new("test", 123.0);
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = json!({
+            "baseType": "MetricData",
+            "baseData": {
+                "ver": 2,
+                "metrics": [
+                    {
+                        "ns": null,
+                        "name": "test",
+                        "kind": "Measurement",
+                        "value": 123.0,
+                        "count": 1,
+                        "min": null,
+                        "max": null,
+                        "stdDev": null
+                    }
+                ],
+                "properties": {}
+            }
+        });
+        assert_eq!(serde_json::to_value(envelop).unwrap()["data"], expected)
+    }
 }
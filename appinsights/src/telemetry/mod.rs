@@ -1,37 +1,60 @@
 //! Module for Application Insights telemetry items.
+//!
+//! All telemetry items implement `Display` as a human-readable one-line summary, e.g.
+//! `[TRACE warn] disk is almost full (properties: {"disk": "c"})`, to print them while debugging,
+//! while `Debug` shows all their fields.
 mod availability;
+mod correlation;
 mod event;
 mod exception;
 mod measurements;
 mod metric;
+mod operation;
 mod page_view;
 mod properties;
 mod remote_dependency;
 mod request;
+mod sampling;
+#[cfg(feature = "serde")]
+mod schema;
 mod tags;
 mod trace;
 
 pub use availability::AvailabilityTelemetry;
-pub use event::EventTelemetry;
-pub use measurements::Measurements;
+pub use correlation::CorrelationContext;
+pub(crate) use event::MAX_EVENT_NAME_LENGTH;
+pub use event::{EventTelemetry, NameTooLongError};
+pub(crate) use exception::panic_message;
+pub use exception::{ExceptionDetail, ExceptionTelemetry};
+pub use measurements::{MeasurementError, Measurements};
 pub use metric::{AggregateMetricTelemetry, MetricTelemetry, Stats};
+pub use operation::{OperationContext, TraceparentError};
 pub use page_view::PageViewTelemetry;
-pub use properties::Properties;
+pub use properties::{ErrorPolicy, Properties, PropertyTooLongError};
+pub(crate) use remote_dependency::http_target;
 pub use remote_dependency::RemoteDependencyTelemetry;
-pub use request::RequestTelemetry;
+pub use request::{ActiveRequest, RequestTelemetry};
+pub use sampling::SamplingPercentageError;
 pub use tags::{
     ApplicationTags, CloudTags, ContextTags, DeviceTags, InternalTags, LocationTags, OperationTags, SessionTags,
-    UserTags,
+    TagsBuilder, UserTags,
 };
 pub use trace::{SeverityLevel, TraceTelemetry};
 
 use chrono::{DateTime, Utc};
 
+use crate::{context::TelemetryContext, contracts::Envelope};
+
 /// A trait that provides Application Insights telemetry items.
 pub trait Telemetry {
     /// Returns the time when this telemetry was measured.
     fn timestamp(&self) -> DateTime<Utc>;
 
+    /// Overrides the time when this telemetry was measured, e.g. to back-fill historical data or to
+    /// report the exact time of an item created long before it is submitted. The server silently
+    /// discards telemetry items with a time stamp more than 48 hours away from now.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>);
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties;
 
@@ -43,4 +66,110 @@ pub trait Telemetry {
 
     /// Returns mutable reference to custom tags.
     fn tags_mut(&mut self) -> &mut ContextTags;
+
+    /// Determines whether the telemetry item is sent regardless of a sampling rate configured for
+    /// the client. Telemetry items are subject to sampling unless overridden.
+    fn never_sample(&self) -> bool {
+        false
+    }
+
+    /// Returns a severity level of a trace telemetry item that is compared with a minimum trace
+    /// severity configured for the client. Other telemetry items are never filtered by severity.
+    fn trace_severity(&self) -> Option<SeverityLevel> {
+        None
+    }
+
+    /// Attaches the telemetry item to an operation. Overrides operation found on client telemetry context.
+    /// Entries of a correlation context the operation carries are added to custom properties.
+    fn set_operation(&mut self, operation: &OperationContext) {
+        let mut tags = self.tags_mut().operation_mut();
+        tags.set_id(operation.id().into());
+        if let Some(parent_id) = operation.parent_id() {
+            tags.set_parent_id(parent_id.into());
+        }
+
+        if let Some(correlation) = operation.correlation() {
+            for (key, value) in correlation.iter() {
+                self.properties_mut().insert(key.into(), value.into());
+            }
+        }
+    }
+}
+
+/// A telemetry item of any type that can be tracked behind a trait object, e.g. to submit telemetry
+/// items of different types together with
+/// [`track_batch`](../struct.TelemetryClient.html#method.track_batch). It is implemented for all
+/// telemetry items.
+///
+/// # Examples
+///
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+/// use appinsights::telemetry::{AnyTelemetry, EventTelemetry, SeverityLevel, TraceTelemetry};
+///
+/// let items: Vec<Box<dyn AnyTelemetry>> = vec![
+///     Box::new(EventTelemetry::new("imported")),
+///     Box::new(TraceTelemetry::new("line 42 is malformed", SeverityLevel::Warning)),
+/// ];
+/// client.track_batch(items);
+/// ```
+pub trait AnyTelemetry: Telemetry + Send {
+    /// Converts the telemetry item to an envelope with specified context.
+    #[doc(hidden)]
+    fn into_envelope(self: Box<Self>, context: TelemetryContext) -> Envelope;
+}
+
+impl<T> AnyTelemetry for T
+where
+    T: Telemetry + Send,
+    (TelemetryContext, T): Into<Envelope>,
+{
+    fn into_envelope(self: Box<Self>, context: TelemetryContext) -> Envelope {
+        (context, *self).into()
+    }
+}
+
+impl Telemetry for Box<dyn AnyTelemetry> {
+    fn timestamp(&self) -> DateTime<Utc> {
+        (**self).timestamp()
+    }
+
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        (**self).set_timestamp(timestamp)
+    }
+
+    fn properties(&self) -> &Properties {
+        (**self).properties()
+    }
+
+    fn properties_mut(&mut self) -> &mut Properties {
+        (**self).properties_mut()
+    }
+
+    fn tags(&self) -> &ContextTags {
+        (**self).tags()
+    }
+
+    fn tags_mut(&mut self) -> &mut ContextTags {
+        (**self).tags_mut()
+    }
+
+    fn never_sample(&self) -> bool {
+        (**self).never_sample()
+    }
+
+    fn trace_severity(&self) -> Option<SeverityLevel> {
+        (**self).trace_severity()
+    }
+
+    fn set_operation(&mut self, operation: &OperationContext) {
+        (**self).set_operation(operation)
+    }
+}
+
+impl From<(TelemetryContext, Box<dyn AnyTelemetry>)> for Envelope {
+    fn from((context, telemetry): (TelemetryContext, Box<dyn AnyTelemetry>)) -> Self {
+        telemetry.into_envelope(context)
+    }
 }
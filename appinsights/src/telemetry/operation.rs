@@ -0,0 +1,428 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use http::{HeaderMap, HeaderValue};
+
+use crate::{telemetry::CorrelationContext, uuid};
+
+/// A W3C Trace Context version this crate produces `traceparent` headers with.
+const TRACEPARENT_VERSION: &str = "00";
+
+/// Identifies an operation a telemetry item belongs to. Application Insights uses it to correlate
+/// requests, dependencies and other telemetry items into end-to-end transaction views.
+///
+/// Operation is attached to a telemetry item as `ai.operation.id` and `ai.operation.parentId` tags
+/// overriding values found on client telemetry context.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+/// use appinsights::telemetry::{OperationContext, RequestTelemetry, Telemetry};
+/// use http::{Method, Uri};
+/// use std::time::Duration;
+///
+/// // start a new operation
+/// let operation = OperationContext::new_root();
+///
+/// // create a telemetry item and attach it to the operation
+/// let uri: Uri = "https://api.github.com/dmolokanov/appinsights-rs".parse().unwrap();
+/// let mut telemetry = RequestTelemetry::new(Method::GET, uri, Duration::from_millis(100), "200");
+/// telemetry.set_operation(&operation);
+///
+/// // submit telemetry item to server
+/// client.track(telemetry);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OperationContext {
+    /// A unique identifier for the operation instance.
+    id: String,
+
+    /// The unique identifier of the telemetry item's immediate parent.
+    parent_id: Option<String>,
+
+    /// Custom properties propagated together with the operation.
+    correlation: Option<CorrelationContext>,
+
+    /// A number of child contexts created so far, shared by all copies of the context.
+    children: Arc<AtomicU32>,
+}
+
+impl PartialEq for OperationContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.parent_id == other.parent_id && self.correlation == other.correlation
+    }
+}
+
+impl OperationContext {
+    /// Creates an operation with specified identifier.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            parent_id: Option::default(),
+            correlation: Option::default(),
+            children: Arc::default(),
+        }
+    }
+
+    /// Creates a new root operation with a randomly generated identifier: 128 bits written as 32 hex
+    /// digits, the same format as a W3C Trace Context trace id.
+    pub fn new_root() -> Self {
+        Self::new(uuid::new().to_simple().to_string())
+    }
+
+    /// Returns a unique identifier for the operation instance.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the unique identifier of the telemetry item's immediate parent if any.
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
+
+    /// Sets the unique identifier of the telemetry item's immediate parent.
+    pub fn set_parent_id(&mut self, parent_id: impl Into<String>) {
+        self.parent_id = Some(parent_id.into());
+    }
+
+    /// Creates a context of a nested call within the same operation. Its parent id is a hierarchical
+    /// identifier of the form `<parent id>.<n>` as described by the Application Insights correlation
+    /// schema, where `<parent id>` is this context's parent id, or the operation id of a root context,
+    /// and `<n>` is the number of child contexts created from this one or its copies so far. A legacy
+    /// `Request-Id` like `|root.1.` keeps its trailing dot, so its children are `|root.1.<n>.`. Use the
+    /// parent id as an id of a dependency the nested telemetry belongs to. Correlation context entries
+    /// are inherited.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use appinsights::telemetry::OperationContext;
+    ///
+    /// let root = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+    /// let first = root.child_context();
+    /// let second = root.child_context();
+    ///
+    /// assert_eq!(first.parent_id(), Some("0af7651916cd43dd8448eb211c80319c.1"));
+    /// assert_eq!(second.parent_id(), Some("0af7651916cd43dd8448eb211c80319c.2"));
+    /// assert_eq!(first.child_context().parent_id(), Some("0af7651916cd43dd8448eb211c80319c.1.1"));
+    /// ```
+    pub fn child_context(&self) -> OperationContext {
+        let seq = self.children.fetch_add(1, Ordering::Relaxed) + 1;
+        let parent_id = self.parent_id.as_deref().unwrap_or(&self.id);
+
+        let mut child = Self::new(self.id.clone());
+        if parent_id.ends_with('.') {
+            child.set_parent_id(format!("{}{}.", parent_id, seq));
+        } else {
+            child.set_parent_id(format!("{}.{}", parent_id, seq));
+        }
+        child.correlation = self.correlation.clone();
+        child
+    }
+
+    /// Returns custom properties propagated together with the operation if any.
+    pub fn correlation(&self) -> Option<&CorrelationContext> {
+        self.correlation.as_ref()
+    }
+
+    /// Sets custom properties propagated together with the operation.
+    pub fn set_correlation(&mut self, correlation: CorrelationContext) {
+        self.correlation = Some(correlation);
+    }
+
+    /// Creates an operation from a W3C Trace Context `traceparent` header value in
+    /// `version-traceid-parentid-flags` format. A trace id becomes an operation id and a parent id
+    /// becomes an identifier of the telemetry item's immediate parent.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use appinsights::telemetry::OperationContext;
+    ///
+    /// let operation =
+    ///     OperationContext::from_traceparent("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+    ///
+    /// assert_eq!(operation.id(), "0af7651916cd43dd8448eb211c80319c");
+    /// assert_eq!(operation.parent_id(), Some("b7ad6b7169203331"));
+    /// ```
+    pub fn from_traceparent(header: &str) -> Result<Self, TraceparentError> {
+        let header = header.trim();
+        let malformed = || TraceparentError::MalformedHeader(header.into());
+
+        let mut parts = header.split('-');
+        let version = parts
+            .next()
+            .filter(|version| is_hex(version, 2))
+            .ok_or_else(malformed)?;
+        let trace_id = parts.next().ok_or_else(malformed)?;
+        let parent_id = parts.next().ok_or_else(malformed)?;
+        parts.next().filter(|flags| is_hex(flags, 2)).ok_or_else(malformed)?;
+
+        if version == "ff" {
+            return Err(TraceparentError::UnsupportedVersion(version.into()));
+        }
+
+        // future versions are allowed to append more fields
+        if version == TRACEPARENT_VERSION && parts.next().is_some() {
+            return Err(malformed());
+        }
+
+        if !is_hex(trace_id, 32) || is_zero(trace_id) {
+            return Err(TraceparentError::InvalidTraceId(trace_id.into()));
+        }
+
+        if !is_hex(parent_id, 16) || is_zero(parent_id) {
+            return Err(TraceparentError::InvalidParentId(parent_id.into()));
+        }
+
+        let mut operation = Self::new(trace_id);
+        operation.set_parent_id(parent_id);
+        Ok(operation)
+    }
+
+    /// Returns a W3C Trace Context `traceparent` header value to propagate the operation to downstream
+    /// services. It expects an operation id to be a 32 hex digits trace id as created by
+    /// [`new_root`](#method.new_root) or [`from_traceparent`](#method.from_traceparent). When a parent id
+    /// is not a 16 hex digits span id, a new random one is generated instead.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use appinsights::telemetry::OperationContext;
+    ///
+    /// let mut operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+    /// operation.set_parent_id("b7ad6b7169203331");
+    ///
+    /// assert_eq!(operation.to_traceparent(), "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+    /// ```
+    pub fn to_traceparent(&self) -> String {
+        let parent_id = match self.parent_id() {
+            Some(parent_id) if is_hex(parent_id, 16) && !is_zero(parent_id) => parent_id.into(),
+            _ => uuid::new().to_simple().to_string()[..16].to_string(),
+        };
+
+        format!("{}-{}-{}-01", TRACEPARENT_VERSION, self.id, parent_id)
+    }
+
+    /// Returns headers to inject into an outgoing call made within the operation, so the downstream
+    /// service can correlate its telemetry with the call. They include W3C Trace Context `traceparent`
+    /// and legacy `Request-Id` headers with specified dependency id as a parent, as well as
+    /// `Correlation-Context` and `baggage` headers when the operation carries a correlation context.
+    /// The same id is expected to be set to the dependency telemetry that tracks the call.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use appinsights::telemetry::OperationContext;
+    ///
+    /// let operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+    /// let headers = operation.outgoing_headers("b7ad6b7169203331");
+    ///
+    /// assert_eq!(headers["traceparent"], "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+    /// assert_eq!(headers["Request-Id"], "|0af7651916cd43dd8448eb211c80319c.b7ad6b7169203331.");
+    /// ```
+    pub fn outgoing_headers(&self, dependency_id: &str) -> HeaderMap {
+        let mut downstream = OperationContext::new(self.id());
+        downstream.set_parent_id(dependency_id);
+
+        let mut headers = HeaderMap::new();
+        if let Ok(traceparent) = HeaderValue::from_str(&downstream.to_traceparent()) {
+            headers.insert("traceparent", traceparent);
+        }
+        if let Ok(request_id) = HeaderValue::from_str(&format!("|{}.{}.", self.id(), dependency_id)) {
+            headers.insert("Request-Id", request_id);
+        }
+        if let Some(correlation) = self.correlation().filter(|correlation| !correlation.is_empty()) {
+            if let Ok(value) = HeaderValue::from_str(&correlation.to_header()) {
+                headers.insert("Correlation-Context", value.clone());
+                headers.insert("baggage", value);
+            }
+        }
+        headers
+    }
+}
+
+/// Determines whether a value consists of exactly specified number of lowercase hex digits.
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+/// Determines whether a value consists of zeros only which is not a valid identifier.
+fn is_zero(value: &str) -> bool {
+    value.chars().all(|c| c == '0')
+}
+
+/// Describes an error occurred during parsing of a W3C Trace Context `traceparent` header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceparentError {
+    /// A header is not in `version-traceid-parentid-flags` format.
+    MalformedHeader(String),
+
+    /// A header version is not supported.
+    UnsupportedVersion(String),
+
+    /// A trace id is not 32 lowercase hex digits or all zeros.
+    InvalidTraceId(String),
+
+    /// A parent id is not 16 lowercase hex digits or all zeros.
+    InvalidParentId(String),
+}
+
+impl Display for TraceparentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceparentError::MalformedHeader(header) => {
+                write!(
+                    f,
+                    "traceparent '{}' is not in version-traceid-parentid-flags format",
+                    header
+                )
+            }
+            TraceparentError::UnsupportedVersion(version) => {
+                write!(f, "traceparent version '{}' is not supported", version)
+            }
+            TraceparentError::InvalidTraceId(trace_id) => write!(f, "trace id '{}' is invalid", trace_id),
+            TraceparentError::InvalidParentId(parent_id) => write!(f, "parent id '{}' is invalid", parent_id),
+        }
+    }
+}
+
+impl Error for TraceparentError {}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{
+        context::TelemetryContext,
+        contracts::Envelope,
+        telemetry::{ContextTags, EventTelemetry, Properties, Telemetry},
+        uuid::Uuid,
+    };
+
+    #[test]
+    fn it_creates_root_operation_with_random_id() {
+        uuid::set(Uuid::from_str("910b414a-f368-4b3a-aff6-326632aac566").unwrap());
+
+        let operation = OperationContext::new_root();
+
+        assert_eq!(operation.id(), "910b414af3684b3aaff6326632aac566");
+        assert_eq!(operation.parent_id(), None);
+    }
+
+    #[test]
+    fn it_creates_hierarchical_child_contexts() {
+        let mut parent = OperationContext::new("operation");
+        parent.set_parent_id("b7ad6b7169203331");
+        parent.set_correlation(CorrelationContext::from_header("tenant=contoso"));
+        let copy = parent.clone();
+
+        let first = parent.child_context();
+        let second = copy.child_context();
+
+        assert_eq!(first.id(), "operation");
+        assert_eq!(first.parent_id(), Some("b7ad6b7169203331.1"));
+        assert_eq!(second.parent_id(), Some("b7ad6b7169203331.2"));
+        assert_eq!(first.correlation(), parent.correlation());
+        assert_eq!(first.child_context().parent_id(), Some("b7ad6b7169203331.1.1"));
+    }
+
+    #[test]
+    fn it_keeps_trailing_dot_of_legacy_request_id_in_child_contexts() {
+        let mut parent = OperationContext::new("operation");
+        parent.set_parent_id("|operation.1.");
+
+        assert_eq!(parent.child_context().parent_id(), Some("|operation.1.1."));
+        assert_eq!(parent.child_context().parent_id(), Some("|operation.1.2."));
+    }
+
+    #[test]
+    fn it_overrides_operation_from_context() {
+        let mut tags = ContextTags::default();
+        tags.operation_mut().set_id("context".into());
+        tags.operation_mut().set_name("GET /".into());
+        let context = TelemetryContext::new("instrumentation".into(), tags, Properties::default());
+
+        let mut operation = OperationContext::new("operation");
+        operation.set_parent_id("parent");
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry.set_operation(&operation);
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let tags = envelop.tags.unwrap();
+        assert_eq!(tags.get("ai.operation.id"), Some(&"operation".to_string()));
+        assert_eq!(tags.get("ai.operation.parentId"), Some(&"parent".to_string()));
+        assert_eq!(tags.get("ai.operation.name"), Some(&"GET /".to_string()));
+    }
+
+    #[test]
+    fn it_adds_correlation_context_to_properties() {
+        let mut operation = OperationContext::new("operation");
+        operation.set_correlation(CorrelationContext::from_header("tenant=contoso,region=westus"));
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry.set_operation(&operation);
+
+        let properties = telemetry.properties();
+        assert_eq!(properties.get("tenant"), Some(&"contoso".to_string()));
+        assert_eq!(properties.get("region"), Some(&"westus".to_string()));
+    }
+
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"; "sampled")]
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00"; "not sampled")]
+    #[test_case(" 00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01 "; "extra whitespaces")]
+    #[test_case("01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-future"; "future version")]
+    fn it_creates_operation_from_traceparent(header: &str) {
+        let operation = OperationContext::from_traceparent(header).unwrap();
+
+        assert_eq!(operation.id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(operation.parent_id(), Some("b7ad6b7169203331"));
+    }
+
+    #[test_case("", TraceparentError::MalformedHeader("".into()); "empty")]
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331", TraceparentError::MalformedHeader("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331".into()); "missing flags")]
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra", TraceparentError::MalformedHeader("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra".into()); "extra fields")]
+    #[test_case("ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01", TraceparentError::UnsupportedVersion("ff".into()); "invalid version")]
+    #[test_case("00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01", TraceparentError::InvalidTraceId("0AF7651916CD43DD8448EB211C80319C".into()); "uppercase trace id")]
+    #[test_case("00-00000000000000000000000000000000-b7ad6b7169203331-01", TraceparentError::InvalidTraceId("00000000000000000000000000000000".into()); "zero trace id")]
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-b7ad6b71692033-01", TraceparentError::InvalidParentId("b7ad6b71692033".into()); "short parent id")]
+    #[test_case("00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01", TraceparentError::InvalidParentId("0000000000000000".into()); "zero parent id")]
+    fn it_fails_to_create_operation_from_invalid_traceparent(header: &str, expected: TraceparentError) {
+        assert_eq!(OperationContext::from_traceparent(header), Err(expected));
+    }
+
+    #[test]
+    fn it_generates_traceparent_parent_id_when_missing() {
+        uuid::set(Uuid::from_str("910b414a-f368-4b3a-aff6-326632aac566").unwrap());
+
+        let operation = OperationContext::from_traceparent(&OperationContext::new_root().to_traceparent()).unwrap();
+
+        assert_eq!(operation.id(), "910b414af3684b3aaff6326632aac566");
+        assert_eq!(operation.parent_id(), Some("910b414af3684b3a"));
+    }
+
+    #[test]
+    fn it_creates_outgoing_headers() {
+        let mut operation = OperationContext::new("0af7651916cd43dd8448eb211c80319c");
+        operation.set_correlation(CorrelationContext::from_header("tenant=contoso"));
+
+        let headers = operation.outgoing_headers("b7ad6b7169203331");
+
+        assert_eq!(
+            headers["traceparent"],
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+        assert_eq!(
+            headers["Request-Id"],
+            "|0af7651916cd43dd8448eb211c80319c.b7ad6b7169203331."
+        );
+        assert_eq!(headers["Correlation-Context"], "tenant=contoso");
+        assert_eq!(headers["baggage"], "tenant=contoso");
+    }
+}
@@ -1,10 +1,12 @@
+use std::time::Duration as StdDuration;
+
 use chrono::{DateTime, SecondsFormat, Utc};
 use http::Uri;
 
 use crate::{
     context::TelemetryContext,
     contracts::{Base, Data, Envelope, PageViewData},
-    telemetry::{ContextTags, Measurements, Properties, Telemetry},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, Telemetry},
     time::{self, Duration},
     uuid::Uuid,
 };
@@ -28,12 +30,12 @@ use crate::{
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("body_size".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("body_size", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PageViewTelemetry {
     /// Identifier of a generic action on a page.
     /// It is used to correlate a generic action on a page and telemetry generated by the service.
@@ -57,6 +59,9 @@ pub struct PageViewTelemetry {
     /// Telemetry context containing extra, optional tags.
     tags: ContextTags,
 
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
     /// Custom measurements.
     measurements: Measurements,
 }
@@ -72,10 +77,16 @@ impl PageViewTelemetry {
             timestamp: time::now(),
             properties: Properties::default(),
             tags: ContextTags::default(),
+            sampling_percentage: None,
             measurements: Measurements::default(),
         }
     }
 
+    /// Sets a time it took to load the page.
+    pub fn set_duration(&mut self, duration: StdDuration) {
+        self.duration = Some(duration.into());
+    }
+
     /// Returns custom measurements to submit with the telemetry item.
     pub fn measurements(&self) -> &Measurements {
         &self.measurements
@@ -85,6 +96,20 @@ impl PageViewTelemetry {
     pub fn measurements_mut(&mut self) -> &mut Measurements {
         &mut self.measurements
     }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
 }
 
 impl Telemetry for PageViewTelemetry {
@@ -93,6 +118,11 @@ impl Telemetry for PageViewTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -114,12 +144,20 @@ impl Telemetry for PageViewTelemetry {
     }
 }
 
+impl std::fmt::Display for PageViewTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[PAGE VIEW] {} {}", self.name, self.uri)?;
+        self.properties.fmt_summary(f)
+    }
+}
+
 impl From<(TelemetryContext, PageViewTelemetry)> for Envelope {
     fn from((context, telemetry): (TelemetryContext, PageViewTelemetry)) -> Self {
         Self {
             name: "Microsoft.ApplicationInsights.PageView".into(),
             time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
             i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::PageViewData(PageViewData {
                 name: telemetry.name,
@@ -130,7 +168,9 @@ impl From<(TelemetryContext, PageViewTelemetry)> for Envelope {
                     .id
                     .map(|id| id.to_hyphenated().to_string())
                     .unwrap_or_default(),
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..PageViewData::default()
             }))),
@@ -139,14 +179,83 @@ impl From<(TelemetryContext, PageViewTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for PageViewTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::PageViewData(data) => Ok(Self {
+                id: Uuid::parse_str(&data.id).ok(),
+                name: data.name,
+                uri: data
+                    .url
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|err| format!("invalid url: {}", err))?,
+                duration: data.duration.map(|duration| duration.parse()).transpose()?,
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("PageViewData")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use chrono::TimeZone;
+    use serde_json::json;
 
     use super::*;
 
+    #[test]
+    fn it_omits_duration_when_not_specified() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let telemetry = PageViewTelemetry::new("page updated", "https://example.com/main.html".parse().unwrap());
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = json!({
+            "baseType": "PageViewData",
+            "baseData": {
+                "ver": 2,
+                "name": "page updated",
+                "url": "https://example.com/main.html",
+                "referrerUri": null,
+                "id": "",
+                "properties": {},
+                "measurements": {}
+            }
+        });
+        assert_eq!(serde_json::to_value(envelop).unwrap()["data"], expected)
+    }
+
+    #[test]
+    fn it_includes_duration_when_specified() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = PageViewTelemetry::new("page updated", "https://example.com/main.html".parse().unwrap());
+        telemetry.set_duration(StdDuration::from_millis(1500));
+
+        let envelop = Envelope::from((context, telemetry));
+
+        assert_eq!(
+            serde_json::to_value(envelop).unwrap()["data"]["baseData"]["duration"],
+            json!("0.00:00:01.5000000")
+        )
+    }
+
     #[test]
     fn it_overrides_properties_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
@@ -158,7 +267,7 @@ mod tests {
 
         let mut telemetry = PageViewTelemetry::new("page updated", "https://example.com/main.html".parse().unwrap());
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("latency".into(), 200.0);
+        telemetry.measurements_mut().insert("latency", 200.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
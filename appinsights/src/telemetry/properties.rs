@@ -1,19 +1,226 @@
 use std::{
     collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter},
     ops::{Deref, DerefMut},
 };
 
+use log::warn;
+
+/// Maximum number of characters in a property key accepted by the server.
+const MAX_KEY_LENGTH: usize = 150;
+
+/// Maximum number of characters in a property value accepted by the server.
+const MAX_VALUE_LENGTH: usize = 8192;
+
+/// Describes what happens to a property whose key is longer than 150 characters or whose value is
+/// longer than 8192 characters, since the server would drop or truncate it anyway.
+///
+/// # Examples
+/// ```rust, no_run
+/// # use appinsights::TelemetryClient;
+/// use appinsights::telemetry::ErrorPolicy;
+///
+/// let mut client = TelemetryClient::new("<instrumentation key>".to_string());
+/// client.context_mut().set_error_policy(ErrorPolicy::Reject);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// A key or a value is truncated to the maximum length.
+    Truncate,
+
+    /// A key or a value is truncated to the maximum length with a warning.
+    #[default]
+    Warn,
+
+    /// A property is rejected. [`insert_with`](struct.Properties.html#method.insert_with) returns an
+    /// error, while properties of a submitted telemetry item are discarded with a warning.
+    Reject,
+}
+
 /// Contains all properties for telemetry to submit.
+///
+/// Keys longer than 150 characters and values longer than 8192 characters are handled according to
+/// the [`ErrorPolicy`](enum.ErrorPolicy.html) of a telemetry context when a telemetry item is
+/// submitted. By default they are truncated with a warning. Use [`try_insert`](#method.try_insert) or
+/// [`insert_with`](#method.insert_with) to check them right away instead.
 #[derive(Debug, Clone, Default)]
 pub struct Properties(BTreeMap<String, String>);
 
 impl Properties {
     /// Combines all properties from two objects. It can override some properties with values found
-    /// in the second properties bag.
+    /// in the second properties bag. Properties that are too long are truncated with a warning.
     pub fn combine(a: Properties, b: Properties) -> Self {
-        let items = a.0.into_iter().chain(b.0).collect();
+        Self::combine_with(a, b, ErrorPolicy::default())
+    }
+
+    /// Combines all properties from two objects like [`combine`](#method.combine) does, but handles
+    /// properties that are too long according to specified policy.
+    pub fn combine_with(a: Properties, b: Properties, policy: ErrorPolicy) -> Self {
+        let items =
+            a.0.into_iter()
+                .chain(b.0)
+                .filter_map(|property| match limit(property, policy) {
+                    Ok(property) => Some(property),
+                    Err(err) => {
+                        warn!("Property discarded: {}", err);
+                        None
+                    }
+                })
+                .collect();
         Self(items)
     }
+
+    /// Inserts a property. Returns the previous value of the property if any. A key or a value that is
+    /// too long is handled when a telemetry item is submitted.
+    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
+        self.0.insert(key, value)
+    }
+
+    /// Inserts a property handling its key and value that are too long according to specified policy.
+    /// Returns the previous value of the property if any.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use appinsights::telemetry::{ErrorPolicy, Properties};
+    /// let mut properties = Properties::default();
+    ///
+    /// assert!(properties.insert_with("k".repeat(200), "value".into(), ErrorPolicy::Truncate).is_ok());
+    /// assert!(properties.insert_with("k".repeat(200), "value".into(), ErrorPolicy::Reject).is_err());
+    /// ```
+    pub fn insert_with(
+        &mut self,
+        key: String,
+        value: String,
+        policy: ErrorPolicy,
+    ) -> Result<Option<String>, PropertyTooLongError> {
+        let (key, value) = limit((key, value), policy)?;
+        Ok(self.0.insert(key, value))
+    }
+
+    /// Inserts a property unless its key or value is longer than the server accepts. Returns the
+    /// previous value of the property if any.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use appinsights::telemetry::Properties;
+    /// let mut properties = Properties::default();
+    ///
+    /// assert!(properties.try_insert("tenant".into(), "contoso".into()).is_ok());
+    /// assert!(properties.try_insert("k".repeat(200), "value".into()).is_err());
+    /// ```
+    pub fn try_insert(&mut self, key: String, value: String) -> Result<Option<String>, PropertyTooLongError> {
+        self.insert_with(key, value, ErrorPolicy::Reject)
+    }
+
+    /// Inserts a property for every environment variable whose name starts with the prefix, e.g. to tag
+    /// all telemetry with deployment metadata. A property key is the rest of the variable name in lower
+    /// case, so `APPINSIGHTS_TAG_DEPLOY_VERSION` with prefix `APPINSIGHTS_TAG_` becomes
+    /// `deploy_version`. Variables with non-Unicode names or values are skipped.
+    ///
+    /// # Examples
+    /// ```rust, no_run
+    /// # use appinsights::TelemetryClient;
+    /// let mut client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// client.context_mut().properties_mut().extend_from_env("APPINSIGHTS_TAG_");
+    /// ```
+    pub fn extend_from_env(&mut self, prefix: &str) {
+        let vars =
+            std::env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+        for (name, value) in vars {
+            match name.strip_prefix(prefix) {
+                Some(key) if !key.is_empty() => {
+                    self.insert(key.to_lowercase(), value);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Appends properties to a one-line summary of a telemetry item unless there are none.
+    pub(crate) fn fmt_summary(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " (properties: {:?})", self.0)
+        }
+    }
+}
+
+/// Describes an error occurred when a property key or value is longer than the server accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyTooLongError {
+    /// A key is longer than 150 characters. Contains the number of characters in the key.
+    Key(usize),
+
+    /// A value is longer than 8192 characters. Contains the key and the number of characters in the value.
+    Value(String, usize),
+}
+
+impl Display for PropertyTooLongError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(length) => write!(
+                f,
+                "property key of {} characters exceeds {} characters",
+                length, MAX_KEY_LENGTH
+            ),
+            Self::Value(key, length) => write!(
+                f,
+                "value of property {} of {} characters exceeds {} characters",
+                key, length, MAX_VALUE_LENGTH
+            ),
+        }
+    }
+}
+
+impl Error for PropertyTooLongError {}
+
+/// Limits a key and a value of a property to the maximum length according to specified policy.
+fn limit(
+    (mut key, mut value): (String, String),
+    policy: ErrorPolicy,
+) -> Result<(String, String), PropertyTooLongError> {
+    if policy == ErrorPolicy::Reject {
+        return match (key.chars().count(), value.chars().count()) {
+            (length, _) if length > MAX_KEY_LENGTH => Err(PropertyTooLongError::Key(length)),
+            (_, length) if length > MAX_VALUE_LENGTH => Err(PropertyTooLongError::Value(key, length)),
+            _ => Ok((key, value)),
+        };
+    }
+
+    if truncate(&mut key, MAX_KEY_LENGTH) && policy == ErrorPolicy::Warn {
+        warn!(
+            "Property key exceeds {} characters and was truncated: {}",
+            MAX_KEY_LENGTH, key
+        );
+    }
+
+    if truncate(&mut value, MAX_VALUE_LENGTH) && policy == ErrorPolicy::Warn {
+        warn!(
+            "Value of property {} exceeds {} characters and was truncated",
+            key, MAX_VALUE_LENGTH
+        );
+    }
+
+    Ok((key, value))
+}
+
+/// Shortens a string to specified number of characters. Returns `true` if the string was truncated.
+fn truncate(s: &mut String, max_chars: usize) -> bool {
+    match s.char_indices().nth(max_chars) {
+        Some((index, _)) => {
+            s.truncate(index);
+            true
+        }
+        None => false,
+    }
+}
+
+impl From<BTreeMap<String, String>> for Properties {
+    fn from(properties: BTreeMap<String, String>) -> Self {
+        Self(properties)
+    }
 }
 
 impl From<Properties> for BTreeMap<String, String> {
@@ -35,3 +242,108 @@ impl DerefMut for Properties {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn it_rejects_long_keys_and_values_on_try_insert() {
+        let mut properties = Properties::default();
+
+        assert_eq!(
+            properties.try_insert("k".repeat(200), "value".into()),
+            Err(PropertyTooLongError::Key(200))
+        );
+        assert_eq!(
+            properties.try_insert("key".into(), "v".repeat(10_000)),
+            Err(PropertyTooLongError::Value("key".into(), 10_000))
+        );
+        assert_eq!(properties.try_insert("key".into(), "value".into()), Ok(None));
+        assert_eq!(properties.len(), 1);
+    }
+
+    #[test]
+    fn it_truncates_long_keys_and_values_on_insert() {
+        let mut properties = Properties::default();
+        properties
+            .insert_with("k".repeat(200), "v".repeat(10_000), ErrorPolicy::Truncate)
+            .unwrap();
+
+        let (key, value) = properties.iter().next().unwrap();
+        assert_eq!(key.len(), MAX_KEY_LENGTH);
+        assert_eq!(value.len(), MAX_VALUE_LENGTH);
+    }
+
+    #[test]
+    fn it_truncates_at_character_boundary() {
+        let mut properties = Properties::default();
+        properties
+            .insert_with("ключ".repeat(50), "значение".into(), ErrorPolicy::Warn)
+            .unwrap();
+
+        let key = properties.keys().next().unwrap();
+        assert_eq!(key.chars().count(), MAX_KEY_LENGTH);
+        assert!(key.starts_with("ключключ"));
+    }
+
+    #[test]
+    fn it_extends_properties_from_env_with_prefix() {
+        std::env::set_var("PROPERTIES_TEST_TAG_DEPLOY_VERSION", "1.2.3");
+        std::env::set_var("PROPERTIES_TEST_TAG_", "empty");
+        std::env::set_var("PROPERTIES_TEST_OTHER", "skipped");
+
+        let mut properties = Properties::default();
+        properties.extend_from_env("PROPERTIES_TEST_TAG_");
+
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties.get("deploy_version"), Some(&"1.2.3".to_string()));
+    }
+
+    #[test]
+    fn it_keeps_short_properties_intact() {
+        let mut properties = Properties::default();
+        assert_eq!(properties.insert("component".into(), "data_processor".into()), None);
+        assert_eq!(
+            properties.insert("component".into(), "aggregator".into()),
+            Some("data_processor".into())
+        );
+
+        assert_eq!(properties.get("component"), Some(&"aggregator".to_string()));
+    }
+
+    #[test]
+    fn it_truncates_properties_modified_in_place_when_combined() {
+        let mut properties = Properties::default();
+        properties
+            .entry("k".repeat(200))
+            .or_default()
+            .push_str(&"v".repeat(10_000));
+
+        let properties = Properties::combine(Properties::default(), properties);
+
+        let (key, value) = properties.iter().next().unwrap();
+        assert_eq!(key.len(), MAX_KEY_LENGTH);
+        assert_eq!(value.len(), MAX_VALUE_LENGTH);
+    }
+
+    #[test_case(ErrorPolicy::Truncate, Some((MAX_KEY_LENGTH, MAX_VALUE_LENGTH)); "truncate")]
+    #[test_case(ErrorPolicy::Warn, Some((MAX_KEY_LENGTH, MAX_VALUE_LENGTH)); "warn")]
+    #[test_case(ErrorPolicy::Reject, None; "reject")]
+    fn it_limits_combined_properties_according_to_policy(policy: ErrorPolicy, expected: Option<(usize, usize)>) {
+        let mut properties = Properties::default();
+        properties.insert("k".repeat(200), "v".repeat(10_000));
+        properties.insert("component".into(), "data_processor".into());
+
+        let properties = Properties::combine_with(Properties::default(), properties, policy);
+
+        let long = properties
+            .iter()
+            .find(|(key, _)| key.starts_with('k'))
+            .map(|(key, value)| (key.len(), value.len()));
+        assert_eq!(long, expected);
+        assert_eq!(properties.get("component"), Some(&"data_processor".to_string()));
+    }
+}
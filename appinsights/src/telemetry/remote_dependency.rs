@@ -5,8 +5,9 @@ use chrono::{DateTime, SecondsFormat, Utc};
 use crate::{
     context::TelemetryContext,
     contracts::{Base, Data, Envelope, RemoteDependencyData},
-    telemetry::{ContextTags, Measurements, Properties, Telemetry},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, Telemetry},
     time::{self, Duration},
+    InstrumentationKey,
 };
 
 /// Represents interactions of the monitored component with a remote component/service like SQL or an HTTP endpoint.
@@ -30,12 +31,12 @@ use crate::{
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("body_size".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("body_size", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RemoteDependencyTelemetry {
     /// Identifier of a dependency call instance.
     /// It is used for correlation with the request telemetry item corresponding to this dependency call.
@@ -76,6 +77,9 @@ pub struct RemoteDependencyTelemetry {
     /// Telemetry context containing extra, optional tags.
     tags: ContextTags,
 
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
     /// Custom measurements.
     measurements: Measurements,
 }
@@ -101,10 +105,49 @@ impl RemoteDependencyTelemetry {
             timestamp: time::now(),
             properties: Properties::default(),
             tags: ContextTags::default(),
+            sampling_percentage: None,
             measurements: Measurements::default(),
         }
     }
 
+    /// Creates a new telemetry item for a SQL query. The dependency is named and targeted after the server
+    /// and the database joined with ` | ` and records the statement as a command, the way the portal
+    /// expects SQL calls to be reported.
+    pub fn from_sql(server: &str, database: &str, statement: &str, duration: StdDuration, success: bool) -> Self {
+        let target = format!("{} | {}", server, database);
+        let mut telemetry = Self::new(target.clone(), "SQL", duration, target, success);
+        telemetry.set_data(statement);
+        telemetry
+    }
+
+    /// Creates a new telemetry item for a HTTP call named after the method and the URL path and targeted
+    /// at the host of the URL. The whole URL is recorded as a command and the status code as a result code.
+    /// A call is considered as failed when the status code is `400` or above.
+    pub fn from_http(method: &str, url: &str, status_code: u16, duration: StdDuration) -> Self {
+        let (name, target) = match reqwest::Url::parse(url) {
+            Ok(parsed) => (format!("{} {}", method, parsed.path()), http_target(&parsed)),
+            Err(_) => (format!("{} {}", method, url), String::default()),
+        };
+        let mut telemetry = Self::new(name, "HTTP", duration, target, status_code < 400);
+        telemetry.set_data(url);
+        telemetry.set_result_code(status_code.to_string());
+        telemetry
+    }
+
+    /// Creates a new telemetry item for a gRPC call named `/<service>/<method>` and targeted at the service.
+    /// A numeric gRPC status code, e.g. `tonic::Code as i32`, is recorded as a result code and a call is
+    /// considered as failed unless the code is `0` (`OK`). A non-empty status message is recorded as
+    /// `grpc.message` property.
+    pub fn from_grpc(service: &str, method: &str, code: i32, message: &str, duration: StdDuration) -> Self {
+        let name = format!("/{}/{}", service, method);
+        let mut telemetry = Self::new(name, "GRPC", duration, service, code == 0);
+        telemetry.set_result_code(code.to_string());
+        if !message.is_empty() {
+            telemetry.properties.insert("grpc.message".into(), message.into());
+        }
+        telemetry
+    }
+
     /// Returns custom measurements to submit with the telemetry item.
     pub fn measurements(&self) -> &Measurements {
         &self.measurements
@@ -115,6 +158,36 @@ impl RemoteDependencyTelemetry {
         &mut self.measurements
     }
 
+    /// Sets a result code of a dependency call. Examples are SQL error code and HTTP status code.
+    pub fn set_result_code(&mut self, result_code: impl Into<String>) {
+        self.result_code = Some(result_code.into());
+    }
+
+    /// Sets a command initiated by this dependency call.
+    /// Examples are SQL statement and HTTP URL's with all the query parameters.
+    pub fn set_data(&mut self, data: impl Into<String>) {
+        self.data = Some(data.into());
+    }
+
+    /// Appends ` | <instrumentation key>` of a called component to the target, so Application Map draws
+    /// the call as an edge to that component rather than to an external service. The key is appended
+    /// once even if the method is called again.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use appinsights::{telemetry::RemoteDependencyTelemetry, InstrumentationKey};
+    /// # use std::time::Duration;
+    /// let mut dependency =
+    ///     RemoteDependencyTelemetry::from_http("GET", "https://orders.contoso.com/api", 200, Duration::from_millis(12));
+    /// dependency.mark_as_cross_component(&InstrumentationKey::from_static("00000000-0000-0000-0000-000000000000"));
+    /// ```
+    pub fn mark_as_cross_component(&mut self, target_i_key: &InstrumentationKey) {
+        let suffix = format!(" | {}", target_i_key);
+        if !self.target.ends_with(&suffix) {
+            self.target.push_str(&suffix);
+        }
+    }
+
     /// Sets the dependency id. Use this to link other telemetry to this dependency by setting their operation
     /// parent id to this id.
     ///
@@ -146,6 +219,20 @@ impl RemoteDependencyTelemetry {
     pub fn set_id(&mut self, id: impl Into<String>) {
         self.id = Some(id.into());
     }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
 }
 
 impl Telemetry for RemoteDependencyTelemetry {
@@ -154,6 +241,11 @@ impl Telemetry for RemoteDependencyTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -175,12 +267,39 @@ impl Telemetry for RemoteDependencyTelemetry {
     }
 }
 
+impl std::fmt::Display for RemoteDependencyTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[DEPENDENCY {}] {} -> {}",
+            self.dependency_type, self.name, self.target
+        )?;
+        if let Some(result_code) = &self.result_code {
+            write!(f, " {}", result_code)?;
+        }
+        write!(f, " in {:?}", *self.duration)?;
+        if !self.success {
+            write!(f, " failed")?;
+        }
+        self.properties.fmt_summary(f)
+    }
+}
+
+/// Returns a target of a HTTP dependency call: a host of the URL with a port when it is specified.
+pub(crate) fn http_target(url: &reqwest::Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (host, _) => host.unwrap_or_default().to_string(),
+    }
+}
+
 impl From<(TelemetryContext, RemoteDependencyTelemetry)> for Envelope {
     fn from((context, telemetry): (TelemetryContext, RemoteDependencyTelemetry)) -> Self {
         Self {
             name: "Microsoft.ApplicationInsights.RemoteDependency".into(),
             time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
             i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::RemoteDependencyData(RemoteDependencyData {
                 name: telemetry.name,
@@ -191,7 +310,9 @@ impl From<(TelemetryContext, RemoteDependencyTelemetry)> for Envelope {
                 data: telemetry.data,
                 target: Some(telemetry.target),
                 type_: Some(telemetry.dependency_type),
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..RemoteDependencyData::default()
             }))),
@@ -200,6 +321,34 @@ impl From<(TelemetryContext, RemoteDependencyTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for RemoteDependencyTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::RemoteDependencyData(data) => Ok(Self {
+                id: data.id,
+                name: data.name,
+                duration: data.duration.parse()?,
+                result_code: data.result_code,
+                success: data.success.unwrap_or(true),
+                data: data.data,
+                dependency_type: data.type_.unwrap_or_default(),
+                target: data.target.unwrap_or_default(),
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("RemoteDependencyData")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -208,6 +357,92 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn it_formats_summary() {
+        let mut telemetry = RemoteDependencyTelemetry::new(
+            "GET /users",
+            "HTTP",
+            StdDuration::from_millis(182),
+            "api.github.com",
+            false,
+        );
+        telemetry.set_result_code("404");
+
+        assert_eq!(
+            telemetry.to_string(),
+            "[DEPENDENCY HTTP] GET /users -> api.github.com 404 in 182ms failed"
+        );
+    }
+
+    #[test]
+    fn it_creates_sql_dependency() {
+        let telemetry = RemoteDependencyTelemetry::from_sql(
+            "orders.database.windows.net",
+            "orders",
+            "SELECT * FROM orders",
+            StdDuration::from_millis(12),
+            true,
+        );
+
+        assert_eq!(
+            telemetry.to_string(),
+            "[DEPENDENCY SQL] orders.database.windows.net | orders -> orders.database.windows.net | orders in 12ms"
+        );
+        assert_eq!(telemetry.data, Some("SELECT * FROM orders".into()));
+    }
+
+    #[test]
+    fn it_creates_http_dependency() {
+        let telemetry = RemoteDependencyTelemetry::from_http(
+            "GET",
+            "https://api.github.com:8443/users?page=2",
+            404,
+            StdDuration::from_millis(182),
+        );
+
+        assert_eq!(
+            telemetry.to_string(),
+            "[DEPENDENCY HTTP] GET /users -> api.github.com:8443 404 in 182ms failed"
+        );
+        assert_eq!(telemetry.data, Some("https://api.github.com:8443/users?page=2".into()));
+    }
+
+    #[test]
+    fn it_creates_grpc_dependency() {
+        let telemetry = RemoteDependencyTelemetry::from_grpc(
+            "orders.OrderService",
+            "PlaceOrder",
+            14,
+            "connection refused",
+            StdDuration::from_millis(35),
+        );
+
+        assert_eq!(
+            telemetry.to_string(),
+            "[DEPENDENCY GRPC] /orders.OrderService/PlaceOrder -> orders.OrderService 14 in 35ms failed \
+             (properties: {\"grpc.message\": \"connection refused\"})"
+        );
+    }
+
+    #[test]
+    fn it_marks_dependency_as_cross_component() {
+        let i_key = InstrumentationKey::from_static("00000000-0000-0000-0000-000000000000");
+        let mut telemetry = RemoteDependencyTelemetry::from_http(
+            "GET",
+            "https://orders.contoso.com/api",
+            200,
+            StdDuration::from_millis(12),
+        );
+
+        telemetry.mark_as_cross_component(&i_key);
+        telemetry.mark_as_cross_component(&i_key);
+
+        assert_eq!(
+            telemetry.target,
+            "orders.contoso.com | 00000000-0000-0000-0000-000000000000"
+        );
+    }
+
     #[test]
     fn it_uses_specified_id() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
@@ -246,6 +481,46 @@ mod tests {
         assert_eq!(envelop, expected)
     }
 
+    #[test]
+    fn it_uses_specified_result_code_and_data() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let mut telemetry = RemoteDependencyTelemetry::new(
+            "SELECT orders",
+            "SQL",
+            StdDuration::from_secs(2),
+            "db.example.com",
+            false,
+        );
+        telemetry.set_result_code("1205");
+        telemetry.set_data("SELECT * FROM orders WHERE id = @id");
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = Envelope {
+            name: "Microsoft.ApplicationInsights.RemoteDependency".into(),
+            time: "2019-01-02T03:04:05.800Z".into(),
+            i_key: Some("instrumentation".into()),
+            tags: Some(BTreeMap::default()),
+            data: Some(Base::Data(Data::RemoteDependencyData(RemoteDependencyData {
+                name: "SELECT orders".into(),
+                result_code: Some("1205".into()),
+                duration: "0.00:00:02.0000000".into(),
+                success: Some(false),
+                data: Some("SELECT * FROM orders WHERE id = @id".into()),
+                target: Some("db.example.com".into()),
+                type_: Some("SQL".into()),
+                properties: Some(BTreeMap::default()),
+                measurements: Some(BTreeMap::default()),
+                ..RemoteDependencyData::default()
+            }))),
+            ..Envelope::default()
+        };
+
+        assert_eq!(envelop, expected)
+    }
+
     #[test]
     fn it_overrides_properties_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
@@ -263,7 +538,7 @@ mod tests {
             true,
         );
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("latency".into(), 200.0);
+        telemetry.measurements_mut().insert("latency", 200.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
@@ -1,4 +1,7 @@
-use std::{str::FromStr, time::Duration as StdDuration};
+use std::{
+    str::FromStr,
+    time::{Duration as StdDuration, Instant},
+};
 
 use chrono::{DateTime, SecondsFormat, Utc};
 use http::{Method, StatusCode, Uri};
@@ -6,7 +9,7 @@ use http::{Method, StatusCode, Uri};
 use crate::{
     context::TelemetryContext,
     contracts::{Base, Data, Envelope, RequestData},
-    telemetry::{ContextTags, Measurements, Properties, Telemetry},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, Telemetry},
     time::{self, Duration},
     uuid,
 };
@@ -33,12 +36,12 @@ use crate::{
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("body_size".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("body_size", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RequestTelemetry {
     /// Identifier of a request call instance.
     /// It is used for correlation between request and other telemetry items.
@@ -56,6 +59,9 @@ pub struct RequestTelemetry {
     /// Results of a request execution. HTTP status code for HTTP requests.
     response_code: String,
 
+    /// Indication of successful or unsuccessful call. Inferred from response code when not set.
+    success: Option<bool>,
+
     /// The time stamp when this telemetry was measured.
     timestamp: DateTime<Utc>,
 
@@ -65,6 +71,9 @@ pub struct RequestTelemetry {
     /// Telemetry context containing extra, optional tags.
     tags: ContextTags,
 
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
     /// Custom measurements.
     measurements: Measurements,
 }
@@ -98,13 +107,37 @@ impl RequestTelemetry {
             uri,
             duration: duration.into(),
             response_code: response_code.into(),
+            success: Option::default(),
             timestamp: time::now(),
             properties: Properties::default(),
             tags,
+            sampling_percentage: None,
             measurements: Measurements::default(),
         }
     }
 
+    /// Starts measuring a request to the application. The returned [`ActiveRequest`] computes the
+    /// request duration once it is stopped.
+    ///
+    /// ```rust,no_run
+    /// # use appinsights::TelemetryClient;
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// use appinsights::telemetry::RequestTelemetry;
+    /// use http::{Method, Uri};
+    ///
+    /// let request = RequestTelemetry::start(Method::GET, "https://example.com/main.html".parse::<Uri>().unwrap());
+    /// // ... serve the request
+    /// client.track(request.stop("200", true));
+    /// ```
+    pub fn start(method: Method, uri: Uri) -> ActiveRequest {
+        ActiveRequest {
+            method,
+            uri,
+            timestamp: time::now(),
+            started: Instant::now(),
+        }
+    }
+
     /// Returns custom measurements to submit with the telemetry item.
     pub fn measurements(&self) -> &Measurements {
         &self.measurements
@@ -115,15 +148,26 @@ impl RequestTelemetry {
         &mut self.measurements
     }
 
-    /// Returns an indication of successful or unsuccessful call.
+    /// Returns an indication of successful or unsuccessful call. Unless set explicitly with
+    /// [`set_success`](#method.set_success), a request is considered as failed when the response
+    /// code is `400` or above, except `401`.
     pub fn is_success(&self) -> bool {
-        if let Ok(response_code) = StatusCode::from_str(&self.response_code) {
+        if let Some(success) = self.success {
+            success
+        } else if let Ok(response_code) = StatusCode::from_str(&self.response_code) {
             response_code < StatusCode::BAD_REQUEST || response_code == StatusCode::UNAUTHORIZED
         } else {
             true
         }
     }
 
+    /// Sets an indication of successful or unsuccessful call overriding the one inferred from the
+    /// response code. For example, a `404` response may be expected by an API and should not be
+    /// reported as a failure.
+    pub fn set_success(&mut self, success: bool) {
+        self.success = Some(success);
+    }
+
     /// Sets the request id. Use this to link other telemetry to this request by setting their operation
     /// parent id to this request's id.
     ///
@@ -157,6 +201,54 @@ impl RequestTelemetry {
     pub fn set_id(&mut self, id: impl Into<String>) {
         self.id = Some(id.into());
     }
+
+    /// Sets an id of the authenticated user who made the request as `ai.user.authUserId` tag of this
+    /// item only, so concurrent requests of different users never share a client context.
+    pub fn set_authenticated_user_id(&mut self, id: impl Into<String>) {
+        self.tags.user_mut().set_auth_user_id(id.into());
+    }
+
+    /// Sets a request name and an operation name this request starts.
+    #[cfg_attr(not(any(feature = "opentelemetry", feature = "tower")), allow(dead_code))]
+    pub(crate) fn set_name(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+        self.tags.operation_mut().set_name(self.name.clone());
+    }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
+}
+
+/// A request to the application which is being served. Created by
+/// [`RequestTelemetry::start`](struct.RequestTelemetry.html#method.start).
+#[derive(Debug)]
+pub struct ActiveRequest {
+    method: Method,
+    uri: Uri,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+}
+
+impl ActiveRequest {
+    /// Stops measuring the request and returns a telemetry item with duration elapsed since the
+    /// request was started. The telemetry item time stamp is the time the request was started at.
+    pub fn stop(self, response_code: impl Into<String>, success: bool) -> RequestTelemetry {
+        let mut telemetry = RequestTelemetry::new(self.method, self.uri, self.started.elapsed(), response_code);
+        telemetry.timestamp = self.timestamp;
+        telemetry.set_success(success);
+        telemetry
+    }
 }
 
 impl Telemetry for RequestTelemetry {
@@ -165,6 +257,11 @@ impl Telemetry for RequestTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -186,6 +283,17 @@ impl Telemetry for RequestTelemetry {
     }
 }
 
+impl std::fmt::Display for RequestTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[REQUEST] {} {} in {:?}",
+            self.name, self.response_code, *self.duration
+        )?;
+        self.properties.fmt_summary(f)
+    }
+}
+
 impl From<(TelemetryContext, RequestTelemetry)> for Envelope {
     fn from((context, telemetry): (TelemetryContext, RequestTelemetry)) -> Self {
         let success = telemetry.is_success();
@@ -193,6 +301,7 @@ impl From<(TelemetryContext, RequestTelemetry)> for Envelope {
             name: "Microsoft.ApplicationInsights.Request".into(),
             time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
             i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::RequestData(RequestData {
                 id: telemetry.id.unwrap_or_else(|| uuid::new().to_hyphenated().to_string()),
@@ -201,7 +310,9 @@ impl From<(TelemetryContext, RequestTelemetry)> for Envelope {
                 response_code: telemetry.response_code,
                 success,
                 url: Some(telemetry.uri.to_string()),
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..RequestData::default()
             }))),
@@ -210,16 +321,97 @@ impl From<(TelemetryContext, RequestTelemetry)> for Envelope {
     }
 }
 
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for RequestTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::RequestData(data) => Ok(Self {
+                id: Some(data.id),
+                name: data.name.unwrap_or_default(),
+                uri: data
+                    .url
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|err| format!("invalid url: {}", err))?,
+                duration: data.duration.parse()?,
+                response_code: data.response_code,
+                success: Some(data.success),
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("RequestData")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
     use std::str::FromStr;
 
     use chrono::TimeZone;
+    use test_case::test_case;
 
     use super::*;
     use crate::uuid::{self, Uuid};
 
+    #[test_case("200", None, true; "ok")]
+    #[test_case("401", None, true; "unauthorized")]
+    #[test_case("404", None, false; "not found")]
+    #[test_case("500", None, false; "server error")]
+    #[test_case("custom", None, true; "non http code")]
+    #[test_case("404", Some(true), true; "expected not found")]
+    #[test_case("200", Some(false), false; "failed with ok")]
+    fn it_determines_success(response_code: &str, success: Option<bool>, expected: bool) {
+        let mut telemetry = RequestTelemetry::new(
+            Method::GET,
+            "https://example.com/main.html".parse().unwrap(),
+            StdDuration::from_secs(2),
+            response_code,
+        );
+        if let Some(success) = success {
+            telemetry.set_success(success);
+        }
+
+        assert_eq!(telemetry.is_success(), expected);
+    }
+
+    #[test]
+    fn it_measures_started_request() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+        let request = RequestTelemetry::start(Method::GET, "https://example.com/main.html".parse().unwrap());
+        std::thread::sleep(StdDuration::from_millis(10));
+
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 6, 0));
+        let telemetry = request.stop("404", true);
+
+        assert_eq!(telemetry.timestamp, Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+        assert!(*telemetry.duration >= StdDuration::from_millis(10));
+        assert_eq!(telemetry.response_code, "404");
+        assert!(telemetry.is_success());
+    }
+
+    #[test]
+    fn it_sets_authenticated_user_id_tag() {
+        let mut request = RequestTelemetry::new(
+            Method::GET,
+            "https://example.com/main.html".parse().unwrap(),
+            StdDuration::from_millis(182),
+            "200",
+        );
+
+        request.set_authenticated_user_id("alice@contoso.com");
+
+        assert_eq!(request.tags().user().auth_user_id(), Some("alice@contoso.com"));
+    }
+
     #[test]
     fn it_uses_specified_id() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
@@ -280,7 +472,7 @@ mod tests {
             "200",
         );
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("latency".into(), 200.0);
+        telemetry.measurements_mut().insert("latency", 200.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
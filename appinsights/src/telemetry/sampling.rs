@@ -0,0 +1,26 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+};
+
+/// Describes an error occurred when a sampling percentage is not in range from `0` exclusive to `100`
+/// inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingPercentageError(pub f64);
+
+impl Display for SamplingPercentageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sampling percentage {} is not in range (0, 100]", self.0)
+    }
+}
+
+impl Error for SamplingPercentageError {}
+
+/// Returns a sampling percentage if it is in range from `0` exclusive to `100` inclusive.
+pub(crate) fn validate(percentage: f64) -> Result<f64, SamplingPercentageError> {
+    if percentage > 0.0 && percentage <= 100.0 {
+        Ok(percentage)
+    } else {
+        Err(SamplingPercentageError(percentage))
+    }
+}
@@ -0,0 +1,193 @@
+//! Serialization of telemetry items to the Application Insights JSON schema.
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    context::TelemetryContext,
+    contracts::{Base, Data, Envelope},
+    telemetry::{
+        AggregateMetricTelemetry, AvailabilityTelemetry, ContextTags, EventTelemetry, ExceptionTelemetry,
+        MetricTelemetry, PageViewTelemetry, Properties, RemoteDependencyTelemetry, RequestTelemetry, TraceTelemetry,
+    },
+};
+
+/// Splits an envelope into a time stamp, context tags and data of a telemetry item.
+pub(crate) fn parts(envelope: Envelope) -> Result<(DateTime<Utc>, ContextTags, Data), String> {
+    let timestamp = DateTime::parse_from_rfc3339(&envelope.time)
+        .map_err(|err| format!("invalid time {}: {}", envelope.time, err))?
+        .with_timezone(&Utc);
+    let tags = envelope.tags.unwrap_or_default().into();
+
+    match envelope.data {
+        Some(Base::Data(data)) => Ok((timestamp, tags, data)),
+        None => Err("missing telemetry data".into()),
+    }
+}
+
+/// Describes an error occurred when an envelope contains data of another telemetry type.
+pub(crate) fn unexpected(expected: &str) -> String {
+    format!("expected {} telemetry data", expected)
+}
+
+macro_rules! impl_serde {
+    ($($telemetry: ident),*) => {
+        $(
+            /// Serializes a telemetry item to the same JSON that is submitted to the server except for
+            /// the instrumentation key, tags and properties found on client telemetry context.
+            impl Serialize for $telemetry {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let context = TelemetryContext::new(String::default(), ContextTags::default(), Properties::default());
+                    let mut envelope = Envelope::from((context, self.clone()));
+                    envelope.i_key = None;
+                    envelope.serialize(serializer)
+                }
+            }
+
+            /// Deserializes a telemetry item from the JSON that is submitted to the server.
+            impl<'de> Deserialize<'de> for $telemetry {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let envelope = Envelope::deserialize(deserializer)?;
+                    Self::try_from(envelope).map_err(de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+impl_serde!(
+    AggregateMetricTelemetry,
+    AvailabilityTelemetry,
+    EventTelemetry,
+    ExceptionTelemetry,
+    MetricTelemetry,
+    PageViewTelemetry,
+    RemoteDependencyTelemetry,
+    RequestTelemetry,
+    TraceTelemetry
+);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+    use http::Method;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        telemetry::{SeverityLevel, Telemetry},
+        time,
+    };
+
+    #[test]
+    fn it_serializes_telemetry_to_envelope_schema() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let mut telemetry = EventTelemetry::new("test");
+        telemetry
+            .properties_mut()
+            .insert("component".into(), "data_processor".into());
+        telemetry.tags_mut().operation_mut().set_id("operation".into());
+        telemetry.measurements_mut().insert("records_count", 115.0).unwrap();
+
+        let expected = json!({
+            "ver": 1,
+            "name": "Microsoft.ApplicationInsights.Event",
+            "time": "2019-01-02T03:04:05.600Z",
+            "sampleRate": 100.0,
+            "seq": null,
+            "iKey": null,
+            "flags": null,
+            "tags": {
+                "ai.operation.id": "operation"
+            },
+            "data": {
+                "baseType": "EventData",
+                "baseData": {
+                    "ver": 2,
+                    "name": "test",
+                    "properties": {
+                        "component": "data_processor"
+                    },
+                    "measurements": {
+                        "records_count": 115.0
+                    }
+                }
+            }
+        });
+
+        assert_eq!(serde_json::to_value(&telemetry).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_deserializes_telemetry_from_envelope_schema() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let mut telemetry = TraceTelemetry::new("message", SeverityLevel::Warning);
+        telemetry
+            .properties_mut()
+            .insert("component".into(), "data_processor".into());
+
+        let json = serde_json::to_string(&telemetry).unwrap();
+        let telemetry: TraceTelemetry = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(telemetry.timestamp(), Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+        assert_eq!(
+            telemetry.properties().get("component"),
+            Some(&"data_processor".to_string())
+        );
+    }
+
+    #[test]
+    fn it_keeps_telemetry_content_after_round_trip() {
+        let mut request = RequestTelemetry::new(
+            Method::GET,
+            "https://example.com/main.html".parse().unwrap(),
+            Duration::from_millis(182),
+            "404",
+        );
+        request.set_success(true);
+        assert_round_trip(request);
+
+        let mut dependency =
+            RemoteDependencyTelemetry::new("GET /", "HTTP", Duration::from_secs(2), "example.com", false);
+        dependency.set_result_code("500");
+        assert_round_trip(dependency);
+
+        let mut exception = ExceptionTelemetry::new("Error", "message");
+        exception.set_severity(SeverityLevel::Critical);
+        assert_round_trip(exception);
+
+        let mut page_view = PageViewTelemetry::new("check github repo page", "https://example.com".parse().unwrap());
+        page_view.set_duration(Duration::from_millis(330));
+        assert_round_trip(page_view);
+
+        let mut aggregate = AggregateMetricTelemetry::new("latency");
+        aggregate.stats_mut().add_data(&[113.0, 250.0, 316.0]);
+        assert_round_trip(aggregate);
+
+        assert_round_trip(AvailabilityTelemetry::new("GET /", Duration::from_millis(100), true));
+        assert_round_trip(EventTelemetry::new("event"));
+        assert_round_trip(MetricTelemetry::new("records_count", 115.0));
+        assert_round_trip(TraceTelemetry::new("message", SeverityLevel::Verbose));
+    }
+
+    #[test]
+    fn it_rejects_envelope_of_another_telemetry_type() {
+        let json = serde_json::to_value(MetricTelemetry::new("records_count", 115.0)).unwrap();
+
+        assert!(serde_json::from_value::<EventTelemetry>(json.clone()).is_err());
+        assert!(serde_json::from_value::<AggregateMetricTelemetry>(json).is_err());
+    }
+
+    fn assert_round_trip<T: Serialize + DeserializeOwned>(telemetry: T) {
+        let expected: Value = serde_json::to_value(&telemetry).unwrap();
+        let telemetry: T = serde_json::from_value(expected.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&telemetry).unwrap(), expected);
+    }
+}
@@ -16,6 +16,12 @@ impl ContextTags {
     }
 }
 
+impl From<BTreeMap<String, String>> for ContextTags {
+    fn from(tags: BTreeMap<String, String>) -> Self {
+        Self(tags)
+    }
+}
+
 impl From<ContextTags> for BTreeMap<String, String> {
     fn from(tags: ContextTags) -> Self {
         tags.0
@@ -36,6 +42,49 @@ impl DerefMut for ContextTags {
     }
 }
 
+/// Builds a map of context tags with typed setters for all well-known tags, e.g. to fill
+/// [`tags`](../schema/struct.Envelope.html#structfield.tags) of a raw envelope.
+///
+/// # Examples
+///
+/// ```rust
+/// # use appinsights::telemetry::TagsBuilder;
+/// let tags = TagsBuilder::new()
+///     .cloud_role("frontend")
+///     .operation_id("0af7651916cd43dd8448eb211c80319c")
+///     .insert_raw("ai.custom.tag", "value")
+///     .build();
+///
+/// assert_eq!(tags.get("ai.cloud.role").map(String::as_str), Some("frontend"));
+/// ```
+#[derive(Debug, Clone, Default)]
+#[must_use = "a builder does nothing until tags are built"]
+pub struct TagsBuilder(BTreeMap<String, String>);
+
+impl TagsBuilder {
+    /// Creates a new empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a tag that has no typed setter.
+    pub fn insert_raw(mut self, key: &str, value: &str) -> Self {
+        self.0.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns a map of all tags set.
+    pub fn build(self) -> BTreeMap<String, String> {
+        self.0
+    }
+}
+
+impl From<TagsBuilder> for ContextTags {
+    fn from(builder: TagsBuilder) -> Self {
+        Self(builder.0)
+    }
+}
+
 /// Macros to generate well-known context tags.
 #[macro_export]
 macro_rules! tags {
@@ -84,6 +133,13 @@ macro_rules! tags {
                 fn new(items: &'a mut std::collections::BTreeMap<String, String>) -> Self {
                     Self { items }
                 }
+
+                /// Removes all tags of this group.
+                pub fn clear(&mut self) {
+                    $(
+                        self.items.remove($key);
+                    )*
+                }
                 $(
                     $(#[$attr_method])*
                     pub fn [<set_ $method>](&mut self, value: String) {
@@ -91,6 +147,16 @@ macro_rules! tags {
                     }
                 )*
             }
+
+            impl TagsBuilder {
+                $(
+                    $(#[$attr_method])*
+                    pub fn [<$factory _ $method>]<V: Into<String>>(mut self, value: V) -> Self {
+                        self.0.insert($key.into(), value.into());
+                        self
+                    }
+                )*
+            }
         }
     };
 }
@@ -243,6 +309,25 @@ mod tests {
         assert_eq!(example.bar(), Some("bar"));
     }
 
+    #[test]
+    fn it_builds_well_known_and_raw_tags() {
+        let tags = TagsBuilder::new()
+            .example_foo("foo")
+            .cloud_role("role")
+            .device_type("PC")
+            .insert_raw("custom", "value")
+            .build();
+
+        let expected = [
+            ("ai.cloud.role", "role"),
+            ("ai.device.type", "PC"),
+            ("custom", "value"),
+            ("foo", "foo"),
+        ];
+        let expected = expected.iter().map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(tags, expected.collect::<BTreeMap<_, _>>());
+    }
+
     tags!(
         /// Returns example wrapper
         example,
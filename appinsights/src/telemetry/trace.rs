@@ -3,7 +3,7 @@ use chrono::{DateTime, SecondsFormat, Utc};
 use crate::{
     context::TelemetryContext,
     contracts::{SeverityLevel as ContractsSeverityLevel, *},
-    telemetry::{ContextTags, Measurements, Properties, Telemetry},
+    telemetry::{sampling, ContextTags, Measurements, Properties, SamplingPercentageError, Telemetry},
     time,
 };
 
@@ -22,12 +22,12 @@ use crate::{
 /// // attach custom properties, measurements and context tags
 /// telemetry.properties_mut().insert("component".to_string(), "data_processor".to_string());
 /// telemetry.tags_mut().insert("os_version".to_string(), "linux x86_64".to_string());
-/// telemetry.measurements_mut().insert("records_count".to_string(), 115.0);
+/// telemetry.measurements_mut().insert("records_count", 115.0).unwrap();
 ///
 /// // submit telemetry item to server
 /// client.track(telemetry);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TraceTelemetry {
     /// A trace message.
     message: String,
@@ -44,6 +44,9 @@ pub struct TraceTelemetry {
     /// Telemetry context containing extra, optional tags.
     tags: ContextTags,
 
+    /// Percentage of telemetry items represented by this one when sampled by the application if any.
+    sampling_percentage: Option<f64>,
+
     /// Custom measurements.
     measurements: Measurements,
 }
@@ -57,6 +60,7 @@ impl TraceTelemetry {
             timestamp: time::now(),
             properties: Properties::default(),
             tags: ContextTags::default(),
+            sampling_percentage: None,
             measurements: Measurements::default(),
         }
     }
@@ -70,6 +74,20 @@ impl TraceTelemetry {
     pub fn measurements_mut(&mut self) -> &mut Measurements {
         &mut self.measurements
     }
+
+    /// Returns a percentage of telemetry items represented by this one when the application samples
+    /// telemetry itself if any.
+    pub fn sampling_percentage(&self) -> Option<f64> {
+        self.sampling_percentage
+    }
+
+    /// Marks the telemetry item as sampled by the application with specified percentage of items kept,
+    /// so the portal can compensate counts. The item is not sampled again by the client. Returns an
+    /// error when the percentage is not in range from `0` exclusive to `100` inclusive.
+    pub fn set_sampling_percentage(&mut self, percentage: f64) -> Result<(), SamplingPercentageError> {
+        self.sampling_percentage = Some(sampling::validate(percentage)?);
+        Ok(())
+    }
 }
 
 impl Telemetry for TraceTelemetry {
@@ -78,6 +96,11 @@ impl Telemetry for TraceTelemetry {
         self.timestamp
     }
 
+    /// Overrides the time when this telemetry was measured.
+    fn set_timestamp(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+    }
+
     /// Returns custom properties to submit with the telemetry item.
     fn properties(&self) -> &Properties {
         &self.properties
@@ -97,6 +120,25 @@ impl Telemetry for TraceTelemetry {
     fn tags_mut(&mut self) -> &mut ContextTags {
         &mut self.tags
     }
+
+    /// Returns severity level of the trace.
+    fn trace_severity(&self) -> Option<SeverityLevel> {
+        Some(self.severity)
+    }
+}
+
+impl std::fmt::Display for TraceTelemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            SeverityLevel::Verbose => "verbose",
+            SeverityLevel::Information => "info",
+            SeverityLevel::Warning => "warn",
+            SeverityLevel::Error => "error",
+            SeverityLevel::Critical => "critical",
+        };
+        write!(f, "[TRACE {}] {}", severity, self.message)?;
+        self.properties.fmt_summary(f)
+    }
 }
 
 impl From<(TelemetryContext, TraceTelemetry)> for Envelope {
@@ -105,11 +147,14 @@ impl From<(TelemetryContext, TraceTelemetry)> for Envelope {
             name: "Microsoft.ApplicationInsights.Message".into(),
             time: telemetry.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
             i_key: Some(context.i_key),
+            sample_rate: Some(telemetry.sampling_percentage.unwrap_or(100.0)),
             tags: Some(ContextTags::combine(context.tags, telemetry.tags).into()),
             data: Some(Base::Data(Data::MessageData(MessageData {
                 message: telemetry.message,
                 severity_level: Some(telemetry.severity.into()),
-                properties: Some(Properties::combine(context.properties, telemetry.properties).into()),
+                properties: Some(
+                    Properties::combine_with(context.properties, telemetry.properties, context.error_policy).into(),
+                ),
                 measurements: Some(telemetry.measurements.into()),
                 ..MessageData::default()
             }))),
@@ -118,8 +163,31 @@ impl From<(TelemetryContext, TraceTelemetry)> for Envelope {
     }
 }
 
-/// Defines the level of severity for the event.
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<Envelope> for TraceTelemetry {
+    type Error = String;
+
+    fn try_from(envelope: Envelope) -> Result<Self, Self::Error> {
+        let sampling_percentage = envelope.sample_rate.filter(|rate| *rate < 100.0);
+        let (timestamp, tags, data) = crate::telemetry::schema::parts(envelope)?;
+        match data {
+            Data::MessageData(data) => Ok(Self {
+                message: data.message,
+                severity: data.severity_level.map_or(SeverityLevel::Information, Into::into),
+                timestamp,
+                properties: data.properties.unwrap_or_default().into(),
+                tags,
+                sampling_percentage,
+                measurements: data.measurements.unwrap_or_default().into(),
+            }),
+            _ => Err(crate::telemetry::schema::unexpected("MessageData")),
+        }
+    }
+}
+
+/// Defines the level of severity for the event. Levels are ordered from the least to the most severe one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SeverityLevel {
     /// Verbose severity level.
     Verbose,
@@ -149,11 +217,24 @@ impl From<SeverityLevel> for ContractsSeverityLevel {
     }
 }
 
+impl From<ContractsSeverityLevel> for SeverityLevel {
+    fn from(severity: ContractsSeverityLevel) -> Self {
+        match severity {
+            ContractsSeverityLevel::Verbose => SeverityLevel::Verbose,
+            ContractsSeverityLevel::Information => SeverityLevel::Information,
+            ContractsSeverityLevel::Warning => SeverityLevel::Warning,
+            ContractsSeverityLevel::Error => SeverityLevel::Error,
+            ContractsSeverityLevel::Critical => SeverityLevel::Critical,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use chrono::{TimeZone, Utc};
+    use serde_json::json;
 
     use super::{SeverityLevel, TraceTelemetry};
     use crate::{
@@ -162,6 +243,18 @@ mod tests {
         time, TelemetryContext,
     };
 
+    #[test]
+    fn it_formats_summary() {
+        let mut telemetry = TraceTelemetry::new("disk is almost full", SeverityLevel::Warning);
+        assert_eq!(telemetry.to_string(), "[TRACE warn] disk is almost full");
+
+        telemetry.properties_mut().insert("disk".into(), "c".into());
+        assert_eq!(
+            telemetry.to_string(),
+            r#"[TRACE warn] disk is almost full (properties: {"disk": "c"})"#
+        );
+    }
+
     #[test]
     fn it_overrides_properties_from_context() {
         time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
@@ -173,7 +266,7 @@ mod tests {
 
         let mut telemetry = TraceTelemetry::new("message", SeverityLevel::Information);
         telemetry.properties_mut().insert("no-write".into(), "ok".into());
-        telemetry.measurements_mut().insert("value".into(), 5.0);
+        telemetry.measurements_mut().insert("value", 5.0).unwrap();
 
         let envelop = Envelope::from((context, telemetry));
 
@@ -240,4 +333,36 @@ mod tests {
 
         assert_eq!(envelop, expected)
     }
+
+    #[test]
+    fn it_serializes_to_message_data_schema() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 600));
+
+        let context = TelemetryContext::new("instrumentation".into(), ContextTags::default(), Properties::default());
+        let telemetry = TraceTelemetry::new("message", SeverityLevel::Warning);
+
+        let envelop = Envelope::from((context, telemetry));
+
+        let expected = json!({
+            "ver": 1,
+            "name": "Microsoft.ApplicationInsights.Message",
+            "time": "2019-01-02T03:04:05.600Z",
+            "sampleRate": 100.0,
+            "seq": null,
+            "iKey": "instrumentation",
+            "flags": null,
+            "tags": {},
+            "data": {
+                "baseType": "MessageData",
+                "baseData": {
+                    "ver": 2,
+                    "message": "message",
+                    "severityLevel": "Warning",
+                    "properties": {},
+                    "measurements": {}
+                }
+            }
+        });
+        assert_eq!(serde_json::to_value(envelop).unwrap(), expected)
+    }
 }
@@ -6,6 +6,9 @@ use std::{
     time::Duration as StdDuration,
 };
 
+use chrono::{DateTime, Utc};
+use log::warn;
+
 #[cfg(not(test))]
 mod imp {
     use chrono::{DateTime, Utc};
@@ -22,7 +25,7 @@ mod imp {
 
     use chrono::{DateTime, Utc};
 
-    thread_local!(static NOW: RefCell<Option<DateTime<Utc>>> = RefCell::new(None));
+    thread_local!(static NOW: RefCell<Option<DateTime<Utc>>> = const { RefCell::new(None) });
 
     /// Returns a DateTime which corresponds to a current date or the value user set in advance.
     pub fn now() -> DateTime<Utc> {
@@ -35,13 +38,33 @@ mod imp {
     }
 
     /// Resets pre-defined DateTime value to use Utc::now() instead.
+    #[allow(dead_code)]
     pub fn reset() {
         NOW.with(|ts| *ts.borrow_mut() = None)
     }
 }
 
+/// Maximum number of hours a time stamp of a telemetry item may differ from current time before the
+/// server discards it.
+pub const MAX_TIMESTAMP_SKEW_HOURS: i64 = 48;
+
+/// Determines whether a time stamp of a telemetry item is close enough to current time to be accepted
+/// by the server. Logs a warning otherwise.
+pub fn check_timestamp(timestamp: DateTime<Utc>) -> bool {
+    let skew = (now() - timestamp).num_hours().abs();
+    if skew < MAX_TIMESTAMP_SKEW_HOURS {
+        true
+    } else {
+        warn!(
+            "Telemetry item time stamp {} is more than {} hours away from now. The server will discard it",
+            timestamp, MAX_TIMESTAMP_SKEW_HOURS
+        );
+        false
+    }
+}
+
 /// Provides dotnet duration aware formatting rules.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Duration(StdDuration);
 
 impl From<StdDuration> for Duration {
@@ -68,6 +91,59 @@ impl Display for Duration {
     }
 }
 
+/// Parses a duration in dotnet `d.hh:mm:ss.fffffff` format.
+impl std::str::FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (days, time) = s.split_once('.')?;
+            let (time, ticks) = time.split_once('.')?;
+
+            let mut time = time.split(':').map(|part| part.parse::<u64>().ok());
+            let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+            if time.next().is_some() || hours >= 24 || minutes >= 60 || seconds >= 60 || ticks.len() != 7 {
+                return None;
+            }
+
+            let seconds = ((days.parse::<u64>().ok()? * 24 + hours) * 60 + minutes) * 60 + seconds;
+            Some(StdDuration::new(seconds, ticks.parse::<u32>().ok()? * 100))
+        };
+
+        parse().map(Duration).ok_or_else(|| format!("invalid duration: {}", s))
+    }
+}
+
+/// Formats a duration as the server expects it in `d.hh:mm:ss.fffffff` format, where the fraction is
+/// a number of 100-nanosecond ticks. Precision beyond ticks is truncated.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// use appinsights::schema::utils::format_duration;
+///
+/// assert_eq!(format_duration(Duration::from_millis(90_061_001)), "1.01:01:01.0010000");
+/// ```
+pub fn format_duration(duration: StdDuration) -> String {
+    Duration(duration).to_string()
+}
+
+/// Parses a duration in `d.hh:mm:ss.fffffff` format the server uses. Returns `None` when the string
+/// is not a valid duration.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// use appinsights::schema::utils::parse_duration;
+///
+/// assert_eq!(parse_duration("0.00:00:01.5000000"), Some(Duration::from_millis(1500)));
+/// ```
+pub fn parse_duration(s: &str) -> Option<StdDuration> {
+    s.parse::<Duration>().ok().map(|duration| duration.0)
+}
+
 impl Deref for Duration {
     type Target = StdDuration;
 
@@ -78,7 +154,7 @@ impl Deref for Duration {
 
 #[cfg(test)]
 mod tests {
-    use chrono::{TimeZone, Utc};
+    use chrono::TimeZone;
     use test_case::test_case;
 
     use super::*;
@@ -92,4 +168,38 @@ mod tests {
     fn it_converts_duration_to_string(duration: Duration, expected: &'static str) {
         assert_eq!(duration.to_string(), expected.to_string());
     }
+
+    #[test_case("0.01:00:00.0000000", Some(StdDuration::from_secs(3600))   ; "hour")]
+    #[test_case("0.00:00:00.0010000", Some(StdDuration::from_millis(1))    ; "millisecond")]
+    #[test_case("2.01:02:03.0000001", Some(StdDuration::new(176_523, 100)) ; "custom")]
+    #[test_case("0.24:00:00.0000000", None                                 ; "hours out of range")]
+    #[test_case("01:00:00",           None                                 ; "no days")]
+    #[test_case("0.01:00:00.00",      None                                 ; "short ticks")]
+    fn it_parses_duration_from_string(s: &str, expected: Option<StdDuration>) {
+        assert_eq!(s.parse::<Duration>().ok().map(|duration| duration.0), expected);
+    }
+
+    #[test_case(StdDuration::ZERO                     ; "zero")]
+    #[test_case(StdDuration::from_secs(86_400)        ; "full day")]
+    #[test_case(StdDuration::from_secs(400 * 86_400)  ; "many days")]
+    #[test_case(StdDuration::new(86_399, 999_999_900) ; "end of day")]
+    #[test_case(StdDuration::from_nanos(123_400)      ; "sub-millisecond")]
+    fn it_roundtrips_duration(duration: StdDuration) {
+        assert_eq!(parse_duration(&format_duration(duration)), Some(duration));
+    }
+
+    #[test]
+    fn it_truncates_duration_to_ticks() {
+        assert_eq!(format_duration(StdDuration::from_nanos(199)), "0.00:00:00.0000001");
+    }
+
+    #[test_case(Utc.ymd(2019, 1, 2).and_hms(3, 4, 5), true  ; "now")]
+    #[test_case(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0), true  ; "past")]
+    #[test_case(Utc.ymd(2018, 12, 31).and_hms(3, 4, 5), false ; "too old")]
+    #[test_case(Utc.ymd(2019, 1, 4).and_hms(3, 4, 5), false ; "too far in future")]
+    fn it_checks_timestamp_is_accepted_by_server(timestamp: DateTime<Utc>, expected: bool) {
+        set(Utc.ymd(2019, 1, 2).and_hms(3, 4, 5));
+
+        assert_eq!(check_timestamp(timestamp), expected);
+    }
 }
@@ -11,6 +11,11 @@ mod imp {
         let timeout = Instant::now() + duration;
         time::sleep_until(timeout).await;
     }
+
+    /// Returns an instant which corresponds to current time.
+    pub fn now() -> std::time::Instant {
+        std::time::Instant::now()
+    }
 }
 
 #[cfg(test)]
@@ -19,46 +24,70 @@ mod imp {
 
     use lazy_static::lazy_static;
     use parking_lot::Mutex;
-    use tokio::{sync::Notify, time::Instant};
+    use tokio::{sync::Semaphore, time::Instant};
 
     lazy_static! {
-        static ref CHANNEL: Mutex<Option<Arc<Notify>>> = Mutex::new(None);
+        static ref CHANNEL: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+        static ref OFFSET: Mutex<Duration> = Mutex::new(Duration::ZERO);
+        static ref DURATIONS: Mutex<Vec<Duration>> = Mutex::new(Vec::new());
     }
 
     /// Initializes a channel which emulates timeout expiration event. External code should run
     /// [`expire`](#method.expire) method in order to emulate timeout expiration.
     pub fn init() {
         let mut channel = CHANNEL.lock();
-        *channel = Some(Arc::new(Notify::new()));
+        *channel = Some(Arc::new(Semaphore::new(0)));
+        DURATIONS.lock().clear();
     }
 
     /// Creates a copy of a receiver that delivers a current time stamp in order to emulate
     /// timeout expiration for tests.
     pub async fn sleep(duration: Duration) {
-        let maybe_notify = CHANNEL.lock().clone();
+        DURATIONS.lock().push(duration);
+        let maybe_expirations = CHANNEL.lock().clone();
 
-        if let Some(notify) = maybe_notify {
-            notify.notified().await;
+        if let Some(expirations) = maybe_expirations {
+            if let Ok(expiration) = expirations.acquire().await {
+                expiration.forget();
+            }
         } else {
             let timeout = Instant::now() + duration;
             tokio::time::sleep_until(timeout).await;
         }
     }
 
+    /// Returns durations of all timeouts requested since a channel was initialized or reset.
+    pub fn durations() -> Vec<Duration> {
+        DURATIONS.lock().clone()
+    }
+
     /// Emulates timeout expiration event.
-    /// It sends a current time stamp to receiver in order to trigger an action if a channel was
-    /// initialized in advance. Does nothing otherwise.
+    /// It triggers an action of a receiver if a channel was initialized in advance. Does nothing
+    /// otherwise. Expirations are counted, so each of them completes exactly one timeout even when
+    /// the receiver starts waiting only afterwards.
     pub fn expire() {
-        if let Some(notify) = CHANNEL.lock().clone() {
-            log::error!("notify_one");
-            notify.notify_one();
+        if let Some(expirations) = CHANNEL.lock().clone() {
+            expirations.add_permits(1);
         }
     }
 
+    /// Returns an instant which corresponds to current time moved forward by all calls to
+    /// [`advance`](#method.advance) so far.
+    pub fn now() -> std::time::Instant {
+        std::time::Instant::now() + *OFFSET.lock()
+    }
+
+    /// Moves time returned by [`now`](#method.now) forward to emulate that given duration elapsed.
+    pub fn advance(duration: Duration) {
+        *OFFSET.lock() += duration;
+    }
+
     /// Resets a channel that emulates timeout expiration event with default
     /// timer base timeout expiration instead.
     pub fn reset() {
         let mut channel = CHANNEL.lock();
         *channel = None;
+        *OFFSET.lock() = Duration::ZERO;
+        DURATIONS.lock().clear();
     }
 }
@@ -0,0 +1,348 @@
+//! Module for tracking incoming requests of any [`tower`](https://docs.rs/tower) based HTTP server,
+//! e.g. `hyper`, Axum or `warp`.
+//!
+//! [`AppInsightsLayer`](struct.AppInsightsLayer.html) wraps a service handling `http::Request` and
+//! submits a [`RequestTelemetry`](../telemetry/struct.RequestTelemetry.html) for every request timed
+//! from when the request was received until the response is ready. An operation the request belongs
+//! to is read from W3C Trace Context `traceparent` or legacy `Request-Id` headers, a new root
+//! operation is started otherwise. The service inserts an [`OperationContext`](../telemetry/struct.OperationContext.html)
+//! parented to the request into request extensions and makes it [current](../context/index.html)
+//! while the handler runs, so telemetry tracked by handlers is correlated with the request. Entries of `Correlation-Context` and `baggage` headers with keys allowed by
+//! [`correlation_keys`](../struct.TelemetryConfigBuilder.html#method.correlation_keys) are attached
+//! to the operation as a [`CorrelationContext`](../telemetry/struct.CorrelationContext.html).
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{tower::AppInsightsLayer, TelemetryClient};
+//! use hyper::{service::service_fn, Body, Request, Response};
+//! use tower_layer::Layer;
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let service = AppInsightsLayer::new(client).layer(service_fn(|_: Request<Body>| async {
+//!     Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+//! }));
+//! ```
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use http::{HeaderMap, Method, Request, Response, StatusCode, Uri};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    telemetry::{CorrelationContext, OperationContext, RequestTelemetry, Telemetry},
+    uuid, TelemetryClient,
+};
+
+/// Produces a request name from a method and a URI of an incoming request.
+type OperationNameProvider = Arc<dyn Fn(&Method, &Uri) -> String + Send + Sync>;
+
+/// A `tower` layer that submits a request telemetry item for every incoming request.
+#[derive(Clone)]
+pub struct AppInsightsLayer {
+    client: TelemetryClient,
+    operation_name: Option<OperationNameProvider>,
+}
+
+impl AppInsightsLayer {
+    /// Creates a new layer that submits requests with specified telemetry client.
+    pub fn new(client: TelemetryClient) -> Self {
+        Self {
+            client,
+            operation_name: None,
+        }
+    }
+
+    /// Names requests and operations they start with specified function instead of a method and a URL
+    /// without a query string, e.g. to replace identifiers in a path, so the portal groups requests to
+    /// the same route together.
+    ///
+    /// # Examples
+    /// ```rust, no_run
+    /// # use appinsights::{tower::AppInsightsLayer, TelemetryClient};
+    /// # let client = TelemetryClient::new("<instrumentation key>".to_string());
+    /// let layer = AppInsightsLayer::new(client).operation_name(|method, uri| {
+    ///     let path: Vec<_> = uri
+    ///         .path()
+    ///         .split('/')
+    ///         .map(|segment| match segment.parse::<u64>() {
+    ///             Ok(_) => "{id}",
+    ///             Err(_) => segment,
+    ///         })
+    ///         .collect();
+    ///     format!("{} {}", method, path.join("/"))
+    /// });
+    /// ```
+    pub fn operation_name<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&Method, &Uri) -> String + Send + Sync + 'static,
+    {
+        self.operation_name = Some(Arc::new(provider));
+        self
+    }
+}
+
+impl<S> Layer<S> for AppInsightsLayer {
+    type Service = AppInsightsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AppInsightsService {
+            inner,
+            client: self.client.clone(),
+            operation_name: self.operation_name.clone(),
+        }
+    }
+}
+
+/// A `tower` service created by [`AppInsightsLayer`](struct.AppInsightsLayer.html).
+#[derive(Clone)]
+pub struct AppInsightsService<S> {
+    inner: S,
+    client: TelemetryClient,
+    operation_name: Option<OperationNameProvider>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AppInsightsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let operation = incoming_operation(req.headers(), self.client.context().correlation_keys());
+        let id = uuid::new().to_simple().to_string()[..16].to_string();
+
+        // telemetry tracked by handlers becomes a child of the request
+        let mut context = OperationContext::new(operation.id());
+        context.set_parent_id(id.clone());
+        if let Some(correlation) = operation.correlation() {
+            context.set_correlation(correlation.clone());
+        }
+        req.extensions_mut().insert(context.clone());
+
+        let name = self
+            .operation_name
+            .as_ref()
+            .map(|provider| provider(req.method(), req.uri()));
+        let request = RequestTelemetry::start(req.method().clone(), req.uri().clone());
+        let response = self.inner.call(req);
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let response = crate::context::scope(context, response).await;
+
+            // a service error is tracked as a failed request, since no response is sent then
+            let status = match &response {
+                Ok(response) => response.status(),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let success = status < StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED;
+            let mut telemetry = request.stop(status.as_str(), success);
+            if let Some(name) = name {
+                telemetry.set_name(name);
+            }
+            telemetry.set_id(id);
+            telemetry.set_operation(&operation);
+            client.track(telemetry);
+
+            response
+        })
+    }
+}
+
+/// Reads an operation from `traceparent` or `Request-Id` request headers. Starts a new root operation
+/// when neither is present or valid. Correlation context entries with specified keys are attached to
+/// the operation.
+pub(crate) fn incoming_operation(headers: &HeaderMap, correlation_keys: &[String]) -> OperationContext {
+    let mut operation = parent_operation(headers);
+
+    let mut correlation = CorrelationContext::new();
+    let values = headers
+        .get_all("Correlation-Context")
+        .iter()
+        .chain(headers.get_all("baggage"));
+    for value in values.filter_map(|value| value.to_str().ok()) {
+        correlation.extend_from_header(value);
+    }
+    correlation.retain_keys(correlation_keys);
+    if !correlation.is_empty() {
+        operation.set_correlation(correlation);
+    }
+
+    operation
+}
+
+/// Reads an operation from `traceparent` or `Request-Id` request headers or starts a new one.
+fn parent_operation(headers: &HeaderMap) -> OperationContext {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(operation) = header("traceparent").and_then(|value| OperationContext::from_traceparent(value).ok()) {
+        return operation;
+    }
+
+    // hierarchical request id has a form of |root.parent.child.
+    if let Some(request_id) = header("Request-Id") {
+        let root = request_id.trim_start_matches('|').split('.').next().unwrap_or_default();
+        if !root.is_empty() {
+            let mut operation = OperationContext::new(root);
+            operation.set_parent_id(request_id);
+            return operation;
+        }
+    }
+
+    OperationContext::new_root()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_queue::SegQueue;
+    use hyper::{service::service_fn, Body};
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data},
+        TelemetryConfig,
+    };
+
+    #[test_case(Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"), None, Some(("0af7651916cd43dd8448eb211c80319c", "b7ad6b7169203331")) ; "traceparent")]
+    #[test_case(None, Some("|4bf92f35.a1b2c3."), Some(("4bf92f35", "|4bf92f35.a1b2c3.")) ; "request id")]
+    #[test_case(Some("invalid"), Some("|4bf92f35."), Some(("4bf92f35", "|4bf92f35.")) ; "invalid traceparent")]
+    #[test_case(None, None, None ; "none")]
+    fn it_reads_incoming_operation(
+        traceparent: Option<&str>,
+        request_id: Option<&str>,
+        expected: Option<(&str, &str)>,
+    ) {
+        let mut headers = HeaderMap::new();
+        if let Some(traceparent) = traceparent {
+            headers.insert("traceparent", traceparent.parse().unwrap());
+        }
+        if let Some(request_id) = request_id {
+            headers.insert("Request-Id", request_id.parse().unwrap());
+        }
+
+        let operation = incoming_operation(&headers, &[]);
+
+        match expected {
+            Some((id, parent_id)) => {
+                assert_eq!(operation.id(), id);
+                assert_eq!(operation.parent_id(), Some(parent_id));
+            }
+            None => assert_eq!(operation.parent_id(), None),
+        }
+    }
+
+    #[test]
+    fn it_reads_allowed_correlation_context_entries() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Correlation-Context", "tenant=contoso,secret=42".parse().unwrap());
+        headers.insert("baggage", "region=westus;ttl=30,tenant=fabrikam".parse().unwrap());
+
+        let operation = incoming_operation(&headers, &["tenant".into(), "region".into()]);
+
+        let correlation = operation.correlation().unwrap();
+        assert_eq!(correlation.to_header(), "tenant=fabrikam,region=westus");
+        assert_eq!(incoming_operation(&headers, &[]).correlation(), None);
+    }
+
+    #[tokio::test]
+    async fn it_tracks_incoming_request() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        let mut service = AppInsightsLayer::new(client).layer(service_fn(|req: Request<Body>| async move {
+            let operation = req.extensions().get::<OperationContext>().unwrap();
+            let body = Body::from(operation.parent_id().unwrap_or_default().to_string());
+            Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(body)
+        }));
+
+        let request = Request::post("http://example.com/users?id=1")
+            .header("Request-Id", "|4bf92f35.a1b2c3.")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        let tags = envelope.tags.unwrap();
+        assert_eq!(tags.get("ai.operation.id"), Some(&"4bf92f35".to_string()));
+        assert_eq!(
+            tags.get("ai.operation.parentId"),
+            Some(&"|4bf92f35.a1b2c3.".to_string())
+        );
+
+        match envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.name, Some("POST http://example.com/users".into()));
+                assert_eq!(data.response_code, "500");
+                assert!(!data.success);
+                assert_eq!(body, data.id.as_bytes());
+            }
+            _ => panic!("unexpected data"),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_tracks_failed_request_when_service_fails() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        let mut service = AppInsightsLayer::new(client).layer(service_fn(|_: Request<Body>| async {
+            Err::<Response<Body>, _>("connection reset")
+        }));
+
+        let request = Request::get("http://example.com/users").body(Body::empty()).unwrap();
+        assert_eq!(service.call(request).await.unwrap_err(), "connection reset");
+
+        match events.pop().unwrap().data {
+            Some(Base::Data(Data::RequestData(data))) => {
+                assert_eq!(data.response_code, "500");
+                assert!(!data.success);
+            }
+            _ => panic!("unexpected data"),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_names_request_with_operation_name_provider() {
+        let events = Arc::new(SegQueue::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let client = TelemetryClient::with_channel(&config, TestChannel::new(events.clone()));
+
+        let layer = AppInsightsLayer::new(client)
+            .operation_name(|method, uri| format!("{} {}", method, uri.path().replace("42", "{id}")));
+        let mut service = layer.layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }));
+
+        let request = Request::get("http://example.com/users/42").body(Body::empty()).unwrap();
+        service.call(request).await.unwrap();
+
+        let envelope = events.pop().unwrap();
+        assert_eq!(
+            envelope.tags.unwrap().get("ai.operation.name"),
+            Some(&"GET /users/{id}".to_string())
+        );
+        match envelope.data {
+            Some(Base::Data(Data::RequestData(data))) => assert_eq!(data.name, Some("GET /users/{id}".into())),
+            _ => panic!("unexpected data"),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Module for forwarding [tracing](https://docs.rs/tracing) events to Application Insights.
+//!
+//! Events are converted to [`TraceTelemetry`](../telemetry/struct.TraceTelemetry.html) items and
+//! submitted with a [`TelemetryClient`](../struct.TelemetryClient.html). Submission never blocks
+//! the calling thread: telemetry items are queued and sent by the client in the background.
+//!
+//! # Examples
+//! ```rust, no_run
+//! use appinsights::{telemetry::SeverityLevel, tracing::AppInsightsLayer, TelemetryClient};
+//! use tracing_subscriber::{layer::SubscriberExt, Registry};
+//!
+//! let client = TelemetryClient::new("<instrumentation key>".to_string());
+//! let layer = AppInsightsLayer::new(client).min_severity(SeverityLevel::Information);
+//!
+//! let subscriber = Registry::default().with(layer);
+//! tracing::subscriber::set_global_default(subscriber).expect("subscriber set");
+//! ```
+use std::fmt::Debug;
+
+use ::tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{
+    telemetry::{Properties, SeverityLevel, Telemetry, TraceTelemetry},
+    TelemetryClient,
+};
+
+/// A name of the field tracing macros store a formatted message in.
+const MESSAGE_FIELD: &str = "message";
+
+/// A `tracing_subscriber` layer that submits tracing events as trace telemetry items. Event fields
+/// and fields of all spans the event happened in are attached as custom properties.
+pub struct AppInsightsLayer {
+    client: TelemetryClient,
+    min_severity: SeverityLevel,
+}
+
+impl AppInsightsLayer {
+    /// Creates a new layer that submits events with specified telemetry client.
+    pub fn new(client: TelemetryClient) -> Self {
+        Self {
+            client,
+            min_severity: SeverityLevel::Verbose,
+        }
+    }
+
+    /// Sets a minimum severity level of events to submit. Events below this level are discarded.
+    pub fn min_severity(mut self, min_severity: SeverityLevel) -> Self {
+        self.min_severity = min_severity;
+        self
+    }
+}
+
+impl<S> Layer<S> for AppInsightsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = SpanFields::default();
+            attrs.record(&mut PropertiesVisitor(&mut fields.0));
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut PropertiesVisitor(&mut fields.0));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let severity = severity(event.metadata().level());
+        if severity < self.min_severity {
+            return;
+        }
+
+        let mut fields = Properties::default();
+        event.record(&mut PropertiesVisitor(&mut fields));
+        let message = fields.remove(MESSAGE_FIELD).unwrap_or_default();
+
+        let mut properties = Properties::default();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(span_properties)) = span.extensions().get::<SpanFields>() {
+                    properties.append(&mut span_properties.clone());
+                }
+            }
+        }
+
+        let mut telemetry = TraceTelemetry::new(message, severity);
+        properties.append(&mut fields);
+        *telemetry.properties_mut() = properties;
+
+        self.client.track(telemetry);
+    }
+}
+
+/// Maps tracing level to Application Insights severity level.
+fn severity(level: &Level) -> SeverityLevel {
+    match *level {
+        Level::TRACE | Level::DEBUG => SeverityLevel::Verbose,
+        Level::INFO => SeverityLevel::Information,
+        Level::WARN => SeverityLevel::Warning,
+        Level::ERROR => SeverityLevel::Error,
+    }
+}
+
+/// Fields recorded for a span and stored in its extensions.
+#[derive(Default)]
+struct SpanFields(Properties);
+
+/// Records tracing fields as custom properties.
+struct PropertiesVisitor<'a>(&'a mut Properties);
+
+impl Visit for PropertiesVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name().into(), format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_queue::SegQueue;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::*;
+    use crate::{
+        client::tests::TestChannel,
+        contracts::{Base, Data, Envelope, MessageData, SeverityLevel as ContractsSeverityLevel},
+        TelemetryConfig,
+    };
+
+    #[test]
+    fn it_submits_events_as_traces() {
+        let events = Arc::new(SegQueue::default());
+        let layer = create_layer(events.clone());
+
+        ::tracing::subscriber::with_default(Registry::default().with(layer), || {
+            ::tracing::warn!(records = 42, name = "users", "unable to process");
+        });
+
+        let data = message_data(events.pop().unwrap());
+        assert_eq!(data.message, "unable to process");
+        assert_eq!(data.severity_level, Some(ContractsSeverityLevel::Warning));
+
+        let properties = data.properties.unwrap();
+        assert_eq!(properties.get("records"), Some(&"42".to_string()));
+        assert_eq!(properties.get("name"), Some(&"users".to_string()));
+        assert_eq!(properties.get(MESSAGE_FIELD), None);
+    }
+
+    #[test]
+    fn it_merges_span_fields_into_properties() {
+        let events = Arc::new(SegQueue::default());
+        let layer = create_layer(events.clone());
+
+        ::tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let outer = ::tracing::info_span!("outer", request_id = "1", user = ::tracing::field::Empty);
+            let _outer = outer.enter();
+            outer.record("user", "admin");
+
+            let inner = ::tracing::info_span!("inner", request_id = "2", step = "load");
+            let _inner = inner.enter();
+
+            ::tracing::info!(step = "save", "saved");
+        });
+
+        let properties = message_data(events.pop().unwrap()).properties.unwrap();
+        assert_eq!(properties.get("request_id"), Some(&"2".to_string()));
+        assert_eq!(properties.get("user"), Some(&"admin".to_string()));
+        assert_eq!(properties.get("step"), Some(&"save".to_string()));
+    }
+
+    #[test]
+    fn it_discards_events_below_min_severity() {
+        let events = Arc::new(SegQueue::default());
+        let layer = create_layer(events.clone()).min_severity(SeverityLevel::Warning);
+
+        ::tracing::subscriber::with_default(Registry::default().with(layer), || {
+            ::tracing::debug!("debug");
+            ::tracing::info!("info");
+            ::tracing::error!("error");
+        });
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(message_data(events.pop().unwrap()).message, "error");
+    }
+
+    fn create_layer(events: Arc<SegQueue<Envelope>>) -> AppInsightsLayer {
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        AppInsightsLayer::new(TelemetryClient::with_channel(&config, TestChannel::new(events)))
+    }
+
+    fn message_data(envelope: Envelope) -> MessageData {
+        match envelope.data {
+            Some(Base::Data(Data::MessageData(data))) => data,
+            data => panic!("unexpected telemetry data {:?}", data),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::{error::Error, time::Duration};
+
+use http::{Method, Uri};
+
+use crate::{telemetry::SeverityLevel, TelemetryClient};
+
+/// An object safe interface to track telemetry items. Application code that depends on
+/// `&dyn TelemetryTracker` or `Arc<dyn TelemetryTracker>` instead of a concrete client can use a
+/// [`TelemetryClient`](struct.TelemetryClient.html) in production and a
+/// [no-op client](struct.TelemetryClient.html#method.new_noop) or a custom mock in tests.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use appinsights::{TelemetryClient, TelemetryTracker};
+///
+/// struct Service {
+///     telemetry: Arc<dyn TelemetryTracker>,
+/// }
+///
+/// impl Service {
+///     fn start(&self) {
+///         self.telemetry.track_event("service started".to_string());
+///     }
+/// }
+///
+/// let service = Service { telemetry: Arc::new(TelemetryClient::new_noop()) };
+/// service.start();
+/// ```
+pub trait TelemetryTracker: Send + Sync {
+    /// Logs a user action with the specified name.
+    fn track_event(&self, name: String);
+
+    /// Logs a trace message with a specified severity level.
+    fn track_trace(&self, message: String, severity: SeverityLevel);
+
+    /// Logs an error with its chain of sources.
+    fn track_exception(&self, error: &dyn Error);
+
+    /// Logs a numeric value that is not specified with a specific event.
+    fn track_metric(&self, name: String, value: f64);
+
+    /// Logs a HTTP request with the specified method, URL, duration and response code.
+    fn track_request(&self, method: Method, uri: Uri, duration: Duration, response_code: String);
+
+    /// Logs a dependency with the specified name, type, target, and success status.
+    fn track_remote_dependency(&self, name: String, dependency_type: String, target: String, success: bool);
+
+    /// Logs an availability test result with the specified test name, duration, and success status.
+    fn track_availability(&self, name: String, duration: Duration, success: bool);
+
+    /// Forces all pending telemetry items to be submitted. The current thread will not be blocked.
+    fn flush_channel(&self);
+}
+
+impl TelemetryTracker for TelemetryClient {
+    fn track_event(&self, name: String) {
+        TelemetryClient::track_event(self, name)
+    }
+
+    fn track_trace(&self, message: String, severity: SeverityLevel) {
+        TelemetryClient::track_trace(self, message, severity)
+    }
+
+    fn track_exception(&self, error: &dyn Error) {
+        TelemetryClient::track_exception(self, error)
+    }
+
+    fn track_metric(&self, name: String, value: f64) {
+        TelemetryClient::track_metric(self, name, value)
+    }
+
+    fn track_request(&self, method: Method, uri: Uri, duration: Duration, response_code: String) {
+        TelemetryClient::track_request(self, method, uri, duration, response_code)
+    }
+
+    fn track_remote_dependency(&self, name: String, dependency_type: String, target: String, success: bool) {
+        TelemetryClient::track_remote_dependency(self, name, dependency_type, target, success)
+    }
+
+    fn track_availability(&self, name: String, duration: Duration, success: bool) {
+        TelemetryClient::track_availability(self, name, duration, success)
+    }
+
+    fn flush_channel(&self) {
+        TelemetryClient::flush_channel(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_queue::SegQueue;
+
+    use super::*;
+    use crate::{client::tests::TestChannel, contracts::Envelope, TelemetryConfig};
+
+    #[test]
+    fn it_tracks_telemetry_through_trait_object() {
+        let events = Arc::new(SegQueue::<Envelope>::default());
+        let config = TelemetryConfig::new("00000000-0000-0000-0000-000000000000".into());
+        let tracker: Arc<dyn TelemetryTracker> =
+            Arc::new(TelemetryClient::with_channel(&config, TestChannel::new(events.clone())));
+
+        tracker.track_event("event".into());
+        tracker.track_trace("trace".into(), SeverityLevel::Warning);
+        tracker.track_metric("metric".into(), 42.0);
+        tracker.track_exception(&"value".parse::<i32>().unwrap_err());
+        tracker.track_remote_dependency("name".into(), "HTTP".into(), "target".into(), true);
+        tracker.track_availability("test".into(), Duration::from_secs(1), true);
+        tracker.track_request(
+            Method::GET,
+            "https://example.com".parse().unwrap(),
+            Duration::from_secs(1),
+            "200".into(),
+        );
+
+        let names: Vec<_> = std::iter::from_fn(|| events.pop())
+            .map(|envelope| envelope.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Microsoft.ApplicationInsights.Event",
+                "Microsoft.ApplicationInsights.Message",
+                "Microsoft.ApplicationInsights.Metric",
+                "Microsoft.ApplicationInsights.Exception",
+                "Microsoft.ApplicationInsights.RemoteDependency",
+                "Microsoft.ApplicationInsights.Availability",
+                "Microsoft.ApplicationInsights.Request",
+            ]
+        );
+    }
+}
@@ -1,25 +1,78 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::{DateTime, Utc};
-use http::{header::RETRY_AFTER, StatusCode};
-use log::debug;
-use reqwest::Client;
+use flate2::{write::GzEncoder, Compression};
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER},
+    HeaderValue, StatusCode,
+};
+use log::{debug, warn};
+use reqwest::{Client, Proxy};
+use serde::Serialize;
 
 use crate::{
     contracts::{Envelope, Transmission, TransmissionItem},
-    Result,
+    time,
+    transport::{Transport, TransportError},
+    Result, TelemetryConfig, TelemetryItem,
 };
 
+/// Default maximum time to wait for a response from the server.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A response header with an identifier the server assigned to a submitted batch.
+const REQUEST_ID: &str = "x-ms-request-id";
+
+/// Returns an HTTP client configured with specified configuration to submit telemetry with.
+pub fn http_client(config: &TelemetryConfig) -> Client {
+    if let Some(client) = config.http_client() {
+        return client.clone();
+    }
+
+    let mut builder = Client::builder().pool_max_idle_per_host(config.connection_pool_size());
+    if let Some(proxy) = config.proxy() {
+        match Proxy::all(proxy) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(err) => warn!(
+                "Unable to configure proxy {}: {}. Sending telemetry directly",
+                proxy, err
+            ),
+        }
+    }
+
+    builder.build().unwrap_or_else(|err| {
+        warn!("Unable to configure HTTP client: {}. Using default one", err);
+        Client::new()
+    })
+}
+
 #[derive(Debug, PartialEq)]
-pub enum Response {
+pub enum Response<T = Envelope> {
     Success,
-    Retry(Vec<Envelope>),
-    Throttled(DateTime<Utc>, Vec<Envelope>),
-    NoRetry,
+    Retry(Vec<T>),
+    Throttled(DateTime<Utc>, Vec<T>),
+    /// Specified number of telemetry items were rejected and must not be sent again.
+    NoRetry(usize),
 }
 
 /// Sends telemetry items to the server.
+#[derive(Clone)]
 pub struct Transmitter {
     url: String,
     client: Client,
+    compression_threshold: Option<usize>,
+    timeout: Duration,
+    transport: Option<Arc<dyn Transport>>,
+    payload: BytesMut,
+    compressed: BytesMut,
+    last_request_id: Arc<Mutex<Option<String>>>,
 }
 
 impl Transmitter {
@@ -29,39 +82,153 @@ impl Transmitter {
         Self {
             url: url.into(),
             client,
+            compression_threshold: None,
+            timeout: DEFAULT_TIMEOUT,
+            transport: None,
+            payload: BytesMut::new(),
+            compressed: BytesMut::new(),
+            last_request_id: Arc::default(),
         }
     }
 
-    /// Sends a telemetry items to the server.
-    pub async fn send(&self, mut items: Vec<Envelope>) -> Result<Response> {
-        let payload = serde_json::to_string(&items)?;
+    /// Creates a new instance of telemetry items sender configured with specified configuration.
+    pub fn from_config(config: &TelemetryConfig) -> Self {
+        let mut transmitter = Self::new(config.endpoint());
+        transmitter.client = http_client(config);
+        if config.compression() {
+            transmitter.compression_threshold = Some(config.compression_threshold());
+        }
+        transmitter.timeout = config.send_timeout();
+        transmitter.transport = config.transport().cloned();
+        transmitter
+    }
+
+    /// Returns a shared identifier of the last batch accepted by the server. It is updated by all clones
+    /// of this transmitter.
+    pub fn last_request_id(&self) -> Arc<Mutex<Option<String>>> {
+        self.last_request_id.clone()
+    }
+
+    /// Determines whether telemetry items are submitted with a custom transport instead of HTTP.
+    pub fn is_custom(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Sends telemetry items with a custom transport if configured or to the server otherwise.
+    pub async fn send_items(&mut self, items: Vec<Envelope>) -> Result<Response> {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => return self.send(items).await,
+        };
+
+        let batch = items.iter().cloned().map(TelemetryItem::from_envelope).collect();
+        let response = match transport.send(batch).await {
+            Ok(()) => {
+                debug!("Successfully sent {} items with custom transport", items.len());
+                Response::Success
+            }
+            Err(TransportError::Transient(reason)) => {
+                debug!(
+                    "Unable to send items with custom transport: {}. Retry sending {} items",
+                    reason,
+                    items.len()
+                );
+                Response::Retry(items)
+            }
+            Err(TransportError::Permanent(reason)) => {
+                warn!(
+                    "{} telemetry items rejected by custom transport: {}",
+                    items.len(),
+                    reason
+                );
+                Response::NoRetry(items.len())
+            }
+        };
+
+        Ok(response)
+    }
+
+    /// Sends a telemetry items to the server. Payloads are written into buffers reused across
+    /// submissions, so they are allocated again only when a payload outgrows them.
+    pub async fn send<T: Serialize>(&mut self, mut items: Vec<T>) -> Result<Response<T>> {
+        let payload = serialize(&mut self.payload, &items)?;
+
+        let request = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header(CONTENT_TYPE, "application/json");
+        let request = match self.compression_threshold {
+            Some(threshold) if payload.len() >= threshold => {
+                let mut encoder = GzEncoder::new((&mut self.compressed).writer(), Compression::default());
+                encoder.write_all(&payload)?;
+                encoder.finish()?;
+
+                request
+                    .header(CONTENT_ENCODING, "gzip")
+                    .body(self.compressed.split().freeze())
+            }
+            _ => request.body(payload),
+        };
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => {
+                debug!(
+                    "Unable to reach the server: {}. Retry sending {} items",
+                    err,
+                    items.len()
+                );
+                return Ok(Response::Retry(items));
+            }
+        };
+
+        let request_id = response
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|request_id| request_id.to_str().ok())
+            .map(String::from);
+        if let Some(request_id) = &request_id {
+            debug!("Server responded with {} to request {}", response.status(), request_id);
+            if matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+                *self.last_request_id.lock().unwrap() = Some(request_id.clone());
+            }
+        }
 
-        let response = self.client.post(&self.url).body(payload).send().await?;
         let response = match response.status() {
             StatusCode::OK => {
                 debug!("Successfully sent {} items", items.len());
                 Response::Success
             }
-            StatusCode::PARTIAL_CONTENT => {
-                let content: Transmission = response.json().await?;
-                let log_prefix = format!(
-                    "Successfully sent {}/{} telemetry items",
-                    content.items_accepted, content.items_received
-                );
-                if content.items_received == content.items_accepted {
-                    debug!("{}", log_prefix);
-                    Response::Success
-                } else {
-                    retain_retry_items(&mut items, content);
-                    if items.is_empty() {
-                        debug!("{}. Nothing to re-send", log_prefix);
-                        Response::NoRetry
+            StatusCode::PARTIAL_CONTENT => match response.json::<Transmission>().await {
+                Ok(content) => {
+                    let log_prefix = format!(
+                        "Successfully sent {}/{} telemetry items",
+                        content.items_accepted, content.items_received
+                    );
+                    if content.items_received == content.items_accepted {
+                        debug!("{}", log_prefix);
+                        Response::Success
                     } else {
-                        debug!("{}. Retry sending {} items", log_prefix, items.len());
-                        Response::Retry(items)
+                        let rejected = retain_retry_items(&mut items, content);
+                        if items.is_empty() {
+                            debug!("{}. Nothing to re-send", log_prefix);
+                            Response::NoRetry(rejected)
+                        } else {
+                            debug!("{}. Retry sending {} items", log_prefix, items.len());
+                            Response::Retry(items)
+                        }
                     }
                 }
-            }
+                Err(err) => {
+                    debug!(
+                        "Unable to read partial success response: {}. Retry sending {} items",
+                        err,
+                        items.len()
+                    );
+                    Response::Retry(items)
+                }
+            },
             StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT => {
                 let retry_after = response.headers().get(RETRY_AFTER).cloned();
 
@@ -69,9 +236,7 @@ impl Transmitter {
                     retain_retry_items(&mut items, content);
                 }
 
-                if let Some(retry_after) = retry_after {
-                    let retry_after = retry_after.to_str()?;
-                    let retry_after = DateTime::parse_from_rfc2822(retry_after)?.with_timezone(&Utc);
+                if let Some(retry_after) = retry_after.as_ref().and_then(parse_retry_after) {
                     debug!(
                         "Some items were discarded. Retry sending {} items after {}",
                         items.len(),
@@ -85,30 +250,31 @@ impl Transmitter {
             }
             StatusCode::SERVICE_UNAVAILABLE => {
                 debug!("Service unavailable. Retry sending {} items", items.len());
-                Response::Retry(items.to_vec())
+                Response::Retry(items)
             }
-            StatusCode::INTERNAL_SERVER_ERROR => {
+            status if status.is_server_error() => {
                 if let Ok(content) = response.json::<Transmission>().await {
-                    retain_retry_items(&mut items, content);
+                    let rejected = retain_retry_items(&mut items, content);
                     if items.is_empty() {
                         debug!("Service error. Nothing to re-send");
-                        Response::NoRetry
+                        Response::NoRetry(rejected)
                     } else {
                         debug!("Service error. Retry sending {} items", items.len());
                         Response::Retry(items)
                     }
                 } else {
                     debug!("Service error. Retry sending {} items", items.len());
-                    Response::Retry(items.to_vec())
+                    Response::Retry(items)
                 }
             }
-            _ => {
-                debug!(
-                    "Unknown status: {}. {}. Nothing to re-send",
-                    response.status(),
+            status => {
+                warn!(
+                    "{} telemetry items rejected by the server with status {}: {}",
+                    items.len(),
+                    status,
                     response.text().await.unwrap_or_default()
                 );
-                Response::NoRetry
+                Response::NoRetry(items.len())
             }
         };
 
@@ -116,14 +282,58 @@ impl Transmitter {
     }
 }
 
+/// Serializes telemetry items into the payload submitted to the server: a JSON array of envelopes.
+pub fn serialize_batch(items: &[Envelope]) -> serde_json::Result<Bytes> {
+    serialize(&mut BytesMut::new(), items)
+}
+
+/// Converts a telemetry item into an untyped JSON value exactly as it is submitted to the server, e.g.
+/// to assert on specific fields in tests or to forward the item to another sink.
+pub fn to_json_value(item: &Envelope) -> serde_json::Result<serde_json::Value> {
+    serde_json::to_value(item)
+}
+
+/// Serializes telemetry items into a buffer and takes them out as an immutable payload. Once the
+/// payload is dropped the buffer reclaims its allocation, so the next payload is written in place.
+fn serialize<T: Serialize>(buffer: &mut BytesMut, items: &[T]) -> serde_json::Result<Bytes> {
+    buffer.clear();
+    serde_json::to_writer((&mut *buffer).writer(), items)?;
+    Ok(buffer.split().freeze())
+}
+
 /// Filters out those telemetry items that cannot be re-sent.
-fn retain_retry_items(items: &mut Vec<Envelope>, content: Transmission) {
-    let mut retry_items = Vec::default();
-    for error in content.errors.iter().filter(|error| can_retry_item(error)) {
-        retry_items.push(items.remove(error.index - retry_items.len()));
-    }
+fn retain_retry_items<T>(items: &mut Vec<T>, content: Transmission) -> usize {
+    let errors: HashMap<_, _> = content.errors.iter().map(|error| (error.index, error)).collect();
+
+    let mut rejected = 0;
+    *items = mem::take(items)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| match errors.get(&index) {
+            Some(error) if can_retry_item(error) => Some(item),
+            Some(error) => {
+                warn!(
+                    "Telemetry item rejected by the server: index={} status={} message={:?}",
+                    index, error.status_code, error.message
+                );
+                rejected += 1;
+                None
+            }
+            None => None,
+        })
+        .collect();
+    rejected
+}
 
-    *items = retry_items;
+/// Parses a `Retry-After` header value given either as an HTTP date or as a number of seconds to wait.
+fn parse_retry_after(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    let value = value.to_str().ok()?.trim();
+    match value.parse::<u32>() {
+        Ok(seconds) => time::now().checked_add_signed(chrono::Duration::seconds(i64::from(seconds))),
+        Err(_) => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|retry_after| retry_after.with_timezone(&Utc)),
+    }
 }
 
 /// Determines that a telemetry item can be re-send corresponding to this submission status
@@ -138,6 +348,8 @@ fn can_retry_item(item: &TransmissionItem) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Read;
+
     use chrono::TimeZone;
     use http::{Request, StatusCode};
     use hyper::{
@@ -151,17 +363,24 @@ mod tests {
 
     #[test_case(items(), StatusCode::OK, None, Some(all_accepted()), Response::Success; "success")]
     #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(partial_some_retries()), Response::Retry(retry_items()); "partial. resend some items")]
-    #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(partial_no_retries()), Response::NoRetry; "partial. nothing to resend")]
+    #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(partial_no_retries()), Response::NoRetry(2); "partial. nothing to resend")]
+    #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(partial_unordered_errors()), Response::Retry(retry_items()); "partial. errors in any order")]
     #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(none_accepted()), Response::Retry(items()); "partial. resend everything")]
     #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, Some(all_accepted()), Response::Success; "partial. everything accepted")]
-    #[test_case(items(), StatusCode::BAD_REQUEST, None, None, Response::NoRetry; "bad request. no retry")]
+    #[test_case(items(), StatusCode::PARTIAL_CONTENT, None, None, Response::Retry(items()); "partial. malformed response. resend everything")]
+    #[test_case(items(), StatusCode::BAD_REQUEST, None, None, Response::NoRetry(5); "bad request. no retry")]
     #[test_case(items(), StatusCode::REQUEST_TIMEOUT, None, None, Response::Retry(items()); "timeout. resend everything")]
     #[test_case(items(), StatusCode::REQUEST_TIMEOUT, Some(retry_after_str()), None, Response::Throttled(retry_after(), items()); "timeout. throttled")]
     #[test_case(items(), StatusCode::TOO_MANY_REQUESTS, None, None,Response::Retry(items()); "too many requests. no retry-after. resend everything")]
     #[test_case(items(), StatusCode::TOO_MANY_REQUESTS, Some(retry_after_str()), None, Response::Throttled(retry_after(), items()); "too many requests. retry-after. throttled")]
+    #[test_case(items(), StatusCode::TOO_MANY_REQUESTS, Some("soon"), None, Response::Retry(items()); "too many requests. malformed retry-after. resend everything")]
     #[test_case(items(), StatusCode::INTERNAL_SERVER_ERROR, None, None, Response::Retry(items()); "server error. resend everything")]
     #[test_case(items(), StatusCode::SERVICE_UNAVAILABLE, None, None, Response::Retry(items()); "service unavailable. resend everything")]
-    #[test_case(items(), StatusCode::UNAUTHORIZED, None, None, Response::NoRetry; "unauthorized. no retry")]
+    #[test_case(items(), StatusCode::BAD_GATEWAY, None, None, Response::Retry(items()); "bad gateway. resend everything")]
+    #[test_case(items(), StatusCode::GATEWAY_TIMEOUT, None, None, Response::Retry(items()); "gateway timeout. resend everything")]
+    #[test_case(items(), StatusCode::UNAUTHORIZED, None, None, Response::NoRetry(5); "unauthorized. no retry")]
+    #[test_case(items(), StatusCode::FORBIDDEN, None, None, Response::NoRetry(5); "forbidden. no retry")]
+    #[test_case(items(), StatusCode::NOT_FOUND, None, None, Response::NoRetry(5); "not found. no retry")]
     #[test_case(items(), StatusCode::REQUEST_TIMEOUT, None, Some(partial_some_retries()), Response::Retry(retry_items()); "timeout. resend some items")]
     #[test_case(items(), StatusCode::INTERNAL_SERVER_ERROR, None, Some(partial_some_retries()), Response::Retry(retry_items()); "server error. resend some items")]
     fn it_sends_telemetry_and_handles_server_response(
@@ -175,7 +394,7 @@ mod tests {
         rt.block_on(async {
             let url = create_server(status_code, retry_after, body);
 
-            let transmitter = Transmitter::new(&format!("{}/track", url));
+            let mut transmitter = Transmitter::new(&format!("{}/track", url));
 
             let response = transmitter.send(items).await.unwrap();
 
@@ -183,6 +402,264 @@ mod tests {
         });
     }
 
+    #[tokio::test]
+    async fn it_resends_everything_when_server_unreachable() {
+        // nothing listens on this port
+        let mut transmitter = Transmitter::new("http://127.0.0.1:1/track");
+
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::Retry(items()));
+    }
+
+    #[test_case(Some(0), Some("gzip"); "compressed")]
+    #[test_case(Some(usize::MAX), None; "below threshold")]
+    #[test_case(None, None; "compression disabled")]
+    #[tokio::test]
+    async fn it_compresses_payload_exceeding_threshold(
+        compression_threshold: Option<usize>,
+        expected_encoding: Option<&'static str>,
+    ) {
+        let url = create_decoding_server(expected_encoding);
+        let mut transmitter = Transmitter::new(&format!("{}/track", url));
+        transmitter.compression_threshold = compression_threshold;
+
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::Success);
+    }
+
+    #[test]
+    fn it_reuses_payload_buffer() {
+        let mut buffer = BytesMut::new();
+
+        let payload = serialize(&mut buffer, &items()).unwrap();
+        assert_eq!(payload, serde_json::to_vec(&items()).unwrap());
+        let allocation = payload.as_ptr();
+        drop(payload);
+
+        let payload = serialize(&mut buffer, &items()).unwrap();
+        assert_eq!(payload.as_ptr(), allocation);
+    }
+
+    #[test]
+    fn it_serializes_batch_as_json_array() {
+        let payload = serialize_batch(&items()).unwrap();
+
+        let batch: Vec<Value> = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(batch.len(), items().len());
+    }
+
+    #[test]
+    fn it_converts_envelope_to_json_value() {
+        let item = items().remove(0);
+
+        let value = to_json_value(&item).unwrap();
+
+        assert_eq!(value["name"], Value::from("event 0"));
+    }
+
+    #[tokio::test]
+    async fn it_sends_telemetry_with_custom_http_client() {
+        let url = create_header_server("x-custom-client");
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-custom-client", "true".parse().unwrap());
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint(format!("{}/track", url))
+            .http_client(Client::builder().default_headers(headers).build().unwrap())
+            .build()
+            .unwrap();
+        let mut transmitter = Transmitter::from_config(&config);
+
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::Success);
+    }
+
+    #[tokio::test]
+    async fn it_resends_everything_when_server_does_not_respond_in_time() {
+        let url = create_slow_server(Duration::from_secs(5));
+        let mut transmitter = Transmitter::new(&format!("{}/track", url));
+        transmitter.timeout = Duration::from_millis(100);
+
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::Retry(items()));
+    }
+
+    #[tokio::test]
+    async fn it_sends_batch_as_json() {
+        let url = create_json_server();
+        let mut transmitter = Transmitter::new(&format!("{}/track", url));
+
+        let single = transmitter.send(items().into_iter().take(1).collect()).await.unwrap();
+        let batch = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(single, Response::Success);
+        assert_eq!(batch, Response::Success);
+    }
+
+    #[tokio::test]
+    async fn it_sends_telemetry_through_proxy() {
+        // a proxy receives requests to any host together with credentials specified in its URL
+        let proxy = create_header_server("proxy-authorization");
+        let config = TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .endpoint("http://127.0.0.1:9/track")
+            .proxy(proxy.replace("http://", "http://user:password@"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut transmitter = Transmitter::from_config(&config);
+
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::Success);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_request_id_of_accepted_batch() {
+        let url = create_request_id_server();
+        let mut transmitter = Transmitter::new(&format!("{}/track", url));
+        let last_request_id = transmitter.clone().last_request_id();
+
+        transmitter.send(items()).await.unwrap();
+        let response = transmitter.send(items()).await.unwrap();
+
+        assert_eq!(response, Response::NoRetry(5));
+        assert_eq!(*last_request_id.lock().unwrap(), Some("request-1".into()));
+    }
+
+    /// Creates a server that accepts the first request only and responds with an incrementing request id.
+    fn create_request_id_server() -> String {
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let make_service = make_service_fn(move |_| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_: Request<Body>| {
+                    let request = requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                    async move {
+                        let status_code = if request == 1 {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::BAD_REQUEST
+                        };
+                        hyper::Response::builder()
+                            .status(status_code)
+                            .header(REQUEST_ID, format!("request-{}", request))
+                            .body(Body::empty())
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
+    /// Creates a server that responds with `200 OK` after specified delay.
+    fn create_slow_server(delay: Duration) -> String {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, hyper::Error>(service_fn(move |_: Request<Body>| async move {
+                tokio::time::sleep(delay).await;
+                hyper::Response::builder().status(StatusCode::OK).body(Body::empty())
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
+    /// Creates a server that responds with `200 OK` only when a request contains expected header.
+    fn create_header_server(expected_header: &'static str) -> String {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| async move {
+                let status_code = if request.headers().contains_key(expected_header) {
+                    StatusCode::OK
+                } else {
+                    StatusCode::BAD_REQUEST
+                };
+
+                hyper::Response::builder().status(status_code).body(Body::empty())
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
+    /// Creates a server that responds with `200 OK` only when a request declares a JSON payload.
+    fn create_json_server() -> String {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| async move {
+                let status_code = match request.headers().get(CONTENT_TYPE) {
+                    Some(content_type) if content_type == "application/json" => StatusCode::OK,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+
+                hyper::Response::builder().status(status_code).body(Body::empty())
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
+    /// Creates a server that responds with `200 OK` only when a request is encoded with expected
+    /// content encoding and contains a valid batch of telemetry items.
+    fn create_decoding_server(expected_encoding: Option<&'static str>) -> String {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| async move {
+                let encoding = request
+                    .headers()
+                    .get(CONTENT_ENCODING)
+                    .map(|encoding| encoding.to_str().unwrap().to_string());
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+
+                let payload = if encoding.is_some() {
+                    let mut payload = Vec::default();
+                    flate2::read::GzDecoder::new(&body[..])
+                        .read_to_end(&mut payload)
+                        .unwrap();
+                    payload
+                } else {
+                    body.to_vec()
+                };
+
+                let status_code = match serde_json::from_slice::<Vec<Value>>(&payload) {
+                    Ok(items) if encoding.as_deref() == expected_encoding && items.len() == 5 => StatusCode::OK,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+
+                hyper::Response::builder().status(status_code).body(Body::empty())
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
     fn create_server(status_code: StatusCode, retry_after: Option<&'static str>, body: Option<Value>) -> String {
         let make_service = make_service_fn(move |_| {
             let retry_after = retry_after.map(ToString::to_string);
@@ -206,7 +683,7 @@ mod tests {
             }
         });
 
-        let server = Server::bind(&([0, 0, 0, 0], 0).into()).serve(make_service);
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
         let url = format!("http://{}", server.local_addr());
 
         tokio::spawn(server);
@@ -252,6 +729,30 @@ mod tests {
         })
     }
 
+    fn partial_unordered_errors() -> Value {
+        json!({
+            "itemsAccepted": 2,
+            "itemsReceived": 5,
+            "errors": [
+                {
+                    "index": 4,
+                    "statusCode": 503,
+                    "message": "OK Later"
+                },
+                {
+                    "index": 1,
+                    "statusCode": 400,
+                    "message": "Bad 1"
+                },
+                {
+                    "index": 7,
+                    "statusCode": 500,
+                    "message": "Unknown item"
+                },
+            ],
+        })
+    }
+
     fn none_accepted() -> Value {
         json!({
             "itemsAccepted": 0,
@@ -294,6 +795,15 @@ mod tests {
         })
     }
 
+    #[test_case("Wed, 09 Aug 2017 23:43:57 GMT", Some(retry_after()); "http date")]
+    #[test_case("120", Some(Utc.ymd(2017, 8, 9).and_hms(23, 43, 57) + chrono::Duration::seconds(120)); "delta seconds")]
+    #[test_case("soon", None; "malformed")]
+    fn it_parses_retry_after(value: &'static str, expected: Option<DateTime<Utc>>) {
+        time::set(retry_after());
+
+        assert_eq!(parse_retry_after(&HeaderValue::from_static(value)), expected);
+    }
+
     fn retry_after_str() -> &'static str {
         "Wed, 09 Aug 2017 23:43:57 GMT"
     }
@@ -0,0 +1,164 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+
+use crate::TelemetryItem;
+
+/// A transport submits batches of telemetry items to a destination other than Application Insights
+/// HTTP endpoint, e.g. an MQTT broker, a Unix socket or a local collector. It replaces HTTP submission
+/// of a channel when configured with [`transport`](struct.TelemetryConfigBuilder.html#method.transport),
+/// while batching, flushing and retries are still handled by the channel.
+///
+/// Telemetry items serialize to the same JSON the server accepts.
+///
+/// # Examples
+///
+/// ```rust
+/// use appinsights::{TelemetryConfig, TelemetryItem, Transport, TransportError};
+///
+/// struct StdoutTransport;
+///
+/// #[async_trait::async_trait]
+/// impl Transport for StdoutTransport {
+///     async fn send(&self, items: Vec<TelemetryItem>) -> Result<(), TransportError> {
+///         let payload = serde_json::to_string(&items).map_err(|err| TransportError::Permanent(err.to_string()))?;
+///         println!("{}", payload);
+///         Ok(())
+///     }
+/// }
+///
+/// let config = TelemetryConfig::builder()
+///     .i_key("00000000-0000-0000-0000-000000000000")
+///     .transport(StdoutTransport)
+///     .build()
+///     .expect("valid instrumentation key");
+/// ```
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Submits a batch of telemetry items.
+    async fn send(&self, items: Vec<TelemetryItem>) -> Result<(), TransportError>;
+}
+
+/// Describes an error occurred when a transport was not able to submit telemetry items.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    /// Telemetry items were not submitted and will be retried according to the retry policy.
+    Transient(String),
+
+    /// Telemetry items were rejected and will be discarded.
+    Permanent(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Transient(reason) => write!(f, "telemetry items were not submitted: {}", reason),
+            TransportError::Permanent(reason) => write!(f, "telemetry items were rejected: {}", reason),
+        }
+    }
+}
+
+impl Error for TransportError {}
+
+/// A shared reference to a custom transport kept by configuration.
+#[derive(Clone)]
+pub(crate) struct CustomTransport(pub(crate) Arc<dyn Transport>);
+
+impl Debug for CustomTransport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomTransport")
+    }
+}
+
+impl PartialEq for CustomTransport {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        contracts::Envelope,
+        transmitter::{Response, Transmitter},
+        TelemetryClient, TelemetryConfig,
+    };
+
+    #[tokio::test]
+    async fn it_submits_items_with_custom_transport() {
+        let transport = TestTransport::new(Ok(()));
+        let client = TelemetryClient::from_config(create_config(transport.clone()));
+
+        client.track_event("first");
+        client.track_event("second");
+
+        assert_eq!(client.flush(Some(Duration::from_secs(5))).await, Ok(()));
+
+        let names: Vec<_> = transport.items().iter().map(|item| item.name().to_string()).collect();
+        assert_eq!(names, vec!["Microsoft.ApplicationInsights.Event"; 2]);
+    }
+
+    #[tokio::test]
+    async fn it_retries_items_when_transport_fails_temporarily() {
+        let transport = TestTransport::new(Err(TransportError::Transient("offline".into())));
+        let mut transmitter = Transmitter::from_config(&create_config(transport));
+
+        let response = transmitter.send_items(vec![Envelope::default()]).await.unwrap();
+
+        assert_matches!(response, Response::Retry(items) if items.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn it_discards_items_rejected_by_transport() {
+        let transport = TestTransport::new(Err(TransportError::Permanent("too large".into())));
+        let mut transmitter = Transmitter::from_config(&create_config(transport));
+
+        let response = transmitter.send_items(vec![Envelope::default()]).await.unwrap();
+
+        assert_eq!(response, Response::NoRetry(1));
+    }
+
+    fn create_config(transport: TestTransport) -> TelemetryConfig {
+        TelemetryConfig::builder()
+            .i_key("00000000-0000-0000-0000-000000000000")
+            .transport(transport)
+            .build()
+            .unwrap()
+    }
+
+    #[derive(Clone)]
+    struct TestTransport {
+        items: Arc<Mutex<Vec<TelemetryItem>>>,
+        result: Result<(), TransportError>,
+    }
+
+    impl TestTransport {
+        fn new(result: Result<(), TransportError>) -> Self {
+            Self {
+                items: Arc::default(),
+                result,
+            }
+        }
+
+        fn items(&self) -> Vec<TelemetryItem> {
+            self.items.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Transport for TestTransport {
+        async fn send(&self, items: Vec<TelemetryItem>) -> Result<(), TransportError> {
+            self.items.lock().unwrap().extend(items);
+            self.result.clone()
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    contracts::{Base, Data, Envelope},
+    telemetry::MAX_EVENT_NAME_LENGTH,
+    time,
+};
+
+/// Maximum number of characters in names, types and codes accepted by the server.
+const MAX_NAME_LENGTH: usize = 1024;
+
+/// Maximum number of characters in identifiers accepted by the server.
+const MAX_ID_LENGTH: usize = 128;
+
+/// Maximum number of characters in URLs accepted by the server.
+const MAX_URL_LENGTH: usize = 2048;
+
+/// Maximum number of characters in messages accepted by the server.
+const MAX_MESSAGE_LENGTH: usize = 32768;
+
+/// Maximum number of characters in property keys and measurement names accepted by the server.
+const MAX_KEY_LENGTH: usize = 150;
+
+/// Maximum number of characters in property values and dependency commands accepted by the server.
+const MAX_VALUE_LENGTH: usize = 8192;
+
+/// Describes a violation of the schema the server expects an envelope to conform to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// A required field is missing or empty.
+    Missing(String),
+
+    /// A string field is longer than the server accepts.
+    TooLong {
+        /// A path to the field.
+        field: String,
+        /// An actual number of characters.
+        length: usize,
+        /// A maximum number of characters.
+        max: usize,
+    },
+
+    /// A numeric field is NaN or infinite.
+    NotFinite {
+        /// A path to the field.
+        field: String,
+        /// An actual value.
+        value: f64,
+    },
+
+    /// A field is not formatted as the server expects, e.g. a malformed duration.
+    Malformed {
+        /// A path to the field.
+        field: String,
+        /// An actual value.
+        value: String,
+    },
+
+    /// A time stamp is too far from current time, so the server discards the envelope.
+    TimestampOutOfRange(String),
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(field) => write!(f, "required field {} is missing", field),
+            Self::TooLong { field, length, max } => {
+                write!(
+                    f,
+                    "field {} has {} characters but at most {} are accepted",
+                    field, length, max
+                )
+            }
+            Self::NotFinite { field, value } => write!(f, "field {} is not a finite number: {}", field, value),
+            Self::Malformed { field, value } => write!(f, "field {} is malformed: {}", field, value),
+            Self::TimestampOutOfRange(time) => write!(
+                f,
+                "time stamp {} is more than {} hours away from now",
+                time,
+                time::MAX_TIMESTAMP_SKEW_HOURS
+            ),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Checks an envelope against the schema the server expects: required fields are present, strings fit
+/// the server limits, numbers are finite, durations are well-formed and the time stamp is close
+/// enough to current time. Returns all violations found.
+///
+/// # Examples
+///
+/// ```rust
+/// use appinsights::schema::{validation::validate_envelope, Envelope};
+///
+/// let errors = validate_envelope(&Envelope::default());
+/// assert!(!errors.is_empty());
+/// ```
+pub fn validate_envelope(envelope: &Envelope) -> Vec<ValidationError> {
+    let mut validator = Validator::default();
+
+    validator.required("name", &envelope.name);
+    validator.required("iKey", envelope.i_key.as_deref().unwrap_or_default());
+    if validator.required("time", &envelope.time) {
+        validator.time(&envelope.time);
+    }
+    if let Some(sample_rate) = envelope.sample_rate {
+        validator.finite("sampleRate", sample_rate);
+    }
+
+    match &envelope.data {
+        Some(Base::Data(data)) => validator.data(data),
+        None => validator.errors.push(ValidationError::Missing("data".into())),
+    }
+
+    validator.errors
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    fn data(&mut self, data: &Data) {
+        match data {
+            Data::AvailabilityData(data) => {
+                self.string("baseData.id", &data.id, MAX_ID_LENGTH, true);
+                self.string("baseData.name", &data.name, MAX_NAME_LENGTH, true);
+                self.duration("baseData.duration", &data.duration);
+                self.optional("baseData.runLocation", &data.run_location, MAX_NAME_LENGTH);
+                self.optional("baseData.message", &data.message, MAX_MESSAGE_LENGTH);
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::EventData(data) => {
+                self.string("baseData.name", &data.name, MAX_EVENT_NAME_LENGTH, true);
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::ExceptionData(data) => {
+                if data.exceptions.is_empty() {
+                    self.errors.push(ValidationError::Missing("baseData.exceptions".into()));
+                }
+                for (i, exception) in data.exceptions.iter().enumerate() {
+                    let field = |name| format!("baseData.exceptions[{}].{}", i, name);
+                    self.string(&field("typeName"), &exception.type_name, MAX_NAME_LENGTH, true);
+                    self.string(&field("message"), &exception.message, MAX_MESSAGE_LENGTH, true);
+                }
+                self.optional("baseData.problemId", &data.problem_id, MAX_NAME_LENGTH);
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::MessageData(data) => {
+                self.string("baseData.message", &data.message, MAX_MESSAGE_LENGTH, true);
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::MetricData(data) => {
+                if data.metrics.is_empty() {
+                    self.errors.push(ValidationError::Missing("baseData.metrics".into()));
+                }
+                for (i, metric) in data.metrics.iter().enumerate() {
+                    let field = |name| format!("baseData.metrics[{}].{}", i, name);
+                    self.string(&field("name"), &metric.name, MAX_NAME_LENGTH, true);
+                    let values = [Some(metric.value), metric.min, metric.max, metric.std_dev];
+                    for (name, value) in ["value", "min", "max", "stdDev"].iter().zip(values.iter()) {
+                        if let Some(value) = value {
+                            self.finite(&field(name), *value);
+                        }
+                    }
+                }
+                self.extensions(&data.properties, &None);
+            }
+            Data::PageViewData(data) => {
+                self.string("baseData.id", &data.id, MAX_ID_LENGTH, false);
+                self.string("baseData.name", &data.name, MAX_NAME_LENGTH, true);
+                self.optional("baseData.url", &data.url, MAX_URL_LENGTH);
+                if let Some(duration) = &data.duration {
+                    self.duration("baseData.duration", duration);
+                }
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::RemoteDependencyData(data) => {
+                self.string("baseData.name", &data.name, MAX_NAME_LENGTH, true);
+                self.optional("baseData.id", &data.id, MAX_ID_LENGTH);
+                self.optional("baseData.resultCode", &data.result_code, MAX_NAME_LENGTH);
+                self.duration("baseData.duration", &data.duration);
+                self.optional("baseData.data", &data.data, MAX_VALUE_LENGTH);
+                self.optional("baseData.target", &data.target, MAX_NAME_LENGTH);
+                self.optional("baseData.type", &data.type_, MAX_NAME_LENGTH);
+                self.extensions(&data.properties, &data.measurements);
+            }
+            Data::RequestData(data) => {
+                self.string("baseData.id", &data.id, MAX_ID_LENGTH, true);
+                self.optional("baseData.source", &data.source, MAX_NAME_LENGTH);
+                self.optional("baseData.name", &data.name, MAX_NAME_LENGTH);
+                self.duration("baseData.duration", &data.duration);
+                self.string("baseData.responseCode", &data.response_code, MAX_NAME_LENGTH, true);
+                self.optional("baseData.url", &data.url, MAX_URL_LENGTH);
+                self.extensions(&data.properties, &data.measurements);
+            }
+        }
+    }
+
+    /// Checks that a required string is not empty. Returns `true` if it is present.
+    fn required(&mut self, field: &str, value: &str) -> bool {
+        if value.is_empty() {
+            self.errors.push(ValidationError::Missing(field.into()));
+            false
+        } else {
+            true
+        }
+    }
+
+    fn string(&mut self, field: &str, value: &str, max: usize, required: bool) {
+        if required && !self.required(field, value) {
+            return;
+        }
+
+        let length = value.chars().count();
+        if length > max {
+            self.errors.push(ValidationError::TooLong {
+                field: field.into(),
+                length,
+                max,
+            });
+        }
+    }
+
+    fn optional(&mut self, field: &str, value: &Option<String>, max: usize) {
+        if let Some(value) = value {
+            self.string(field, value, max, false);
+        }
+    }
+
+    fn finite(&mut self, field: &str, value: f64) {
+        if !value.is_finite() {
+            self.errors.push(ValidationError::NotFinite {
+                field: field.into(),
+                value,
+            });
+        }
+    }
+
+    fn duration(&mut self, field: &str, value: &str) {
+        if time::parse_duration(value).is_none() {
+            self.malformed(field, value);
+        }
+    }
+
+    fn time(&mut self, value: &str) {
+        match DateTime::parse_from_rfc3339(value) {
+            Ok(timestamp) => {
+                let skew = (time::now() - timestamp.with_timezone(&Utc)).num_hours().abs();
+                if skew >= time::MAX_TIMESTAMP_SKEW_HOURS {
+                    self.errors.push(ValidationError::TimestampOutOfRange(value.into()));
+                }
+            }
+            Err(_) => self.malformed("time", value),
+        }
+    }
+
+    fn extensions(
+        &mut self,
+        properties: &Option<BTreeMap<String, String>>,
+        measurements: &Option<BTreeMap<String, f64>>,
+    ) {
+        for (key, value) in properties.iter().flatten() {
+            let field = format!("baseData.properties.{}", key);
+            self.string(&field, key, MAX_KEY_LENGTH, false);
+            self.string(&field, value, MAX_VALUE_LENGTH, false);
+        }
+
+        for (name, value) in measurements.iter().flatten() {
+            let field = format!("baseData.measurements.{}", name);
+            self.string(&field, name, MAX_KEY_LENGTH, false);
+            self.finite(&field, *value);
+        }
+    }
+
+    fn malformed(&mut self, field: &str, value: &str) {
+        self.errors.push(ValidationError::Malformed {
+            field: field.into(),
+            value: value.into(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::contracts::{MetricData, RequestData};
+
+    #[test]
+    fn it_accepts_valid_envelope() {
+        time::set(Utc.ymd(2019, 1, 2).and_hms_milli(3, 4, 5, 800));
+
+        let envelope = envelope(Data::RequestData(RequestData {
+            id: "0af7651916cd43dd".into(),
+            duration: "0.00:00:01.0000000".into(),
+            response_code: "200".into(),
+            ..RequestData::default()
+        }));
+
+        assert_eq!(validate_envelope(&envelope), Vec::default());
+    }
+
+    #[test]
+    fn it_reports_all_violations() {
+        time::set(Utc.ymd(2019, 1, 5).and_hms_milli(3, 4, 5, 800));
+
+        let mut measurements = BTreeMap::new();
+        measurements.insert("latency".to_string(), f64::INFINITY);
+        let envelope = envelope(Data::RequestData(RequestData {
+            id: "a".repeat(129),
+            duration: "1s".into(),
+            measurements: Some(measurements),
+            ..RequestData::default()
+        }));
+
+        assert_eq!(
+            validate_envelope(&envelope),
+            vec![
+                ValidationError::TimestampOutOfRange("2019-01-02T03:04:05.678Z".into()),
+                ValidationError::TooLong {
+                    field: "baseData.id".into(),
+                    length: 129,
+                    max: MAX_ID_LENGTH
+                },
+                ValidationError::Malformed {
+                    field: "baseData.duration".into(),
+                    value: "1s".into()
+                },
+                ValidationError::Missing("baseData.responseCode".into()),
+                ValidationError::NotFinite {
+                    field: "baseData.measurements.latency".into(),
+                    value: f64::INFINITY
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_missing_envelope_fields() {
+        let errors = validate_envelope(&Envelope {
+            data: Some(Base::Data(Data::MetricData(MetricData::default()))),
+            ..Envelope::default()
+        });
+
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::Missing("name".into()),
+                ValidationError::Missing("iKey".into()),
+                ValidationError::Missing("time".into()),
+                ValidationError::Missing("baseData.metrics".into()),
+            ]
+        );
+    }
+
+    fn envelope(data: Data) -> Envelope {
+        Envelope {
+            name: "Microsoft.ApplicationInsights.Request".into(),
+            time: "2019-01-02T03:04:05.678Z".into(),
+            i_key: Some("00000000-0000-0000-0000-000000000000".into()),
+            data: Some(Base::Data(data)),
+            ..Envelope::default()
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use appinsights::{
+    schema::{Base, Data, Envelope},
+    telemetry::{ContextTags, EventTelemetry, Properties},
+    Telemetry, TelemetryContext,
+};
+
+#[derive(Telemetry)]
+#[telemetry(name = "OrderPlaced")]
+struct Order {
+    #[telemetry(property)]
+    customer: String,
+    #[telemetry(property)]
+    region: &'static str,
+    #[telemetry(measurement)]
+    total: f64,
+    #[telemetry(measurement)]
+    items: u32,
+    #[allow(dead_code)]
+    notes: Vec<String>,
+}
+
+#[derive(Telemetry)]
+struct JobCompleted {}
+
+#[test]
+fn it_converts_struct_into_event() {
+    let order = Order {
+        customer: "contoso".into(),
+        region: "westus",
+        total: 42.5,
+        items: 3,
+        notes: vec!["gift".into()],
+    };
+
+    let event = event_data(EventTelemetry::from(order));
+
+    assert_eq!(event.name, "OrderPlaced");
+    let properties = event.properties.unwrap();
+    assert_eq!(properties.len(), 2);
+    assert_eq!(properties["customer"], "contoso");
+    assert_eq!(properties["region"], "westus");
+    let measurements = event.measurements.unwrap();
+    assert_eq!(measurements.len(), 2);
+    assert_eq!(measurements["total"], 42.5);
+    assert_eq!(measurements["items"], 3.0);
+}
+
+#[test]
+fn it_names_event_after_struct_by_default() {
+    let event = event_data(EventTelemetry::from(JobCompleted {}));
+
+    assert_eq!(event.name, "JobCompleted");
+}
+
+fn event_data(telemetry: EventTelemetry) -> appinsights::schema::EventData {
+    let context = TelemetryContext::new(
+        "00000000-0000-0000-0000-000000000000".into(),
+        ContextTags::default(),
+        Properties::default(),
+    );
+    match Envelope::from((context, telemetry)).data {
+        Some(Base::Data(Data::EventData(data))) => data,
+        data => panic!("unexpected data: {:?}", data),
+    }
+}
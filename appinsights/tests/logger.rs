@@ -6,6 +6,8 @@ use std::{
 use chrono::Utc;
 use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
 
+// a guard is dropped before awaiting, which the lint does not recognize
+#[allow(dead_code, clippy::await_holding_lock)]
 pub async fn wait_until(entries: &Arc<RwLock<Vec<String>>>, msg: &str, panic_after: Duration) {
     let panic_after = Utc::now() + chrono::Duration::from_std(panic_after).unwrap();
     loop {
@@ -22,6 +24,7 @@ pub async fn wait_until(entries: &Arc<RwLock<Vec<String>>>, msg: &str, panic_aft
     }
 }
 
+#[allow(dead_code)]
 pub fn wait_until_blocking(entries: &Arc<RwLock<Vec<String>>>, msg: &str, panic_after: Duration) {
     let panic_after = Utc::now() + chrono::Duration::from_std(panic_after).unwrap();
     loop {
@@ -38,6 +41,7 @@ pub fn wait_until_blocking(entries: &Arc<RwLock<Vec<String>>>, msg: &str, panic_
     }
 }
 
+#[allow(dead_code)]
 pub fn init(entries: Arc<RwLock<Vec<String>>>) {
     builder(entries).init()
 }
@@ -61,6 +65,7 @@ impl Builder {
         }
     }
 
+    #[allow(dead_code)]
     pub fn level(&mut self, level: Level) -> &mut Self {
         self.level = level;
         self